@@ -13,6 +13,15 @@ app.kubernetes.io/version: {{ .Chart.AppVersion | default .Chart.Version }}
 app.kubernetes.io/managed-by: {{ .Release.Service }}
 {{- end -}}
 
+{{- define "nyx.secretName" -}}
+{{ default (printf "%s-secret" (include "nyx.fullname" .)) .Values.secrets.name }}
+{{- end -}}
+
+{{/* A persisted keystore needs a StatefulSet so every replica keeps its own identity volume. */}}
+{{- define "nyx.workloadKind" -}}
+{{ if .Values.keystore.persistence.enabled }}StatefulSet{{ else }}Deployment{{ end }}
+{{- end -}}
+
 {{- define "nyx.selectorLabels" -}}
 app.kubernetes.io/name: {{ include "nyx.name" . }}
 app.kubernetes.io/instance: {{ .Release.Name }}
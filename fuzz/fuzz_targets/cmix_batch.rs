@@ -1 +1,31 @@
-﻿
\ No newline at end of file
+﻿#![no_main]
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use libfuzzer_sys::fuzz_target;
+use nyx_core::cmix::{MixConfig, MixMode, MIN_VDF_ITERATIONS};
+use nyx_mix::cmix::{verify_batch, CmixBatcher, HashChainDelay};
+use nyx_mix::sphinx::PACKET_LEN;
+
+fuzz_target!(|data: &[u8]| {
+	let Some((&size, rest)) = data.split_first() else { return };
+	let cfg = MixConfig { mode: MixMode::Cmix, batch_size: 2 + size as usize % 16, ..Default::default() };
+	let delay = Arc::new(HashChainDelay::new(MIN_VDF_ITERATIONS));
+	let mut batcher = CmixBatcher::new(&cfg, delay.clone()).unwrap();
+	let now = Instant::now();
+	// Short chunks, zero-extended, so small inputs still fill batches.
+	for chunk in rest.chunks(32) {
+		let mut p = chunk.to_vec();
+		p.resize(PACKET_LEN, 0);
+		if batcher.push(p, now).is_err() {
+			break;
+		}
+	}
+	if let Some(pending) = batcher.flush() {
+		let inputs = pending.inputs().to_vec();
+		let batch = pending.seal(delay.as_ref());
+		assert_eq!(batch.packets.len(), cfg.batch_size);
+		assert!(verify_batch(delay.as_ref(), &inputs, &batch, &batch.audit_nonce()).is_ok());
+	}
+});
@@ -1 +1,80 @@
-﻿
\ No newline at end of file
+﻿#![no_main]
+
+use std::collections::HashSet;
+
+use libfuzzer_sys::fuzz_target;
+use nyx_daemon::path_builder::{NodeInfo, PathBuilder, PathBuilderConfig, PathBuilderError};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+/// Bytes per directory entry: id, operator, ASN, country, flags, bandwidth, latency.
+const ENTRY: usize = 13;
+
+/// Zero bytes decode to `None` so entries without diversity metadata are covered.
+fn attr(b: u8) -> Option<u8> { (b != 0).then_some(b) }
+
+fn entry(b: &[u8]) -> (NodeInfo, bool) {
+	let country = attr(b[3]).map(|c| if c & 1 == 0 { format!("C{}", c >> 1) } else { format!("c{}", c >> 1) });
+	let node = NodeInfo {
+		node_id: [b[0]; 32],
+		public_key: [0; 32],
+		operator: attr(b[1]).map(|o| format!("op{o}")),
+		asn: attr(b[2]).map(u32::from),
+		country,
+		// Raw f32 bits reach NaN, infinities, negatives and subnormals.
+		bandwidth_mbps: f32::from_le_bytes(b[5..9].try_into().unwrap()) as f64,
+		latency_ms: f32::from_le_bytes(b[9..13].try_into().unwrap()) as f64,
+	};
+	(node, b[4] & 1 == 1)
+}
+
+fuzz_target!(|data: &[u8]| {
+	let Some((header, rest)) = data.split_first_chunk::<10>() else { return };
+	let config = PathBuilderConfig {
+		min_hops: usize::from(header[0] % 8),
+		max_hops: usize::from(header[1] % 10),
+		latency_ref_ms: f32::from_le_bytes(header[2..6].try_into().unwrap()) as f64,
+		max_attempts: usize::from(header[6] % 8),
+	};
+	let Ok(pb) = PathBuilder::new(config) else { return };
+	let mut rng = StdRng::seed_from_u64(u64::from(u16::from_le_bytes([header[7], header[8]])));
+
+	let mut directory = Vec::new();
+	let mut blacklist = HashSet::new();
+	for chunk in rest.chunks_exact(ENTRY).take(64) {
+		let (node, banned) = entry(chunk);
+		if banned {
+			blacklist.insert(node.node_id);
+		}
+		directory.push(node);
+	}
+
+	let eligible = pb.hop_weights(&directory, &blacklist);
+	assert!(eligible.iter().all(|w| w.is_finite() && *w > 0.0));
+
+	let hops = pb.config().min_hops + usize::from(header[9]) % (pb.config().max_hops - pb.config().min_hops + 1);
+	match pb.build_with_hops(&directory, &blacklist, hops, &mut rng) {
+		Ok(path) => {
+			assert_eq!(path.len(), hops);
+			for (i, hop) in path.hops.iter().enumerate() {
+				assert!(!blacklist.contains(&hop.node_id));
+				assert!(pb.weight(hop) > 0.0);
+				assert!(directory.contains(hop));
+				for other in &path.hops[i + 1..] {
+					assert_ne!(hop.node_id, other.node_id);
+					assert!(hop.operator.is_none() || hop.operator != other.operator);
+					assert!(hop.asn.is_none() || hop.asn != other.asn);
+					let cc = |n: &NodeInfo| n.country.as_ref().map(|c| c.to_ascii_uppercase());
+					assert!(cc(hop).is_none() || cc(hop) != cc(other));
+				}
+			}
+			assert!(path.entropy_bits.is_finite() && path.entropy_bits >= 0.0, "entropy {}", path.entropy_bits);
+			assert!(path.entropy_bits <= path.max_entropy_bits + 1e-9, "{} > {}", path.entropy_bits, path.max_entropy_bits);
+		}
+		Err(PathBuilderError::InsufficientDiversity { hops: h, eligible: e }) => {
+			assert_eq!(h, hops);
+			assert_eq!(e, eligible.len());
+		}
+		Err(e) => panic!("in-range hop count rejected: {e}"),
+	}
+});
@@ -1 +1,16 @@
-﻿
\ No newline at end of file
+﻿#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nyx_mix::cmix::{DelayFunction, DelayOutput};
+use nyx_mix::vdf::{Vdf, WesolowskiDelay};
+
+// Arbitrary outputs and proofs must be rejected without panicking.
+fuzz_target!(|data: &[u8]| {
+	let delay = WesolowskiDelay::new(Vdf::rsa2048(), 16);
+	let len = delay.vdf().element_len();
+	let mut buf = data.to_vec();
+	buf.resize(2 * len + 32, 0);
+	let challenge: [u8; 32] = buf[..32].try_into().unwrap();
+	let out = DelayOutput { iterations: delay.iterations(), output: buf[32..32 + len].to_vec(), proof: buf[32 + len..].to_vec() };
+	let _ = delay.verify(&challenge, &out);
+});
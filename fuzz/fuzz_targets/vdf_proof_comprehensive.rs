@@ -1 +1,20 @@
-﻿
\ No newline at end of file
+﻿#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nyx_mix::vdf::{Proof, Vdf};
+
+// Honest proofs verify for any input and small iteration count; flipping a
+// proof bit breaks them.
+fuzz_target!(|data: &[u8]| {
+	let Some((&t, rest)) = data.split_first() else { return };
+	let vdf = Vdf::rsa2048();
+	let x = vdf.hash_to_group(rest);
+	let t = u64::from(t);
+	let (y, proof) = vdf.eval_and_prove(&x, t);
+	assert!(vdf.verify(&x, &y, t, &proof));
+	let mut bytes = vdf.encode(&proof.0);
+	bytes[rest.len() % bytes.len()] ^= 1;
+	if let Ok(pi) = vdf.decode(&bytes) {
+		assert!(!vdf.verify(&x, &y, t, &Proof(pi)));
+	}
+});
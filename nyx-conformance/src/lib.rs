@@ -1 +1,5 @@
+#![forbid(unsafe_code)]
 
+//! Nyx protocol conformance suite.
+
+pub mod noise_vectors;
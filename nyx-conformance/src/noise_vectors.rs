@@ -0,0 +1,262 @@
+﻿//! Noise_Nyx handshake test vectors (spec §18.1).
+//!
+//! The vector file pins every key, so each handshake and transport message is
+//! fully determined. [`generate`] produces the published set from
+//! `nyx-crypto`; [`verify`] replays a vector set against `nyx-crypto` and
+//! reports the first byte-level mismatch. Third-party implementations can
+//! consume the same JSON file.
+//!
+//! Messages alternate initiator, responder, initiator, ... The first
+//! `handshake_messages` entries are handshake messages; the rest are transport
+//! messages encrypted with the split keys.
+
+use std::path::Path;
+
+use nyx_crypto::noise::{HandshakeState, Keypair, Pattern, TransportState, PROLOGUE};
+use serde::{Deserialize, Serialize};
+
+/// Published vector file, relative to the crate root.
+pub const VECTOR_FILE: &str = "vectors/noise_nyx.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorFile {
+	pub description: String,
+	pub vectors: Vec<Vector>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vector {
+	pub name: String,
+	pub protocol_name: String,
+	pub prologue: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub init_static: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub init_static_public: Option<String>,
+	pub init_ephemeral: String,
+	pub init_ephemeral_public: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub resp_static: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub resp_static_public: Option<String>,
+	pub resp_ephemeral: String,
+	pub resp_ephemeral_public: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub psk: Option<String>,
+	pub handshake_messages: usize,
+	pub messages: Vec<Message>,
+	pub handshake_hash: String,
+	pub init_send_key: String,
+	pub init_recv_key: String,
+	pub resumption_secret: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+	pub payload: String,
+	pub ciphertext: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VectorError {
+	#[error("{vector}: {field} mismatch (expected {expected}, got {actual})")]
+	Mismatch { vector: String, field: String, expected: String, actual: String },
+	#[error("{vector}: {msg}")]
+	Invalid { vector: String, msg: String },
+	#[error("crypto: {0}")]
+	Crypto(#[from] nyx_crypto::Error),
+	#[error("io: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("json: {0}")]
+	Json(#[from] serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, VectorError>;
+
+/// Two 16-byte runs, e.g. `split_key(0x11, 0x22)` = `11 × 16 ‖ 22 × 16`.
+fn split_key(a: u8, b: u8) -> [u8; 32] {
+	let mut k = [a; 32];
+	k[16..].fill(b);
+	k
+}
+
+const IK_PAYLOADS: [&[u8]; 6] = [b"Nyx 0-RTT early data", b"", b"ping", b"pong", b"", b"Nyx transport"];
+const PSK_PAYLOADS: [&[u8]; 4] = [b"resumed 0-RTT", b"ack", b"ping", b"pong"];
+
+/// Generate the published vector set.
+pub fn generate() -> Result<VectorFile> {
+	let ik = Keys {
+		init_static: Some(split_key(0x33, 0x44)),
+		init_ephemeral: split_key(0xaa, 0xbb),
+		resp_static: Some(split_key(0x11, 0x22)),
+		resp_ephemeral: split_key(0xcc, 0xdd),
+		psk: None,
+	};
+	let psk = Keys { init_static: None, init_ephemeral: split_key(0x55, 0x66), resp_static: None, resp_ephemeral: split_key(0x77, 0x88), psk: Some(split_key(0x99, 0xee)) };
+	Ok(VectorFile {
+		description: "Noise_Nyx handshake vectors (X25519, ChaCha20-Poly1305, SHA-256). All values are hex. Messages alternate initiator/responder starting with the initiator; the first handshake_messages entries are handshake messages, the rest are transport messages. Keys are raw X25519 secrets (clamped on use).".into(),
+		vectors: vec![
+			build("Noise_Nyx_IK", Pattern::IK, PROLOGUE, &ik, &IK_PAYLOADS)?,
+			build("Noise_Nyx_IK_empty_prologue", Pattern::IK, b"", &ik, &IK_PAYLOADS[..2])?,
+			build("Noise_Nyx_NNpsk0_resumption", Pattern::NNpsk0, PROLOGUE, &psk, &PSK_PAYLOADS)?,
+		],
+	})
+}
+
+struct Keys {
+	init_static: Option<[u8; 32]>,
+	init_ephemeral: [u8; 32],
+	resp_static: Option<[u8; 32]>,
+	resp_ephemeral: [u8; 32],
+	psk: Option<[u8; 32]>,
+}
+
+fn build(name: &str, pattern: Pattern, prologue: &[u8], keys: &Keys, payloads: &[&[u8]]) -> Result<Vector> {
+	let pub_of = |k: [u8; 32]| hex::encode(Keypair::from_secret_bytes(k).public_bytes());
+	let mut v = Vector {
+		name: name.into(),
+		protocol_name: pattern.protocol_name().into(),
+		prologue: hex::encode(prologue),
+		init_static: keys.init_static.map(hex::encode),
+		init_static_public: keys.init_static.map(pub_of),
+		init_ephemeral: hex::encode(keys.init_ephemeral),
+		init_ephemeral_public: pub_of(keys.init_ephemeral),
+		resp_static: keys.resp_static.map(hex::encode),
+		resp_static_public: keys.resp_static.map(pub_of),
+		resp_ephemeral: hex::encode(keys.resp_ephemeral),
+		resp_ephemeral_public: pub_of(keys.resp_ephemeral),
+		psk: keys.psk.map(hex::encode),
+		handshake_messages: 2,
+		messages: payloads.iter().map(|p| Message { payload: hex::encode(p), ciphertext: String::new() }).collect(),
+		handshake_hash: String::new(),
+		init_send_key: String::new(),
+		init_recv_key: String::new(),
+		resumption_secret: String::new(),
+	};
+	// Run the exchange once, recording what the implementation produces.
+	run(&mut v, true)?;
+	Ok(v)
+}
+
+/// Check every vector byte for byte against this implementation.
+pub fn verify(file: &VectorFile) -> Result<()> {
+	for v in &file.vectors {
+		run(&mut v.clone(), false)?;
+	}
+	Ok(())
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<VectorFile> { Ok(serde_json::from_slice(&std::fs::read(path)?)?) }
+
+pub fn to_json(file: &VectorFile) -> Result<String> {
+	let mut s = serde_json::to_string_pretty(file)?;
+	s.push('\n');
+	Ok(s)
+}
+
+fn invalid(v: &Vector, msg: impl Into<String>) -> VectorError { VectorError::Invalid { vector: v.name.clone(), msg: msg.into() } }
+
+fn unhex(v: &Vector, field: &str, s: &str) -> Result<Vec<u8>> { hex::decode(s).map_err(|e| invalid(v, format!("{field}: {e}"))) }
+
+fn key(v: &Vector, field: &str, s: Option<&String>) -> Result<[u8; 32]> {
+	let s = s.ok_or_else(|| invalid(v, format!("missing {field}")))?;
+	unhex(v, field, s)?.try_into().map_err(|_| invalid(v, format!("{field}: expected 32 bytes")))
+}
+
+/// Compare (or, when `record`, fill in) an expected hex field.
+fn check(vector: &str, record: bool, field: &str, expected: &mut String, actual: &[u8]) -> Result<()> {
+	let actual = hex::encode(actual);
+	if record {
+		*expected = actual;
+		return Ok(());
+	}
+	if expected.to_ascii_lowercase() != actual {
+		return Err(VectorError::Mismatch { vector: vector.into(), field: field.into(), expected: expected.clone(), actual });
+	}
+	Ok(())
+}
+
+fn run(v: &mut Vector, record: bool) -> Result<()> {
+	let pattern = match v.protocol_name.as_str() {
+		n if n == Pattern::IK.protocol_name() => Pattern::IK,
+		n if n == Pattern::NNpsk0.protocol_name() => Pattern::NNpsk0,
+		n => return Err(invalid(v, format!("unsupported protocol {n}"))),
+	};
+	let name = v.name.clone();
+	let prologue = unhex(v, "prologue", &v.prologue)?;
+	let ie = Keypair::from_secret_bytes(key(v, "init_ephemeral", Some(&v.init_ephemeral))?);
+	let re = Keypair::from_secret_bytes(key(v, "resp_ephemeral", Some(&v.resp_ephemeral))?);
+	check(&name, false, "init_ephemeral_public", &mut v.init_ephemeral_public, &ie.public_bytes())?;
+	check(&name, false, "resp_ephemeral_public", &mut v.resp_ephemeral_public, &re.public_bytes())?;
+	let (i, r) = match pattern {
+		Pattern::IK => {
+			let is = Keypair::from_secret_bytes(key(v, "init_static", v.init_static.as_ref())?);
+			let rs = Keypair::from_secret_bytes(key(v, "resp_static", v.resp_static.as_ref())?);
+			if let Some(expected) = v.init_static_public.as_mut() {
+				check(&name, false, "init_static_public", expected, &is.public_bytes())?;
+			}
+			if let Some(expected) = v.resp_static_public.as_mut() {
+				check(&name, false, "resp_static_public", expected, &rs.public_bytes())?;
+			}
+			let rs_pub = rs.public_bytes();
+			(HandshakeState::initiator(is, rs_pub, &prologue), HandshakeState::responder(rs, &prologue))
+		}
+		Pattern::NNpsk0 => {
+			let psk = key(v, "psk", v.psk.as_ref())?;
+			(HandshakeState::psk_initiator(psk, &prologue), HandshakeState::psk_responder(psk, &prologue))
+		}
+	};
+	let mut hs = [i.with_ephemeral(ie), r.with_ephemeral(re)];
+
+	if v.messages.len() < v.handshake_messages {
+		return Err(invalid(v, "fewer messages than handshake_messages"));
+	}
+	let mut messages = std::mem::take(&mut v.messages);
+	for (n, m) in messages[..v.handshake_messages].iter_mut().enumerate() {
+		let (tx, rx) = if n % 2 == 0 { (0, 1) } else { (1, 0) };
+		let payload = unhex(v, "payload", &m.payload)?;
+		let ct = hs[tx].write_message(&payload)?;
+		check(&name, record, &format!("messages[{n}].ciphertext"), &mut m.ciphertext, &ct)?;
+		let pt = hs[rx].read_message(&ct)?;
+		check(&name, false, &format!("messages[{n}].payload"), &mut m.payload, &pt)?;
+	}
+	let [i, r] = hs;
+	if !i.is_finished() || !r.is_finished() {
+		return Err(invalid(v, "handshake did not complete"));
+	}
+	check(&name, record, "handshake_hash", &mut v.handshake_hash, &i.handshake_hash())?;
+	check(&name, false, "handshake_hash (responder)", &mut v.handshake_hash, &r.handshake_hash())?;
+	let mut ts: [TransportState; 2] = [i.into_transport()?, r.into_transport()?];
+	let (send, recv) = ts[0].keys();
+	check(&name, record, "init_send_key", &mut v.init_send_key, send.as_bytes())?;
+	check(&name, record, "init_recv_key", &mut v.init_recv_key, recv.as_bytes())?;
+	check(&name, record, "resumption_secret", &mut v.resumption_secret, ts[0].resumption_secret())?;
+	check(&name, false, "resumption_secret (responder)", &mut v.resumption_secret, ts[1].resumption_secret())?;
+
+	for (n, m) in messages.iter_mut().enumerate().skip(v.handshake_messages) {
+		let (tx, rx) = if n % 2 == 0 { (0, 1) } else { (1, 0) };
+		let payload = unhex(v, "payload", &m.payload)?;
+		let ct = ts[tx].encrypt(&payload)?;
+		check(&name, record, &format!("messages[{n}].ciphertext"), &mut m.ciphertext, &ct)?;
+		let pt = ts[rx].decrypt(&ct)?;
+		check(&name, false, &format!("messages[{n}].payload"), &mut m.payload, &pt)?;
+	}
+	v.messages = messages;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn generated_vectors_verify_and_corruption_is_caught() {
+		let file = generate().unwrap();
+		verify(&file).unwrap();
+		let mut bad = file.clone();
+		let ct = &mut bad.vectors[0].messages[1].ciphertext;
+		let flipped = if ct.starts_with('0') { '1' } else { '0' };
+		ct.replace_range(0..1, &flipped.to_string());
+		assert!(matches!(verify(&bad), Err(VectorError::Mismatch { .. })));
+	}
+}
@@ -1 +1,39 @@
+//! cMix batching: fixed batch size, verifiable shuffle, delay before release.
 
+use std::sync::Arc;
+use std::time::Instant;
+
+use nyx_core::cmix::{MixConfig, MixMode, MIN_VDF_ITERATIONS};
+use nyx_mix::cmix::{verify_batch, CmixBatcher, HashChainDelay};
+use nyx_mix::sphinx::{Hop, NodeKey, SphinxBuilder};
+use rand::{rngs::StdRng, SeedableRng};
+
+#[test]
+fn sphinx_packets_survive_a_cmix_batch() {
+	let mut rng = StdRng::seed_from_u64(35);
+	let keys: Vec<NodeKey> = (0..3).map(|_| NodeKey::generate(&mut rng)).collect();
+	let route: Vec<Hop> = keys.iter().enumerate().map(|(i, k)| Hop { node_id: [i as u8; 32], public_key: k.public_key() }).collect();
+	let builder = SphinxBuilder::new(&route).unwrap();
+
+	let cfg = MixConfig { mode: MixMode::Cmix, batch_size: 10, ..Default::default() };
+	let delay = Arc::new(HashChainDelay::new(MIN_VDF_ITERATIONS));
+	let mut batcher = CmixBatcher::new(&cfg, delay.clone()).unwrap();
+	let now = Instant::now();
+	let sent: Vec<Vec<u8>> = (0..10u8).map(|i| builder.build_with_rng(&[i], &mut rng).unwrap().as_bytes().to_vec()).collect();
+	for p in &sent {
+		batcher.push(p.clone(), now).unwrap();
+	}
+	let pending = batcher.take_ready(now).expect("batch is full");
+	assert_eq!(pending.dummies(), 0);
+	let inputs = pending.inputs().to_vec();
+	let batch = pending.seal(delay.as_ref());
+
+	let mut out = batch.packets.clone();
+	out.sort();
+	let mut expected = sent;
+	expected.sort();
+	assert_eq!(out, expected);
+	assert!(batch.proof.verify_delay(delay.as_ref()));
+	verify_batch(delay.as_ref(), &inputs, &batch, &batch.audit_nonce()).unwrap();
+	assert!(verify_batch(delay.as_ref(), &inputs, &batch, &[0u8; 32]).is_err());
+}
@@ -1 +1,52 @@
+//! cMix batch proofs must reject tampering and foreign packets.
 
+use std::sync::Arc;
+use std::time::Instant;
+
+use nyx_core::cmix::{MixConfig, MixMode, MIN_VDF_ITERATIONS};
+use nyx_mix::cmix::{verify_batch, Batch, CmixBatcher, HashChainDelay};
+use nyx_mix::sphinx::PACKET_LEN;
+
+fn sealed(seed: u8, delay: &Arc<HashChainDelay>) -> (Vec<Vec<u8>>, Batch) {
+	let cfg = MixConfig { mode: MixMode::Cmix, batch_size: 6, ..Default::default() };
+	let mut batcher = CmixBatcher::new(&cfg, delay.clone()).unwrap();
+	let now = Instant::now();
+	for i in 0..6u8 {
+		batcher.push(vec![seed.wrapping_add(i); PACKET_LEN], now).unwrap();
+	}
+	let pending = batcher.take_ready(now).unwrap();
+	let inputs = pending.inputs().to_vec();
+	(inputs, pending.seal(delay.as_ref()))
+}
+
+#[test]
+fn tampered_accumulator_invalidates_the_delay_proof() {
+	let delay = Arc::new(HashChainDelay::new(MIN_VDF_ITERATIONS));
+	let (inputs, batch) = sealed(0, &delay);
+	let mut forged = batch.clone();
+	forged.proof.accumulator[10] ^= 0x01;
+	assert!(!forged.proof.verify_delay(delay.as_ref()));
+	assert!(verify_batch(delay.as_ref(), &inputs, &forged, &batch.audit_nonce()).is_err());
+}
+
+#[test]
+fn witnesses_do_not_transfer_between_batches_or_packets() {
+	let delay = Arc::new(HashChainDelay::new(MIN_VDF_ITERATIONS));
+	let (inputs_a, batch_a) = sealed(0, &delay);
+	let (inputs_b, batch_b) = sealed(100, &delay);
+	let w = batch_a.inclusion_witness(&inputs_a[2]).unwrap();
+	assert!(batch_a.proof.verify_inclusion(&inputs_a[2], &w));
+	assert!(!batch_a.proof.verify_inclusion(&inputs_a[3], &w));
+	assert!(!batch_b.proof.verify_inclusion(&inputs_a[2], &w));
+	assert!(batch_b.inclusion_witness(&inputs_a[2]).is_none());
+	let wb = batch_b.inclusion_witness(&inputs_b[0]).unwrap();
+	assert!(!batch_a.proof.verify_inclusion(&inputs_b[0], &wb));
+}
+
+#[test]
+fn reordered_inputs_fail_the_audit() {
+	let delay = Arc::new(HashChainDelay::new(MIN_VDF_ITERATIONS));
+	let (mut inputs, batch) = sealed(7, &delay);
+	inputs.swap(0, 1);
+	assert!(verify_batch(delay.as_ref(), &inputs, &batch, &batch.audit_nonce()).is_err());
+}
@@ -1 +1,51 @@
+//! cMix batches gated by the Wesolowski VDF.
 
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use nyx_core::cmix::{MixConfig, MixMode, MIN_VDF_ITERATIONS};
+use nyx_mix::cmix::{verify_batch, CmixBatcher, DelayFunction};
+use nyx_mix::sphinx::PACKET_LEN;
+use nyx_mix::vdf::{Vdf, WesolowskiDelay};
+use nyx_mix::vdf_calib::Calibration;
+
+#[test]
+fn batch_proof_verifies_with_the_published_parameters() {
+	let delay = Arc::new(WesolowskiDelay::new(Vdf::rsa2048(), MIN_VDF_ITERATIONS));
+	let cfg = MixConfig { mode: MixMode::Cmix, batch_size: 4, ..Default::default() };
+	let mut batcher = CmixBatcher::new(&cfg, delay.clone()).unwrap();
+	let now = Instant::now();
+	for i in 0..4u8 {
+		batcher.push(vec![i; PACKET_LEN], now).unwrap();
+	}
+	let pending = batcher.take_ready(now).unwrap();
+	let inputs = pending.inputs().to_vec();
+	let batch = pending.seal(delay.as_ref());
+
+	// A verifier only needs the modulus; T is recorded in the proof.
+	let verifier = WesolowskiDelay::new(Vdf::rsa2048(), 1);
+	assert_eq!(batch.proof.iterations(), MIN_VDF_ITERATIONS);
+	assert!(batch.proof.verify_delay(&verifier));
+	let mut relabelled = batch.proof.clone();
+	relabelled.delay.iterations /= 2;
+	assert!(!relabelled.verify_delay(&verifier));
+	verify_batch(&verifier, &inputs, &batch, &batch.audit_nonce()).unwrap();
+
+	let mut tampered = batch.proof.delay.clone();
+	tampered.output[255] ^= 1;
+	assert!(!verifier.verify(&batch.proof.challenge(), &tampered));
+}
+
+#[test]
+fn calibrated_delay_tracks_the_target() {
+	let vdf = Vdf::rsa2048();
+	let cal = Calibration::measure_with(&vdf, 256);
+	let target = Duration::from_millis(50);
+	let delay = WesolowskiDelay::new(vdf, cal.iterations_for(target));
+	let start = Instant::now();
+	let out = delay.evaluate(&[7; 32]);
+	let took = start.elapsed();
+	assert!(delay.verify(&[7; 32], &out));
+	// Loose bounds: CI machines are noisy.
+	assert!(took >= target / 5 && took <= target * 10, "took {took:?} for a {target:?} target");
+}
@@ -1 +1,31 @@
+//! RS FEC over 1280-byte packets: default code and streaming round trip.
 
+use nyx_fec::reed_solomon::{RsConfig, RsDecoder, RsEncoder, Shard, SHARD_SIZE};
+
+fn packets(n: usize) -> Vec<Shard> { (0..n).map(|i| [i as u8; SHARD_SIZE]).collect() }
+
+#[test]
+fn default_code_targets_thirty_percent_redundancy() {
+	let cfg = RsConfig::default();
+	assert_eq!((cfg.data_shards, cfg.parity_shards), (10, 3));
+	assert!((cfg.redundancy() - 0.30).abs() < 1e-9);
+	assert!(cfg.total_shards() <= 255);
+}
+
+#[test]
+fn lossless_stream_passes_data_through_unchanged() {
+	let mut enc = RsEncoder::new(RsConfig::default()).unwrap();
+	let mut dec = RsDecoder::new(RsConfig::default()).unwrap();
+	let mut wire = Vec::new();
+	for p in &packets(25) {
+		enc.push(p, |h, s| wire.push((h, *s))).unwrap();
+	}
+	enc.flush(|h, s| wire.push((h, *s)));
+	assert_eq!(wire.len(), 25 + 3 * 3);
+	let mut out = Vec::new();
+	for (h, s) in &wire {
+		dec.push(*h, s, |_, s| out.push(*s)).unwrap();
+	}
+	assert_eq!(out, packets(25));
+	assert_eq!(dec.stats().recovered, 0);
+}
@@ -0,0 +1,74 @@
+//! FEC blocks spread over scheduler-chosen paths survive losing a whole path.
+
+use std::time::Duration;
+
+use nyx_fec::interleave::{Deinterleaver, Interleaver};
+use nyx_fec::reed_solomon::{RsConfig, RsEncoder, Shard, ShardHeader, SHARD_SIZE};
+use nyx_stream::multipath::scheduler::{PathId, PathMetric, WeightedScheduler};
+
+const DEPTH: usize = 4;
+
+fn scheduler() -> WeightedScheduler {
+	let m = PathMetric { rtt: Duration::from_millis(20), loss: 0.0, weight: 1 };
+	WeightedScheduler::new(&[(PathId(1), m), (PathId(2), m), (PathId(3), m)])
+}
+
+fn send(cfg: RsConfig, data: &[Shard]) -> Vec<(PathId, ShardHeader, Shard)> {
+	let mut sched = scheduler();
+	let mut enc = RsEncoder::new(cfg).unwrap();
+	let mut il = Interleaver::new(cfg, DEPTH).unwrap();
+	let mut wire = Vec::new();
+	for d in data {
+		enc.push(d, |h, s| il.push(h, s, || sched.next_path(), |p, h, s| wire.push((p, h, *s)))).unwrap();
+	}
+	enc.flush(|h, s| il.push(h, s, || sched.next_path(), |p, h, s| wire.push((p, h, *s))));
+	il.flush(|| sched.next_path(), |p, h, s| wire.push((p, h, *s)));
+	wire
+}
+
+#[test]
+fn path_outage_mid_block_is_recovered() {
+	// A third of each block travels on each path, so parity of half the data covers one path.
+	let cfg = RsConfig::new(10, 5);
+	let data: Vec<Shard> = (0..200u32).map(|i| { let mut s = [0u8; SHARD_SIZE]; s[..4].copy_from_slice(&i.to_be_bytes()); s }).collect();
+	let wire = send(cfg, &data);
+	for b in 0..20 {
+		for p in 1..=3 {
+			assert_eq!(wire.iter().filter(|(q, h, _)| h.block == b && q.0 == p).count(), 5, "block {b} path {p}");
+		}
+	}
+
+	// Path 2 goes dark part-way through the third interleaved group.
+	let cut = 2 * DEPTH * cfg.total_shards() + 7;
+	let mut de = Deinterleaver::new(cfg, DEPTH).unwrap();
+	let mut out = Vec::new();
+	for (i, (p, h, s)) in wire.iter().enumerate() {
+		if i >= cut && *p == PathId(2) {
+			continue;
+		}
+		de.push(*p, *h, s, |_, s| out.push(*s)).unwrap();
+	}
+	de.flush(|_, s| out.push(*s));
+	assert_eq!(out, data);
+	assert!(de.received_on(PathId(2)) < de.received_on(PathId(1)));
+	assert_eq!(de.decoder().stats().blocks_lost, 0);
+	assert!(de.decoder().stats().recovered > 0);
+}
+
+#[test]
+fn burst_loss_hits_each_block_once() {
+	let cfg = RsConfig::new(10, 1);
+	let data: Vec<Shard> = (0..80u8).map(|i| [i; SHARD_SIZE]).collect();
+	let mut wire = send(cfg, &data);
+	// A burst as long as the interleaving depth, in each group.
+	for group in (0..2).rev() {
+		let start = group * DEPTH * cfg.total_shards() + 13;
+		wire.drain(start..start + DEPTH);
+	}
+	let mut de = Deinterleaver::new(cfg, DEPTH).unwrap();
+	let mut out = Vec::new();
+	for (p, h, s) in &wire {
+		de.push(*p, *h, s, |_, s| out.push(*s)).unwrap();
+	}
+	assert_eq!(out, data);
+}
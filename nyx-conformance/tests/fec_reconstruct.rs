@@ -1 +1,37 @@
+//! Any `data_shards` of a block reconstruct it; fewer do not.
 
+use nyx_fec::reed_solomon::{RsCodec, RsConfig, Shard, SHARD_SIZE};
+use nyx_fec::Error;
+use rand::{rngs::StdRng, seq::index::sample, Rng, SeedableRng};
+
+#[test]
+fn every_loss_pattern_up_to_parity_count_recovers() {
+	let mut rng = StdRng::seed_from_u64(41);
+	for cfg in [RsConfig::default(), RsConfig::new(4, 2), RsConfig::RS_255_223] {
+		let codec = RsCodec::new(cfg).unwrap();
+		let data: Vec<Shard> = (0..cfg.data_shards).map(|_| { let mut s = [0u8; SHARD_SIZE]; rng.fill(&mut s[..]); s }).collect();
+		let parity = codec.encode(&data).unwrap();
+		for _ in 0..8 {
+			let lost = rng.gen_range(0..=cfg.parity_shards);
+			let mut got: Vec<Option<Shard>> = data.iter().copied().map(Some).collect();
+			let mut par: Vec<Option<Shard>> = parity.iter().copied().map(Some).collect();
+			for i in sample(&mut rng, cfg.total_shards(), lost) {
+				if i < cfg.data_shards { got[i] = None } else { par[i - cfg.data_shards] = None }
+			}
+			codec.reconstruct(&mut got, &par).unwrap();
+			assert!(got.iter().zip(&data).all(|(g, d)| g.as_ref() == Some(d)));
+		}
+	}
+}
+
+#[test]
+fn one_loss_too_many_is_reported() {
+	let cfg = RsConfig::default();
+	let codec = RsCodec::new(cfg).unwrap();
+	let data = vec![[1u8; SHARD_SIZE]; 10];
+	let par: Vec<Option<Shard>> = codec.encode(&data).unwrap().into_iter().map(Some).collect();
+	let mut got: Vec<Option<Shard>> = data.into_iter().map(Some).collect();
+	got[..4].fill(None);
+	assert!(matches!(codec.reconstruct(&mut got, &par), Err(Error::TooFewShards)));
+	assert!(got[..4].iter().all(Option::is_none));
+}
@@ -1 +1,35 @@
+//! Shards of interleaved blocks arriving in random order still decode.
 
+use nyx_fec::reed_solomon::{RsConfig, RsDecoder, RsEncoder, Shard, ShardHeader, SHARD_SIZE};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+#[test]
+fn shuffled_lossy_stream_decodes() {
+	let cfg = RsConfig::default();
+	let mut rng = StdRng::seed_from_u64(44);
+	let sent: Vec<Shard> = (0..47u32).map(|i| { let mut s = [0u8; SHARD_SIZE]; s[..4].copy_from_slice(&i.to_be_bytes()); s }).collect();
+	let mut enc = RsEncoder::new(cfg).unwrap();
+	let mut wire: Vec<(ShardHeader, Shard)> = Vec::new();
+	for s in &sent {
+		enc.push(s, |h, s| wire.push((h, *s))).unwrap();
+	}
+	enc.flush(|h, s| wire.push((h, *s)));
+	wire.shuffle(&mut rng);
+	// Lose up to the parity count per block, chosen after shuffling.
+	let mut lost_per_block = std::collections::HashMap::new();
+	wire.retain(|(h, _)| {
+		let lost = lost_per_block.entry(h.block).or_insert(0);
+		if *lost < cfg.parity_shards && h.index % 4 == 1 { *lost += 1; false } else { true }
+	});
+
+	let mut dec = RsDecoder::new(cfg).unwrap();
+	let mut out = Vec::new();
+	for (h, s) in &wire {
+		// Re-encoding the header exercises the wire format.
+		dec.push(ShardHeader::from_bytes(&h.to_bytes()).unwrap(), s, |h, s| out.push((h.block, h.index, *s))).unwrap();
+	}
+	out.sort_by_key(|(b, i, _)| (*b, *i));
+	assert_eq!(out.into_iter().map(|(_, _, s)| s).collect::<Vec<_>>(), sent);
+	assert!(dec.stats().recovered > 0);
+	assert_eq!(dec.stats().blocks_lost, 0);
+}
@@ -1 +1,55 @@
+//! ICE agents must ignore STUN responses whose transaction id they never
+//! issued, and reject checks that fail authentication.
 
+use std::time::Duration;
+
+use nyx_transport::ice::CandidateKind;
+use nyx_transport::stun_server::{Attribute, Class, StunMessage, BINDING};
+use nyx_transport::{IceAgent, IceConfig, IceRole};
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn forged_responses_do_not_complete_checks() {
+	let cfg = IceConfig { rto: Duration::from_millis(50), connect_timeout: Duration::from_secs(3), ..Default::default() };
+	let a = IceAgent::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), IceRole::Controlling, cfg.clone()).unwrap();
+	let b = IceAgent::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), IceRole::Controlled, cfg).unwrap();
+	let (ca, cb) = (a.gather().await, b.gather().await);
+
+	// Flood `a` with success responses for transactions it never started,
+	// each claiming a bogus mapped address.
+	let attacker = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+	let target = ca[0].addr;
+	let flood = tokio::spawn(async move {
+		loop {
+			let forged = StunMessage::new(Class::Success, BINDING, rand::random()).with(Attribute::XorMappedAddress("203.0.113.9:1".parse().unwrap()));
+			let _ = attacker.send_to(&forged.encode(None), target).await;
+			tokio::time::sleep(Duration::from_millis(1)).await;
+		}
+	});
+
+	let (oa, ob) = tokio::join!(a.connect(b.credentials(), &ca, &cb), b.connect(a.credentials(), &cb, &ca));
+	flood.abort();
+	let (oa, ob) = (oa.unwrap(), ob.unwrap());
+	assert_eq!(oa.pair.remote.addr, cb[0].addr);
+	assert_eq!(ob.pair.remote.addr, ca[0].addr);
+	assert_eq!(oa.pair.remote.kind, CandidateKind::Host);
+}
+
+#[tokio::test]
+async fn unauthenticated_check_gets_401() {
+	let a = IceAgent::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), IceRole::Controlled, IceConfig::default()).unwrap();
+	let target = a.gather().await[0].addr;
+	let probe = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+	let username = format!("{}:intruder", a.credentials().ufrag);
+	let req = StunMessage::binding_request().with(Attribute::Username(username));
+	probe.send_to(&req.encode(Some(b"not the password")), target).await.unwrap();
+
+	let mut buf = [0u8; 1500];
+	let (n, _) = timeout(Duration::from_secs(2), probe.recv_from(&mut buf)).await.expect("error response").unwrap();
+	let resp = StunMessage::decode(&buf[..n]).unwrap();
+	assert_eq!(resp.txid, req.txid);
+	assert_eq!(resp.class, Class::Error);
+	assert!(resp.attrs.iter().any(|a| matches!(a, Attribute::ErrorCode(401, _))));
+}
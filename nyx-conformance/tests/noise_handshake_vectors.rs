@@ -1 +1,30 @@
+//! Checks the published Noise_Nyx vectors byte for byte.
+//!
+//! Regenerate the file with `NYX_REGEN_VECTORS=1 cargo test -p nyx-conformance --test noise_handshake_vectors`.
 
+use std::path::PathBuf;
+
+use nyx_conformance::noise_vectors::{generate, load, to_json, verify, VECTOR_FILE};
+
+fn vector_path() -> PathBuf { PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(VECTOR_FILE) }
+
+#[test]
+fn published_vectors_match_implementation() {
+	let path = vector_path();
+	if std::env::var_os("NYX_REGEN_VECTORS").is_some() {
+		std::fs::write(&path, to_json(&generate().unwrap()).unwrap()).unwrap();
+	}
+	let file = load(&path).unwrap();
+	verify(&file).unwrap();
+	assert_eq!(file, generate().unwrap(), "vector file is stale; regenerate with NYX_REGEN_VECTORS=1");
+}
+
+#[test]
+fn vectors_use_spec_prologue_and_keys() {
+	let file = load(vector_path()).unwrap();
+	let ik = &file.vectors[0];
+	assert_eq!(ik.prologue, "4e7978302e31");
+	assert!(ik.resp_static.as_deref().unwrap().starts_with("1111"));
+	assert!(ik.init_ephemeral.starts_with("aaaa"));
+	assert!(file.vectors.iter().all(|v| v.messages.iter().all(|m| !m.ciphertext.is_empty())));
+}
@@ -1 +1,31 @@
+//! RaptorQ recovers any object from its symbols after losses and reordering.
 
+use nyx_fec::raptorq::{RaptorQDecoder, RaptorQEncoder, RqSymbol};
+use proptest::prelude::*;
+
+proptest! {
+	#![proptest_config(ProptestConfig::with_cases(32))]
+
+	#[test]
+	fn raptorq_roundtrip_with_loss(data in proptest::collection::vec(any::<u8>(), 1..24_000), drop_every in 2usize..6, seed in any::<u64>()) {
+		let mut enc = RaptorQEncoder::new(&data).unwrap();
+		let source = enc.source_symbols();
+		let lost = source.len().div_ceil(drop_every);
+		let mut kept: Vec<RqSymbol> = source.into_iter().enumerate().filter(|(i, _)| i % drop_every != 0).map(|(_, s)| s).collect();
+		// A little over the loss, as RaptorQ decodes from K plus a few symbols.
+		kept.extend(enc.repair_symbols(lost as u32 + 2));
+		let n = kept.len();
+		for i in 0..n {
+			kept.swap(i, (seed as usize).wrapping_mul(i + 1) % n);
+		}
+
+		let mut dec = RaptorQDecoder::new(&enc.oti()).unwrap();
+		let mut out = None;
+		for s in &kept {
+			if let Some(o) = dec.push(s).unwrap() {
+				out = Some(o);
+			}
+		}
+		prop_assert_eq!(out.as_deref(), Some(data.as_slice()));
+	}
+}
@@ -1 +1,47 @@
+//! Constant-rate sending fed through a channel, on a paused clock.
 
+use std::time::Duration;
+
+use nyx_fec::padding::{Padder, Unpadder};
+use nyx_fec::timing::{DropPolicy, TimingConfig, TimingMode, TimingQueue};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+#[tokio::test(start_paused = true)]
+async fn bursty_input_leaves_at_a_constant_rate() {
+	let cfg = TimingConfig { mode: TimingMode::ConstantRate { interval: Duration::from_millis(5) }, capacity: 16, drop_policy: DropPolicy::RejectNew, ..Default::default() };
+	let mut queue = TimingQueue::with_seed(cfg, 5).unwrap();
+	let (tx, mut rx) = mpsc::channel(64);
+	tokio::spawn(async move {
+		// Two bursts of 10 frames, 200 ms apart.
+		let mut padder = Padder::new([1; 32]);
+		for burst in 0..2u8 {
+			for i in 0..10u8 {
+				padder.push(&[burst * 10 + i; 1000], |c| tx.try_send(*c).unwrap()).unwrap();
+			}
+			padder.flush(|c| tx.try_send(*c).unwrap());
+			tokio::time::sleep(Duration::from_millis(200)).await;
+		}
+	});
+
+	let start = Instant::now();
+	let mut sent_at = Vec::new();
+	let mut unpad = Unpadder::new();
+	let mut frames = Vec::new();
+	while start.elapsed() < Duration::from_millis(400) {
+		tokio::select! {
+			cell = queue.next() => {
+				sent_at.push(start.elapsed());
+				unpad.push(&cell, |f| frames.push(f[0])).unwrap();
+			}
+			Some(cell) = rx.recv() => assert!(queue.push(cell)),
+		}
+	}
+	assert_eq!(frames, (0..20).collect::<Vec<u8>>());
+	// Gaps are exactly one interval whether or not there was data.
+	assert!(sent_at.windows(2).all(|w| w[1] - w[0] == Duration::from_millis(5)), "{sent_at:?}");
+	let stats = queue.stats();
+	assert_eq!(stats.sent + stats.idle, sent_at.len() as u64);
+	assert_eq!(stats.dropped, 0);
+	assert!(stats.max_queued <= 16);
+}
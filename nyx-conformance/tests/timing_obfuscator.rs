@@ -1 +1,44 @@
+//! Jitter breaks the timing pattern of periodic traffic within its bounds.
 
+use std::time::Duration;
+
+use nyx_fec::padding::CELL_SIZE;
+use nyx_fec::timing::{DropPolicy, Jitter, TimingConfig, TimingMode, TimingQueue};
+use tokio::time::Instant;
+
+#[tokio::test(start_paused = true)]
+async fn periodic_input_gets_gaussian_gaps() {
+	let mean = Duration::from_millis(40);
+	let sigma = Duration::from_millis(8);
+	let cfg = TimingConfig { mode: TimingMode::Jitter(Jitter::Gaussian { mean, sigma }), capacity: 64, max_delay: Duration::from_millis(100), drop_policy: DropPolicy::DropOldest };
+	let mut queue = TimingQueue::with_seed(cfg, 77).unwrap();
+	let start = Instant::now();
+	let mut sent = Vec::new();
+	let mut next_in = start;
+	let mut i = 0u32;
+	// One cell every 10 ms; cells are tagged with their send time.
+	while sent.len() < 1000 {
+		tokio::select! {
+			cell = queue.next() => sent.push((u32::from_be_bytes(cell[..4].try_into().unwrap()), start.elapsed())),
+			_ = tokio::time::sleep_until(next_in), if i < 1000 => {
+				let mut cell = [0u8; CELL_SIZE];
+				cell[..4].copy_from_slice(&(i * 10).to_be_bytes());
+				assert!(queue.push(cell));
+				i += 1;
+				next_in += Duration::from_millis(10);
+			}
+		}
+	}
+	let delays: Vec<f64> = sent.iter().map(|(t, at)| at.as_secs_f64() * 1000.0 - *t as f64).collect();
+	let n = delays.len() as f64;
+	let m = delays.iter().sum::<f64>() / n;
+	let sd = (delays.iter().map(|d| (d - m).powi(2)).sum::<f64>() / n).sqrt();
+	assert!((m - 40.0).abs() < 1.5, "mean delay {m} ms");
+	assert!((sd - 8.0).abs() < 1.5, "delay sd {sd} ms");
+	assert!(delays.iter().all(|d| (0.0..=101.0).contains(d)));
+	// The input was perfectly periodic; the output gaps are not.
+	let gaps: Vec<f64> = sent.windows(2).map(|w| (w[1].1 - w[0].1).as_secs_f64()).collect();
+	let distinct = gaps.iter().map(|g| (g * 1000.0).round() as i64).collect::<std::collections::BTreeSet<_>>();
+	assert!(distinct.len() > 10, "{distinct:?}");
+	assert_eq!(queue.stats().dropped, 0);
+}
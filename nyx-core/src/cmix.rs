@@ -1 +1,75 @@
-﻿
\ No newline at end of file
+﻿//! Mix mode selection shared by the daemon and the mix layer (spec v1.0 §4).
+//!
+//! Maps the `[mix]` section of the node config. Only the fields that pick and
+//! tune the batching mode live here; other keys in the section are ignored.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+pub const DEFAULT_VDF_DELAY_MS: u64 = 100;
+pub const DEFAULT_BATCH_TIMEOUT_MS: u64 = 1000;
+/// Upper bound on `batch_size`; a batch is held in memory until released.
+pub const MAX_BATCH_SIZE: usize = 10_000;
+/// Network-wide floor on the delay iterations `T` a batch proof may claim.
+/// Relays never calibrate below it and verifiers reject shorter delays, so a
+/// relay cannot release a batch after a token wait.
+pub const MIN_VDF_ITERATIONS: u64 = 1 << 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MixMode {
+	/// Per-packet forwarding with cover traffic.
+	#[default]
+	Standard,
+	/// Batch mixing: collect, shuffle, release after a VDF delay.
+	Cmix,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MixConfig {
+	pub mode: MixMode,
+	/// Packets per cMix batch.
+	pub batch_size: usize,
+	/// Target wall-clock cost of the VDF evaluated before each release.
+	pub vdf_delay_ms: u64,
+	/// A partial batch is padded and released this long after its first packet.
+	pub batch_timeout_ms: u64,
+}
+
+impl Default for MixConfig {
+	fn default() -> Self {
+		Self { mode: MixMode::Standard, batch_size: DEFAULT_BATCH_SIZE, vdf_delay_ms: DEFAULT_VDF_DELAY_MS, batch_timeout_ms: DEFAULT_BATCH_TIMEOUT_MS }
+	}
+}
+
+impl MixConfig {
+	pub fn is_cmix(&self) -> bool { self.mode == MixMode::Cmix }
+
+	pub fn validate(&self) -> Result<()> {
+		if !(2..=MAX_BATCH_SIZE).contains(&self.batch_size) {
+			return Err(Error::config(format!("mix.batch_size must be within 2..={MAX_BATCH_SIZE}")));
+		}
+		if self.batch_timeout_ms == 0 {
+			return Err(Error::config("mix.batch_timeout_ms must be positive"));
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_mix_section() {
+		let cfg: MixConfig = toml::from_str("mode = \"cmix\"\nbatch_size = 100\nvdf_delay_ms = 100\ncover_traffic_rate = 10.0\n").unwrap();
+		assert!(cfg.is_cmix());
+		assert_eq!(cfg.batch_timeout_ms, DEFAULT_BATCH_TIMEOUT_MS);
+		cfg.validate().unwrap();
+		assert_eq!(MixConfig::default().mode, MixMode::Standard);
+		assert!(MixConfig { batch_size: 1, ..Default::default() }.validate().is_err());
+	}
+}
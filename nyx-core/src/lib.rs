@@ -4,4 +4,5 @@
 pub mod types;
 pub mod error;
 pub mod config;
+pub mod cmix;
 
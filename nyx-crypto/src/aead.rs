@@ -1 +1,156 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Session AEADs used by the stream layer: ChaCha20-Poly1305 (default) and
+//! Ascon-128a (lightweight alternative, spec §7).
+//!
+//! Nonces follow the spec's per-direction layout: a 32-bit direction
+//! identifier followed by a 64-bit big-endian sequence number.
+//!
+//! Keys are always 32 bytes as produced by the suite KDF. Ascon-128a takes a
+//! 128-bit key and nonce, so it uses the first 16 key bytes and the 96-bit
+//! nonce left-padded with zeros.
+
+use ascon_aead::Ascon128a;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand_core_06::{OsRng, RngCore};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::{Error, Result};
+
+pub const KEY_LEN: usize = 32;
+pub const NONCE_LEN: usize = 12;
+pub const TAG_LEN: usize = 16;
+
+/// Symmetric AEAD key. Wiped from memory on drop.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct AeadKey(pub [u8; KEY_LEN]);
+
+impl AeadKey {
+	pub fn generate() -> Self {
+		let mut k = [0u8; KEY_LEN];
+		OsRng.fill_bytes(&mut k);
+		Self(k)
+	}
+
+	pub fn from_slice(bytes: &[u8]) -> Result<Self> {
+		let k: [u8; KEY_LEN] = bytes.try_into().map_err(|_| Error::invalid_key(format!("aead key must be {KEY_LEN} bytes")))?;
+		Ok(Self(k))
+	}
+
+	pub fn as_bytes(&self) -> &[u8; KEY_LEN] { &self.0 }
+}
+
+impl core::fmt::Debug for AeadKey {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result { f.write_str("AeadKey(..)") }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AeadNonce(pub [u8; NONCE_LEN]);
+
+impl AeadNonce {
+	pub fn new(direction: u32, seq: u64) -> Self {
+		let mut n = [0u8; NONCE_LEN];
+		n[..4].copy_from_slice(&direction.to_be_bytes());
+		n[4..].copy_from_slice(&seq.to_be_bytes());
+		Self(n)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AeadAlgorithm {
+	ChaCha20Poly1305,
+	Ascon128a,
+}
+
+pub struct AeadCipher {
+	inner: Inner,
+}
+
+enum Inner {
+	ChaCha(ChaCha20Poly1305),
+	Ascon(Box<Ascon128a>),
+}
+
+impl AeadCipher {
+	/// ChaCha20-Poly1305 cipher (the default suite).
+	pub fn new(key: &AeadKey) -> Self { Self::with_algorithm(AeadAlgorithm::ChaCha20Poly1305, key) }
+
+	pub fn with_algorithm(alg: AeadAlgorithm, key: &AeadKey) -> Self {
+		let inner = match alg {
+			AeadAlgorithm::ChaCha20Poly1305 => Inner::ChaCha(ChaCha20Poly1305::new(Key::from_slice(&key.0))),
+			AeadAlgorithm::Ascon128a => {
+				use ascon_aead::aead::KeyInit;
+				Inner::Ascon(Box::new(Ascon128a::new(ascon_aead::Key::<Ascon128a>::from_slice(&key.0[..16]))))
+			}
+		};
+		Self { inner }
+	}
+
+	pub fn algorithm(&self) -> AeadAlgorithm {
+		match self.inner { Inner::ChaCha(_) => AeadAlgorithm::ChaCha20Poly1305, Inner::Ascon(_) => AeadAlgorithm::Ascon128a }
+	}
+
+	pub fn seal(&self, nonce: AeadNonce, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+		match &self.inner {
+			Inner::ChaCha(c) => c.encrypt(Nonce::from_slice(&nonce.0), Payload { msg: plaintext, aad }).map_err(|_| Error::Aead),
+			Inner::Ascon(c) => {
+				use ascon_aead::aead::Aead as _;
+				c.encrypt(&ascon_nonce(nonce), ascon_aead::aead::Payload { msg: plaintext, aad }).map_err(|_| Error::Aead)
+			}
+		}
+	}
+
+	pub fn open(&self, nonce: AeadNonce, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+		match &self.inner {
+			Inner::ChaCha(c) => c.decrypt(Nonce::from_slice(&nonce.0), Payload { msg: ciphertext, aad }).map_err(|_| Error::Aead),
+			Inner::Ascon(c) => {
+				use ascon_aead::aead::Aead as _;
+				c.decrypt(&ascon_nonce(nonce), ascon_aead::aead::Payload { msg: ciphertext, aad }).map_err(|_| Error::Aead)
+			}
+		}
+	}
+}
+
+fn ascon_nonce(nonce: AeadNonce) -> ascon_aead::Nonce<Ascon128a> {
+	let mut n = [0u8; 16];
+	n[4..].copy_from_slice(&nonce.0);
+	n.into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn seal_open_roundtrip() {
+		let key = AeadKey::generate();
+		let c = AeadCipher::new(&key);
+		let n = AeadNonce::new(1, 7);
+		let ct = c.seal(n, b"hdr", b"hello").unwrap();
+		assert_eq!(ct.len(), 5 + TAG_LEN);
+		assert_eq!(c.open(n, b"hdr", &ct).unwrap(), b"hello");
+	}
+
+	#[test]
+	fn ascon_roundtrip_and_differs_from_chacha() {
+		let key = AeadKey([3u8; KEY_LEN]);
+		let a = AeadCipher::with_algorithm(AeadAlgorithm::Ascon128a, &key);
+		let c = AeadCipher::new(&key);
+		assert_eq!(a.algorithm(), AeadAlgorithm::Ascon128a);
+		let n = AeadNonce::new(2, 9);
+		let ct = a.seal(n, b"aad", b"iot payload").unwrap();
+		assert_eq!(ct.len(), 11 + TAG_LEN);
+		assert_ne!(ct, c.seal(n, b"aad", b"iot payload").unwrap());
+		assert_eq!(a.open(n, b"aad", &ct).unwrap(), b"iot payload");
+		assert!(c.open(n, b"aad", &ct).is_err());
+	}
+
+	#[test]
+	fn wrong_aad_or_nonce_rejected() {
+		let c = AeadCipher::new(&AeadKey([7u8; KEY_LEN]));
+		let ct = c.seal(AeadNonce::new(0, 1), b"a", b"payload").unwrap();
+		assert!(matches!(c.open(AeadNonce::new(0, 1), b"b", &ct), Err(Error::Aead)));
+		assert!(matches!(c.open(AeadNonce::new(1, 1), b"a", &ct), Err(Error::Aead)));
+	}
+}
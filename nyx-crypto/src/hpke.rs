@@ -1 +1,115 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! HPKE (RFC 9180) single-shot sealing for the suite
+//! DHKEM(X25519, HKDF-SHA256) / HKDF-SHA256 / ChaCha20-Poly1305.
+//!
+//! Base mode provides confidentiality to the holder of the recipient key;
+//! auth mode additionally binds the ciphertext to the sender's static key.
+
+use hpke::aead::ChaCha20Poly1305;
+use hpke::kdf::HkdfSha256;
+use hpke::kem::X25519HkdfSha256;
+use hpke::{Deserializable, Kem as KemTrait, OpModeR, OpModeS, Serializable};
+use rand_core_06::OsRng;
+
+use crate::{Error, Result};
+
+pub type Kem = X25519HkdfSha256;
+pub type Kdf = HkdfSha256;
+pub type Aead = ChaCha20Poly1305;
+
+pub type PrivateKey = <Kem as KemTrait>::PrivateKey;
+pub type PublicKey = <Kem as KemTrait>::PublicKey;
+type EncappedKey = <Kem as KemTrait>::EncappedKey;
+
+/// Length of the serialized encapsulated key (`enc`) for X25519.
+pub const ENC_LEN: usize = 32;
+/// Length of serialized public and private keys.
+pub const KEY_LEN: usize = 32;
+
+pub fn gen_keypair() -> (PrivateKey, PublicKey) { Kem::gen_keypair(&mut OsRng) }
+
+/// Deterministic key derivation (RFC 9180 §7.1.3), used by tests and vectors.
+pub fn derive_keypair(ikm: &[u8]) -> (PrivateKey, PublicKey) { Kem::derive_keypair(ikm) }
+
+pub fn public_key_to_bytes(pk: &PublicKey) -> [u8; KEY_LEN] { pk.to_bytes().into() }
+
+pub fn public_key_from_bytes(bytes: &[u8]) -> Result<PublicKey> {
+	PublicKey::from_bytes(bytes).map_err(|e| Error::invalid_key(format!("hpke public key: {e}")))
+}
+
+pub fn private_key_to_bytes(sk: &PrivateKey) -> [u8; KEY_LEN] { sk.to_bytes().into() }
+
+pub fn private_key_from_bytes(bytes: &[u8]) -> Result<PrivateKey> {
+	PrivateKey::from_bytes(bytes).map_err(|e| Error::invalid_key(format!("hpke private key: {e}")))
+}
+
+/// Sealed message: encapsulated key plus AEAD ciphertext.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sealed {
+	pub enc: [u8; ENC_LEN],
+	pub ciphertext: Vec<u8>,
+}
+
+pub fn seal_base(pk_recip: &PublicKey, info: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Sealed> {
+	seal(&OpModeS::Base, pk_recip, info, aad, plaintext)
+}
+
+pub fn open_base(sk_recip: &PrivateKey, sealed: &Sealed, info: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+	open(&OpModeR::Base, sk_recip, sealed, info, aad)
+}
+
+pub fn seal_auth(sk_sender: &PrivateKey, pk_sender: &PublicKey, pk_recip: &PublicKey, info: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Sealed> {
+	seal(&OpModeS::Auth((sk_sender.clone(), pk_sender.clone())), pk_recip, info, aad, plaintext)
+}
+
+pub fn open_auth(sk_recip: &PrivateKey, pk_sender: &PublicKey, sealed: &Sealed, info: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+	open(&OpModeR::Auth(pk_sender.clone()), sk_recip, sealed, info, aad)
+}
+
+fn seal(mode: &OpModeS<Kem>, pk_recip: &PublicKey, info: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Sealed> {
+	let (enc, ciphertext) = hpke::single_shot_seal::<Aead, Kdf, Kem, _>(mode, pk_recip, info, plaintext, aad, &mut OsRng)
+		.map_err(|e| Error::Hpke(e.to_string()))?;
+	Ok(Sealed { enc: enc.to_bytes().into(), ciphertext })
+}
+
+fn open(mode: &OpModeR<Kem>, sk_recip: &PrivateKey, sealed: &Sealed, info: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+	let enc = EncappedKey::from_bytes(&sealed.enc).map_err(|e| Error::Hpke(e.to_string()))?;
+	hpke::single_shot_open::<Aead, Kdf, Kem>(mode, sk_recip, &enc, info, &sealed.ciphertext, aad)
+		.map_err(|e| Error::Hpke(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn base_roundtrip() {
+		let (sk, pk) = gen_keypair();
+		let sealed = seal_base(&pk, b"info", b"aad", b"secret").unwrap();
+		assert_eq!(open_base(&sk, &sealed, b"info", b"aad").unwrap(), b"secret");
+		// Context mismatch must fail
+		assert!(open_base(&sk, &sealed, b"other", b"aad").is_err());
+		assert!(open_base(&sk, &sealed, b"info", b"bad").is_err());
+	}
+
+	#[test]
+	fn auth_mode_binds_sender() {
+		let (sk_s, pk_s) = gen_keypair();
+		let (sk_r, pk_r) = gen_keypair();
+		let (_, pk_other) = gen_keypair();
+		let sealed = seal_auth(&sk_s, &pk_s, &pk_r, b"i", b"", b"msg").unwrap();
+		assert_eq!(open_auth(&sk_r, &pk_s, &sealed, b"i", b"").unwrap(), b"msg");
+		assert!(open_auth(&sk_r, &pk_other, &sealed, b"i", b"").is_err());
+	}
+
+	#[test]
+	fn key_serialization_roundtrip() {
+		let (sk, pk) = derive_keypair(&[1u8; 32]);
+		let pk2 = public_key_from_bytes(&public_key_to_bytes(&pk)).unwrap();
+		let sk2 = private_key_from_bytes(&private_key_to_bytes(&sk)).unwrap();
+		let sealed = seal_base(&pk2, b"", b"", b"x").unwrap();
+		assert_eq!(open_base(&sk2, &sealed, b"", b"").unwrap(), b"x");
+		assert!(public_key_from_bytes(&[0u8; 5]).is_err());
+	}
+}
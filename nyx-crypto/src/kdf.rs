@@ -1 +1,100 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Hash and key-derivation primitives for the negotiable cipher suites.
+//!
+//! Both KDFs follow the extract-then-expand shape of HKDF (RFC 5869):
+//! - `HkdfSha256`: HKDF with HMAC-SHA-256.
+//! - `Blake3`: extract is BLAKE3 keyed by `BLAKE3(salt)`, expand is the keyed
+//!   BLAKE3 XOF over `info`. Labels are domain separated by the caller's `info`.
+
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+
+use crate::{Error, Result};
+
+pub const HASH_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+	Sha256,
+	Blake3,
+}
+
+impl HashAlgorithm {
+	pub fn hash(&self, data: &[u8]) -> [u8; HASH_LEN] {
+		match self {
+			Self::Sha256 => Sha256::digest(data).into(),
+			Self::Blake3 => *blake3::hash(data).as_bytes(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KdfAlgorithm {
+	HkdfSha256,
+	Blake3,
+}
+
+impl KdfAlgorithm {
+	/// Condense input keying material into a pseudorandom key.
+	pub fn extract(&self, salt: &[u8], ikm: &[u8]) -> [u8; HASH_LEN] {
+		match self {
+			Self::HkdfSha256 => {
+				let (prk, _) = Hkdf::<Sha256>::extract(Some(salt), ikm);
+				prk.into()
+			}
+			Self::Blake3 => *blake3::keyed_hash(blake3::hash(salt).as_bytes(), ikm).as_bytes(),
+		}
+	}
+
+	/// Expand a pseudorandom key into `out.len()` bytes bound to `info`.
+	pub fn expand(&self, prk: &[u8; HASH_LEN], info: &[u8], out: &mut [u8]) -> Result<()> {
+		match self {
+			Self::HkdfSha256 => {
+				let h = Hkdf::<Sha256>::from_prk(prk).map_err(|_| Error::invalid_key("hkdf prk length"))?;
+				h.expand(info, out).map_err(|_| Error::invalid_key("hkdf output too long"))
+			}
+			Self::Blake3 => {
+				let mut h = blake3::Hasher::new_keyed(prk);
+				h.update(info);
+				h.finalize_xof().fill(out);
+				Ok(())
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn hkdf_sha256_matches_rfc5869_case1() {
+		let ikm = [0x0bu8; 22];
+		let salt: Vec<u8> = (0x00u8..=0x0c).collect();
+		let info: Vec<u8> = (0xf0u8..=0xf9).collect();
+		let prk = KdfAlgorithm::HkdfSha256.extract(&salt, &ikm);
+		assert_eq!(prk, hex_literal::hex!("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5"));
+		let mut okm = [0u8; 42];
+		KdfAlgorithm::HkdfSha256.expand(&prk, &info, &mut okm).unwrap();
+		assert_eq!(okm, hex_literal::hex!("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"));
+	}
+
+	#[test]
+	fn blake3_kdf_is_deterministic_and_label_separated() {
+		let prk = KdfAlgorithm::Blake3.extract(b"salt", b"ikm");
+		assert_eq!(prk, KdfAlgorithm::Blake3.extract(b"salt", b"ikm"));
+		assert_ne!(prk, KdfAlgorithm::Blake3.extract(b"salt2", b"ikm"));
+		let (mut a, mut b) = ([0u8; 64], [0u8; 64]);
+		KdfAlgorithm::Blake3.expand(&prk, b"key", &mut a).unwrap();
+		KdfAlgorithm::Blake3.expand(&prk, b"iv", &mut b).unwrap();
+		assert_ne!(a, b);
+		assert_ne!(prk, KdfAlgorithm::HkdfSha256.extract(b"salt", b"ikm"));
+	}
+
+	#[test]
+	fn hash_algorithms() {
+		assert_eq!(HashAlgorithm::Sha256.hash(b"abc"), hex_literal::hex!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+		assert_eq!(HashAlgorithm::Blake3.hash(b""), *blake3::hash(b"").as_bytes());
+	}
+}
@@ -1 +1,286 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Passphrase-encrypted on-disk keystore for the node's long-term keys.
+//!
+//! Holds the Ed25519 identity key and the X25519 static key used by the
+//! handshake. The container is sealed with ChaCha20-Poly1305 under a key
+//! stretched from the passphrase with Argon2id; the header (KDF parameters,
+//! salt, nonce) is authenticated as associated data.
+//!
+//! Layout (big-endian integers):
+//! `magic(8) | m_cost_kib(4) | t_cost(4) | p_cost(4) | salt(16) | nonce(12) | ciphertext`
+//!
+//! The NodeID is `BLAKE3(identity_public_key)` (spec §6).
+
+use std::io::Write;
+use std::path::Path;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use rand_core_06::{OsRng, RngCore};
+use x25519_dalek::{PublicKey as X25519Public, StaticSecret};
+use zeroize::Zeroizing;
+
+use crate::aead::{AeadCipher, AeadKey, AeadNonce, NONCE_LEN};
+use crate::{Error, Result};
+
+const MAGIC: &[u8; 8] = b"NYXKEYS1";
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = 8 + 12 + SALT_LEN + NONCE_LEN;
+/// Number of retired static keys kept after rotation so in-flight handshakes can finish.
+pub const MAX_RETIRED: usize = 2;
+/// Upper bound on Argon2 memory accepted from a file (1 GiB), to refuse hostile headers.
+const MAX_M_COST_KIB: u32 = 1024 * 1024;
+
+pub type NodeId = [u8; 32];
+
+/// Derive the NodeID from an identity public key.
+pub fn node_id_from_public_key(pk: &[u8]) -> NodeId { *blake3::hash(pk).as_bytes() }
+
+/// Argon2id cost parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+	pub m_cost_kib: u32,
+	pub t_cost: u32,
+	pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+	fn default() -> Self { Self { m_cost_kib: 64 * 1024, t_cost: 3, p_cost: 1 } }
+}
+
+impl KdfParams {
+	fn derive(&self, passphrase: &[u8], salt: &[u8]) -> Result<AeadKey> {
+		if self.m_cost_kib > MAX_M_COST_KIB { return Err(Error::keystore("argon2 memory cost too large")); }
+		let params = Params::new(self.m_cost_kib, self.t_cost, self.p_cost, Some(32)).map_err(|e| Error::keystore(format!("argon2 params: {e}")))?;
+		let mut key = AeadKey([0u8; 32]);
+		Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+			.hash_password_into(passphrase, salt, &mut key.0)
+			.map_err(|e| Error::keystore(format!("argon2: {e}")))?;
+		Ok(key)
+	}
+}
+
+/// A static key that was rotated out but is still accepted for a grace period.
+#[derive(Clone)]
+pub struct RetiredKey {
+	pub generation: u32,
+	pub secret: StaticSecret,
+}
+
+#[derive(Clone)]
+pub struct Keystore {
+	identity: SigningKey,
+	static_secret: StaticSecret,
+	generation: u32,
+	retired: Vec<RetiredKey>,
+}
+
+impl core::fmt::Debug for Keystore {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.debug_struct("Keystore").field("node_id", &hex(&self.node_id())).field("generation", &self.generation).finish_non_exhaustive()
+	}
+}
+
+impl Keystore {
+	pub fn generate() -> Self {
+		Self { identity: SigningKey::generate(&mut OsRng), static_secret: StaticSecret::random_from_rng(OsRng), generation: 0, retired: Vec::new() }
+	}
+
+	pub fn node_id(&self) -> NodeId { node_id_from_public_key(self.identity_public().as_bytes()) }
+	pub fn identity_public(&self) -> VerifyingKey { self.identity.verifying_key() }
+	/// The Ed25519 identity key, for transports that authenticate with it directly (QUIC certificates).
+	pub fn identity(&self) -> &SigningKey { &self.identity }
+	pub fn sign(&self, msg: &[u8]) -> [u8; 64] { self.identity.sign(msg).to_bytes() }
+	pub fn static_secret(&self) -> &StaticSecret { &self.static_secret }
+	pub fn static_public(&self) -> X25519Public { X25519Public::from(&self.static_secret) }
+	/// Incremented on every static key rotation.
+	pub fn generation(&self) -> u32 { self.generation }
+	pub fn retired(&self) -> &[RetiredKey] { &self.retired }
+
+	/// Replace the X25519 static key, keeping the previous one in the retired list.
+	pub fn rotate_static(&mut self) {
+		let old = std::mem::replace(&mut self.static_secret, StaticSecret::random_from_rng(OsRng));
+		self.retired.insert(0, RetiredKey { generation: self.generation, secret: old });
+		self.retired.truncate(MAX_RETIRED);
+		self.generation = self.generation.wrapping_add(1);
+	}
+
+	/// Replace the Ed25519 identity. This changes the NodeID.
+	pub fn rotate_identity(&mut self) { self.identity = SigningKey::generate(&mut OsRng); }
+
+	/// Encrypt the keystore into a portable blob (same format as the file on disk).
+	pub fn export(&self, passphrase: &str, params: KdfParams) -> Result<Vec<u8>> {
+		let mut salt = [0u8; SALT_LEN];
+		let mut nonce = [0u8; NONCE_LEN];
+		OsRng.fill_bytes(&mut salt);
+		OsRng.fill_bytes(&mut nonce);
+		let key = params.derive(passphrase.as_bytes(), &salt)?;
+
+		let mut out = Vec::with_capacity(HEADER_LEN + 128);
+		out.extend_from_slice(MAGIC);
+		out.extend_from_slice(&params.m_cost_kib.to_be_bytes());
+		out.extend_from_slice(&params.t_cost.to_be_bytes());
+		out.extend_from_slice(&params.p_cost.to_be_bytes());
+		out.extend_from_slice(&salt);
+		out.extend_from_slice(&nonce);
+		let ct = AeadCipher::new(&key).seal(AeadNonce(nonce), &out, &self.encode_payload())?;
+		out.extend_from_slice(&ct);
+		Ok(out)
+	}
+
+	/// Decrypt a blob produced by [`Keystore::export`].
+	pub fn import(blob: &[u8], passphrase: &str) -> Result<Self> {
+		if blob.len() < HEADER_LEN || &blob[..8] != MAGIC { return Err(Error::keystore("not a nyx keystore")); }
+		let u32_at = |i: usize| u32::from_be_bytes([blob[i], blob[i + 1], blob[i + 2], blob[i + 3]]);
+		let params = KdfParams { m_cost_kib: u32_at(8), t_cost: u32_at(12), p_cost: u32_at(16) };
+		let salt = &blob[20..20 + SALT_LEN];
+		let mut nonce = [0u8; NONCE_LEN];
+		nonce.copy_from_slice(&blob[20 + SALT_LEN..HEADER_LEN]);
+		let key = params.derive(passphrase.as_bytes(), salt)?;
+		let pt = AeadCipher::new(&key)
+			.open(AeadNonce(nonce), &blob[..HEADER_LEN], &blob[HEADER_LEN..])
+			.map_err(|_| Error::keystore("wrong passphrase or corrupted keystore"))?;
+		Self::decode_payload(&Zeroizing::new(pt))
+	}
+
+	/// Write the encrypted keystore atomically with owner-only permissions.
+	pub fn save(&self, path: impl AsRef<Path>, passphrase: &str, params: KdfParams) -> Result<()> {
+		let path = path.as_ref();
+		let blob = self.export(passphrase, params)?;
+		if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) { std::fs::create_dir_all(parent)?; }
+		let tmp = path.with_extension("tmp");
+		let mut opts = std::fs::OpenOptions::new();
+		opts.write(true).create(true).truncate(true);
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::OpenOptionsExt;
+			opts.mode(0o600);
+		}
+		let mut f = opts.open(&tmp)?;
+		f.write_all(&blob)?;
+		f.sync_all()?;
+		drop(f);
+		std::fs::rename(&tmp, path)?;
+		Ok(())
+	}
+
+	pub fn load(path: impl AsRef<Path>, passphrase: &str) -> Result<Self> {
+		let path = path.as_ref();
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = std::fs::metadata(path)?.permissions().mode();
+			if mode & 0o077 != 0 { tracing::warn!("keystore {} is accessible by other users (mode {:o})", path.display(), mode & 0o777); }
+		}
+		let blob = std::fs::read(path)?;
+		Self::import(&blob, passphrase)
+	}
+
+	/// Load the keystore at `path`, creating and saving a new one if it does not exist.
+	pub fn load_or_create(path: impl AsRef<Path>, passphrase: &str, params: KdfParams) -> Result<Self> {
+		let path = path.as_ref();
+		if path.exists() { return Self::load(path, passphrase); }
+		let ks = Self::generate();
+		ks.save(path, passphrase, params)?;
+		Ok(ks)
+	}
+
+	fn encode_payload(&self) -> Zeroizing<Vec<u8>> {
+		let mut p = Zeroizing::new(Vec::with_capacity(69 + self.retired.len() * 36));
+		p.extend_from_slice(&self.generation.to_be_bytes());
+		p.extend_from_slice(self.identity.as_bytes());
+		p.extend_from_slice(self.static_secret.as_bytes());
+		p.push(self.retired.len() as u8);
+		for r in &self.retired {
+			p.extend_from_slice(&r.generation.to_be_bytes());
+			p.extend_from_slice(r.secret.as_bytes());
+		}
+		p
+	}
+
+	fn decode_payload(p: &[u8]) -> Result<Self> {
+		let bad = || Error::keystore("malformed keystore payload");
+		if p.len() < 69 { return Err(bad()); }
+		let generation = u32::from_be_bytes(p[0..4].try_into().unwrap());
+		let identity = SigningKey::from_bytes(&p[4..36].try_into().unwrap());
+		let static_secret = StaticSecret::from(<[u8; 32]>::try_from(&p[36..68]).unwrap());
+		let count = p[68] as usize;
+		if count > MAX_RETIRED || p.len() != 69 + count * 36 { return Err(bad()); }
+		let retired = p[69..].chunks_exact(36).map(|c| RetiredKey {
+			generation: u32::from_be_bytes(c[0..4].try_into().unwrap()),
+			secret: StaticSecret::from(<[u8; 32]>::try_from(&c[4..36]).unwrap()),
+		}).collect();
+		Ok(Self { identity, static_secret, generation, retired })
+	}
+}
+
+fn hex(b: &[u8]) -> String { b.iter().map(|x| format!("{x:02x}")).collect() }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Cheap parameters so tests stay fast; production uses KdfParams::default().
+	const FAST: KdfParams = KdfParams { m_cost_kib: 8, t_cost: 1, p_cost: 1 };
+
+	#[test]
+	fn export_import_roundtrip() {
+		let ks = Keystore::generate();
+		let blob = ks.export("pw", FAST).unwrap();
+		let back = Keystore::import(&blob, "pw").unwrap();
+		assert_eq!(back.node_id(), ks.node_id());
+		assert_eq!(back.static_public().as_bytes(), ks.static_public().as_bytes());
+		assert!(matches!(Keystore::import(&blob, "wrong"), Err(Error::Keystore(_))));
+	}
+
+	#[test]
+	fn header_tampering_detected() {
+		let blob = Keystore::generate().export("pw", FAST).unwrap();
+		let mut t = blob.clone();
+		t[20] ^= 1; // salt
+		assert!(Keystore::import(&t, "pw").is_err());
+		let mut t = blob;
+		t[0] = b'X';
+		assert!(Keystore::import(&t, "pw").is_err());
+	}
+
+	#[test]
+	fn node_id_is_blake3_of_identity_key() {
+		let ks = Keystore::generate();
+		assert_eq!(ks.node_id(), *blake3::hash(ks.identity_public().as_bytes()).as_bytes());
+		let sig = ks.sign(b"hello");
+		ks.identity_public().verify_strict(b"hello", &ed25519_dalek::Signature::from_bytes(&sig)).unwrap();
+	}
+
+	#[test]
+	fn rotation_keeps_bounded_history() {
+		let mut ks = Keystore::generate();
+		let id = ks.node_id();
+		let first = *ks.static_public().as_bytes();
+		for _ in 0..3 { ks.rotate_static(); }
+		assert_eq!(ks.generation(), 3);
+		assert_eq!(ks.retired().len(), MAX_RETIRED);
+		assert_eq!(ks.retired()[0].generation, 2);
+		assert_ne!(*ks.static_public().as_bytes(), first);
+		assert_eq!(ks.node_id(), id);
+		let back = Keystore::import(&ks.export("p", FAST).unwrap(), "p").unwrap();
+		assert_eq!(back.retired()[1].secret.as_bytes(), ks.retired()[1].secret.as_bytes());
+		ks.rotate_identity();
+		assert_ne!(ks.node_id(), id);
+	}
+
+	#[test]
+	fn save_load_with_owner_only_permissions() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("nested").join("keystore");
+		let ks = Keystore::load_or_create(&path, "pw", FAST).unwrap();
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+		}
+		let again = Keystore::load_or_create(&path, "pw", FAST).unwrap();
+		assert_eq!(again.node_id(), ks.node_id());
+	}
+}
@@ -1 +1,42 @@
+#![forbid(unsafe_code)]
 
+pub mod aead;
+pub mod kdf;
+pub mod replay;
+pub mod suite;
+#[cfg(feature = "classic")]
+pub mod keystore;
+#[cfg(feature = "classic")]
+pub mod noise;
+#[cfg(feature = "classic")]
+pub mod resumption;
+#[cfg(feature = "hpke")]
+pub mod hpke;
+
+use thiserror::Error;
+
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+	#[error("invalid key material: {0}")]
+	InvalidKey(String),
+	#[error("aead: authentication failed")]
+	Aead,
+	#[error("hpke: {0}")]
+	Hpke(String),
+	#[error("protocol: {0}")]
+	Protocol(String),
+	#[error("keystore: {0}")]
+	Keystore(String),
+	#[error("replay: {0}")]
+	Replay(String),
+	#[error("io: {0}")]
+	Io(#[from] std::io::Error),
+}
+
+impl Error {
+	pub fn invalid_key(msg: impl Into<String>) -> Self { Self::InvalidKey(msg.into()) }
+	pub fn protocol(msg: impl Into<String>) -> Self { Self::Protocol(msg.into()) }
+	pub fn keystore(msg: impl Into<String>) -> Self { Self::Keystore(msg.into()) }
+}
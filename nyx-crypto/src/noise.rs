@@ -1 +1,529 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Noise_Nyx handshake (spec §7.1) over X25519 / ChaCha20-Poly1305 / SHA-256.
+//!
+//! The full handshake is Noise IK with the responder's static key known in
+//! advance (`<- s`):
+//!
+//! ```text
+//! <- s
+//! -> e, es, s, ss
+//! <- e, ee, se
+//! ```
+//!
+//! The trailing `es` listed for the second message in the spec is already
+//! mixed in by the first message, so it is not repeated here.
+//!
+//! Session resumption uses `NNpsk0` (`-> psk, e` / `<- e, ee`) keyed by a PSK
+//! from a resumption ticket; the first message carries 0-RTT early data (see
+//! [`crate::resumption`]).
+//!
+//! Symmetric state, nonce encoding (32 zero bits + 64-bit little-endian
+//! counter) and HKDF follow revision 34 of the Noise specification, so the
+//! handshake can be checked against third-party implementations.
+
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::aead::{AeadCipher, AeadKey, AeadNonce, TAG_LEN};
+use crate::kdf::{HashAlgorithm, KdfAlgorithm, HASH_LEN};
+use crate::{Error, Result};
+
+/// Prologue bound into every Nyx handshake ("Nyx0.1").
+pub const PROLOGUE: &[u8] = b"Nyx0.1";
+pub const DH_LEN: usize = 32;
+pub const MAX_MESSAGE_LEN: usize = 65535;
+/// Label used to derive the resumption secret from the final chaining key.
+pub const RESUMPTION_LABEL: &[u8] = b"Nyx-resumption";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+	E,
+	S,
+	Ee,
+	Es,
+	Se,
+	Ss,
+	Psk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+	/// Full handshake with responder static pre-message.
+	IK,
+	/// PSK resumption; the first message may carry 0-RTT data.
+	NNpsk0,
+}
+
+impl Pattern {
+	pub fn protocol_name(&self) -> &'static str {
+		match self {
+			Self::IK => "Noise_IK_25519_ChaChaPoly_SHA256",
+			Self::NNpsk0 => "Noise_NNpsk0_25519_ChaChaPoly_SHA256",
+		}
+	}
+
+	fn messages(&self) -> &'static [&'static [Token]] {
+		use Token::*;
+		match self {
+			Self::IK => &[&[E, Es, S, Ss], &[E, Ee, Se]],
+			Self::NNpsk0 => &[&[Psk, E], &[E, Ee]],
+		}
+	}
+
+	fn is_psk(&self) -> bool { matches!(self, Self::NNpsk0) }
+}
+
+/// X25519 key pair. Ephemeral keys use the same type so test vectors can pin them.
+#[derive(Clone)]
+pub struct Keypair {
+	secret: StaticSecret,
+	public: PublicKey,
+}
+
+impl Keypair {
+	pub fn generate() -> Self { Self::from_secret(StaticSecret::random_from_rng(rand_core_06::OsRng)) }
+
+	pub fn from_secret_bytes(bytes: [u8; DH_LEN]) -> Self { Self::from_secret(StaticSecret::from(bytes)) }
+
+	pub fn from_secret(secret: StaticSecret) -> Self {
+		let public = PublicKey::from(&secret);
+		Self { secret, public }
+	}
+
+	pub fn public_bytes(&self) -> [u8; DH_LEN] { self.public.to_bytes() }
+
+	pub fn secret_bytes(&self) -> [u8; DH_LEN] { self.secret.to_bytes() }
+
+	fn dh(&self, remote: &[u8; DH_LEN]) -> Result<[u8; DH_LEN]> {
+		let shared = self.secret.diffie_hellman(&PublicKey::from(*remote));
+		// Reject low-order points, which yield an all-zero shared secret.
+		if !shared.was_contributory() {
+			return Err(Error::protocol("non-contributory dh"));
+		}
+		Ok(shared.to_bytes())
+	}
+}
+
+impl core::fmt::Debug for Keypair {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result { f.debug_struct("Keypair").field("public", &self.public).finish_non_exhaustive() }
+}
+
+/// Noise CipherState: a key plus 64-bit counter nonce.
+#[derive(Zeroize, ZeroizeOnDrop)]
+struct CipherState {
+	k: Option<[u8; 32]>,
+	n: u64,
+}
+
+impl CipherState {
+	fn empty() -> Self { Self { k: None, n: 0 } }
+
+	fn with_key(k: [u8; 32]) -> Self { Self { k: Some(k), n: 0 } }
+
+	fn nonce(n: u64) -> AeadNonce {
+		let mut b = [0u8; 12];
+		b[4..].copy_from_slice(&n.to_le_bytes());
+		AeadNonce(b)
+	}
+
+	fn next_nonce(&mut self) -> Result<AeadNonce> {
+		// 2^64-1 is reserved by Noise.
+		if self.n == u64::MAX {
+			return Err(Error::protocol("nonce exhausted"));
+		}
+		let nonce = Self::nonce(self.n);
+		self.n += 1;
+		Ok(nonce)
+	}
+
+	fn encrypt_with_ad(&mut self, ad: &[u8], pt: &[u8]) -> Result<Vec<u8>> {
+		match self.k {
+			None => Ok(pt.to_vec()),
+			Some(k) => {
+				let nonce = self.next_nonce()?;
+				AeadCipher::new(&AeadKey(k)).seal(nonce, ad, pt)
+			}
+		}
+	}
+
+	fn decrypt_with_ad(&mut self, ad: &[u8], ct: &[u8]) -> Result<Vec<u8>> {
+		match self.k {
+			None => Ok(ct.to_vec()),
+			Some(k) => {
+				let pt = AeadCipher::new(&AeadKey(k)).open(Self::nonce(self.n), ad, ct)?;
+				// Only advance on success so a forged message does not desync the peer.
+				self.n += 1;
+				Ok(pt)
+			}
+		}
+	}
+}
+
+#[derive(Zeroize, ZeroizeOnDrop)]
+struct SymmetricState {
+	cs: CipherState,
+	ck: [u8; HASH_LEN],
+	h: [u8; HASH_LEN],
+}
+
+impl SymmetricState {
+	fn new(protocol_name: &str) -> Self {
+		let name = protocol_name.as_bytes();
+		let mut h = [0u8; HASH_LEN];
+		if name.len() <= HASH_LEN {
+			h[..name.len()].copy_from_slice(name);
+		} else {
+			h = HashAlgorithm::Sha256.hash(name);
+		}
+		Self { cs: CipherState::empty(), ck: h, h }
+	}
+
+	/// Noise `HKDF(ck, ikm)` returning the first `N` 32-byte outputs.
+	fn hkdf<const N: usize>(ck: &[u8; HASH_LEN], ikm: &[u8]) -> Result<[[u8; HASH_LEN]; N]> {
+		let prk = KdfAlgorithm::HkdfSha256.extract(ck, ikm);
+		let mut okm = vec![0u8; HASH_LEN * N];
+		KdfAlgorithm::HkdfSha256.expand(&prk, &[], &mut okm)?;
+		let mut out = [[0u8; HASH_LEN]; N];
+		for (o, chunk) in out.iter_mut().zip(okm.chunks_exact(HASH_LEN)) {
+			o.copy_from_slice(chunk);
+		}
+		okm.zeroize();
+		Ok(out)
+	}
+
+	fn mix_hash(&mut self, data: &[u8]) {
+		let mut buf = Vec::with_capacity(HASH_LEN + data.len());
+		buf.extend_from_slice(&self.h);
+		buf.extend_from_slice(data);
+		self.h = HashAlgorithm::Sha256.hash(&buf);
+	}
+
+	fn mix_key(&mut self, ikm: &[u8]) -> Result<()> {
+		let [ck, k] = Self::hkdf::<2>(&self.ck, ikm)?;
+		self.ck = ck;
+		self.cs = CipherState::with_key(k);
+		Ok(())
+	}
+
+	fn mix_key_and_hash(&mut self, ikm: &[u8]) -> Result<()> {
+		let [ck, th, k] = Self::hkdf::<3>(&self.ck, ikm)?;
+		self.ck = ck;
+		self.mix_hash(&th);
+		self.cs = CipherState::with_key(k);
+		Ok(())
+	}
+
+	fn encrypt_and_hash(&mut self, pt: &[u8]) -> Result<Vec<u8>> {
+		let ct = self.cs.encrypt_with_ad(&self.h, pt)?;
+		self.mix_hash(&ct);
+		Ok(ct)
+	}
+
+	fn decrypt_and_hash(&mut self, ct: &[u8]) -> Result<Vec<u8>> {
+		let pt = self.cs.decrypt_with_ad(&self.h, ct)?;
+		self.mix_hash(ct);
+		Ok(pt)
+	}
+
+	fn has_key(&self) -> bool { self.cs.k.is_some() }
+
+	fn split(&self) -> Result<(CipherState, CipherState)> {
+		let [k1, k2] = Self::hkdf::<2>(&self.ck, &[])?;
+		Ok((CipherState::with_key(k1), CipherState::with_key(k2)))
+	}
+}
+
+/// In-progress Noise_Nyx handshake for one side.
+pub struct HandshakeState {
+	pattern: Pattern,
+	initiator: bool,
+	ss: SymmetricState,
+	s: Option<Keypair>,
+	e: Option<Keypair>,
+	rs: Option<[u8; DH_LEN]>,
+	re: Option<[u8; DH_LEN]>,
+	psk: Option<[u8; 32]>,
+	msg: usize,
+}
+
+impl HandshakeState {
+	fn new(pattern: Pattern, initiator: bool, prologue: &[u8], s: Option<Keypair>, rs: Option<[u8; DH_LEN]>, psk: Option<[u8; 32]>) -> Self {
+		let mut ss = SymmetricState::new(pattern.protocol_name());
+		ss.mix_hash(prologue);
+		if pattern == Pattern::IK {
+			// Pre-message `<- s`.
+			let responder_static = if initiator { rs.expect("IK initiator needs rs") } else { s.as_ref().expect("IK responder needs s").public_bytes() };
+			ss.mix_hash(&responder_static);
+		}
+		Self { pattern, initiator, ss, s, e: None, rs, re: None, psk, msg: 0 }
+	}
+
+	/// Full handshake, initiator side. `responder_static` is the peer's known static key.
+	pub fn initiator(s: Keypair, responder_static: [u8; DH_LEN], prologue: &[u8]) -> Self { Self::new(Pattern::IK, true, prologue, Some(s), Some(responder_static), None) }
+
+	/// Full handshake, responder side.
+	pub fn responder(s: Keypair, prologue: &[u8]) -> Self { Self::new(Pattern::IK, false, prologue, Some(s), None, None) }
+
+	/// PSK resumption, initiator side.
+	pub fn psk_initiator(psk: [u8; 32], prologue: &[u8]) -> Self { Self::new(Pattern::NNpsk0, true, prologue, None, None, Some(psk)) }
+
+	/// PSK resumption, responder side.
+	pub fn psk_responder(psk: [u8; 32], prologue: &[u8]) -> Self { Self::new(Pattern::NNpsk0, false, prologue, None, None, Some(psk)) }
+
+	/// Pin the local ephemeral key instead of generating one (test vectors only).
+	pub fn with_ephemeral(mut self, e: Keypair) -> Self {
+		self.e = Some(e);
+		self
+	}
+
+	pub fn pattern(&self) -> Pattern { self.pattern }
+
+	pub fn is_initiator(&self) -> bool { self.initiator }
+
+	pub fn is_finished(&self) -> bool { self.msg >= self.pattern.messages().len() }
+
+	/// True if it is our turn to write the next handshake message.
+	pub fn is_my_turn(&self) -> bool { !self.is_finished() && self.msg.is_multiple_of(2) == self.initiator }
+
+	pub fn handshake_hash(&self) -> [u8; HASH_LEN] { self.ss.h }
+
+	pub fn remote_static(&self) -> Option<[u8; DH_LEN]> { self.rs }
+
+	fn local_static(&self) -> Result<&Keypair> { self.s.as_ref().ok_or_else(|| Error::protocol("missing local static key")) }
+
+	fn remote_static_key(&self) -> Result<[u8; DH_LEN]> { self.rs.ok_or_else(|| Error::protocol("missing remote static key")) }
+
+	fn local_ephemeral(&self) -> Result<&Keypair> { self.e.as_ref().ok_or_else(|| Error::protocol("missing local ephemeral key")) }
+
+	fn remote_ephemeral(&self) -> Result<[u8; DH_LEN]> { self.re.ok_or_else(|| Error::protocol("missing remote ephemeral key")) }
+
+	fn mix_dh(&mut self, token: Token) -> Result<()> {
+		// `es` is DH(initiator e, responder s); `se` is DH(initiator s, responder e).
+		let shared = match (token, self.initiator) {
+			(Token::Ee, _) => self.local_ephemeral()?.dh(&self.remote_ephemeral()?)?,
+			(Token::Es, true) | (Token::Se, false) => self.local_ephemeral()?.dh(&self.remote_static_key()?)?,
+			(Token::Es, false) | (Token::Se, true) => self.local_static()?.dh(&self.remote_ephemeral()?)?,
+			(Token::Ss, _) => self.local_static()?.dh(&self.remote_static_key()?)?,
+			_ => unreachable!("not a dh token"),
+		};
+		self.ss.mix_key(&shared)
+	}
+
+	pub fn write_message(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+		if !self.is_my_turn() {
+			return Err(Error::protocol("handshake: not our turn to write"));
+		}
+		let mut out = Vec::new();
+		for &token in self.pattern.messages()[self.msg] {
+			match token {
+				Token::E => {
+					let e = self.e.take().unwrap_or_else(Keypair::generate);
+					let pk = e.public_bytes();
+					out.extend_from_slice(&pk);
+					self.ss.mix_hash(&pk);
+					if self.pattern.is_psk() {
+						self.ss.mix_key(&pk)?;
+					}
+					self.e = Some(e);
+				}
+				Token::S => {
+					let pk = self.local_static()?.public_bytes();
+					let ct = self.ss.encrypt_and_hash(&pk)?;
+					out.extend_from_slice(&ct);
+				}
+				Token::Psk => {
+					let psk = self.psk.ok_or_else(|| Error::protocol("missing psk"))?;
+					self.ss.mix_key_and_hash(&psk)?;
+				}
+				dh => self.mix_dh(dh)?,
+			}
+		}
+		let ct = self.ss.encrypt_and_hash(payload)?;
+		out.extend_from_slice(&ct);
+		if out.len() > MAX_MESSAGE_LEN {
+			return Err(Error::protocol("handshake message too long"));
+		}
+		self.msg += 1;
+		Ok(out)
+	}
+
+	pub fn read_message(&mut self, message: &[u8]) -> Result<Vec<u8>> {
+		if self.is_finished() || self.is_my_turn() {
+			return Err(Error::protocol("handshake: not our turn to read"));
+		}
+		if message.len() > MAX_MESSAGE_LEN {
+			return Err(Error::protocol("handshake message too long"));
+		}
+		let mut rest = message;
+		for &token in self.pattern.messages()[self.msg] {
+			match token {
+				Token::E => {
+					let re: [u8; DH_LEN] = take(&mut rest, DH_LEN)?.try_into().expect("length checked");
+					self.ss.mix_hash(&re);
+					if self.pattern.is_psk() {
+						self.ss.mix_key(&re)?;
+					}
+					self.re = Some(re);
+				}
+				Token::S => {
+					let len = if self.ss.has_key() { DH_LEN + TAG_LEN } else { DH_LEN };
+					let ct = take(&mut rest, len)?;
+					let rs = self.ss.decrypt_and_hash(ct)?;
+					self.rs = Some(rs.as_slice().try_into().expect("decrypted static key length"));
+				}
+				Token::Psk => {
+					let psk = self.psk.ok_or_else(|| Error::protocol("missing psk"))?;
+					self.ss.mix_key_and_hash(&psk)?;
+				}
+				dh => self.mix_dh(dh)?,
+			}
+		}
+		let payload = self.ss.decrypt_and_hash(rest)?;
+		self.msg += 1;
+		Ok(payload)
+	}
+
+	/// Finish the handshake and derive transport keys.
+	pub fn into_transport(self) -> Result<TransportState> {
+		if !self.is_finished() {
+			return Err(Error::protocol("handshake not finished"));
+		}
+		let (c1, c2) = self.ss.split()?;
+		let (send, recv) = if self.initiator { (c1, c2) } else { (c2, c1) };
+		let mut resumption_secret = [0u8; HASH_LEN];
+		KdfAlgorithm::HkdfSha256.expand(&self.ss.ck, RESUMPTION_LABEL, &mut resumption_secret)?;
+		Ok(TransportState { send, recv, handshake_hash: self.ss.h, remote_static: self.rs, resumption_secret })
+	}
+}
+
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+	if buf.len() < n {
+		return Err(Error::protocol("handshake message truncated"));
+	}
+	let (head, tail) = buf.split_at(n);
+	*buf = tail;
+	Ok(head)
+}
+
+/// Post-handshake transport keys.
+pub struct TransportState {
+	send: CipherState,
+	recv: CipherState,
+	handshake_hash: [u8; HASH_LEN],
+	remote_static: Option<[u8; DH_LEN]>,
+	resumption_secret: [u8; HASH_LEN],
+}
+
+impl TransportState {
+	pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> { self.send.encrypt_with_ad(&[], plaintext) }
+
+	pub fn decrypt(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> { self.recv.decrypt_with_ad(&[], ciphertext) }
+
+	pub fn handshake_hash(&self) -> [u8; HASH_LEN] { self.handshake_hash }
+
+	pub fn remote_static(&self) -> Option<[u8; DH_LEN]> { self.remote_static }
+
+	/// Secret both sides can use to derive resumption PSKs for this session.
+	pub fn resumption_secret(&self) -> &[u8; HASH_LEN] { &self.resumption_secret }
+
+	/// Raw send/receive keys, for handing to the stream layer's AEAD.
+	pub fn keys(&self) -> (AeadKey, AeadKey) {
+		let k = |cs: &CipherState| AeadKey(cs.k.expect("transport keys are always set"));
+		(k(&self.send), k(&self.recv))
+	}
+}
+
+impl Drop for TransportState {
+	fn drop(&mut self) {
+		self.resumption_secret.zeroize();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ik_pair() -> (HandshakeState, HandshakeState, Keypair, Keypair) {
+		let si = Keypair::generate();
+		let sr = Keypair::generate();
+		let i = HandshakeState::initiator(si.clone(), sr.public_bytes(), PROLOGUE);
+		let r = HandshakeState::responder(sr.clone(), PROLOGUE);
+		(i, r, si, sr)
+	}
+
+	#[test]
+	fn ik_handshake_and_transport() {
+		let (mut i, mut r, si, _) = ik_pair();
+		let m1 = i.write_message(b"early").unwrap();
+		assert_eq!(r.read_message(&m1).unwrap(), b"early");
+		assert_eq!(r.remote_static(), Some(si.public_bytes()));
+		let m2 = r.write_message(b"resp").unwrap();
+		assert_eq!(i.read_message(&m2).unwrap(), b"resp");
+		assert!(i.is_finished() && r.is_finished());
+		assert_eq!(i.handshake_hash(), r.handshake_hash());
+
+		let mut ti = i.into_transport().unwrap();
+		let mut tr = r.into_transport().unwrap();
+		assert_eq!(ti.resumption_secret(), tr.resumption_secret());
+		let ct = ti.encrypt(b"hello").unwrap();
+		assert_eq!(tr.decrypt(&ct).unwrap(), b"hello");
+		let ct = tr.encrypt(b"world").unwrap();
+		assert_eq!(ti.decrypt(&ct).unwrap(), b"world");
+		assert_eq!(ti.keys().0 .0, tr.keys().1 .0);
+	}
+
+	#[test]
+	fn ik_rejects_wrong_responder_key_and_tampering() {
+		let si = Keypair::generate();
+		let sr = Keypair::generate();
+		let mut i = HandshakeState::initiator(si, Keypair::generate().public_bytes(), PROLOGUE);
+		let mut r = HandshakeState::responder(sr, PROLOGUE);
+		let m1 = i.write_message(b"").unwrap();
+		assert!(r.read_message(&m1).is_err());
+
+		let (mut i, mut r, _, _) = ik_pair();
+		let mut m1 = i.write_message(b"x").unwrap();
+		let last = m1.len() - 1;
+		m1[last] ^= 1;
+		assert!(r.read_message(&m1).is_err());
+		assert!(r.read_message(&m1[..10]).is_err());
+	}
+
+	#[test]
+	fn prologue_mismatch_fails() {
+		let si = Keypair::generate();
+		let sr = Keypair::generate();
+		let mut i = HandshakeState::initiator(si, sr.public_bytes(), b"Nyx0.1");
+		let mut r = HandshakeState::responder(sr, b"Nyx0.2");
+		let m1 = i.write_message(b"").unwrap();
+		assert!(r.read_message(&m1).is_err());
+	}
+
+	#[test]
+	fn psk_handshake_requires_matching_psk() {
+		let mut i = HandshakeState::psk_initiator([7; 32], PROLOGUE);
+		let mut r = HandshakeState::psk_responder([7; 32], PROLOGUE);
+		let m1 = i.write_message(b"0-rtt").unwrap();
+		assert_eq!(r.read_message(&m1).unwrap(), b"0-rtt");
+		let m2 = r.write_message(b"").unwrap();
+		i.read_message(&m2).unwrap();
+		let mut ti = i.into_transport().unwrap();
+		let mut tr = r.into_transport().unwrap();
+		let ct = ti.encrypt(b"data").unwrap();
+		assert_eq!(tr.decrypt(&ct).unwrap(), b"data");
+
+		let mut i = HandshakeState::psk_initiator([7; 32], PROLOGUE);
+		let mut r = HandshakeState::psk_responder([8; 32], PROLOGUE);
+		let m1 = i.write_message(b"0-rtt").unwrap();
+		assert!(r.read_message(&m1).is_err());
+	}
+
+	#[test]
+	fn out_of_turn_is_rejected() {
+		let (mut i, mut r, _, _) = ik_pair();
+		assert!(r.write_message(b"").is_err());
+		assert!(i.read_message(&[0u8; 64]).is_err());
+		assert!(i.into_transport().is_err());
+	}
+}
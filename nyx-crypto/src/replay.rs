@@ -0,0 +1,110 @@
+﻿#![forbid(unsafe_code)]
+
+//! Sliding anti-replay window over 64-bit sequence numbers.
+//!
+//! The window tracks the highest accepted sequence and a bitmap of the
+//! `REPLAY_WINDOW` values below it (spec: 2^20). Anything older than the
+//! window, or already seen inside it, is rejected.
+
+pub const REPLAY_WINDOW: u64 = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub struct ReplayWindow {
+	size: u64,
+	top: Option<u64>,
+	bits: Vec<u64>,
+}
+
+impl Default for ReplayWindow {
+	fn default() -> Self { Self::new(REPLAY_WINDOW) }
+}
+
+impl ReplayWindow {
+	/// `size` is rounded up to a multiple of 64.
+	pub fn new(size: u64) -> Self {
+		let words = size.max(1).div_ceil(64);
+		Self { size: words * 64, top: None, bits: vec![0; words as usize] }
+	}
+
+	pub fn size(&self) -> u64 { self.size }
+
+	/// Highest sequence accepted so far.
+	pub fn top(&self) -> Option<u64> { self.top }
+
+	fn slot(&self, seq: u64) -> (usize, u64) {
+		let i = seq % self.size;
+		((i / 64) as usize, 1u64 << (i % 64))
+	}
+
+	/// Whether `seq` would be accepted, without recording it.
+	pub fn check(&self, seq: u64) -> bool {
+		match self.top {
+			None => true,
+			Some(top) if seq > top => true,
+			Some(top) if top - seq >= self.size => false,
+			Some(_) => {
+				let (w, m) = self.slot(seq);
+				self.bits[w] & m == 0
+			}
+		}
+	}
+
+	/// Accept `seq` if it is fresh, recording it. Returns false on replay or too-old.
+	pub fn check_and_update(&mut self, seq: u64) -> bool {
+		if !self.check(seq) {
+			return false;
+		}
+		match self.top {
+			Some(top) if seq <= top => {}
+			Some(top) if seq - top < self.size => {
+				// Slide forward: clear the slots that now represent new sequence numbers.
+				for s in top + 1..=seq {
+					let (w, m) = self.slot(s);
+					self.bits[w] &= !m;
+				}
+				self.top = Some(seq);
+			}
+			_ => {
+				self.bits.iter_mut().for_each(|b| *b = 0);
+				self.top = Some(seq);
+			}
+		}
+		let (w, m) = self.slot(seq);
+		self.bits[w] |= m;
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rejects_duplicates_and_old() {
+		let mut w = ReplayWindow::new(128);
+		assert!(w.check_and_update(5));
+		assert!(!w.check_and_update(5));
+		assert!(w.check_and_update(3));
+		assert!(w.check_and_update(200));
+		assert!(!w.check_and_update(3));
+		assert!(!w.check_and_update(72));
+		assert!(w.check_and_update(73));
+		assert!(!w.check_and_update(200));
+		assert_eq!(w.top(), Some(200));
+	}
+
+	#[test]
+	fn large_jump_resets_and_slots_are_reused() {
+		let mut w = ReplayWindow::new(64);
+		for s in 0..64 {
+			assert!(w.check_and_update(s));
+		}
+		assert!(w.check_and_update(100));
+		// 64 reuses the slot of 0; 36 has fallen out of the window.
+		assert!(w.check_and_update(64));
+		assert!(!w.check_and_update(36));
+		assert!(w.check_and_update(1_000_000));
+		assert!(w.check_and_update(999_999));
+		assert_eq!(ReplayWindow::default().size(), REPLAY_WINDOW);
+	}
+}
@@ -0,0 +1,370 @@
+﻿#![forbid(unsafe_code)]
+
+//! Session resumption tickets and PSK-based 0-RTT.
+//!
+//! After a full Noise_Nyx handshake the responder hands out opaque tickets
+//! sealed under its own ticket key. Each ticket carries a sequence number and
+//! the PSK `HKDF-Expand(resumption_secret, "Nyx-psk" || seq)`, which the
+//! initiator derives independently (the sequence number also travels in the
+//! encrypted `NewSessionTicket`) and caches keyed by the peer's NodeID.
+//! On the wire a ticket shows only a random id, so resumptions cannot be
+//! linked to each other or to the session that issued them.
+//!
+//! A resumed connection sends `ticket || NNpsk0 message 1`, with early data as
+//! the message payload. The ticket is bound into the prologue. The responder
+//! accepts a ticket at most once (the sealed sequence numbers go through a
+//! [`ReplayWindow`]) and only within its lifetime. Early data is not
+//! forward secret with respect to the PSK; the second message mixes in a fresh
+//! `ee` so everything after it is.
+//!
+//! Ticket layout: `version (1) | id (12, random, also the AEAD nonce) |
+//! AEAD(seq (8, BE) | psk (32) | issued_at (8, BE unix secs) | lifetime (4, BE
+//! secs))`, header as AAD.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rand_core_06::{OsRng, RngCore};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::aead::{AeadCipher, AeadKey, AeadNonce, NONCE_LEN, TAG_LEN};
+use crate::kdf::KdfAlgorithm;
+use crate::keystore::NodeId;
+use crate::noise::{HandshakeState, TransportState, PROLOGUE};
+use crate::replay::ReplayWindow;
+use crate::{Error, Result};
+
+pub const TICKET_VERSION: u8 = 1;
+pub const PSK_LABEL: &[u8] = b"Nyx-psk";
+pub const DEFAULT_TICKET_LIFETIME: Duration = Duration::from_secs(24 * 3600);
+/// Upper bound on lifetimes we issue or honour.
+pub const MAX_TICKET_LIFETIME: Duration = Duration::from_secs(7 * 24 * 3600);
+/// Tolerated clock skew for tickets that appear issued in the future.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+/// Tickets kept per peer by the initiator.
+pub const MAX_TICKETS_PER_PEER: usize = 4;
+
+const HEADER_LEN: usize = 1 + NONCE_LEN;
+const BODY_LEN: usize = 8 + 32 + 8 + 4;
+pub const TICKET_LEN: usize = HEADER_LEN + BODY_LEN + TAG_LEN;
+
+/// Resumption pre-shared key. Wiped from memory on drop.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct Psk(pub [u8; 32]);
+
+impl core::fmt::Debug for Psk {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result { f.write_str("Psk(..)") }
+}
+
+/// Derive the PSK for ticket `seq` from a session's resumption secret.
+pub fn derive_psk(resumption_secret: &[u8; 32], seq: u64) -> Result<Psk> {
+	let mut info = PSK_LABEL.to_vec();
+	info.extend_from_slice(&seq.to_be_bytes());
+	let mut psk = Psk([0u8; 32]);
+	KdfAlgorithm::HkdfSha256.expand(resumption_secret, &info, &mut psk.0)?;
+	Ok(psk)
+}
+
+fn unix_secs(t: SystemTime) -> u64 { t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) }
+
+/// Ticket as delivered to the initiator (inside the encrypted session).
+/// `seq` selects the PSK; it is never sent in the clear.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewSessionTicket {
+	pub lifetime: Duration,
+	pub seq: u64,
+	pub ticket: Vec<u8>,
+}
+
+impl NewSessionTicket {
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut out = Vec::with_capacity(12 + self.ticket.len());
+		out.extend_from_slice(&(self.lifetime.as_secs() as u32).to_be_bytes());
+		out.extend_from_slice(&self.seq.to_be_bytes());
+		out.extend_from_slice(&self.ticket);
+		out
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+		if bytes.len() != 12 + TICKET_LEN {
+			return Err(Error::protocol("malformed session ticket"));
+		}
+		let lifetime = u32::from_be_bytes(bytes[..4].try_into().expect("length checked"));
+		let seq = u64::from_be_bytes(bytes[4..12].try_into().expect("length checked"));
+		Ok(Self { lifetime: Duration::from_secs(lifetime as u64), seq, ticket: bytes[12..].to_vec() })
+	}
+}
+
+/// Opaque id from the ticket header.
+pub fn ticket_id(ticket: &[u8]) -> Result<[u8; NONCE_LEN]> {
+	if ticket.len() != TICKET_LEN || ticket[0] != TICKET_VERSION {
+		return Err(Error::protocol("malformed session ticket"));
+	}
+	Ok(ticket[1..HEADER_LEN].try_into().expect("length checked"))
+}
+
+/// Responder side: issues tickets and redeems each at most once.
+pub struct TicketIssuer {
+	cipher: AeadCipher,
+	lifetime: Duration,
+	next_seq: u64,
+	redeemed: ReplayWindow,
+}
+
+impl core::fmt::Debug for TicketIssuer {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.debug_struct("TicketIssuer").field("lifetime", &self.lifetime).field("next_seq", &self.next_seq).finish_non_exhaustive()
+	}
+}
+
+impl TicketIssuer {
+	/// New issuer with a random ticket key. Tickets do not survive a restart.
+	pub fn new(lifetime: Duration) -> Self { Self::with_key(AeadKey::generate(), lifetime) }
+
+	pub fn with_key(key: AeadKey, lifetime: Duration) -> Self {
+		Self { cipher: AeadCipher::new(&key), lifetime: lifetime.min(MAX_TICKET_LIFETIME), next_seq: 0, redeemed: ReplayWindow::default() }
+	}
+
+	pub fn lifetime(&self) -> Duration { self.lifetime }
+
+	/// Issue a ticket for the session that produced `transport`.
+	pub fn issue(&mut self, transport: &TransportState, now: SystemTime) -> Result<NewSessionTicket> {
+		let seq = self.next_seq;
+		self.next_seq = self.next_seq.checked_add(1).ok_or_else(|| Error::protocol("ticket sequence exhausted"))?;
+		let psk = derive_psk(transport.resumption_secret(), seq)?;
+
+		let mut id = [0u8; NONCE_LEN];
+		OsRng.fill_bytes(&mut id);
+		let mut ticket = Vec::with_capacity(TICKET_LEN);
+		ticket.push(TICKET_VERSION);
+		ticket.extend_from_slice(&id);
+
+		let mut body = [0u8; BODY_LEN];
+		body[..8].copy_from_slice(&seq.to_be_bytes());
+		body[8..40].copy_from_slice(&psk.0);
+		body[40..48].copy_from_slice(&unix_secs(now).to_be_bytes());
+		body[48..].copy_from_slice(&(self.lifetime.as_secs() as u32).to_be_bytes());
+		let ct = self.cipher.seal(AeadNonce(id), &ticket, &body);
+		body.zeroize();
+		ticket.extend_from_slice(&ct?);
+		Ok(NewSessionTicket { lifetime: self.lifetime, seq, ticket })
+	}
+
+	/// Validate a ticket and consume it. A ticket is accepted at most once.
+	pub fn redeem(&mut self, ticket: &[u8], now: SystemTime) -> Result<Psk> {
+		let id = ticket_id(ticket)?;
+		let mut body = self.cipher.open(AeadNonce(id), &ticket[..HEADER_LEN], &ticket[HEADER_LEN..])?;
+		let seq = u64::from_be_bytes(body[..8].try_into().expect("body length"));
+		let psk = Psk(body[8..40].try_into().expect("body length"));
+		let issued_at = u64::from_be_bytes(body[40..48].try_into().expect("body length"));
+		let lifetime = u32::from_be_bytes(body[48..52].try_into().expect("body length")) as u64;
+		body.zeroize();
+
+		let now = unix_secs(now);
+		let lifetime = lifetime.min(MAX_TICKET_LIFETIME.as_secs());
+		if issued_at > now + MAX_CLOCK_SKEW.as_secs() || now >= issued_at.saturating_add(lifetime) {
+			return Err(Error::protocol("session ticket expired"));
+		}
+		if !self.redeemed.check_and_update(seq) {
+			return Err(Error::Replay(format!("session ticket {} already used", hex_id(&id))));
+		}
+		Ok(psk)
+	}
+}
+
+fn hex_id(id: &[u8]) -> String { id.iter().map(|b| format!("{b:02x}")).collect() }
+
+/// Ticket cached by the initiator.
+#[derive(Debug, Clone)]
+pub struct CachedTicket {
+	pub ticket: Vec<u8>,
+	pub psk: Psk,
+	pub expires_at: SystemTime,
+}
+
+/// Initiator side: tickets keyed by peer NodeID, each handed out once.
+#[derive(Debug, Default)]
+pub struct TicketCache {
+	by_peer: HashMap<NodeId, VecDeque<CachedTicket>>,
+}
+
+impl TicketCache {
+	pub fn new() -> Self { Self::default() }
+
+	/// Store a ticket received from `peer` over `transport`.
+	pub fn insert(&mut self, peer: NodeId, nst: &NewSessionTicket, transport: &TransportState, now: SystemTime) -> Result<()> {
+		ticket_id(&nst.ticket)?;
+		let psk = derive_psk(transport.resumption_secret(), nst.seq)?;
+		let expires_at = now + nst.lifetime.min(MAX_TICKET_LIFETIME);
+		let q = self.by_peer.entry(peer).or_default();
+		q.push_back(CachedTicket { ticket: nst.ticket.clone(), psk, expires_at });
+		while q.len() > MAX_TICKETS_PER_PEER {
+			q.pop_front();
+		}
+		Ok(())
+	}
+
+	/// Remove and return the newest unexpired ticket for `peer`.
+	pub fn take(&mut self, peer: &NodeId, now: SystemTime) -> Option<CachedTicket> {
+		let q = self.by_peer.get_mut(peer)?;
+		q.retain(|t| t.expires_at > now);
+		let t = q.pop_back();
+		if q.is_empty() {
+			self.by_peer.remove(peer);
+		}
+		t
+	}
+
+	pub fn len(&self, peer: &NodeId) -> usize { self.by_peer.get(peer).map_or(0, VecDeque::len) }
+
+	pub fn is_empty(&self) -> bool { self.by_peer.is_empty() }
+
+	pub fn forget(&mut self, peer: &NodeId) { self.by_peer.remove(peer); }
+}
+
+fn resumption_prologue(ticket: &[u8]) -> Vec<u8> {
+	let mut p = PROLOGUE.to_vec();
+	p.extend_from_slice(ticket);
+	p
+}
+
+/// Start a resumed handshake carrying `early_data`. Returns the state and the
+/// first flight (`ticket_len (2, BE) | ticket | noise message`).
+pub fn resume(ticket: &CachedTicket, early_data: &[u8]) -> Result<(HandshakeState, Vec<u8>)> {
+	let len = u16::try_from(ticket.ticket.len()).map_err(|_| Error::protocol("session ticket too long"))?;
+	let mut hs = HandshakeState::psk_initiator(ticket.psk.0, &resumption_prologue(&ticket.ticket));
+	let msg = hs.write_message(early_data)?;
+	let mut out = Vec::with_capacity(2 + ticket.ticket.len() + msg.len());
+	out.extend_from_slice(&len.to_be_bytes());
+	out.extend_from_slice(&ticket.ticket);
+	out.extend_from_slice(&msg);
+	Ok((hs, out))
+}
+
+/// Accept a resumed handshake. Returns the state (ready to write the second
+/// message) and the decrypted early data.
+pub fn accept(issuer: &mut TicketIssuer, first_flight: &[u8], now: SystemTime) -> Result<(HandshakeState, Vec<u8>)> {
+	if first_flight.len() < 2 {
+		return Err(Error::protocol("resumption message truncated"));
+	}
+	let len = u16::from_be_bytes([first_flight[0], first_flight[1]]) as usize;
+	let rest = &first_flight[2..];
+	if rest.len() < len {
+		return Err(Error::protocol("resumption message truncated"));
+	}
+	let (ticket, msg) = rest.split_at(len);
+	let psk = issuer.redeem(ticket, now)?;
+	let mut hs = HandshakeState::psk_responder(psk.0, &resumption_prologue(ticket));
+	let early = hs.read_message(msg)?;
+	Ok((hs, early))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::noise::Keypair;
+
+	fn full_handshake() -> (TransportState, TransportState) {
+		let si = Keypair::generate();
+		let sr = Keypair::generate();
+		let mut i = HandshakeState::initiator(si, sr.public_bytes(), PROLOGUE);
+		let mut r = HandshakeState::responder(sr, PROLOGUE);
+		r.read_message(&i.write_message(b"").unwrap()).unwrap();
+		i.read_message(&r.write_message(b"").unwrap()).unwrap();
+		(i.into_transport().unwrap(), r.into_transport().unwrap())
+	}
+
+	fn finish(mut i: HandshakeState, mut r: HandshakeState) -> (TransportState, TransportState) {
+		i.read_message(&r.write_message(b"").unwrap()).unwrap();
+		(i.into_transport().unwrap(), r.into_transport().unwrap())
+	}
+
+	#[test]
+	fn resume_with_early_data() {
+		let now = SystemTime::now();
+		let peer: NodeId = [1; 32];
+		let (ti, tr) = full_handshake();
+		let mut issuer = TicketIssuer::new(DEFAULT_TICKET_LIFETIME);
+		let nst = issuer.issue(&tr, now).unwrap();
+		let nst = NewSessionTicket::from_bytes(&nst.to_bytes()).unwrap();
+
+		let mut cache = TicketCache::new();
+		cache.insert(peer, &nst, &ti, now).unwrap();
+		let cached = cache.take(&peer, now).unwrap();
+		assert!(cache.take(&peer, now).is_none());
+
+		let (hs_i, flight) = resume(&cached, b"GET /").unwrap();
+		let (hs_r, early) = accept(&mut issuer, &flight, now).unwrap();
+		assert_eq!(early, b"GET /");
+		let (mut ti2, mut tr2) = finish(hs_i, hs_r);
+		let ct = tr2.encrypt(b"200").unwrap();
+		assert_eq!(ti2.decrypt(&ct).unwrap(), b"200");
+		// The resumed session can issue its own tickets.
+		let nst2 = issuer.issue(&tr2, now).unwrap();
+		cache.insert(peer, &nst2, &ti2, now).unwrap();
+		assert_eq!(cache.len(&peer), 1);
+	}
+
+	#[test]
+	fn tickets_do_not_expose_a_sequence() {
+		let now = SystemTime::now();
+		let (_, tr) = full_handshake();
+		let mut issuer = TicketIssuer::new(DEFAULT_TICKET_LIFETIME);
+		let a = issuer.issue(&tr, now).unwrap();
+		let b = issuer.issue(&tr, now).unwrap();
+		assert_eq!((a.seq, b.seq), (0, 1));
+		assert_eq!(a.ticket.len(), TICKET_LEN);
+		// Apart from the version byte, consecutive tickets share nothing.
+		assert_ne!(ticket_id(&a.ticket).unwrap(), ticket_id(&b.ticket).unwrap());
+		assert!(!a.ticket.windows(8).any(|w| w == 0u64.to_be_bytes()));
+		assert!(!b.ticket.windows(8).any(|w| w == 1u64.to_be_bytes()));
+	}
+
+	#[test]
+	fn replayed_first_flight_is_rejected() {
+		let now = SystemTime::now();
+		let (ti, tr) = full_handshake();
+		let mut issuer = TicketIssuer::new(DEFAULT_TICKET_LIFETIME);
+		let nst = issuer.issue(&tr, now).unwrap();
+		let mut cache = TicketCache::new();
+		cache.insert([2; 32], &nst, &ti, now).unwrap();
+		let (_, flight) = resume(&cache.take(&[2; 32], now).unwrap(), b"pay $5").unwrap();
+		assert!(accept(&mut issuer, &flight, now).is_ok());
+		assert!(matches!(accept(&mut issuer, &flight, now), Err(Error::Replay(_))));
+	}
+
+	#[test]
+	fn expired_and_foreign_tickets_are_rejected() {
+		let now = SystemTime::now();
+		let (ti, tr) = full_handshake();
+		let mut issuer = TicketIssuer::new(Duration::from_secs(60));
+		let nst = issuer.issue(&tr, now).unwrap();
+		let mut cache = TicketCache::new();
+		cache.insert([3; 32], &nst, &ti, now).unwrap();
+		let cached = cache.take(&[3; 32], now).unwrap();
+		let (_, flight) = resume(&cached, b"").unwrap();
+		assert!(accept(&mut issuer, &flight, now + Duration::from_secs(61)).is_err());
+
+		let mut other = TicketIssuer::new(DEFAULT_TICKET_LIFETIME);
+		assert!(matches!(accept(&mut other, &flight, now), Err(Error::Aead)));
+		// A tampered ticket fails authentication as well.
+		let mut bad = flight.clone();
+		bad[12] ^= 1;
+		assert!(accept(&mut issuer, &bad, now).is_err());
+	}
+
+	#[test]
+	fn cache_drops_expired_and_caps_per_peer() {
+		let now = SystemTime::now();
+		let (ti, tr) = full_handshake();
+		let mut issuer = TicketIssuer::new(Duration::from_secs(10));
+		let mut cache = TicketCache::new();
+		for _ in 0..6 {
+			let nst = issuer.issue(&tr, now).unwrap();
+			cache.insert([4; 32], &nst, &ti, now).unwrap();
+		}
+		assert_eq!(cache.len(&[4; 32]), MAX_TICKETS_PER_PEER);
+		assert!(cache.take(&[4; 32], now + Duration::from_secs(11)).is_none());
+		assert!(cache.is_empty());
+	}
+}
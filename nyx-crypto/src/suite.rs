@@ -0,0 +1,119 @@
+﻿#![forbid(unsafe_code)]
+
+//! Negotiable cipher suites (AEAD + hash + KDF).
+//!
+//! Each suite is advertised as an optional capability ID in the first CRYPTO
+//! frame. The responder picks the first suite in its own preference order
+//! that the peer also advertised. A peer that advertises no suite at all is a
+//! legacy stack and gets the default suite.
+
+use crate::aead::{AeadAlgorithm, AeadCipher, AeadKey, KEY_LEN};
+use crate::kdf::{HashAlgorithm, KdfAlgorithm, HASH_LEN};
+use crate::{Error, Result};
+
+/// Capability ID of the default ChaCha20-Poly1305 / SHA-256 / HKDF suite.
+pub const CAP_SUITE_CHACHA20POLY1305_SHA256: u32 = 0x0100;
+/// Capability ID of the Ascon-128a / BLAKE3 / BLAKE3-KDF suite.
+pub const CAP_SUITE_ASCON128A_BLAKE3: u32 = 0x0101;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CipherSuiteId {
+	#[default]
+	ChaCha20Poly1305Sha256,
+	Ascon128aBlake3,
+}
+
+impl CipherSuiteId {
+	pub const ALL: [CipherSuiteId; 2] = [CipherSuiteId::ChaCha20Poly1305Sha256, CipherSuiteId::Ascon128aBlake3];
+
+	pub fn capability_id(&self) -> u32 {
+		match self {
+			Self::ChaCha20Poly1305Sha256 => CAP_SUITE_CHACHA20POLY1305_SHA256,
+			Self::Ascon128aBlake3 => CAP_SUITE_ASCON128A_BLAKE3,
+		}
+	}
+
+	pub fn from_capability_id(id: u32) -> Option<Self> { Self::ALL.into_iter().find(|s| s.capability_id() == id) }
+
+	pub fn suite(&self) -> CipherSuite {
+		match self {
+			Self::ChaCha20Poly1305Sha256 => CipherSuite { id: *self, aead: AeadAlgorithm::ChaCha20Poly1305, hash: HashAlgorithm::Sha256, kdf: KdfAlgorithm::HkdfSha256 },
+			Self::Ascon128aBlake3 => CipherSuite { id: *self, aead: AeadAlgorithm::Ascon128a, hash: HashAlgorithm::Blake3, kdf: KdfAlgorithm::Blake3 },
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CipherSuite {
+	pub id: CipherSuiteId,
+	pub aead: AeadAlgorithm,
+	pub hash: HashAlgorithm,
+	pub kdf: KdfAlgorithm,
+}
+
+impl Default for CipherSuite {
+	fn default() -> Self { CipherSuiteId::default().suite() }
+}
+
+impl CipherSuite {
+	pub fn hash(&self, data: &[u8]) -> [u8; HASH_LEN] { self.hash.hash(data) }
+
+	/// Derive an AEAD key from a chaining key and a label (e.g. `b"Nyx-rekey"`).
+	pub fn derive_key(&self, ck: &[u8; HASH_LEN], label: &[u8]) -> Result<AeadKey> {
+		let mut k = AeadKey([0u8; KEY_LEN]);
+		self.kdf.expand(ck, label, &mut k.0)?;
+		Ok(k)
+	}
+
+	pub fn cipher(&self, key: &AeadKey) -> AeadCipher { AeadCipher::with_algorithm(self.aead, key) }
+}
+
+/// Capability IDs to advertise for the given preference list.
+pub fn advertise(prefs: &[CipherSuiteId]) -> Vec<u32> { prefs.iter().map(|s| s.capability_id()).collect() }
+
+/// Choose a suite given our preference order and the capability IDs the peer advertised.
+pub fn negotiate(local_prefs: &[CipherSuiteId], peer_caps: &[u32]) -> Result<CipherSuiteId> {
+	let peer_suites: Vec<CipherSuiteId> = peer_caps.iter().filter_map(|&id| CipherSuiteId::from_capability_id(id)).collect();
+	if peer_suites.is_empty() {
+		// Legacy peer: only the default suite is implied.
+		return if local_prefs.contains(&CipherSuiteId::default()) { Ok(CipherSuiteId::default()) } else { Err(Error::protocol("no common cipher suite")) };
+	}
+	local_prefs.iter().copied().find(|s| peer_suites.contains(s)).ok_or_else(|| Error::protocol("no common cipher suite"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::aead::AeadNonce;
+
+	#[test]
+	fn responder_preference_wins() {
+		let peer = advertise(&[CipherSuiteId::ChaCha20Poly1305Sha256, CipherSuiteId::Ascon128aBlake3]);
+		let iot = [CipherSuiteId::Ascon128aBlake3, CipherSuiteId::ChaCha20Poly1305Sha256];
+		assert_eq!(negotiate(&iot, &peer).unwrap(), CipherSuiteId::Ascon128aBlake3);
+		assert_eq!(negotiate(&CipherSuiteId::ALL, &peer).unwrap(), CipherSuiteId::ChaCha20Poly1305Sha256);
+	}
+
+	#[test]
+	fn legacy_peer_and_mismatch() {
+		// Unrelated capability IDs only: fall back to default
+		assert_eq!(negotiate(&CipherSuiteId::ALL, &[0x0001, 0x0002]).unwrap(), CipherSuiteId::ChaCha20Poly1305Sha256);
+		assert!(negotiate(&[CipherSuiteId::Ascon128aBlake3], &[0x0001]).is_err());
+		assert!(negotiate(&[CipherSuiteId::Ascon128aBlake3], &[CAP_SUITE_CHACHA20POLY1305_SHA256]).is_err());
+	}
+
+	#[test]
+	fn suites_are_not_interchangeable() {
+		let ck = [9u8; HASH_LEN];
+		let a = CipherSuiteId::ChaCha20Poly1305Sha256.suite();
+		let b = CipherSuiteId::Ascon128aBlake3.suite();
+		let ka = a.derive_key(&ck, b"Nyx-rekey").unwrap();
+		let kb = b.derive_key(&ck, b"Nyx-rekey").unwrap();
+		assert_ne!(ka.0, kb.0);
+		let n = AeadNonce::new(0, 1);
+		let ct = b.cipher(&kb).seal(n, b"", b"m").unwrap();
+		assert_eq!(b.cipher(&kb).open(n, b"", &ct).unwrap(), b"m");
+		assert!(a.cipher(&kb).open(n, b"", &ct).is_err());
+		assert_ne!(a.hash(b"x"), b.hash(b"x"));
+	}
+}
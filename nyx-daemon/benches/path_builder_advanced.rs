@@ -1 +1,38 @@
+use std::collections::HashSet;
 
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use nyx_daemon::path_builder::{NodeInfo, PathBuilder};
+use rand::{rngs::StdRng, SeedableRng};
+
+fn directory(n: usize) -> Vec<NodeInfo> {
+    (0..n)
+        .map(|i| {
+            let mut node_id = [0u8; 32];
+            node_id[..8].copy_from_slice(&(i as u64).to_be_bytes());
+            NodeInfo {
+                node_id,
+                public_key: [0; 32],
+                operator: Some(format!("op{}", i % 97)),
+                asn: Some(64_500 + (i % 89) as u32),
+                country: Some(format!("C{}", i % 41)),
+                bandwidth_mbps: 10.0 + (i % 50) as f64,
+                latency_ms: 5.0 + (i % 13) as f64 * 10.0,
+            }
+        })
+        .collect()
+}
+
+fn bench_path_builder(c: &mut Criterion) {
+    let pb = PathBuilder::default();
+    let blacklist: HashSet<[u8; 32]> = HashSet::new();
+    let mut group = c.benchmark_group("path_builder");
+    for n in [100usize, 1_000, 10_000] {
+        let dir = directory(n);
+        let mut rng = StdRng::seed_from_u64(1);
+        group.bench_with_input(BenchmarkId::new("build_5_hops", n), &dir, |b, dir| b.iter(|| pb.build_with_hops(black_box(dir), &blacklist, 5, &mut rng).unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_path_builder);
+criterion_main!(benches);
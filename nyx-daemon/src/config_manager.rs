@@ -7,11 +7,29 @@ use serde::{Deserialize, Serialize};
 use tokio::{fs, sync::RwLock};
 use tracing::{debug, info, warn};
 
+pub use nyx_core::cmix::{MixConfig, MixMode};
+pub use crate::exit::ExitConfig;
+pub use crate::path_builder::{NodeInfo, PathBuilderConfig};
+pub use crate::relay::RelayConfig;
+
+/// What the daemon does with mix traffic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    /// Originates traffic only.
+    #[default]
+    Client,
+    /// Accepts, mixes and forwards packets for other nodes.
+    Relay,
+    /// Bridges Nyx streams to TCP destinations allowed by its exit policy.
+    Exit,
+}
+
 /// Static configuration structure loaded from TOML (kept minimal here to avoid coupling).
 /// Extend this as the daemon grows; unknown fields are ignored via serde defaults.
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct NyxConfig {
-    /// Daemon listen port for transport (kept for compatibility; not used by IPC).
+    /// UDP/TCP port of the peer transport; 0 picks one from 43300-43399.
     #[serde(default)]
     pub listen_port: u16,
     /// Tracing level (e.g., "info", "debug").
@@ -20,6 +38,34 @@ pub struct NyxConfig {
     /// Optional hex-encoded 32-byte node id; generated when absent.
     #[serde(default)]
     pub node_id: Option<String>,
+    /// `[mix]` section: standard or cMix batching.
+    #[serde(default)]
+    pub mix: MixConfig,
+    #[serde(default)]
+    pub role: NodeRole,
+    /// `[relay]` section, used when `role = "relay"`.
+    #[serde(default)]
+    pub relay: RelayConfig,
+    /// `[exit]` section, used when `role = "exit"`.
+    #[serde(default)]
+    pub exit: ExitConfig,
+    /// `[transport]` section: peer transport of the client role.
+    #[serde(default)]
+    pub transport: TransportConfig,
+    /// `[path]` section: hop range and weighting for route selection.
+    #[serde(default)]
+    pub path: PathBuilderConfig,
+    /// `[[directory]]` entries: mix nodes routes are drawn from.
+    #[serde(default)]
+    pub directory: Vec<NodeInfo>,
+}
+
+/// `[transport]` section. Relays and exits always listen; a client only
+/// accepts peer connections on `listen_port` when `listen` is set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransportConfig {
+    pub listen: bool,
 }
 
 /// Dynamic settings that can be changed at runtime via IPC.
@@ -146,6 +192,17 @@ impl ConfigManager {
                 errs.push("node_id must be 32-byte hex".into());
             }
         }
+        if let Err(e) = config.mix.validate() {
+            errs.push(e.to_string());
+        }
+        if let Err(e) = crate::path_builder::PathBuilder::new(config.path.clone()) {
+            errs.push(format!("path: {e}"));
+        }
+        match config.role {
+            NodeRole::Client => {}
+            NodeRole::Relay => errs.extend(config.relay.validate()),
+            NodeRole::Exit => errs.extend(config.exit.validate()),
+        }
         errs
     }
 
@@ -0,0 +1,655 @@
+﻿#![forbid(unsafe_code)]
+
+//! Exit role.
+//!
+//! An exit terminates Nyx streams and bridges them to TCP destinations.
+//! Streams arrive multiplexed over links: TCP connections carrying
+//! length-prefixed CBOR frames ([`FrameCodec`]). The listener binds to
+//! loopback unless configured otherwise.
+//!
+//! A link is authenticated before any stream is opened: the exit sends a
+//! `Crypto` frame on stream 0 carrying a random challenge, and the client
+//! answers with a `Crypto` frame carrying [`link_proof`] over it under the
+//! configured `link_secret`. The exit confirms with an `Ack` on stream 0.
+//! Any other frame first, a wrong proof or a slow answer drops the link.
+//! Frames are not encrypted, so links beyond the local host should be
+//! carried inside the Nyx transport. Per stream id:
+//!
+//! - the first `Data` frame names the target as `host:port`. The exit
+//!   resolves the host itself, applies the [`ExitPolicy`] to every resolved
+//!   address and connects to the first allowed one;
+//! - the exit answers with an `Ack` frame carrying the connected address, or
+//!   a `Close` frame carrying the reason it refused;
+//! - later `Data` frames are written to the destination and its bytes come
+//!   back as `Data` frames. `Close` half-closes the direction it travels in.
+//!   A stream whose destination cannot keep up is reset with an
+//!   `exit overloaded` `Close` rather than stalling the rest of the link.
+//!
+//! Bytes are accounted per stream while it is open and added to the totals
+//! when it ends. Destinations are never logged above debug level.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use bytes::BytesMut;
+use nyx_stream::frame::{Frame, FrameHeader, FrameType};
+use nyx_stream::FrameCodec;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, info, warn};
+
+use crate::exit_policy::ExitPolicy;
+
+/// Destination bytes carried per `Data` frame.
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Time a new link has to answer the challenge.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+const LINK_CONTEXT: &[u8] = b"nyx-exit-link-v1";
+
+/// Answer to a link challenge under the shared link secret.
+pub fn link_proof(secret: &[u8; 32], challenge: &[u8]) -> [u8; 32] {
+    *blake3::Hasher::new_keyed(secret).update(LINK_CONTEXT).update(challenge).finalize().as_bytes()
+}
+
+/// `[exit]` section, used when `role = "exit"`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExitConfig {
+    /// TCP address for incoming links.
+    pub listen: String,
+    /// Hex-encoded 32-byte secret clients prove knowledge of before opening
+    /// streams; required.
+    pub link_secret: Option<String>,
+    /// Exit policy rules, first match wins; see [`crate::exit_policy`].
+    pub policy: Vec<String>,
+    /// Refuse loopback, private and link-local destinations regardless of
+    /// `policy`.
+    pub reject_private: bool,
+    pub dns_timeout_ms: u64,
+    pub connect_timeout_ms: u64,
+    /// Open streams across all links; further streams are refused.
+    pub max_streams: usize,
+}
+
+impl Default for ExitConfig {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:43300".into(),
+            link_secret: None,
+            policy: vec!["accept *:80".into(), "accept *:443".into(), "reject *:*".into()],
+            reject_private: true,
+            dns_timeout_ms: 5_000,
+            connect_timeout_ms: 10_000,
+            max_streams: 1024,
+        }
+    }
+}
+
+impl ExitConfig {
+    pub fn validate(&self) -> Vec<String> {
+        let mut errs = Vec::new();
+        if self.listen.parse::<SocketAddr>().is_err() {
+            errs.push(format!("exit.listen is not a socket address: {}", self.listen));
+        }
+        match &self.link_secret {
+            None => errs.push("exit.link_secret is required".into()),
+            Some(_) if self.secret().is_none() => errs.push("exit.link_secret must be 32-byte hex".into()),
+            Some(_) => {}
+        }
+        for line in &self.policy {
+            if let Err(e) = line.parse::<crate::exit_policy::ExitRule>() {
+                errs.push(format!("exit.policy: {e}"));
+            }
+        }
+        if self.dns_timeout_ms == 0 || self.connect_timeout_ms == 0 || self.max_streams == 0 {
+            errs.push("exit timeouts and max_streams must be positive".into());
+        }
+        errs
+    }
+
+    pub fn exit_policy(&self) -> Result<ExitPolicy> { ExitPolicy::parse(&self.policy, self.reject_private) }
+
+    fn secret(&self) -> Option<[u8; 32]> { hex::decode(self.link_secret.as_deref()?).ok()?.try_into().ok() }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    links: AtomicU64,
+    unauthenticated: AtomicU64,
+    streams_opened: AtomicU64,
+    streams_closed: AtomicU64,
+    malformed: AtomicU64,
+    policy_rejected: AtomicU64,
+    dns_failed: AtomicU64,
+    connect_failed: AtomicU64,
+    overloaded: AtomicU64,
+    bytes_up: AtomicU64,
+    bytes_down: AtomicU64,
+}
+
+fn bump(c: &AtomicU64) { c.fetch_add(1, Ordering::Relaxed); }
+
+/// Totals since the exit started. `bytes_up` flows towards destinations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ExitStats {
+    pub links: u64,
+    /// Links dropped before proving the link secret.
+    pub unauthenticated: u64,
+    pub streams_opened: u64,
+    pub streams_active: u64,
+    pub streams_closed: u64,
+    pub malformed: u64,
+    pub policy_rejected: u64,
+    pub dns_failed: u64,
+    pub connect_failed: u64,
+    pub overloaded: u64,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+}
+
+/// Accounting for one open stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StreamStats {
+    pub link: u64,
+    pub stream_id: u32,
+    /// The requested `host:port`.
+    pub target: String,
+    pub remote: SocketAddr,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub age_ms: u64,
+}
+
+#[derive(Debug)]
+struct StreamEntry {
+    link: u64,
+    stream_id: u32,
+    target: String,
+    remote: SocketAddr,
+    opened: Instant,
+    up: AtomicU64,
+    down: AtomicU64,
+}
+
+struct Shared {
+    secret: [u8; 32],
+    policy: ExitPolicy,
+    dns_timeout: Duration,
+    connect_timeout: Duration,
+    permits: Arc<Semaphore>,
+    counters: Counters,
+    streams: Mutex<HashMap<u64, Arc<StreamEntry>>>,
+    next_link: AtomicU64,
+    next_stream: AtomicU64,
+}
+
+/// A running exit; its links and streams stop when the handle is dropped.
+pub struct ExitHandle {
+    local_addr: SocketAddr,
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+impl ExitHandle {
+    pub fn local_addr(&self) -> SocketAddr { self.local_addr }
+
+    pub fn policy(&self) -> &ExitPolicy { &self.shared.policy }
+
+    pub fn stats(&self) -> ExitStats {
+        let c = &self.shared.counters;
+        let get = |a: &AtomicU64| a.load(Ordering::Relaxed);
+        let (mut bytes_up, mut bytes_down) = (get(&c.bytes_up), get(&c.bytes_down));
+        let streams = self.shared.streams.lock().unwrap_or_else(|e| e.into_inner());
+        for s in streams.values() {
+            bytes_up += s.up.load(Ordering::Relaxed);
+            bytes_down += s.down.load(Ordering::Relaxed);
+        }
+        ExitStats {
+            links: get(&c.links),
+            unauthenticated: get(&c.unauthenticated),
+            streams_opened: get(&c.streams_opened),
+            streams_active: streams.len() as u64,
+            streams_closed: get(&c.streams_closed),
+            malformed: get(&c.malformed),
+            policy_rejected: get(&c.policy_rejected),
+            dns_failed: get(&c.dns_failed),
+            connect_failed: get(&c.connect_failed),
+            overloaded: get(&c.overloaded),
+            bytes_up,
+            bytes_down,
+        }
+    }
+
+    /// Open streams, oldest first.
+    pub fn streams(&self) -> Vec<StreamStats> {
+        let streams = self.shared.streams.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out: Vec<(Instant, StreamStats)> = streams
+            .values()
+            .map(|s| {
+                let stats = StreamStats {
+                    link: s.link,
+                    stream_id: s.stream_id,
+                    target: s.target.clone(),
+                    remote: s.remote,
+                    bytes_up: s.up.load(Ordering::Relaxed),
+                    bytes_down: s.down.load(Ordering::Relaxed),
+                    age_ms: s.opened.elapsed().as_millis() as u64,
+                };
+                (s.opened, stats)
+            })
+            .collect();
+        out.sort_by_key(|(opened, _)| *opened);
+        out.into_iter().map(|(_, s)| s).collect()
+    }
+}
+
+impl Drop for ExitHandle {
+    fn drop(&mut self) { self.task.abort(); }
+}
+
+/// Bind and start an exit.
+pub async fn start(cfg: &ExitConfig) -> Result<ExitHandle> {
+    let errs = cfg.validate();
+    if !errs.is_empty() {
+        return Err(anyhow!("invalid exit config: {}", errs.join("; ")));
+    }
+    let listener = TcpListener::bind(&cfg.listen).await.with_context(|| format!("binding exit listener {}", cfg.listen))?;
+    let local_addr = listener.local_addr()?;
+    let shared = Arc::new(Shared {
+        secret: cfg.secret().ok_or_else(|| anyhow!("exit.link_secret must be 32-byte hex"))?,
+        policy: cfg.exit_policy()?,
+        dns_timeout: Duration::from_millis(cfg.dns_timeout_ms),
+        connect_timeout: Duration::from_millis(cfg.connect_timeout_ms),
+        permits: Arc::new(Semaphore::new(cfg.max_streams)),
+        counters: Counters::default(),
+        streams: Mutex::new(HashMap::new()),
+        next_link: AtomicU64::new(1),
+        next_stream: AtomicU64::new(1),
+    });
+    let task = tokio::spawn(accept_links(listener, shared.clone()));
+    info!("exit listening on {local_addr} with {} policy rules", shared.policy.rules().len());
+    Ok(ExitHandle { local_addr, shared, task })
+}
+
+async fn accept_links(listener: TcpListener, shared: Arc<Shared>) {
+    // Dropping the set when this task is aborted stops every link.
+    let mut links = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    let _ = stream.set_nodelay(true);
+                    let id = shared.next_link.fetch_add(1, Ordering::Relaxed);
+                    bump(&shared.counters.links);
+                    debug!("exit link {id} from {peer}");
+                    links.spawn(run_link(stream, shared.clone(), id));
+                }
+                Err(e) => {
+                    warn!("exit accept error: {e}");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            },
+            Some(_) = links.join_next(), if !links.is_empty() => {}
+        }
+    }
+}
+
+/// A stream's queue towards its destination and the switch that resets it.
+struct StreamTx {
+    up: mpsc::Sender<Vec<u8>>,
+    reset: oneshot::Sender<()>,
+}
+
+/// A stream id in use on a link. The id stays taken until its `run_stream`
+/// task ends, so a reused id cannot interleave replies with the old stream's.
+enum LinkStream {
+    Open(StreamTx),
+    /// Closed by the client or reset by the exit; replies may still be in flight.
+    Closing,
+}
+
+/// Free the ids of streams whose task has ended.
+fn reap(tasks: &mut JoinSet<Option<u32>>, streams: &mut HashMap<u32, LinkStream>) {
+    while let Some(done) = tasks.try_join_next() {
+        if let Ok(Some(id)) = done {
+            streams.remove(&id);
+        }
+    }
+}
+
+async fn run_link<S: AsyncRead + AsyncWrite + Send + 'static>(io: S, shared: Arc<Shared>, link: u64) {
+    let (mut rd, mut wr) = tokio::io::split(io);
+    let (out_tx, mut out_rx) = mpsc::channel::<Frame>(256);
+    let mut tasks = JoinSet::new();
+    tasks.spawn(async move {
+        let mut buf = BytesMut::new();
+        while let Some(frame) = out_rx.recv().await {
+            buf.clear();
+            if FrameCodec::encode(&frame, &mut buf).is_err() || wr.write_all(&buf).await.is_err() {
+                break;
+            }
+        }
+        let _ = wr.shutdown().await;
+        None
+    });
+
+    let mut buf = BytesMut::with_capacity(READ_CHUNK);
+    if !authenticate(&mut rd, &mut buf, &out_tx, &shared.secret).await {
+        debug!("exit link {link} failed to authenticate");
+        bump(&shared.counters.unauthenticated);
+        drop(out_tx);
+        while tasks.join_next().await.is_some() {}
+        return;
+    }
+
+    let mut streams: HashMap<u32, LinkStream> = HashMap::new();
+    loop {
+        let frame = match FrameCodec::decode(&mut buf) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                tokio::select! {
+                    read = rd.read_buf(&mut buf) => match read {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => continue,
+                    },
+                    Some(done) = tasks.join_next(), if tasks.len() > 1 => {
+                        if let Ok(Some(id)) = done {
+                            streams.remove(&id);
+                        }
+                        continue;
+                    }
+                }
+            }
+            Err(e) => {
+                debug!("exit link {link} sent a bad frame: {e}");
+                bump(&shared.counters.malformed);
+                break;
+            }
+        };
+        let id = frame.header.stream_id;
+        match frame.header.ty {
+            FrameType::Data => {
+                if matches!(streams.get(&id), Some(LinkStream::Closing)) {
+                    reap(&mut tasks, &mut streams);
+                }
+                match streams.get(&id) {
+                    Some(LinkStream::Open(stream)) => match stream.up.try_send(frame.payload) {
+                        Ok(()) => {}
+                        Err(mpsc::error::TrySendError::Full(_)) => {
+                            // The destination is not draining; reset this stream
+                            // rather than stall every other one on the link.
+                            if let Some(LinkStream::Open(stream)) = streams.insert(id, LinkStream::Closing) {
+                                let _ = stream.reset.send(());
+                            }
+                            bump(&shared.counters.overloaded);
+                        }
+                        Err(mpsc::error::TrySendError::Closed(_)) => {
+                            streams.insert(id, LinkStream::Closing);
+                        }
+                    },
+                    // The id's previous stream has not finished; reuse is refused.
+                    Some(LinkStream::Closing) => {}
+                    None => {
+                        let (up_tx, up_rx) = mpsc::channel(64);
+                        let (reset_tx, reset_rx) = oneshot::channel();
+                        streams.insert(id, LinkStream::Open(StreamTx { up: up_tx, reset: reset_tx }));
+                        let stream = run_stream(shared.clone(), link, id, frame.payload, up_rx, reset_rx, out_tx.clone());
+                        tasks.spawn(async move {
+                            stream.await;
+                            Some(id)
+                        });
+                    }
+                }
+            }
+            // Dropping the queue ends the upstream once queued data is written.
+            FrameType::Close => {
+                if let Some(entry) = streams.get_mut(&id) {
+                    *entry = LinkStream::Closing;
+                }
+            }
+            FrameType::Ack | FrameType::Crypto => {}
+        }
+    }
+    // The client is gone: stop writing to destinations, let replies drain.
+    drop(streams);
+    drop(out_tx);
+    while tasks.join_next().await.is_some() {}
+    debug!("exit link {link} closed");
+}
+
+/// Challenge the client and check its proof; the first frame it sends must
+/// be the answer.
+async fn authenticate<R: AsyncRead + Unpin>(rd: &mut R, buf: &mut BytesMut, out: &mpsc::Sender<Frame>, secret: &[u8; 32]) -> bool {
+    let challenge: [u8; 32] = rand::random();
+    if out.send(control(FrameType::Crypto, 0, challenge.to_vec())).await.is_err() {
+        return false;
+    }
+    let answer = tokio::time::timeout(AUTH_TIMEOUT, async {
+        loop {
+            match FrameCodec::decode(buf) {
+                Ok(Some(frame)) => return Some(frame),
+                Ok(None) => match rd.read_buf(buf).await {
+                    Ok(0) | Err(_) => return None,
+                    Ok(_) => {}
+                },
+                Err(_) => return None,
+            }
+        }
+    })
+    .await
+    .ok()
+    .flatten();
+    let Some(frame) = answer.filter(|f| f.header.ty == FrameType::Crypto && f.header.stream_id == 0) else { return false };
+    let Ok(proof) = <[u8; 32]>::try_from(frame.payload.as_slice()) else { return false };
+    // blake3::Hash compares in constant time.
+    if blake3::Hash::from(proof) != blake3::Hash::from(link_proof(secret, &challenge)) {
+        return false;
+    }
+    out.send(control(FrameType::Ack, 0, Vec::new())).await.is_ok()
+}
+
+/// Why a stream was refused; sent back as the `Close` payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Refusal {
+    Malformed,
+    Policy,
+    Dns,
+    Connect,
+    Overloaded,
+}
+
+impl Refusal {
+    fn reason(self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed target",
+            Self::Policy => "rejected by exit policy",
+            Self::Dns => "resolution failed",
+            Self::Connect => "connection failed",
+            Self::Overloaded => "exit overloaded",
+        }
+    }
+
+    fn counter(self, c: &Counters) -> &AtomicU64 {
+        match self {
+            Self::Malformed => &c.malformed,
+            Self::Policy => &c.policy_rejected,
+            Self::Dns => &c.dns_failed,
+            Self::Connect => &c.connect_failed,
+            Self::Overloaded => &c.overloaded,
+        }
+    }
+}
+
+/// Split `host:port`; IPv6 literals are bracketed.
+pub fn parse_target(target: &str) -> Option<(&str, u16)> {
+    let (host, port) = target.rsplit_once(':')?;
+    let port = port.parse().ok().filter(|p| *p != 0)?;
+    let host = match host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        Some(v6) => v6.parse::<std::net::Ipv6Addr>().ok().map(|_| v6)?,
+        None if host.is_empty() || host.contains(':') => return None,
+        None => host,
+    };
+    Some((host, port))
+}
+
+async fn connect(shared: &Shared, target: &str) -> std::result::Result<TcpStream, Refusal> {
+    let (host, port) = parse_target(target).ok_or(Refusal::Malformed)?;
+    if !shared.policy.may_accept_port(port) {
+        return Err(Refusal::Policy);
+    }
+    let candidates: Vec<SocketAddr> = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => match tokio::time::timeout(shared.dns_timeout, tokio::net::lookup_host((host, port))).await {
+            Ok(Ok(addrs)) => addrs.collect(),
+            _ => return Err(Refusal::Dns),
+        },
+    };
+    if candidates.is_empty() {
+        return Err(Refusal::Dns);
+    }
+    let allowed: Vec<SocketAddr> = candidates.into_iter().filter(|a| shared.policy.allows(a)).collect();
+    if allowed.is_empty() {
+        return Err(Refusal::Policy);
+    }
+    for addr in allowed {
+        match tokio::time::timeout(shared.connect_timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => debug!("exit connect to {addr} failed: {e}"),
+            Err(_) => debug!("exit connect to {addr} timed out"),
+        }
+    }
+    Err(Refusal::Connect)
+}
+
+fn control(ty: FrameType, stream_id: u32, payload: impl Into<Vec<u8>>) -> Frame { Frame { header: FrameHeader { stream_id, seq: 0, ty }, payload: payload.into() } }
+
+async fn run_stream(
+    shared: Arc<Shared>,
+    link: u64,
+    stream_id: u32,
+    target: Vec<u8>,
+    mut up: mpsc::Receiver<Vec<u8>>,
+    reset: oneshot::Receiver<()>,
+    out: mpsc::Sender<Frame>,
+) {
+    let target = String::from_utf8(target).unwrap_or_default();
+    let opened = match shared.permits.clone().try_acquire_owned() {
+        Ok(permit) => connect(&shared, &target).await.map(|s| (s, permit)),
+        Err(_) => Err(Refusal::Overloaded),
+    };
+    let (dest, _permit) = match opened {
+        Ok(opened) => opened,
+        Err(refusal) => {
+            bump(refusal.counter(&shared.counters));
+            debug!("exit link {link} stream {stream_id} to {target:?} refused: {}", refusal.reason());
+            let _ = out.send(control(FrameType::Close, stream_id, refusal.reason())).await;
+            return;
+        }
+    };
+    let remote = match dest.peer_addr() {
+        Ok(a) => a,
+        Err(_) => {
+            bump(&shared.counters.connect_failed);
+            let _ = out.send(control(FrameType::Close, stream_id, Refusal::Connect.reason())).await;
+            return;
+        }
+    };
+    let _ = dest.set_nodelay(true);
+    bump(&shared.counters.streams_opened);
+    let key = shared.next_stream.fetch_add(1, Ordering::Relaxed);
+    let entry = Arc::new(StreamEntry { link, stream_id, target, remote, opened: Instant::now(), up: AtomicU64::new(0), down: AtomicU64::new(0) });
+    shared.streams.lock().unwrap_or_else(|e| e.into_inner()).insert(key, entry.clone());
+    let _ = out.send(control(FrameType::Ack, stream_id, remote.to_string())).await;
+
+    let (mut dest_rd, mut dest_wr) = dest.into_split();
+    let upstream = async {
+        while let Some(bytes) = up.recv().await {
+            if dest_wr.write_all(&bytes).await.is_err() {
+                break;
+            }
+            entry.up.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        }
+        let _ = dest_wr.shutdown().await;
+    };
+    let downstream = async {
+        let mut buf = vec![0u8; READ_CHUNK];
+        let mut seq = 1;
+        loop {
+            let n = match dest_rd.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            if out.send(Frame::data(stream_id, seq, buf[..n].to_vec())).await.is_err() {
+                return;
+            }
+            seq += 1;
+            entry.down.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        let _ = out.send(control(FrameType::Close, stream_id, Vec::new())).await;
+    };
+    // A dropped reset switch only means the link is gone.
+    let reset = async {
+        if reset.await.is_err() {
+            std::future::pending::<()>().await
+        }
+    };
+    let was_reset = tokio::select! {
+        _ = async { tokio::join!(upstream, downstream) } => false,
+        _ = reset => true,
+    };
+    if was_reset {
+        debug!("exit link {link} stream {stream_id} reset: destination too slow");
+        let _ = out.send(control(FrameType::Close, stream_id, Refusal::Overloaded.reason())).await;
+    }
+
+    let (bytes_up, bytes_down) = (entry.up.load(Ordering::Relaxed), entry.down.load(Ordering::Relaxed));
+    {
+        // Move the stream into the totals atomically with respect to stats().
+        let mut streams = shared.streams.lock().unwrap_or_else(|e| e.into_inner());
+        streams.remove(&key);
+        let c = &shared.counters;
+        c.bytes_up.fetch_add(bytes_up, Ordering::Relaxed);
+        c.bytes_down.fetch_add(bytes_down, Ordering::Relaxed);
+        bump(&c.streams_closed);
+    }
+    metrics::counter!("nyx_exit_bytes_total", "direction" => "up").increment(bytes_up);
+    metrics::counter!("nyx_exit_bytes_total", "direction" => "down").increment(bytes_down);
+    debug!("exit link {link} stream {stream_id} closed: {bytes_up} B up, {bytes_down} B down in {:?}", entry.opened.elapsed());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_parse() {
+        assert_eq!(parse_target("example.org:443"), Some(("example.org", 443)));
+        assert_eq!(parse_target("127.0.0.1:7"), Some(("127.0.0.1", 7)));
+        assert_eq!(parse_target("[::1]:80"), Some(("::1", 80)));
+        for bad in ["example.org", "example.org:0", ":80", "::1:80", "host:http"] {
+            assert_eq!(parse_target(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn config_validation_reports_bad_fields() {
+        assert_eq!(ExitConfig::default().validate(), ["exit.link_secret is required"]);
+        assert!(ExitConfig { link_secret: Some(hex::encode([7; 32])), ..Default::default() }.validate().is_empty());
+        let cfg = ExitConfig { listen: "nowhere".into(), link_secret: Some("abcd".into()), policy: vec!["allow *:*".into()], max_streams: 0, ..Default::default() };
+        assert_eq!(cfg.validate().len(), 4);
+    }
+
+    #[test]
+    fn default_listener_is_loopback() {
+        assert!(ExitConfig::default().listen.parse::<SocketAddr>().unwrap().ip().is_loopback());
+    }
+}
@@ -0,0 +1,281 @@
+﻿#![forbid(unsafe_code)]
+
+//! Exit policy: which destinations an exit node connects to.
+//!
+//! Rules follow Tor's ExitPolicy syntax, one per entry, and the first match
+//! wins: `accept 10.0.0.0/8:80-443`, `reject *:25`,
+//! `accept [2001:db8::]/32:*`. A destination that matches no rule is
+//! rejected. With `reject_private`, loopback, private, link-local and similar
+//! ranges are refused before any rule is consulted, so a broad `accept *:*`
+//! does not expose the exit's own network.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Accept,
+    Reject,
+}
+
+/// An address block. IPv4-mapped IPv6 addresses match IPv4 blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Block of `addr` with `prefix` leading bits; host bits are cleared.
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Self> {
+        let addr = match addr {
+            IpAddr::V4(a) if prefix <= 32 => IpAddr::V4((u32::from(a) & mask32(prefix)).into()),
+            IpAddr::V6(a) if prefix <= 128 => IpAddr::V6((u128::from(a) & mask128(prefix)).into()),
+            _ => return None,
+        };
+        Some(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => u32::from(ip) & mask32(self.prefix) == u32::from(net),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => u128::from(ip) & mask128(self.prefix) == u128::from(net),
+            _ => false,
+        }
+    }
+}
+
+fn mask32(prefix: u8) -> u32 { u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0) }
+
+fn mask128(prefix: u8) -> u128 { u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0) }
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.addr {
+            IpAddr::V4(a) => write!(f, "{a}/{}", self.prefix),
+            IpAddr::V6(a) => write!(f, "[{a}]/{}", self.prefix),
+        }
+    }
+}
+
+/// One `accept|reject ADDR[/BITS]:PORTS` line; `*` matches any address or port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitRule {
+    pub action: Action,
+    /// `None` matches every address.
+    pub net: Option<Cidr>,
+    pub ports: (u16, u16),
+}
+
+impl ExitRule {
+    pub fn matches(&self, addr: &SocketAddr) -> bool { self.matches_port(addr.port()) && self.net.is_none_or(|n| n.contains(addr.ip())) }
+
+    fn matches_port(&self, port: u16) -> bool { (self.ports.0..=self.ports.1).contains(&port) }
+}
+
+impl FromStr for ExitRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (action, pattern) = s.trim().split_once(char::is_whitespace).ok_or_else(|| anyhow!("exit rule needs an action and a pattern: {s:?}"))?;
+        let action = match action {
+            "accept" => Action::Accept,
+            "reject" => Action::Reject,
+            other => bail!("unknown exit rule action {other:?}"),
+        };
+        let (addr, ports) = pattern.trim().rsplit_once(':').ok_or_else(|| anyhow!("exit rule pattern needs ADDR:PORTS: {s:?}"))?;
+        Ok(Self { action, net: parse_net(addr)?, ports: parse_ports(ports)? })
+    }
+}
+
+fn parse_net(s: &str) -> Result<Option<Cidr>> {
+    if s == "*" {
+        return Ok(None);
+    }
+    let (addr, prefix) = match s.split_once('/') {
+        Some((a, p)) => (a, Some(p.parse::<u8>().map_err(|_| anyhow!("bad prefix length in {s:?}"))?)),
+        None => (s, None),
+    };
+    let addr = addr.strip_prefix('[').and_then(|a| a.strip_suffix(']')).unwrap_or(addr);
+    let ip: IpAddr = addr.parse().map_err(|_| anyhow!("bad address {addr:?}"))?;
+    let prefix = prefix.unwrap_or(if ip.is_ipv4() { 32 } else { 128 });
+    Cidr::new(ip, prefix).map(Some).ok_or_else(|| anyhow!("prefix length too long in {s:?}"))
+}
+
+fn parse_ports(s: &str) -> Result<(u16, u16)> {
+    if s == "*" {
+        return Ok((1, u16::MAX));
+    }
+    let port = |p: &str| p.parse::<u16>().ok().filter(|p| *p != 0).ok_or_else(|| anyhow!("bad port {p:?}"));
+    let (lo, hi) = match s.split_once('-') {
+        Some((lo, hi)) => (port(lo)?, port(hi)?),
+        None => (port(s)?, port(s)?),
+    };
+    if lo > hi {
+        bail!("empty port range {s:?}");
+    }
+    Ok((lo, hi))
+}
+
+impl fmt::Display for ExitRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.action {
+            Action::Accept => "accept",
+            Action::Reject => "reject",
+        };
+        match self.net {
+            Some(n) => write!(f, "{action} {n}:")?,
+            None => write!(f, "{action} *:")?,
+        }
+        match self.ports {
+            (1, u16::MAX) => write!(f, "*"),
+            (lo, hi) if lo == hi => write!(f, "{lo}"),
+            (lo, hi) => write!(f, "{lo}-{hi}"),
+        }
+    }
+}
+
+/// Loopback, private, link-local, shared, benchmarking, reserved, multicast
+/// and similar addresses. NAT64 (`64:ff9b::/96`) and 6to4 (`2002::/16`)
+/// addresses are judged by the IPv4 address they embed; site-local and
+/// IPv4-compatible IPv6 addresses are refused outright.
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(a) => {
+            let o = a.octets();
+            a.is_private()
+                || a.is_loopback()
+                || a.is_link_local()
+                || a.is_unspecified()
+                || a.is_multicast()
+                || a.is_documentation()
+                || o[0] == 0
+                || (o[0] == 100 && (o[1] & 0xc0) == 64)
+                || (o[0] == 198 && (o[1] & 0xfe) == 18)
+                || o[0] >= 240
+        }
+        IpAddr::V6(a) => {
+            let s = a.segments();
+            let bits = u128::from(a);
+            if bits >> 32 == 0x0064_ff9b << 64 {
+                return is_private(IpAddr::V4(Ipv4Addr::from(bits as u32)));
+            }
+            if s[0] == 0x2002 {
+                return is_private(IpAddr::V4(Ipv4Addr::from((bits >> 80) as u32)));
+            }
+            a.is_loopback()
+                || a.is_unspecified()
+                || a.is_multicast()
+                || (s[0] & 0xfe00) == 0xfc00
+                || (s[0] & 0xffc0) == 0xfe80
+                || (s[0] & 0xffc0) == 0xfec0
+                || bits >> 32 == 0
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitPolicy {
+    rules: Vec<ExitRule>,
+    reject_private: bool,
+}
+
+impl ExitPolicy {
+    pub fn new(rules: Vec<ExitRule>, reject_private: bool) -> Self { Self { rules, reject_private } }
+
+    /// Parse configured rule lines.
+    pub fn parse(lines: &[String], reject_private: bool) -> Result<Self> {
+        let rules = lines.iter().map(|l| l.parse()).collect::<Result<_>>()?;
+        Ok(Self::new(rules, reject_private))
+    }
+
+    pub fn rules(&self) -> &[ExitRule] { &self.rules }
+
+    pub fn decide(&self, addr: &SocketAddr) -> Action {
+        if self.reject_private && is_private(addr.ip()) {
+            return Action::Reject;
+        }
+        self.rules.iter().find(|r| r.matches(addr)).map_or(Action::Reject, |r| r.action)
+    }
+
+    pub fn allows(&self, addr: &SocketAddr) -> bool { self.decide(addr) == Action::Accept }
+
+    /// Whether some address on `port` could be accepted; lets the exit refuse
+    /// a stream before resolving its host name.
+    pub fn may_accept_port(&self, port: u16) -> bool {
+        for r in self.rules.iter().filter(|r| r.matches_port(port)) {
+            match (r.action, r.net) {
+                (Action::Accept, _) => return true,
+                (Action::Reject, None) => return false,
+                (Action::Reject, Some(_)) => {}
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(lines: &[&str], reject_private: bool) -> ExitPolicy {
+        ExitPolicy::parse(&lines.iter().map(|l| l.to_string()).collect::<Vec<_>>(), reject_private).unwrap()
+    }
+
+    fn sa(s: &str) -> SocketAddr { s.parse().unwrap() }
+
+    #[test]
+    fn rules_parse_and_round_trip() {
+        for line in ["accept *:*", "reject 10.0.0.0/8:25", "accept 192.0.2.7/32:80-443", "reject [2001:db8::]/32:*"] {
+            assert_eq!(line.parse::<ExitRule>().unwrap().to_string(), line);
+        }
+        assert_eq!("accept 10.1.2.3/8:80".parse::<ExitRule>().unwrap().to_string(), "accept 10.0.0.0/8:80");
+        assert_eq!("accept 1.2.3.4:80".parse::<ExitRule>().unwrap().to_string(), "accept 1.2.3.4/32:80");
+        for bad in ["allow *:*", "accept *", "accept 1.2.3.4/33:*", "accept *:0", "accept *:443-80", "accept host:80"] {
+            assert!(bad.parse::<ExitRule>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn first_match_wins_and_default_is_reject() {
+        let p = policy(&["reject 203.0.113.0/24:*", "accept *:80", "accept [2001:db8::]/32:443"], false);
+        assert!(p.allows(&sa("198.51.100.1:80")));
+        assert!(!p.allows(&sa("203.0.113.9:80")));
+        assert!(!p.allows(&sa("198.51.100.1:22")));
+        assert!(p.allows(&sa("[2001:db8::1]:443")));
+        assert!(!p.allows(&sa("[2001:db9::1]:443")));
+        // IPv4-mapped addresses are matched as IPv4.
+        assert!(!p.allows(&sa("[::ffff:203.0.113.9]:80")));
+    }
+
+    #[test]
+    fn private_ranges_are_rejected_first() {
+        let p = policy(&["accept *:*"], true);
+        for a in ["127.0.0.1:80", "10.1.1.1:80", "192.168.0.1:80", "169.254.1.1:80", "100.64.0.1:80", "[::1]:80", "[fd00::1]:80", "[fe80::1]:80", "[::ffff:10.0.0.1]:80"] {
+            assert!(!p.allows(&sa(a)), "{a}");
+        }
+        // Benchmarking, reserved, site-local and IPv4-compatible ranges.
+        for a in ["198.18.0.1:80", "198.19.255.255:80", "240.0.0.1:80", "255.255.255.255:80", "[fec0::1]:80", "[::93.184.216.34]:80", "[::8.8.8.8]:80"] {
+            assert!(!p.allows(&sa(a)), "{a}");
+        }
+        // NAT64 and 6to4 are judged by the embedded IPv4 address.
+        for a in ["[64:ff9b::10.0.0.1]:80", "[64:ff9b::127.0.0.1]:80", "[2002:c0a8:0001::1]:80", "[2002:7f00:1::]:80"] {
+            assert!(!p.allows(&sa(a)), "{a}");
+        }
+        for a in ["93.184.216.34:80", "198.20.0.1:80", "[64:ff9b::93.184.216.34]:80", "[2002:5db8:d822::1]:80", "[2001:4860::8888]:80"] {
+            assert!(p.allows(&sa(a)), "{a}");
+        }
+        assert!(policy(&["accept *:*"], false).allows(&sa("127.0.0.1:80")));
+    }
+
+    #[test]
+    fn port_precheck() {
+        let p = policy(&["reject 10.0.0.0/8:*", "accept *:443", "reject *:*", "accept *:22"], false);
+        assert!(p.may_accept_port(443));
+        assert!(!p.may_accept_port(22));
+        assert!(!policy(&[], false).may_accept_port(80));
+    }
+}
@@ -4,6 +4,11 @@
 // Public modules for daemon runtime; kept minimal and pure Rust.
 pub mod config_manager;
 pub mod event_system;
+pub mod exit;
+pub mod exit_policy;
+pub mod path_builder;
+pub mod prometheus_exporter;
+pub mod relay;
 
 // Re-export with shorter prefixes used in main.rs
 pub use config_manager as nyx_daemon_config;
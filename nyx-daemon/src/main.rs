@@ -7,8 +7,16 @@ use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tracing::{error, info, warn};
 
-use nyx_daemon::nyx_daemon_config::{ConfigManager, ConfigResponse, NyxConfig, VersionSummary};
+use nyx_daemon::nyx_daemon_config::{ConfigManager, ConfigResponse, MixMode, NodeRole, NyxConfig, VersionSummary};
 use nyx_daemon::nyx_daemon_events::{Event, EventSystem};
+use nyx_crypto::keystore::{KdfParams, Keystore};
+use nyx_daemon::path_builder::PathBuilder;
+use nyx_daemon::prometheus_exporter::{self, PrometheusExporter};
+use nyx_daemon::exit::{self, ExitHandle, ExitStats};
+use nyx_daemon::relay::{self, RelayHandle, RelayStats};
+use nyx_mix::anonymity::{AnonymityEstimator, AnonymityMetrics};
+use nyx_transport::tcp_fallback::{FallbackConfig, FallbackTransport, TransportKind};
+use nyx_transport::udp::UdpConfig;
 
 #[cfg(unix)]
 use tokio::net::UnixListener;
@@ -27,6 +35,10 @@ struct DaemonState {
 	cfg: ConfigManager,
 	events: EventSystem,
 	token: Option<String>, // Optional static token for privileged ops
+	anonymity: Arc<std::sync::Mutex<AnonymityEstimator>>,
+	relay: Option<Arc<RelayHandle>>,
+	exit: Option<Arc<ExitHandle>>,
+	transport: Option<Arc<FallbackTransport>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -39,6 +51,9 @@ enum Request {
 	ListConfigVersions,
 	RollbackConfig { version: u64 },
 	CreateConfigSnapshot { description: Option<String> },
+	/// cMix inclusion proof for a packet this relay batched, by the hex
+	/// BLAKE3 digest of the packet as the relay received it after peeling.
+	GetInclusion { packet_digest: String },
 }
 
 /// RPC request envelope carrying optional request id and auth token.
@@ -57,6 +72,62 @@ struct Info {
 	node_id: String,
 	version: String,
 	uptime_sec: u32,
+	mix_mode: String,
+	role: NodeRole,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	relay: Option<RelayInfo>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	exit: Option<ExitInfo>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	transport: Option<TransportInfo>,
+	anonymity: AnonymityMetrics,
+}
+
+#[derive(Debug, Serialize)]
+struct RelayInfo {
+	listen: String,
+	mix_public_key: String,
+	stats: RelayStats,
+}
+
+#[derive(Debug, Serialize)]
+struct ExitInfo {
+	listen: String,
+	policy: Vec<String>,
+	stats: ExitStats,
+}
+
+/// Peer transport in use: `udp`, `tcp` when every peer is on the TCP
+/// fallback, or `udp+tcp` when both are in use.
+#[derive(Debug, Serialize)]
+struct TransportInfo {
+	listen: String,
+	kind: String,
+	udp_peers: usize,
+	tcp_peers: usize,
+	tcp_connections: usize,
+	fallbacks: u64,
+	upgrades: u64,
+}
+
+impl TransportInfo {
+	fn from_transport(t: &FallbackTransport) -> Self {
+		let s = t.stats();
+		let kind = match (s.udp_peers, s.tcp_peers) {
+			(0, n) if n > 0 => TransportKind::Tcp.to_string(),
+			(_, 0) => TransportKind::Udp.to_string(),
+			_ => format!("{}+{}", TransportKind::Udp, TransportKind::Tcp),
+		};
+		Self {
+			listen: t.local_addr().map(|a| a.to_string()).unwrap_or_default(),
+			kind,
+			udp_peers: s.udp_peers,
+			tcp_peers: s.tcp_peers,
+			tcp_connections: s.tcp_connections,
+			fallbacks: s.fallbacks,
+			upgrades: s.upgrades,
+		}
+	}
 }
 
 #[derive(Debug, Serialize)]
@@ -85,20 +156,51 @@ async fn main() -> io::Result<()> {
 	}
 	tracing_subscriber::fmt::init();
 
-	let mut node_id = [0u8; 32];
-	rand::thread_rng().fill_bytes(&mut node_id);
+	let node_id = load_node_id()?;
 	let config_path = std::env::var("NYX_CONFIG").ok().map(PathBuf::from);
+	let load_initial = config_path.is_some();
 	let cfg_mgr = ConfigManager::new(NyxConfig::default(), config_path);
+	if load_initial {
+		match cfg_mgr.reload_from_file().await {
+			Ok(res) if !res.success => warn!("config not applied: {} {:?}", res.message, res.validation_errors),
+			Ok(_) => {}
+			Err(e) => warn!("failed to load config: {e}"),
+		}
+	}
 	let events = EventSystem::new(1024);
 	let token = ensure_token_from_env_or_cookie();
-	let state = Arc::new(DaemonState { start_time: Instant::now(), node_id, cfg: cfg_mgr, events, token });
+	let cfg = cfg_mgr.get_config().await;
+	let anonymity = Arc::new(std::sync::Mutex::new(anonymity_estimator(&cfg)));
+	let (mut relay, mut exit, mut transport) = (None, None, None);
+	match cfg.role {
+		NodeRole::Client => {
+			if cfg.mix.is_cmix() {
+				warn!("mix.mode = \"cmix\" only applies to relays; client traffic is batched by the relays it goes through");
+			}
+			if cfg.transport.listen {
+				transport = start_transport(&cfg);
+			}
+		}
+		// The relay batches its own traffic.
+		NodeRole::Relay => {
+			let r = start_relay(&cfg, &events, anonymity.clone()).await.map_err(|e| io::Error::other(format!("relay role not started: {e:#}")))?;
+			relay = Some(Arc::new(r));
+		}
+		NodeRole::Exit => {
+			let x = exit::start(&cfg.exit).await.map_err(|e| io::Error::other(format!("exit role not started: {e:#}")))?;
+			exit = Some(Arc::new(x));
+		}
+	}
+	let state = Arc::new(DaemonState { start_time: Instant::now(), node_id, cfg: cfg_mgr, events, token, anonymity, relay, exit, transport });
+	start_prometheus(state.anonymity.clone()).await;
 
-	info!("starting nyx-daemon (plain IPC) at {}", DEFAULT_ENDPOINT);
+	let endpoint = ipc_endpoint();
+	info!("starting nyx-daemon (plain IPC) at {}", endpoint);
 
 	#[cfg(unix)]
 	{
-		let _ = std::fs::remove_file(DEFAULT_ENDPOINT);
-		let listener = UnixListener::bind(DEFAULT_ENDPOINT)?;
+		let _ = std::fs::remove_file(&endpoint);
+		let listener = UnixListener::bind(&endpoint)?;
 		loop {
 			match listener.accept().await {
 				Ok((stream, _addr)) => {
@@ -118,7 +220,7 @@ async fn main() -> io::Result<()> {
 	{
 		loop {
 			// Create a new pipe instance for each incoming client.
-			let server = match ServerOptions::new().create(DEFAULT_ENDPOINT) {
+			let server = match ServerOptions::new().create(&endpoint) {
 				Ok(s) => s,
 				Err(e) => {
 					error!("failed to create named pipe: {}", e);
@@ -143,6 +245,103 @@ async fn main() -> io::Result<()> {
 	}
 }
 
+/// IPC endpoint; `NYX_DAEMON_ENDPOINT` overrides the default so several
+/// daemons can run on one machine.
+fn ipc_endpoint() -> String {
+	match std::env::var("NYX_DAEMON_ENDPOINT") {
+		Ok(e) if !e.trim().is_empty() => e.trim().to_string(),
+		_ => DEFAULT_ENDPOINT.to_string(),
+	}
+}
+
+/// Start the relay role, publish deliveries as `relay` events and released
+/// cMix batch proofs as `cmix` events (JSON detail).
+async fn start_relay(cfg: &NyxConfig, events: &EventSystem, anonymity: Arc<std::sync::Mutex<AnonymityEstimator>>) -> anyhow::Result<RelayHandle> {
+	let (handle, mut deliveries) = relay::start(&cfg.relay, &cfg.mix, anonymity).await?;
+	let sender = events.sender();
+	tokio::spawn(async move {
+		while let Some(payload) = deliveries.recv().await {
+			let _ = sender.send(Event { ty: "relay".into(), detail: format!("delivered:{}", payload.len()) });
+		}
+	});
+	let (mut batches, sender) = (handle.subscribe_batches(), events.sender());
+	tokio::spawn(async move {
+		loop {
+			match batches.recv().await {
+				Ok(batch) => {
+					let _ = sender.send(Event { ty: "cmix".into(), detail: serde_json::to_string(&batch).unwrap_or_default() });
+				}
+				Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => warn!("cmix: {n} batch proofs not published as events"),
+				Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+			}
+		}
+	});
+	Ok(handle)
+}
+
+/// Bind the peer transport for a client with `[transport] listen = true`: UDP
+/// on `listen_port` (or the recommended range when 0) with TCP fallback.
+fn start_transport(cfg: &NyxConfig) -> Option<Arc<FallbackTransport>> {
+	let ports = if cfg.listen_port == 0 { nyx_transport::PORT_RANGE } else { cfg.listen_port..=cfg.listen_port };
+	let transport = match FallbackTransport::bind(FallbackConfig { udp: UdpConfig { ports, ..Default::default() }, ..Default::default() }) {
+		Ok(t) => Arc::new(t),
+		Err(e) => {
+			error!("transport not started: {e}");
+			return None;
+		}
+	};
+	if let Ok(addr) = transport.local_addr() {
+		info!("transport listening on {addr} (udp, tcp fallback)");
+	}
+	// Nothing consumes inbound cells in the client role yet. They are unauthenticated,
+	// so they are drained without being published as events.
+	let rx = transport.clone();
+	tokio::spawn(async move { while rx.recv().await.is_some() {} });
+	Some(transport)
+}
+
+/// Estimator seeded with the route distribution of the configured directory
+/// and, for relays, the cover rate mixed into their output.
+fn anonymity_estimator(cfg: &NyxConfig) -> AnonymityEstimator {
+	let mut est = AnonymityEstimator::default();
+	match PathBuilder::new(cfg.path.clone()) {
+		Ok(pb) => est.set_route_distribution(&pb.hop_weights(&cfg.directory, &Default::default()), pb.config().min_hops),
+		Err(e) => warn!("routing entropy not estimated: {e}"),
+	}
+	if cfg.role == NodeRole::Relay {
+		est.set_cover_rate(cfg.relay.cover_pps);
+	}
+	est
+}
+
+/// Serve Prometheus metrics when `NYX_PROMETHEUS_ADDR` is set, refreshing the
+/// anonymity gauges once a second whatever the mix mode.
+async fn start_prometheus(anonymity: Arc<std::sync::Mutex<AnonymityEstimator>>) {
+	let Ok(addr) = std::env::var("NYX_PROMETHEUS_ADDR") else { return };
+	let exporter = match PrometheusExporter::install() {
+		Ok(e) => e,
+		Err(e) => {
+			warn!("prometheus disabled: {e}");
+			return;
+		}
+	};
+	match tokio::net::TcpListener::bind(addr.trim()).await {
+		Ok(listener) => {
+			info!("prometheus metrics at http://{}/metrics", addr.trim());
+			tokio::spawn(exporter.serve(listener));
+			tokio::spawn(async move {
+				let mut tick = tokio::time::interval(std::time::Duration::from_secs(1));
+				loop {
+					tick.tick().await;
+					let m = anonymity.lock().unwrap_or_else(|e| e.into_inner()).metrics();
+					prometheus_exporter::record_anonymity(&m);
+				}
+			});
+		}
+		Err(e) => warn!("failed to bind prometheus listener {addr}: {e}"),
+	}
+}
+
 fn ensure_token_from_env_or_cookie() -> Option<String> {
 	// 1) Environment variable takes precedence (non-empty)
 	if let Ok(t) = std::env::var("NYX_DAEMON_TOKEN") {
@@ -188,6 +387,36 @@ fn ensure_token_from_env_or_cookie() -> Option<String> {
 	Some(tok)
 }
 
+/// Load the node identity from the encrypted keystore so the NodeID survives restarts.
+/// Path: `NYX_KEYSTORE` or the per-user default; passphrase: `NYX_KEYSTORE_PASSPHRASE`.
+/// A missing keystore is created; any other failure aborts startup rather than
+/// running under a throwaway identity. An empty passphrase is refused unless
+/// `NYX_KEYSTORE_ALLOW_EMPTY_PASSPHRASE=1` opts into file-permission-only protection.
+fn load_node_id() -> io::Result<[u8; 32]> {
+	let path = match std::env::var("NYX_KEYSTORE") {
+		Ok(p) if !p.trim().is_empty() => PathBuf::from(p),
+		_ => default_keystore_path(),
+	};
+	let passphrase = std::env::var("NYX_KEYSTORE_PASSPHRASE").unwrap_or_default();
+	if passphrase.is_empty() {
+		if std::env::var("NYX_KEYSTORE_ALLOW_EMPTY_PASSPHRASE").as_deref() != Ok("1") {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "NYX_KEYSTORE_PASSPHRASE is empty; set it, or set NYX_KEYSTORE_ALLOW_EMPTY_PASSPHRASE=1 to rely on file permissions only"));
+		}
+		warn!("keystore passphrase is empty; keystore is protected by file permissions only");
+	}
+	open_keystore(&path, &passphrase, KdfParams::default())
+		.map(|ks| ks.node_id())
+		.map_err(|e| io::Error::other(format!("failed to open keystore {}: {e}", path.display())))
+}
+
+fn open_keystore(path: &std::path::Path, passphrase: &str, params: KdfParams) -> nyx_crypto::Result<Keystore> {
+	let ks = Keystore::load_or_create(path, passphrase, params)?;
+	info!("node identity {} loaded from {}", hex::encode(ks.node_id()), path.display());
+	Ok(ks)
+}
+
+fn default_keystore_path() -> PathBuf { default_cookie_path().with_file_name("keystore") }
+
 fn default_cookie_path() -> std::path::PathBuf {
 	#[cfg(windows)]
 	{
@@ -277,6 +506,12 @@ async fn process_request(req_line: &str, state: &DaemonState) -> (Response<serde
 				node_id: hex::encode(state.node_id),
 				version: env!("CARGO_PKG_VERSION").to_string(),
 				uptime_sec: state.start_time.elapsed().as_secs() as u32,
+				mix_mode: if state.relay.as_ref().is_some_and(|r| r.mix_mode() == MixMode::Cmix) { "cmix" } else { "standard" }.to_string(),
+				role: if state.relay.is_some() { NodeRole::Relay } else if state.exit.is_some() { NodeRole::Exit } else { NodeRole::Client },
+				relay: state.relay.as_ref().map(|r| RelayInfo { listen: r.local_addr().to_string(), mix_public_key: hex::encode(r.public_key()), stats: r.stats() }),
+				exit: state.exit.as_ref().map(|x| ExitInfo { listen: x.local_addr().to_string(), policy: x.policy().rules().iter().map(ToString::to_string).collect(), stats: x.stats() }),
+				transport: state.transport.as_deref().map(TransportInfo::from_transport),
+				anonymity: state.anonymity.lock().unwrap_or_else(|e| e.into_inner()).metrics(),
 			};
 			(Response::ok_with_id(id, serde_json::to_value(info).unwrap()), None, None)
 		}
@@ -315,6 +550,16 @@ async fn process_request(req_line: &str, state: &DaemonState) -> (Response<serde
 				Err(e) => (Response::err_with_id(id, 500, e.to_string()), None, None),
 			}
 		}
+		Ok(RpcRequest { id, auth: _, req: Request::GetInclusion { packet_digest } }) => {
+			let Some(relay) = &state.relay else { return (Response::err_with_id(id, 400, "not a relay"), None, None) };
+			let Some(digest) = hex::decode(&packet_digest).ok().and_then(|d| <[u8; 32]>::try_from(d).ok()) else {
+				return (Response::err_with_id(id, 400, "packet_digest must be 32-byte hex"), None, None);
+			};
+			match relay.inclusion(&digest) {
+				Some(proof) => (Response::ok_with_id(id, serde_json::to_value(proof).unwrap()), None, None),
+				None => (Response::err_with_id(id, 404, "no inclusion proof for this packet"), None, None),
+			}
+		}
 		Err(e) => (Response::err_with_id(None, 400, format!("invalid request: {e}")), None, None),
 	}
 }
@@ -343,6 +588,7 @@ fn is_authorized(state: &DaemonState, auth: Option<&str>) -> bool {
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use std::net::SocketAddr;
 
 	fn make_state_with_token(token: Option<&str>) -> DaemonState {
 		let mut node_id = [0u8; 32];
@@ -355,6 +601,10 @@ mod tests {
 			cfg: cfg_mgr,
 			events,
 			token: token.map(|s| s.to_string()),
+			relay: None,
+			exit: None,
+			transport: None,
+			anonymity: Arc::new(std::sync::Mutex::new(AnonymityEstimator::default())),
 		}
 	}
 
@@ -371,6 +621,47 @@ mod tests {
 		assert_eq!(resp.id.as_deref(), Some("abc"));
 		assert!(rx.is_none());
 		assert!(filter.is_none());
+		let data = resp.data.unwrap();
+		assert_eq!(data["mix_mode"], "standard");
+		assert_eq!(data["role"], "client");
+		assert!(data.get("relay").is_none());
+		assert!(data.get("exit").is_none());
+		assert!(data.get("transport").is_none());
+		assert_eq!(data["anonymity"]["rounds"], 0);
+	}
+
+	#[test]
+	fn anonymity_estimate_uses_directory_and_cover_rate() {
+		use nyx_daemon::path_builder::NodeInfo;
+		let node = |i: u8| NodeInfo { node_id: [i; 32], public_key: [0; 32], operator: None, asn: None, country: None, bandwidth_mbps: 10.0, latency_ms: 0.0 };
+		let mut cfg = NyxConfig { directory: (0..16).map(node).collect(), ..Default::default() };
+		let m = anonymity_estimator(&cfg).metrics();
+		// Uniform over 16 nodes, 3 hops by default.
+		assert!((m.routing_shannon_bits - 12.0).abs() < 1e-9);
+		assert!((m.routing_min_entropy_bits - 12.0).abs() < 1e-9);
+
+		cfg.role = NodeRole::Relay;
+		cfg.relay.cover_pps = 32.0;
+		let mut est = anonymity_estimator(&cfg);
+		est.record_poisson(0.0, std::time::Duration::from_secs(1));
+		assert!((est.metrics().mixing_shannon_bits - 5.0).abs() < 1e-9);
+	}
+
+	#[tokio::test]
+	async fn get_info_reports_transport() {
+		let mut state = make_state_with_token(None);
+		let peer = FallbackTransport::bind(FallbackConfig { udp: UdpConfig { ports: 0..=0, ..Default::default() }, ..Default::default() }).unwrap();
+		state.transport = start_transport(&NyxConfig::default());
+		let transport = state.transport.clone().unwrap();
+		let req = serde_json::json!({ "op": "get_info" }).to_string();
+		let data = process_request(&req, &state).await.0.data.unwrap()["transport"].clone();
+		let port: u16 = data["listen"].as_str().unwrap().rsplit(':').next().unwrap().parse().unwrap();
+		assert!(nyx_transport::PORT_RANGE.contains(&port));
+		assert_eq!(data["kind"], "udp");
+		let dst = SocketAddr::from(([127, 0, 0, 1], peer.local_addr().unwrap().port()));
+		assert_eq!(transport.send(dst, &[0u8; 1280]).await.unwrap(), TransportKind::Udp);
+		let data = process_request(&req, &state).await.0.data.unwrap();
+		assert_eq!(data["transport"]["udp_peers"], 1);
 	}
 
 	#[tokio::test]
@@ -453,6 +744,31 @@ mod tests {
 		assert!(ver >= 1);
 	}
 
+	#[test]
+	fn keystore_node_id_is_stable_across_restarts() {
+		let path = std::env::temp_dir().join(format!("nyx-daemon-ks-{}", std::process::id())).join("keystore");
+		let fast = KdfParams { m_cost_kib: 8, t_cost: 1, p_cost: 1 };
+		let first = open_keystore(&path, "pw", fast).unwrap().node_id();
+		let second = open_keystore(&path, "pw", fast).unwrap().node_id();
+		assert_eq!(first, second);
+		assert!(open_keystore(&path, "other", fast).is_err());
+		let _ = std::fs::remove_dir_all(path.parent().unwrap());
+	}
+
+	#[tokio::test]
+	async fn get_inclusion_needs_a_relay_and_a_digest() {
+		let mut state = make_state_with_token(None);
+		let req = serde_json::json!({ "op": "get_inclusion", "packet_digest": hex::encode([0u8; 32]) }).to_string();
+		assert_eq!(process_request(&req, &state).await.0.code, 400);
+
+		let cfg = relay::RelayConfig { listen: "127.0.0.1:0".into(), cover_pps: 0.0, ..Default::default() };
+		let (handle, _) = relay::start(&cfg, &Default::default(), state.anonymity.clone()).await.unwrap();
+		state.relay = Some(Arc::new(handle));
+		assert_eq!(process_request(&req, &state).await.0.code, 404);
+		let bad = serde_json::json!({ "op": "get_inclusion", "packet_digest": "00" }).to_string();
+		assert_eq!(process_request(&bad, &state).await.0.code, 400);
+	}
+
 	#[tokio::test]
 	async fn invalid_request_returns_400() {
 		let state = make_state_with_token(None);
@@ -466,7 +782,7 @@ mod tests {
 	#[tokio::test]
 	async fn empty_env_token_is_treated_as_disabled() {
 		// Simulate daemon started with empty token ("   ") which should disable auth
-		let mut state = make_state_with_token(Some("   "));
+		let state = make_state_with_token(Some("   "));
 		// ensure internal state reflects token provided
 		// but authorization treats it as None
 		let req = serde_json::json!({
@@ -1 +1,244 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Weighted mix path selection over a node directory.
+//!
+//! Hop count is drawn uniformly from `min_hops..=max_hops` (spec: 3..=7), then
+//! hops are sampled without replacement with probability proportional to
+//! `bandwidth × latency_ref / (latency_ref + latency)`. After each pick every
+//! node sharing its operator, ASN or country is removed from the candidate
+//! set, and blacklisted nodes are never eligible. Unknown operator/ASN/country
+//! values do not conflict with anything.
+//!
+//! Each path reports its selection entropy: the sum over hops of the Shannon
+//! entropy of the weighted distribution that hop was drawn from.
+//!
+//! Directory values come from the network, so a node only becomes a
+//! candidate if its weight is finite and positive; bandwidth is clamped to
+//! [`MAX_BANDWIDTH_MBPS`] so that sums of weights cannot overflow either.
+
+use std::collections::HashSet;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub type NodeId = [u8; 32];
+
+/// Advertised bandwidth above this is treated as this.
+pub const MAX_BANDWIDTH_MBPS: f64 = 1e9;
+
+/// Directory entry for a mix node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeInfo {
+	pub node_id: NodeId,
+	/// X25519 key used for Sphinx packets.
+	#[serde(default)]
+	pub public_key: [u8; 32],
+	#[serde(default)]
+	pub operator: Option<String>,
+	#[serde(default)]
+	pub asn: Option<u32>,
+	/// ISO 3166-1 alpha-2 code.
+	#[serde(default)]
+	pub country: Option<String>,
+	pub bandwidth_mbps: f64,
+	pub latency_ms: f64,
+}
+
+impl NodeInfo {
+	fn conflicts_with(&self, other: &NodeInfo) -> bool {
+		fn same<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> bool { matches!((a, b), (Some(x), Some(y)) if x == y) }
+		self.node_id == other.node_id
+			|| same(&self.operator, &other.operator)
+			|| same(&self.asn, &other.asn)
+			|| same(&self.country.as_ref().map(|c| c.to_ascii_uppercase()), &other.country.as_ref().map(|c| c.to_ascii_uppercase()))
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathBuilderConfig {
+	pub min_hops: usize,
+	pub max_hops: usize,
+	/// Latency at which a node's weight is halved.
+	pub latency_ref_ms: f64,
+	/// Random restarts before giving up when greedy picks dead-end.
+	pub max_attempts: usize,
+}
+
+impl Default for PathBuilderConfig {
+	fn default() -> Self { Self { min_hops: 3, max_hops: 7, latency_ref_ms: 100.0, max_attempts: 16 } }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PathBuilderError {
+	#[error("invalid config: {0}")]
+	InvalidConfig(String),
+	#[error("cannot build a diverse {hops}-hop path from {eligible} eligible nodes")]
+	InsufficientDiversity { hops: usize, eligible: usize },
+}
+
+/// A selected route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+	pub hops: Vec<NodeInfo>,
+	/// Shannon entropy of the selection, in bits.
+	pub entropy_bits: f64,
+	/// Entropy had every hop been chosen uniformly from the same candidates.
+	pub max_entropy_bits: f64,
+}
+
+impl Path {
+	pub fn len(&self) -> usize { self.hops.len() }
+	pub fn is_empty(&self) -> bool { self.hops.is_empty() }
+	pub fn node_ids(&self) -> Vec<NodeId> { self.hops.iter().map(|h| h.node_id).collect() }
+
+	/// `entropy_bits / max_entropy_bits`, 1.0 when selection was uniform.
+	pub fn normalized_entropy(&self) -> f64 {
+		if self.max_entropy_bits > 0.0 { self.entropy_bits / self.max_entropy_bits } else { 0.0 }
+	}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PathBuilder {
+	config: PathBuilderConfig,
+}
+
+impl PathBuilder {
+	pub fn new(config: PathBuilderConfig) -> Result<Self, PathBuilderError> {
+		if config.min_hops == 0 || config.min_hops > config.max_hops {
+			return Err(PathBuilderError::InvalidConfig(format!("hop range {}..={}", config.min_hops, config.max_hops)));
+		}
+		if !(config.latency_ref_ms.is_finite() && config.latency_ref_ms > 0.0) {
+			return Err(PathBuilderError::InvalidConfig("latency_ref_ms must be positive".into()));
+		}
+		Ok(Self { config })
+	}
+
+	pub fn config(&self) -> &PathBuilderConfig { &self.config }
+
+	/// Selection weight of a node; zero for unusable entries, otherwise finite
+	/// and positive.
+	pub fn weight(&self, node: &NodeInfo) -> f64 {
+		let usable = node.bandwidth_mbps.is_finite() && node.bandwidth_mbps > 0.0 && node.latency_ms.is_finite() && node.latency_ms >= 0.0;
+		if !usable {
+			return 0.0;
+		}
+		let latency_ref = self.config.latency_ref_ms;
+		let w = node.bandwidth_mbps.min(MAX_BANDWIDTH_MBPS) * (latency_ref / (latency_ref + node.latency_ms));
+		if w.is_finite() && w > 0.0 { w } else { 0.0 }
+	}
+
+	/// Weights of the nodes eligible for the first hop, i.e. the distribution
+	/// every path starts from.
+	pub fn hop_weights(&self, directory: &[NodeInfo], blacklist: &HashSet<NodeId>) -> Vec<f64> {
+		directory.iter().filter(|n| !blacklist.contains(&n.node_id)).map(|n| self.weight(n)).filter(|w| *w > 0.0).collect()
+	}
+
+	/// Build a path with a random hop count in the configured range.
+	pub fn build<R: Rng + ?Sized>(&self, directory: &[NodeInfo], blacklist: &HashSet<NodeId>, rng: &mut R) -> Result<Path, PathBuilderError> {
+		let hops = rng.gen_range(self.config.min_hops..=self.config.max_hops);
+		self.build_with_hops(directory, blacklist, hops, rng)
+	}
+
+	pub fn build_with_hops<R: Rng + ?Sized>(&self, directory: &[NodeInfo], blacklist: &HashSet<NodeId>, hops: usize, rng: &mut R) -> Result<Path, PathBuilderError> {
+		if !(self.config.min_hops..=self.config.max_hops).contains(&hops) {
+			return Err(PathBuilderError::InvalidConfig(format!("{hops} hops outside {}..={}", self.config.min_hops, self.config.max_hops)));
+		}
+		let eligible: Vec<(&NodeInfo, f64)> =
+			directory.iter().filter(|n| !blacklist.contains(&n.node_id)).map(|n| (n, self.weight(n))).filter(|(_, w)| *w > 0.0).collect();
+		if eligible.len() < hops {
+			return Err(PathBuilderError::InsufficientDiversity { hops, eligible: eligible.len() });
+		}
+		for _ in 0..self.config.max_attempts.max(1) {
+			if let Some(path) = Self::attempt(&eligible, hops, rng) {
+				return Ok(path);
+			}
+		}
+		Err(PathBuilderError::InsufficientDiversity { hops, eligible: eligible.len() })
+	}
+
+	fn attempt<R: Rng + ?Sized>(eligible: &[(&NodeInfo, f64)], hops: usize, rng: &mut R) -> Option<Path> {
+		let mut candidates: Vec<(&NodeInfo, f64)> = eligible.to_vec();
+		let mut path = Path { hops: Vec::with_capacity(hops), entropy_bits: 0.0, max_entropy_bits: 0.0 };
+		while path.hops.len() < hops {
+			let total: f64 = candidates.iter().map(|(_, w)| w).sum();
+			if candidates.is_empty() || total <= 0.0 {
+				return None;
+			}
+			path.entropy_bits += candidates.iter().map(|(_, w)| w / total).filter(|p| *p > 0.0).map(|p| -p * p.log2()).sum::<f64>();
+			path.max_entropy_bits += (candidates.len() as f64).log2();
+
+			let mut x = rng.gen::<f64>() * total;
+			let idx = candidates.iter().position(|(_, w)| { x -= w; x < 0.0 }).unwrap_or(candidates.len() - 1);
+			let picked = candidates[idx].0.clone();
+			candidates.retain(|(n, _)| !n.conflicts_with(&picked));
+			path.hops.push(picked);
+		}
+		Some(path)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::{rngs::StdRng, SeedableRng};
+
+	fn node(i: u8, op: &str, asn: u32, cc: &str, bw: f64, lat: f64) -> NodeInfo {
+		NodeInfo { node_id: [i; 32], public_key: [0; 32], operator: Some(op.into()), asn: Some(asn), country: Some(cc.into()), bandwidth_mbps: bw, latency_ms: lat }
+	}
+
+	#[test]
+	fn weight_prefers_bandwidth_and_low_latency() {
+		let pb = PathBuilder::default();
+		let fast = node(1, "a", 1, "DE", 100.0, 10.0);
+		let slow = node(2, "b", 2, "FR", 100.0, 300.0);
+		let thin = node(3, "c", 3, "NL", 10.0, 10.0);
+		assert!(pb.weight(&fast) > pb.weight(&slow));
+		assert!(pb.weight(&fast) > pb.weight(&thin));
+		assert_eq!(pb.weight(&node(4, "d", 4, "US", f64::NAN, 1.0)), 0.0);
+	}
+
+	#[test]
+	fn hostile_directory_values_keep_weights_and_entropy_finite() {
+		let pb = PathBuilder::default();
+		for (bw, lat) in [(f64::INFINITY, 1.0), (f64::NAN, 1.0), (-5.0, 1.0), (10.0, f64::NAN), (10.0, -1.0), (10.0, f64::INFINITY)] {
+			assert_eq!(pb.weight(&node(1, "a", 1, "DE", bw, lat)), 0.0, "bw={bw} lat={lat}");
+		}
+		assert_eq!(pb.weight(&node(1, "a", 1, "DE", f64::MAX, 0.0)), MAX_BANDWIDTH_MBPS);
+
+		let mut dir: Vec<NodeInfo> = (0..6).map(|i| node(i, &format!("op{i}"), i as u32, &format!("C{i}"), f64::MAX, 0.0)).collect();
+		dir.push(node(6, "op6", 6, "C6", f64::MIN_POSITIVE, 1e300));
+		dir.push(node(7, "op7", 7, "C7", f64::INFINITY, 0.0));
+		let p = pb.build_with_hops(&dir, &HashSet::new(), 5, &mut StdRng::seed_from_u64(3)).unwrap();
+		assert!(p.entropy_bits.is_finite() && p.max_entropy_bits.is_finite());
+		assert!(p.normalized_entropy().is_finite());
+		assert!(p.hops.iter().all(|h| h.node_id != [7; 32]));
+	}
+
+	#[test]
+	fn uniform_weights_give_maximal_entropy() {
+		let dir: Vec<NodeInfo> = (0..8).map(|i| node(i, &format!("op{i}"), i as u32, &format!("C{i}"), 50.0, 20.0)).collect();
+		let pb = PathBuilder::default();
+		let p = pb.build_with_hops(&dir, &HashSet::new(), 3, &mut StdRng::seed_from_u64(1)).unwrap();
+		let expected = 8f64.log2() + 7f64.log2() + 6f64.log2();
+		assert!((p.entropy_bits - expected).abs() < 1e-9);
+		assert!((p.normalized_entropy() - 1.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn hop_weights_skip_blacklisted_and_unusable_nodes() {
+		let dir = vec![node(1, "a", 1, "DE", 100.0, 0.0), node(2, "b", 2, "FR", 0.0, 10.0), node(3, "c", 3, "NL", 50.0, 0.0)];
+		let pb = PathBuilder::default();
+		assert_eq!(pb.hop_weights(&dir, &HashSet::new()), vec![100.0, 50.0]);
+		assert_eq!(pb.hop_weights(&dir, &HashSet::from([[1; 32]])), vec![50.0]);
+	}
+
+	#[test]
+	fn invalid_configs_are_rejected() {
+		assert!(PathBuilder::new(PathBuilderConfig { min_hops: 5, max_hops: 3, ..Default::default() }).is_err());
+		assert!(PathBuilder::new(PathBuilderConfig { latency_ref_ms: 0.0, ..Default::default() }).is_err());
+		let pb = PathBuilder::default();
+		assert!(pb.build_with_hops(&[], &HashSet::new(), 8, &mut StdRng::seed_from_u64(0)).is_err());
+	}
+}
@@ -1 +1,91 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Prometheus text exposition for metrics recorded through the `metrics` crate.
+//!
+//! The daemon installs the recorder once at startup and serves `GET /metrics`
+//! over plain HTTP/1.1 when `NYX_PROMETHEUS_ADDR` is set.
+
+use anyhow::{Context, Result};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use nyx_mix::anonymity::AnonymityMetrics;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::debug;
+
+#[derive(Clone)]
+pub struct PrometheusExporter {
+    handle: PrometheusHandle,
+}
+
+impl PrometheusExporter {
+    /// Install the global recorder. Fails if another recorder is installed.
+    pub fn install() -> Result<Self> {
+        let handle = PrometheusBuilder::new().install_recorder().context("installing prometheus recorder")?;
+        Ok(Self { handle })
+    }
+
+    /// Exporter over an existing handle (e.g. a locally scoped recorder).
+    pub fn from_handle(handle: PrometheusHandle) -> Self { Self { handle } }
+
+    pub fn render(&self) -> String { self.handle.render() }
+
+    /// Serve scrapes until the listener fails.
+    pub async fn serve(self, listener: TcpListener) -> std::io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let this = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = this.respond(stream).await {
+                    debug!("metrics scrape from {peer} failed: {e}");
+                }
+            });
+        }
+    }
+
+    async fn respond(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut buf = Vec::with_capacity(512);
+        let mut tmp = [0u8; 512];
+        while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < 8 * 1024 {
+            let n = stream.read(&mut tmp).await?;
+            if n == 0 { break; }
+            buf.extend_from_slice(&tmp[..n]);
+        }
+        let (status, body) = if buf.starts_with(b"GET /metrics ") { ("200 OK", self.render()) } else { ("404 Not Found", String::new()) };
+        let head = format!("HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+/// Publish anonymity gauges; alert on `nyx_anonymity_degraded == 1`.
+pub fn record_anonymity(m: &AnonymityMetrics) {
+    metrics::gauge!("nyx_anonymity_shannon_bits").set(m.shannon_bits);
+    metrics::gauge!("nyx_anonymity_min_entropy_bits").set(m.min_entropy_bits);
+    metrics::gauge!("nyx_anonymity_set_size").set(m.anonymity_set_size);
+    metrics::gauge!("nyx_anonymity_degraded").set(if m.degraded { 1.0 } else { 0.0 });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn scrape_returns_anonymity_gauges() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let exporter = PrometheusExporter::from_handle(recorder.handle());
+        let m = AnonymityMetrics { shannon_bits: 6.0, min_entropy_bits: 2.0, anonymity_set_size: 64.0, degraded: true, ..Default::default() };
+        metrics::with_local_recorder(&recorder, || record_anonymity(&m));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(exporter.serve(listener));
+        let mut s = TcpStream::connect(addr).await.unwrap();
+        s.write_all(b"GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n").await.unwrap();
+        let mut resp = String::new();
+        s.read_to_string(&mut resp).await.unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK"));
+        assert!(resp.contains("nyx_anonymity_set_size 64"));
+        assert!(resp.contains("nyx_anonymity_degraded 1"));
+    }
+}
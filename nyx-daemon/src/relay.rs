@@ -0,0 +1,635 @@
+﻿#![forbid(unsafe_code)]
+
+//! Mix relay role.
+//!
+//! A relay receives 1280-byte Sphinx packets over UDP, peels its layer and
+//! forwards the result to the next hop after mixing:
+//!
+//! - `[mix] mode = "standard"`: each packet is held for an exponentially
+//!   distributed delay (mean `mean_delay_ms`), so departures form a Poisson
+//!   process independent of arrival order.
+//! - `[mix] mode = "cmix"`: packets go through the cMix batcher; local padding
+//!   dummies are sent to random peers as cover. Each released batch's proof
+//!   is published ([`RelayHandle::subscribe_batches`]) and every forwarded
+//!   packet gets an [`InclusionProof`] its sender can look up by the packet's
+//!   BLAKE3 digest ([`RelayHandle::inclusion`]).
+//!
+//! Packets addressed to this node are handed out on the delivery channel.
+//! Every peer IP has a token bucket, whatever source port it sends from;
+//! replayed packets are caught by the Sphinx replay-tag cache. Cover packets,
+//! Sphinx-shaped dummies, are sent to random peers at `cover_pps`.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use nyx_core::cmix::{MixConfig, MixMode};
+use nyx_mix::anonymity::AnonymityEstimator;
+use nyx_mix::accumulator::{self, Witness};
+use nyx_mix::cmix::{Batch, BatchProof, CmixBatcher, DelayOutput};
+use nyx_mix::cover::CoverGenerator;
+use nyx_mix::vdf::{Vdf, WesolowskiDelay};
+use nyx_mix::sphinx::{process_at_hop, NodeId, NodeKey, ProcessResult, ReplayCache, SphinxPacket, PACKET_LEN};
+use nyx_mix::Error as MixError;
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, mpsc, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// A neighbouring relay: hex node id and UDP address.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelayPeer {
+    pub node_id: String,
+    pub addr: String,
+}
+
+/// `[relay]` section, used when `role = "relay"`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelayConfig {
+    /// UDP address for mix packets.
+    pub listen: String,
+    /// Hex-encoded X25519 Sphinx secret; a fresh key is generated when absent.
+    pub mix_key: Option<String>,
+    pub peers: Vec<RelayPeer>,
+    /// Mean per-packet delay in standard mode.
+    pub mean_delay_ms: u64,
+    /// Cover packets per second to random peers; 0 disables cover.
+    pub cover_pps: f64,
+    /// Sustained packets per second accepted from one peer IP.
+    pub peer_rate_pps: f64,
+    pub peer_burst: f64,
+    /// Replay tags remembered before the oldest are forgotten.
+    pub replay_cache_size: usize,
+    /// Packets held in delay or batch stages at once; excess is dropped.
+    pub max_in_flight: usize,
+    pub delivery_queue: usize,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            listen: "0.0.0.0:43300".into(),
+            mix_key: None,
+            peers: Vec::new(),
+            mean_delay_ms: 50,
+            cover_pps: 10.0,
+            peer_rate_pps: 500.0,
+            peer_burst: 1000.0,
+            replay_cache_size: 1 << 20,
+            max_in_flight: 4096,
+            delivery_queue: 1024,
+        }
+    }
+}
+
+impl RelayConfig {
+    pub fn validate(&self) -> Vec<String> {
+        let mut errs = Vec::new();
+        if self.listen.parse::<SocketAddr>().is_err() {
+            errs.push(format!("relay.listen is not a socket address: {}", self.listen));
+        }
+        if let Some(k) = &self.mix_key {
+            if !hex::decode(k).map(|b| b.len() == 32).unwrap_or(false) {
+                errs.push("relay.mix_key must be 32-byte hex".into());
+            }
+        }
+        for p in &self.peers {
+            if parse_node_id(&p.node_id).is_none() || p.addr.parse::<SocketAddr>().is_err() {
+                errs.push(format!("invalid relay peer {} @ {}", p.node_id, p.addr));
+            }
+        }
+        if !(self.cover_pps.is_finite() && self.cover_pps >= 0.0) {
+            errs.push("relay.cover_pps must be non-negative".into());
+        }
+        if !(self.peer_rate_pps > 0.0 && self.peer_burst >= 1.0) {
+            errs.push("relay.peer_rate_pps must be positive and relay.peer_burst at least 1".into());
+        }
+        if self.replay_cache_size == 0 || self.max_in_flight == 0 || self.delivery_queue == 0 {
+            errs.push("relay queue and cache sizes must be positive".into());
+        }
+        errs
+    }
+
+    /// The configured Sphinx key, or a fresh one.
+    pub fn node_key(&self) -> Result<NodeKey> {
+        match &self.mix_key {
+            Some(k) => {
+                let bytes: [u8; 32] = hex::decode(k).ok().and_then(|b| b.try_into().ok()).ok_or_else(|| anyhow!("relay.mix_key must be 32-byte hex"))?;
+                Ok(NodeKey::from_bytes(bytes))
+            }
+            None => Ok(NodeKey::generate(&mut rand::rngs::OsRng)),
+        }
+    }
+}
+
+fn parse_node_id(s: &str) -> Option<NodeId> { hex::decode(s).ok()?.try_into().ok() }
+
+/// Per-peer token buckets, keyed by IP so that rotating source ports does
+/// not buy a fresh burst.
+///
+/// At most `max_peers` IPs get their own bucket. While the map is full,
+/// unknown IPs share one overflow bucket, so a flood from spoofed sources
+/// neither grows the map nor pushes out peers already tracked.
+#[derive(Debug)]
+pub struct PeerRateLimiter {
+    rate: f64,
+    burst: f64,
+    max_peers: usize,
+    buckets: HashMap<IpAddr, (f64, Instant)>,
+    overflow: Option<(f64, Instant)>,
+    last_sweep: Option<Instant>,
+}
+
+impl PeerRateLimiter {
+    pub fn new(rate: f64, burst: f64) -> Self { Self::with_max_peers(rate, burst, 4096) }
+
+    pub fn with_max_peers(rate: f64, burst: f64, max_peers: usize) -> Self {
+        Self { rate, burst, max_peers: max_peers.max(1), buckets: HashMap::new(), overflow: None, last_sweep: None }
+    }
+
+    /// IPs with a bucket of their own.
+    pub fn tracked(&self) -> usize { self.buckets.len() }
+
+    /// Take one token for `peer`; false when its bucket is empty.
+    pub fn allow(&mut self, peer: IpAddr, now: Instant) -> bool {
+        if self.buckets.len() >= self.max_peers && !self.buckets.contains_key(&peer) {
+            self.sweep(now);
+            if self.buckets.len() >= self.max_peers {
+                let (rate, burst) = (self.rate, self.burst);
+                return take(self.overflow.get_or_insert((burst, now)), rate, burst, now);
+            }
+        }
+        let bucket = self.buckets.entry(peer).or_insert((self.burst, now));
+        take(bucket, self.rate, self.burst, now)
+    }
+
+    /// Forget peers whose buckets have refilled; they lose nothing. The scan
+    /// is O(n), so it runs at most once per full refill time.
+    fn sweep(&mut self, now: Instant) {
+        let refill = Duration::from_secs_f64((self.burst / self.rate).min(3600.0));
+        if self.last_sweep.is_some_and(|t| now.saturating_duration_since(t) < refill) {
+            return;
+        }
+        self.last_sweep = Some(now);
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.retain(|_, (tokens, last)| *tokens + now.saturating_duration_since(*last).as_secs_f64() * rate < burst);
+    }
+}
+
+fn take((tokens, last): &mut (f64, Instant), rate: f64, burst: f64, now: Instant) -> bool {
+    *tokens = (*tokens + now.saturating_duration_since(*last).as_secs_f64() * rate).min(burst);
+    *last = now;
+    if *tokens >= 1.0 {
+        *tokens -= 1.0;
+        true
+    } else {
+        false
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    received: AtomicU64,
+    /// Packets that entered the delay or batch stage.
+    mixed: AtomicU64,
+    forwarded: AtomicU64,
+    delivered: AtomicU64,
+    cover_sent: AtomicU64,
+    rate_limited: AtomicU64,
+    replayed: AtomicU64,
+    invalid: AtomicU64,
+    unroutable: AtomicU64,
+    overloaded: AtomicU64,
+}
+
+fn bump(c: &AtomicU64) { c.fetch_add(1, Ordering::Relaxed); }
+
+/// Packet counters since the relay started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RelayStats {
+    pub received: u64,
+    pub mixed: u64,
+    pub forwarded: u64,
+    pub delivered: u64,
+    pub cover_sent: u64,
+    pub rate_limited: u64,
+    pub replayed: u64,
+    pub invalid: u64,
+    pub unroutable: u64,
+    pub overloaded: u64,
+}
+
+/// Batches whose proofs and witnesses stay available for lookup.
+const RETAINED_BATCHES: usize = 64;
+
+/// A released cMix batch's [`BatchProof`], hex-encoded for publication.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublishedBatch {
+    /// Batch number since the relay started.
+    pub seq: u64,
+    pub input_commitment: String,
+    pub nonce_commitment: String,
+    pub accumulator: String,
+    pub iterations: u64,
+    pub delay_output: String,
+    pub delay_proof: String,
+}
+
+impl PublishedBatch {
+    fn new(seq: u64, proof: &BatchProof) -> Self {
+        Self {
+            seq,
+            input_commitment: hex::encode(proof.input_commitment),
+            nonce_commitment: hex::encode(proof.nonce_commitment),
+            accumulator: hex::encode(&proof.accumulator),
+            iterations: proof.delay.iterations,
+            delay_output: hex::encode(&proof.delay.output),
+            delay_proof: hex::encode(&proof.delay.proof),
+        }
+    }
+
+    /// Decode the proof; `None` if a field is not valid hex of the right length.
+    pub fn proof(&self) -> Option<BatchProof> {
+        Some(BatchProof {
+            input_commitment: hex::decode(&self.input_commitment).ok()?.try_into().ok()?,
+            nonce_commitment: hex::decode(&self.nonce_commitment).ok()?.try_into().ok()?,
+            accumulator: hex::decode(&self.accumulator).ok()?,
+            delay: DelayOutput { iterations: self.iterations, output: hex::decode(&self.delay_output).ok()?, proof: hex::decode(&self.delay_proof).ok()? },
+        })
+    }
+}
+
+/// Proof that a forwarded packet was one of a batch's inputs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub batch: PublishedBatch,
+    /// Hex-encoded accumulator membership witness.
+    pub witness: String,
+}
+
+impl InclusionProof {
+    /// Check, from the sender's side, that `packet` (as this relay received it
+    /// after peeling, see `SphinxBuilder::build_layers`) was in a batch whose
+    /// release waited for the VDF.
+    pub fn verify(&self, packet: &[u8]) -> bool {
+        let Some(proof) = self.batch.proof() else { return false };
+        let Some(witness) = hex::decode(&self.witness).ok().and_then(|w| Witness::from_bytes(&w, &accumulator::default_modulus()).ok()) else { return false };
+        // T comes from the proof; the verifier's own iteration count is unused.
+        proof.verify_delay(&WesolowskiDelay::new(Vdf::rsa2048(), 1)) && proof.verify_inclusion(packet, &witness)
+    }
+}
+
+/// Recently released batches and the witnesses of their forwarded packets.
+#[derive(Debug, Default)]
+struct BatchLog {
+    next_seq: u64,
+    batches: VecDeque<PublishedBatch>,
+    witnesses: HashMap<[u8; 32], (u64, String)>,
+}
+
+impl BatchLog {
+    fn push(&mut self, proof: &BatchProof, witnesses: Vec<([u8; 32], String)>) -> PublishedBatch {
+        let published = PublishedBatch::new(self.next_seq, proof);
+        self.next_seq += 1;
+        if self.batches.len() == RETAINED_BATCHES {
+            if let Some(old) = self.batches.pop_front() {
+                self.witnesses.retain(|_, (seq, _)| *seq != old.seq);
+            }
+        }
+        self.batches.push_back(published.clone());
+        self.witnesses.extend(witnesses.into_iter().map(|(digest, w)| (digest, (published.seq, w))));
+        published
+    }
+
+    fn inclusion(&self, digest: &[u8; 32]) -> Option<InclusionProof> {
+        let (seq, witness) = self.witnesses.get(digest)?;
+        let batch = self.batches.iter().find(|b| b.seq == *seq)?.clone();
+        Some(InclusionProof { batch, witness: witness.clone() })
+    }
+}
+
+type Routes = Arc<RwLock<HashMap<NodeId, SocketAddr>>>;
+
+enum Stage {
+    Delay { permits: Arc<Semaphore>, mean: Duration },
+    Batch { tx: mpsc::Sender<Vec<u8>>, next_hops: Arc<Mutex<HashMap<[u8; 32], SocketAddr>>> },
+}
+
+/// A running relay; its tasks stop when the handle is dropped.
+pub struct RelayHandle {
+    local_addr: SocketAddr,
+    public_key: [u8; 32],
+    mode: MixMode,
+    routes: Routes,
+    counters: Arc<Counters>,
+    batch_log: Arc<Mutex<BatchLog>>,
+    batches: broadcast::Sender<PublishedBatch>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl RelayHandle {
+    pub fn local_addr(&self) -> SocketAddr { self.local_addr }
+
+    /// Sphinx public key to publish for this hop.
+    pub fn public_key(&self) -> [u8; 32] { self.public_key }
+
+    pub fn mix_mode(&self) -> MixMode { self.mode }
+
+    /// Proofs of cMix batches as they are released.
+    pub fn subscribe_batches(&self) -> broadcast::Receiver<PublishedBatch> { self.batches.subscribe() }
+
+    /// Inclusion proof for a packet this relay batched and forwarded, by the
+    /// BLAKE3 digest of the packet; only the last few batches are kept.
+    pub fn inclusion(&self, digest: &[u8; 32]) -> Option<InclusionProof> { self.batch_log.lock().unwrap_or_else(|e| e.into_inner()).inclusion(digest) }
+
+    pub fn add_route(&self, node_id: NodeId, addr: SocketAddr) { self.routes.write().unwrap_or_else(|e| e.into_inner()).insert(node_id, addr); }
+
+    pub fn stats(&self) -> RelayStats {
+        let c = &self.counters;
+        let get = |a: &AtomicU64| a.load(Ordering::Relaxed);
+        RelayStats {
+            received: get(&c.received),
+            mixed: get(&c.mixed),
+            forwarded: get(&c.forwarded),
+            delivered: get(&c.delivered),
+            cover_sent: get(&c.cover_sent),
+            rate_limited: get(&c.rate_limited),
+            replayed: get(&c.replayed),
+            invalid: get(&c.invalid),
+            unroutable: get(&c.unroutable),
+            overloaded: get(&c.overloaded),
+        }
+    }
+}
+
+impl Drop for RelayHandle {
+    fn drop(&mut self) {
+        for t in &self.tasks {
+            t.abort();
+        }
+    }
+}
+
+/// Bind and start a relay. Returns the handle and the channel of payloads
+/// delivered to this node. Mixing feeds `anonymity` with one round per batch
+/// (cMix) or per second (standard).
+pub async fn start(cfg: &RelayConfig, mix: &MixConfig, anonymity: Arc<Mutex<AnonymityEstimator>>) -> Result<(RelayHandle, mpsc::Receiver<Vec<u8>>)> {
+    let errs = cfg.validate();
+    if !errs.is_empty() {
+        return Err(anyhow!("invalid relay config: {}", errs.join("; ")));
+    }
+    let key = cfg.node_key()?;
+    let public_key = key.public_key();
+    let socket = Arc::new(UdpSocket::bind(&cfg.listen).await.with_context(|| format!("binding relay socket {}", cfg.listen))?);
+    let local_addr = socket.local_addr()?;
+    let routes: Routes = Arc::new(RwLock::new(
+        cfg.peers.iter().filter_map(|p| Some((parse_node_id(&p.node_id)?, p.addr.parse().ok()?))).collect(),
+    ));
+    let counters = Arc::new(Counters::default());
+    let (deliver_tx, deliver_rx) = mpsc::channel(cfg.delivery_queue);
+    let batch_log = Arc::new(Mutex::new(BatchLog::default()));
+    let (batches, _) = broadcast::channel(16);
+    let mut tasks = Vec::new();
+
+    let stage = match mix.mode {
+        MixMode::Standard => {
+            let mean = Duration::from_millis(cfg.mean_delay_ms);
+            tasks.push(tokio::spawn(poisson_rounds(counters.clone(), mean, anonymity)));
+            Stage::Delay { permits: Arc::new(Semaphore::new(cfg.max_in_flight)), mean }
+        }
+        MixMode::Cmix => {
+            let batcher = CmixBatcher::from_config(mix).map_err(|e| anyhow!("cmix: {e}"))?;
+            let (in_tx, in_rx) = mpsc::channel(cfg.max_in_flight);
+            let (out_tx, out_rx) = mpsc::channel(4);
+            let next_hops = Arc::new(Mutex::new(HashMap::new()));
+            tasks.push(tokio::spawn(batcher.run(in_rx, out_tx)));
+            tasks.push(tokio::spawn(release_batches(out_rx, socket.clone(), next_hops.clone(), routes.clone(), counters.clone(), anonymity, batch_log.clone(), batches.clone())));
+            Stage::Batch { tx: in_tx, next_hops }
+        }
+    };
+
+    if cfg.cover_pps > 0.0 {
+        let cover = CoverGenerator::new(cfg.cover_pps).map_err(|e| anyhow!("cover: {e}"))?;
+        tasks.push(tokio::spawn(send_cover(cover, socket.clone(), routes.clone(), counters.clone())));
+    }
+
+    let limiter = PeerRateLimiter::new(cfg.peer_rate_pps, cfg.peer_burst);
+    let replay = ReplayCache::with_capacity(cfg.replay_cache_size);
+    tasks.push(tokio::spawn(receive(socket, key, replay, limiter, stage, routes.clone(), counters.clone(), deliver_tx)));
+
+    info!("relay listening on {local_addr} (mix key {})", hex::encode(public_key));
+    Ok((RelayHandle { local_addr, public_key, mode: mix.mode, routes, counters, batch_log, batches, tasks }, deliver_rx))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn receive(
+    socket: Arc<UdpSocket>,
+    key: NodeKey,
+    mut replay: ReplayCache,
+    mut limiter: PeerRateLimiter,
+    stage: Stage,
+    routes: Routes,
+    counters: Arc<Counters>,
+    deliver: mpsc::Sender<Vec<u8>>,
+) {
+    let mut buf = vec![0u8; PACKET_LEN + 1];
+    loop {
+        let (n, peer) = match socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(e) => {
+                debug!("relay recv error: {e}");
+                continue;
+            }
+        };
+        bump(&counters.received);
+        if !limiter.allow(peer.ip(), Instant::now()) {
+            bump(&counters.rate_limited);
+            continue;
+        }
+        let Ok(packet) = SphinxPacket::from_bytes(&buf[..n]) else {
+            bump(&counters.invalid);
+            continue;
+        };
+        match process_at_hop(&key, &mut replay, &packet) {
+            Ok(ProcessResult::Forward { next_hop, packet }) => {
+                let Some(addr) = routes.read().unwrap_or_else(|e| e.into_inner()).get(&next_hop).copied() else {
+                    bump(&counters.unroutable);
+                    continue;
+                };
+                forward(&stage, &socket, &counters, addr, packet.as_bytes().to_vec());
+            }
+            Ok(ProcessResult::Deliver { payload }) => {
+                if deliver.try_send(payload).is_ok() { bump(&counters.delivered) } else { bump(&counters.overloaded) }
+            }
+            Err(MixError::Replay) => bump(&counters.replayed),
+            Err(e) => {
+                debug!("relay dropped packet from {peer}: {e}");
+                bump(&counters.invalid);
+            }
+        }
+    }
+}
+
+fn forward(stage: &Stage, socket: &Arc<UdpSocket>, counters: &Arc<Counters>, addr: SocketAddr, packet: Vec<u8>) {
+    match stage {
+        Stage::Delay { permits, mean } => {
+            let Ok(permit) = permits.clone().try_acquire_owned() else {
+                bump(&counters.overloaded);
+                return;
+            };
+            bump(&counters.mixed);
+            // Exponential delay by inversion; 1 - u lies in (0, 1].
+            let delay = mean.mul_f64(-(1.0 - rand::thread_rng().gen::<f64>()).ln());
+            let (socket, counters) = (socket.clone(), counters.clone());
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                if socket.send_to(&packet, addr).await.is_ok() {
+                    bump(&counters.forwarded);
+                }
+                drop(permit);
+            });
+        }
+        Stage::Batch { tx, next_hops } => {
+            let digest = *blake3::hash(&packet).as_bytes();
+            next_hops.lock().unwrap_or_else(|e| e.into_inner()).insert(digest, addr);
+            if tx.try_send(packet).is_err() {
+                next_hops.lock().unwrap_or_else(|e| e.into_inner()).remove(&digest);
+                bump(&counters.overloaded);
+            } else {
+                bump(&counters.mixed);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn release_batches(
+    mut rx: mpsc::Receiver<Batch>,
+    socket: Arc<UdpSocket>,
+    next_hops: Arc<Mutex<HashMap<[u8; 32], SocketAddr>>>,
+    routes: Routes,
+    counters: Arc<Counters>,
+    anonymity: Arc<Mutex<AnonymityEstimator>>,
+    log: Arc<Mutex<BatchLog>>,
+    published: broadcast::Sender<PublishedBatch>,
+) {
+    while let Some(batch) = rx.recv().await {
+        anonymity.lock().unwrap_or_else(|e| e.into_inner()).record_batch(batch.real_inputs());
+        let mut forwarded = Vec::new();
+        for (k, packet) in batch.packets.iter().enumerate() {
+            let digest = *blake3::hash(packet).as_bytes();
+            let hop = next_hops.lock().unwrap_or_else(|e| e.into_inner()).remove(&digest);
+            let (addr, counter) = match hop {
+                Some(addr) => {
+                    forwarded.push((k, digest));
+                    (Some(addr), &counters.forwarded)
+                }
+                // Padding dummy: spend it as cover.
+                None => (random_route(&routes), &counters.cover_sent),
+            };
+            if let Some(addr) = addr {
+                if socket.send_to(packet, addr).await.is_ok() {
+                    bump(counter);
+                }
+            }
+        }
+        // Witnesses are computed after the release so they never hold packets back.
+        let witnesses = tokio::task::spawn_blocking(move || {
+            let all = batch.inclusion_witnesses();
+            let modulus = accumulator::default_modulus();
+            let witnesses: Vec<_> = forwarded.into_iter().map(|(k, digest)| (digest, hex::encode(all[k].to_bytes(&modulus)))).collect();
+            (batch.proof, witnesses)
+        });
+        let Ok((proof, witnesses)) = witnesses.await else { return };
+        let batch = log.lock().unwrap_or_else(|e| e.into_inner()).push(&proof, witnesses);
+        let _ = published.send(batch);
+    }
+}
+
+async fn send_cover(mut cover: CoverGenerator, socket: Arc<UdpSocket>, routes: Routes, counters: Arc<Counters>) {
+    loop {
+        let packet = cover.next_packet().await;
+        if let Some(addr) = random_route(&routes) {
+            match socket.send_to(&packet, addr).await {
+                Ok(_) => bump(&counters.cover_sent),
+                Err(e) => warn!("cover send to {addr} failed: {e}"),
+            }
+        }
+    }
+}
+
+/// One anonymity round per second: packets that entered the delay stage in
+/// that second are mixed over the mean delay. Rate-limited, invalid and
+/// dropped packets never meet the others and do not count.
+async fn poisson_rounds(counters: Arc<Counters>, mean: Duration, anonymity: Arc<Mutex<AnonymityEstimator>>) {
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    tick.tick().await;
+    let mut last = counters.mixed.load(Ordering::Relaxed);
+    loop {
+        tick.tick().await;
+        let now = counters.mixed.load(Ordering::Relaxed);
+        anonymity.lock().unwrap_or_else(|e| e.into_inner()).record_poisson((now - last) as f64, mean);
+        last = now;
+    }
+}
+
+fn random_route(routes: &Routes) -> Option<SocketAddr> { routes.read().unwrap_or_else(|e| e.into_inner()).values().copied().choose(&mut rand::thread_rng()) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_refills_over_time() {
+        let mut l = PeerRateLimiter::new(10.0, 2.0);
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let other: IpAddr = "127.0.0.2".parse().unwrap();
+        let t0 = Instant::now();
+        assert!(l.allow(peer, t0) && l.allow(peer, t0));
+        assert!(!l.allow(peer, t0));
+        assert!(l.allow(other, t0));
+        assert!(l.allow(peer, t0 + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn rate_limiter_caps_tracked_peers_under_a_source_flood() {
+        let mut l = PeerRateLimiter::with_max_peers(10.0, 2.0, 64);
+        let known: IpAddr = "10.0.0.1".parse().unwrap();
+        let t0 = Instant::now();
+        assert!(l.allow(known, t0));
+        let mut allowed = 0;
+        for i in 0..100_000u32 {
+            let spoofed = IpAddr::from(std::net::Ipv4Addr::from(0x0b00_0000 + i));
+            allowed += usize::from(l.allow(spoofed, t0 + Duration::from_micros(u64::from(i))));
+            assert!(l.buckets.len() <= 64);
+        }
+        // One packet each for the 63 free slots, then one shared bucket: its
+        // burst of 2 plus 0.1 s of refill.
+        assert!(allowed <= 63 + 2 + 1, "{allowed}");
+        assert!(l.allow(known, t0 + Duration::from_millis(100)));
+        // Refilled buckets are swept, making room again.
+        let later = t0 + Duration::from_secs(5);
+        assert!(l.allow("12.0.0.1".parse().unwrap(), later));
+        assert!(l.tracked() <= 2);
+    }
+
+    #[test]
+    fn config_validation_reports_bad_fields() {
+        assert!(RelayConfig::default().validate().is_empty());
+        let cfg = RelayConfig {
+            listen: "nowhere".into(),
+            mix_key: Some("abcd".into()),
+            peers: vec![RelayPeer { node_id: "00".into(), addr: "127.0.0.1:1".into() }],
+            ..Default::default()
+        };
+        assert_eq!(cfg.validate().len(), 3);
+        assert!(cfg.node_key().is_err());
+    }
+}
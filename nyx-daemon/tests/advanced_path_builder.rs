@@ -1 +1,68 @@
+use std::collections::HashSet;
 
+use nyx_daemon::path_builder::{NodeInfo, PathBuilder, PathBuilderError};
+use rand::{rngs::StdRng, SeedableRng};
+
+/// 60 nodes over 12 operators, 10 ASNs and 6 countries.
+fn directory() -> Vec<NodeInfo> {
+    (0..60u8)
+        .map(|i| NodeInfo {
+            node_id: [i; 32],
+            public_key: [0; 32],
+            operator: Some(format!("op{}", i % 12)),
+            asn: Some(64_500 + (i % 10) as u32),
+            country: Some(["DE", "FR", "NL", "US", "JP", "BR"][(i % 6) as usize].into()),
+            bandwidth_mbps: 10.0 + i as f64,
+            latency_ms: 5.0 + (i % 7) as f64 * 20.0,
+        })
+        .collect()
+}
+
+#[test]
+fn paths_are_diverse_and_avoid_blacklist() {
+    let dir = directory();
+    let blacklist: HashSet<[u8; 32]> = (0..10u8).map(|i| [i; 32]).collect();
+    let pb = PathBuilder::default();
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut lengths = HashSet::new();
+    for _ in 0..500 {
+        // Only six countries exist, so longer paths must fail the diversity check.
+        let path = match pb.build(&dir, &blacklist, &mut rng) {
+            Ok(p) => p,
+            Err(PathBuilderError::InsufficientDiversity { hops, .. }) => {
+                assert!(hops > 6);
+                continue;
+            }
+            Err(e) => panic!("{e}"),
+        };
+        lengths.insert(path.len());
+        assert!((3..=7).contains(&path.len()));
+        let ops: HashSet<_> = path.hops.iter().map(|h| h.operator.clone()).collect();
+        let asns: HashSet<_> = path.hops.iter().map(|h| h.asn).collect();
+        let ccs: HashSet<_> = path.hops.iter().map(|h| h.country.clone()).collect();
+        assert_eq!(ops.len(), path.len());
+        assert_eq!(asns.len(), path.len());
+        assert_eq!(ccs.len(), path.len());
+        assert!(path.hops.iter().all(|h| !blacklist.contains(&h.node_id)));
+        assert!(path.entropy_bits > 0.0 && path.entropy_bits <= path.max_entropy_bits + 1e-9);
+    }
+    assert_eq!(lengths, (3..=6).collect());
+}
+
+#[test]
+fn selection_is_biased_towards_capacity() {
+    let mut dir = directory();
+    dir[59].bandwidth_mbps = 10_000.0;
+    dir[59].latency_ms = 1.0;
+    let pb = PathBuilder::default();
+    let mut rng = StdRng::seed_from_u64(3);
+    let hits = (0..300).filter(|_| pb.build_with_hops(&dir, &HashSet::new(), 3, &mut rng).unwrap().node_ids().contains(&[59; 32])).count();
+    assert!(hits > 250, "heavy node picked {hits}/300 times");
+}
+
+#[test]
+fn same_country_directory_cannot_form_a_path() {
+    let dir: Vec<NodeInfo> = directory().into_iter().map(|mut n| { n.country = Some("de".into()); n }).collect();
+    let err = PathBuilder::default().build_with_hops(&dir, &HashSet::new(), 3, &mut StdRng::seed_from_u64(0)).unwrap_err();
+    assert!(matches!(err, PathBuilderError::InsufficientDiversity { hops: 3, .. }));
+}
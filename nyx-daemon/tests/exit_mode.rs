@@ -0,0 +1,264 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bytes::BytesMut;
+use nyx_daemon::exit::{self, link_proof, ExitConfig, ExitHandle};
+use nyx_stream::frame::{Frame, FrameHeader, FrameType};
+use nyx_stream::FrameCodec;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const SECRET: [u8; 32] = [0x5a; 32];
+
+/// Echo server standing in for a destination.
+async fn echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut s, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let (mut r, mut w) = s.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    });
+    addr
+}
+
+async fn start_exit(policy: &[&str], reject_private: bool) -> ExitHandle {
+    let cfg = ExitConfig {
+        listen: "127.0.0.1:0".into(),
+        link_secret: Some(hex::encode(SECRET)),
+        policy: policy.iter().map(|p| p.to_string()).collect(),
+        reject_private,
+        dns_timeout_ms: 2_000,
+        ..Default::default()
+    };
+    exit::start(&cfg).await.unwrap()
+}
+
+/// Client end of a link to the exit.
+struct Link {
+    stream: TcpStream,
+    buf: BytesMut,
+}
+
+impl Link {
+    /// Connect and answer the exit's challenge.
+    async fn connect(exit: &ExitHandle) -> Self {
+        let mut link = Self::connect_unauthenticated(exit).await;
+        let challenge = link.recv().await;
+        assert_eq!((challenge.header.ty, challenge.header.stream_id), (FrameType::Crypto, 0));
+        link.send(control(FrameType::Crypto, 0, &link_proof(&SECRET, &challenge.payload))).await;
+        assert_eq!(link.recv().await.header.ty, FrameType::Ack);
+        link
+    }
+
+    async fn connect_unauthenticated(exit: &ExitHandle) -> Self { Self { stream: TcpStream::connect(exit.local_addr()).await.unwrap(), buf: BytesMut::new() } }
+
+    async fn send(&mut self, frame: Frame) {
+        let mut out = BytesMut::new();
+        FrameCodec::encode(&frame, &mut out).unwrap();
+        self.stream.write_all(&out).await.unwrap();
+    }
+
+    async fn data(&mut self, stream_id: u32, seq: u64, payload: &[u8]) { self.send(Frame::data(stream_id, seq, payload.to_vec())).await }
+
+    async fn close(&mut self, stream_id: u32) { self.send(control(FrameType::Close, stream_id, &[])).await }
+
+    async fn recv(&mut self) -> Frame { self.try_recv(Duration::from_secs(5)).await.expect("frame timed out") }
+
+    async fn try_recv(&mut self, wait: Duration) -> Option<Frame> {
+        tokio::time::timeout(wait, async {
+            loop {
+                if let Some(f) = FrameCodec::decode(&mut self.buf).unwrap() {
+                    return f;
+                }
+                assert!(self.stream.read_buf(&mut self.buf).await.unwrap() > 0, "link closed");
+            }
+        })
+        .await
+        .ok()
+    }
+
+    /// True once the exit has closed the link.
+    async fn is_dropped(&mut self) -> bool {
+        let mut rest = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), self.stream.read_to_end(&mut rest)).await.is_ok_and(|r| r.is_ok())
+    }
+}
+
+fn control(ty: FrameType, stream_id: u32, payload: &[u8]) -> Frame { Frame { header: FrameHeader { stream_id, seq: 0, ty }, payload: payload.to_vec() } }
+
+#[tokio::test]
+async fn streams_are_bridged_to_an_echo_server() {
+    let echo = echo_server().await;
+    let exit = start_exit(&["accept 127.0.0.1/32:*", "reject *:*"], false).await;
+    let mut link = Link::connect(&exit).await;
+
+    link.data(1, 0, echo.to_string().as_bytes()).await;
+    link.data(3, 0, format!("localhost:{}", echo.port()).as_bytes()).await;
+    let mut connected = Vec::new();
+    for _ in 0..2 {
+        let f = link.recv().await;
+        assert_eq!(f.header.ty, FrameType::Ack);
+        assert_eq!(String::from_utf8(f.payload).unwrap(), echo.to_string());
+        connected.push(f.header.stream_id);
+    }
+    connected.sort();
+    assert_eq!(connected, [1, 3]);
+
+    link.data(1, 1, b"hello ").await;
+    link.data(3, 1, b"through the exit").await;
+    link.data(1, 2, b"exit").await;
+    let (mut one, mut three) = (Vec::new(), Vec::new());
+    while one.len() < 10 || three.len() < 16 {
+        let f = link.recv().await;
+        assert_eq!(f.header.ty, FrameType::Data);
+        match f.header.stream_id {
+            1 => one.extend(f.payload),
+            3 => three.extend(f.payload),
+            other => panic!("unexpected stream {other}"),
+        }
+    }
+    assert_eq!(one, b"hello exit");
+    assert_eq!(three, b"through the exit");
+
+    let streams = exit.streams();
+    assert_eq!(streams.len(), 2);
+    let s1 = streams.iter().find(|s| s.stream_id == 1).unwrap();
+    assert_eq!((s1.bytes_up, s1.bytes_down, s1.remote), (10, 10, echo));
+    assert_eq!(streams.iter().find(|s| s.stream_id == 3).unwrap().target, format!("localhost:{}", echo.port()));
+
+    // Half-closing towards the echo server makes it close back.
+    link.close(1).await;
+    let f = link.recv().await;
+    assert_eq!((f.header.ty, f.header.stream_id), (FrameType::Close, 1));
+    assert!(f.payload.is_empty());
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let stats = exit.stats();
+    assert_eq!((stats.links, stats.streams_opened, stats.streams_active, stats.streams_closed), (1, 2, 1, 1));
+    assert_eq!((stats.bytes_up, stats.bytes_down), (26, 26));
+}
+
+#[tokio::test]
+async fn stream_ids_are_not_reused_until_their_stream_ends() {
+    let echo = echo_server().await;
+    let exit = start_exit(&["accept 127.0.0.1/32:*"], false).await;
+    let mut link = Link::connect(&exit).await;
+    link.data(1, 0, echo.to_string().as_bytes()).await;
+    assert_eq!(link.recv().await.header.ty, FrameType::Ack);
+
+    // Reopening straight after Close would mix the new stream's replies
+    // with the old one's; it is ignored while the old stream winds down.
+    let mut both = BytesMut::new();
+    FrameCodec::encode(&control(FrameType::Close, 1, &[]), &mut both).unwrap();
+    FrameCodec::encode(&Frame::data(1, 0, echo.to_string().into_bytes()), &mut both).unwrap();
+    link.stream.write_all(&both).await.unwrap();
+    let f = link.recv().await;
+    assert_eq!((f.header.ty, f.header.stream_id), (FrameType::Close, 1));
+    assert!(link.try_recv(Duration::from_millis(200)).await.is_none());
+    assert_eq!(exit.stats().streams_opened, 1);
+
+    // Once it has ended the id is free again.
+    link.data(1, 0, echo.to_string().as_bytes()).await;
+    let f = link.recv().await;
+    assert_eq!((f.header.ty, f.header.stream_id), (FrameType::Ack, 1));
+    link.data(1, 1, b"again").await;
+    assert_eq!(link.recv().await.payload, b"again");
+}
+
+#[tokio::test]
+async fn policy_refuses_streams_with_a_reason() {
+    let echo = echo_server().await;
+    let exit = start_exit(&["accept *:*"], true).await;
+    let mut link = Link::connect(&exit).await;
+
+    // Private destinations are refused even though the rules accept them.
+    link.data(1, 0, echo.to_string().as_bytes()).await;
+    let f = link.recv().await;
+    assert_eq!((f.header.ty, f.header.stream_id), (FrameType::Close, 1));
+    assert_eq!(f.payload, b"rejected by exit policy");
+
+    link.data(2, 0, b"no port here").await;
+    assert_eq!(link.recv().await.payload, b"malformed target");
+    let stats = exit.stats();
+    assert_eq!((stats.policy_rejected, stats.malformed, stats.streams_opened), (1, 1, 0));
+}
+
+#[tokio::test]
+async fn port_rules_apply_before_resolution() {
+    let exit = start_exit(&["accept *:443", "reject *:*"], true).await;
+    let mut link = Link::connect(&exit).await;
+    link.data(7, 0, b"unresolvable.invalid:25").await;
+    assert_eq!(link.recv().await.payload, b"rejected by exit policy");
+    let stats = exit.stats();
+    assert_eq!((stats.policy_rejected, stats.dns_failed), (1, 0));
+
+    link.data(9, 0, b"unresolvable.invalid:443").await;
+    assert_eq!(link.recv().await.payload, b"resolution failed");
+    assert_eq!(exit.stats().dns_failed, 1);
+}
+
+#[tokio::test]
+async fn links_must_prove_the_secret_first() {
+    let echo = echo_server().await;
+    let exit = start_exit(&["accept 127.0.0.1/32:*"], false).await;
+
+    // Opening a stream before answering the challenge drops the link.
+    let mut link = Link::connect_unauthenticated(&exit).await;
+    assert_eq!(link.recv().await.header.ty, FrameType::Crypto);
+    link.data(1, 0, echo.to_string().as_bytes()).await;
+    assert!(link.is_dropped().await);
+
+    // So does a proof under the wrong secret.
+    let mut link = Link::connect_unauthenticated(&exit).await;
+    let challenge = link.recv().await;
+    link.send(control(FrameType::Crypto, 0, &link_proof(&[0; 32], &challenge.payload))).await;
+    assert!(link.is_dropped().await);
+
+    let stats = exit.stats();
+    assert_eq!((stats.links, stats.unauthenticated, stats.streams_opened), (2, 2, 0));
+}
+
+#[tokio::test]
+async fn a_stalled_destination_resets_only_its_stream() {
+    // Accepts but never reads, so writes towards it back up.
+    let sink = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let sink_addr = sink.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        loop {
+            held.push(sink.accept().await.unwrap());
+        }
+    });
+    let echo = echo_server().await;
+    let exit = start_exit(&["accept 127.0.0.1/32:*"], false).await;
+    let mut link = Link::connect(&exit).await;
+    link.data(1, 0, sink_addr.to_string().as_bytes()).await;
+    link.data(2, 0, echo.to_string().as_bytes()).await;
+    for _ in 0..2 {
+        assert_eq!(link.recv().await.header.ty, FrameType::Ack);
+    }
+
+    let chunk = vec![0u8; 256 * 1024];
+    let mut reset = None;
+    for seq in 1..1024 {
+        link.data(1, seq, &chunk).await;
+        if let Some(f) = link.try_recv(Duration::from_millis(1)).await {
+            reset = Some(f);
+            break;
+        }
+    }
+    let f = reset.expect("stream was never reset");
+    assert_eq!((f.header.ty, f.header.stream_id), (FrameType::Close, 1));
+    assert_eq!(f.payload, b"exit overloaded");
+    assert_eq!(exit.stats().overloaded, 1);
+
+    // The other stream on the link keeps flowing.
+    link.data(2, 1, b"still here").await;
+    let f = link.recv().await;
+    assert_eq!((f.header.ty, f.header.stream_id, f.payload.as_slice()), (FrameType::Data, 2, &b"still here"[..]));
+}
@@ -1 +1,62 @@
+use nyx_daemon::nyx_daemon_config::{ConfigManager, MixMode, NyxConfig};
 
+#[test]
+fn mix_section_defaults_to_standard() {
+    let cfg: NyxConfig = toml::from_str("listen_port = 43300\n").unwrap();
+    assert_eq!(cfg.mix.mode, MixMode::Standard);
+    assert_eq!(cfg.mix.batch_size, 100);
+    assert_eq!(cfg.mix.vdf_delay_ms, 100);
+    assert!(ConfigManager::validate_static(&cfg).is_empty());
+}
+
+#[test]
+fn client_transport_listener_is_opt_in() {
+    let cfg: NyxConfig = toml::from_str("listen_port = 43300
+[transport]
+tcp_fallback = true
+").unwrap();
+    assert!(!cfg.transport.listen);
+    let cfg: NyxConfig = toml::from_str("[transport]
+listen = true
+").unwrap();
+    assert!(cfg.transport.listen);
+}
+
+#[test]
+fn example_cmix_config_selects_cmix() {
+    let text = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/../examples/cmix_config.toml")).unwrap();
+    let cfg: NyxConfig = toml::from_str(&text).unwrap();
+    assert!(cfg.mix.is_cmix());
+    assert_eq!((cfg.mix.batch_size, cfg.mix.vdf_delay_ms), (100, 100));
+    cfg.mix.validate().unwrap();
+}
+
+#[test]
+fn invalid_batch_size_fails_validation() {
+    let cfg: NyxConfig = toml::from_str("[mix]\nmode = \"cmix\"\nbatch_size = 0\n").unwrap();
+    let errs = ConfigManager::validate_static(&cfg);
+    assert_eq!(errs.len(), 1, "{errs:?}");
+    assert!(errs[0].contains("batch_size"));
+}
+
+#[test]
+fn example_relay_config_is_valid() {
+    use nyx_daemon::nyx_daemon_config::NodeRole;
+    let text = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/../examples/relay_config.toml")).unwrap();
+    let cfg: NyxConfig = toml::from_str(&text).unwrap();
+    assert_eq!(cfg.role, NodeRole::Relay);
+    assert_eq!(cfg.relay.peers.len(), 2);
+    assert!(ConfigManager::validate_static(&cfg).is_empty(), "{:?}", ConfigManager::validate_static(&cfg));
+}
+
+#[test]
+fn example_exit_config_is_valid() {
+    use nyx_daemon::nyx_daemon_config::NodeRole;
+    let text = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/../examples/exit_config.toml")).unwrap();
+    let cfg: NyxConfig = toml::from_str(&text).unwrap();
+    assert_eq!(cfg.role, NodeRole::Exit);
+    let policy = cfg.exit.exit_policy().unwrap();
+    assert!(policy.allows(&"93.184.216.34:443".parse().unwrap()));
+    assert!(!policy.allows(&"198.51.100.7:443".parse().unwrap()));
+    assert!(ConfigManager::validate_static(&cfg).is_empty(), "{:?}", ConfigManager::validate_static(&cfg));
+}
@@ -1 +1,21 @@
+use metrics_exporter_prometheus::PrometheusBuilder;
+use nyx_daemon::prometheus_exporter::{record_anonymity, PrometheusExporter};
+use nyx_mix::anonymity::AnonymityEstimator;
 
+#[test]
+fn anonymity_degradation_is_exported() {
+    let recorder = PrometheusBuilder::new().build_recorder();
+    let exporter = PrometheusExporter::from_handle(recorder.handle());
+    let mut est = AnonymityEstimator::default();
+
+    est.record_batch(100);
+    metrics::with_local_recorder(&recorder, || record_anonymity(&est.metrics()));
+    assert!(exporter.render().contains("nyx_anonymity_degraded 0"));
+
+    // A near-empty batch drags min-entropy below the default threshold.
+    est.record_batch(2);
+    metrics::with_local_recorder(&recorder, || record_anonymity(&est.metrics()));
+    let text = exporter.render();
+    assert!(text.contains("nyx_anonymity_degraded 1"), "{text}");
+    assert!(text.contains("nyx_anonymity_min_entropy_bits 1"), "{text}");
+}
@@ -1 +1,43 @@
+//! RS encode/reconstruct throughput; compare `--features simd` against the
+//! pure Rust backend.
 
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nyx_fec::reed_solomon::{RsCodec, RsConfig, RsEncoder, Shard, SHARD_SIZE};
+
+fn block(n: usize) -> Vec<Shard> {
+	(0..n)
+		.map(|i| {
+			let mut s = [0u8; SHARD_SIZE];
+			s.iter_mut().enumerate().for_each(|(j, b)| *b = (i * 131 + j) as u8);
+			s
+		})
+		.collect()
+}
+
+fn bench_rs(c: &mut Criterion) {
+	let mut group = c.benchmark_group("rs");
+	for cfg in [RsConfig::default(), RsConfig::new(20, 6), RsConfig::RS_255_223] {
+		let id = format!("{}+{}", cfg.data_shards, cfg.parity_shards);
+		let codec = RsCodec::new(cfg).unwrap();
+		let data = block(cfg.data_shards);
+		group.throughput(Throughput::Bytes((cfg.data_shards * SHARD_SIZE) as u64));
+		group.bench_with_input(BenchmarkId::new("encode_block", &id), &data, |b, data| b.iter(|| codec.encode(black_box(data)).unwrap()));
+		group.bench_with_input(BenchmarkId::new("encode_streaming", &id), &data, |b, data| {
+			let mut enc = RsEncoder::new(cfg).unwrap();
+			b.iter(|| data.iter().for_each(|s| enc.push(black_box(s), |_, p| { black_box(p); }).unwrap()))
+		});
+		let parity: Vec<Option<Shard>> = codec.encode(&data).unwrap().into_iter().map(Some).collect();
+		group.bench_with_input(BenchmarkId::new("reconstruct_max_loss", &id), &data, |b, data| {
+			b.iter(|| {
+				let mut got: Vec<Option<Shard>> = data.iter().copied().map(Some).collect();
+				got[..cfg.parity_shards].fill(None);
+				codec.reconstruct(&mut got, &parity).unwrap();
+				got
+			})
+		});
+	}
+	group.finish();
+}
+
+criterion_group!(benches, bench_rs);
+criterion_main!(benches);
@@ -0,0 +1,168 @@
+﻿#![forbid(unsafe_code)]
+
+//! Adaptive FEC redundancy driven by measured loss.
+//!
+//! [`RedundancyController`] turns the loss rate and mean loss-burst length
+//! measured per path (see `WeightedScheduler::loss` in nyx-stream) into a
+//! parity/data ratio for the encoder. The ratio is the smallest one whose
+//! expected block-failure probability stays under `target_residual`, with
+//! losses modelled as independent bursts: a burst of length `b` needs `b`
+//! parity shards, and bursts start with probability `rate / b`.
+//!
+//! Redundancy rises as soon as the model asks for more. It falls only after
+//! the model has asked for noticeably less (below the `hysteresis` band) on
+//! `hold_updates` consecutive updates, so the rate does not flap around a
+//! shard boundary.
+
+use crate::errors::{Error, Result};
+use crate::reed_solomon::{DEFAULT_DATA_SHARDS, MAX_TOTAL_SHARDS};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveConfig {
+	/// Redundancy never drops below this, even on a clean link.
+	pub min_redundancy: f64,
+	/// Redundancy never exceeds this, however bad the link.
+	pub max_redundancy: f64,
+	/// Acceptable probability that a block cannot be recovered.
+	pub target_residual: f64,
+	/// Data shards per block the estimate is made for.
+	pub block_shards: usize,
+	/// Relative band below the current redundancy that a lower estimate must clear.
+	pub hysteresis: f64,
+	/// Consecutive low estimates needed before redundancy is lowered.
+	pub hold_updates: u32,
+}
+
+impl Default for AdaptiveConfig {
+	fn default() -> Self {
+		Self { min_redundancy: 0.1, max_redundancy: 1.0, target_residual: 1e-3, block_shards: DEFAULT_DATA_SHARDS, hysteresis: 0.2, hold_updates: 8 }
+	}
+}
+
+impl AdaptiveConfig {
+	pub fn validate(&self) -> Result<()> {
+		if !(self.min_redundancy > 0.0 && self.min_redundancy <= self.max_redundancy) {
+			return Err(Error::config("redundancy bounds must satisfy 0 < min <= max"));
+		}
+		if self.block_shards == 0 || self.block_shards as f64 * (1.0 + self.max_redundancy) > MAX_TOTAL_SHARDS as f64 {
+			return Err(Error::config(format!("block_shards with max redundancy must fit {MAX_TOTAL_SHARDS} shards")));
+		}
+		if !(self.target_residual > 0.0 && self.target_residual < 1.0) {
+			return Err(Error::config("target_residual must be in (0, 1)"));
+		}
+		if !(0.0..1.0).contains(&self.hysteresis) {
+			return Err(Error::config("hysteresis must be in [0, 1)"));
+		}
+		Ok(())
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct RedundancyController {
+	cfg: AdaptiveConfig,
+	current: f64,
+	low_streak: u32,
+}
+
+impl RedundancyController {
+	/// Starts at the minimum redundancy.
+	pub fn new(cfg: AdaptiveConfig) -> Result<Self> {
+		cfg.validate()?;
+		Ok(Self { current: cfg.min_redundancy, cfg, low_streak: 0 })
+	}
+
+	pub fn config(&self) -> &AdaptiveConfig { &self.cfg }
+
+	/// Redundancy currently in force.
+	pub fn redundancy(&self) -> f64 { self.current }
+
+	/// Redundancy the loss model asks for, before hysteresis.
+	pub fn required(&self, loss_rate: f64, mean_burst: f64) -> f64 {
+		let k = self.cfg.block_shards;
+		let max_parity = (k as f64 * self.cfg.max_redundancy).floor() as usize;
+		let rate = if loss_rate.is_finite() { loss_rate.clamp(0.0, 1.0) } else { 1.0 };
+		let burst = if mean_burst.is_finite() { mean_burst.max(1.0) } else { 1.0 };
+		let parity = (1..=max_parity).find(|&m| block_failure(k + m, m, rate, burst) <= self.cfg.target_residual).unwrap_or(max_parity);
+		(parity as f64 / k as f64).clamp(self.cfg.min_redundancy, self.cfg.max_redundancy)
+	}
+
+	/// Feed the latest loss measurement and return the redundancy to use.
+	pub fn update(&mut self, loss_rate: f64, mean_burst: f64) -> f64 {
+		let want = self.required(loss_rate, mean_burst);
+		if want >= self.current {
+			self.current = want;
+			self.low_streak = 0;
+		} else if want < self.current * (1.0 - self.cfg.hysteresis) {
+			self.low_streak += 1;
+			if self.low_streak >= self.cfg.hold_updates {
+				self.current = want;
+				self.low_streak = 0;
+			}
+		} else {
+			self.low_streak = 0;
+		}
+		self.current
+	}
+}
+
+/// Probability that more than `parity` of `n` shards are lost when losses
+/// come in bursts of `burst` shards starting with probability `rate / burst`.
+fn block_failure(n: usize, parity: usize, rate: f64, burst: f64) -> f64 {
+	let q = (rate / burst).min(1.0);
+	let tolerated = (parity as f64 / burst).floor() as usize;
+	if q <= 0.0 {
+		return 0.0;
+	}
+	if q >= 1.0 {
+		return 1.0;
+	}
+	// P(Binomial(n, q) > tolerated), summing the pmf up to `tolerated`.
+	let mut pmf = (1.0 - q).powi(n as i32);
+	let mut cdf = pmf;
+	for i in 0..tolerated.min(n) {
+		pmf *= (n - i) as f64 / (i + 1) as f64 * q / (1.0 - q);
+		cdf += pmf;
+	}
+	(1.0 - cdf).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn required_redundancy_grows_with_rate_and_burst() {
+		let c = RedundancyController::new(AdaptiveConfig::default()).unwrap();
+		assert_eq!(c.required(0.0, 1.0), 0.1);
+		let light = c.required(0.01, 1.0);
+		let heavy = c.required(0.05, 1.0);
+		let bursty = c.required(0.05, 3.0);
+		assert!(light < heavy && heavy < bursty, "{light} {heavy} {bursty}");
+		assert_eq!(c.required(0.9, 10.0), 1.0);
+		assert_eq!(c.required(f64::NAN, f64::NAN), 1.0);
+	}
+
+	#[test]
+	fn lowers_only_after_hold_period() {
+		let cfg = AdaptiveConfig { hold_updates: 3, ..Default::default() };
+		let mut c = RedundancyController::new(cfg).unwrap();
+		let high = c.update(0.1, 2.0);
+		assert!(high > 0.5);
+		// Slightly lower estimates inside the band never lower the rate.
+		let near = c.required(0.08, 2.0);
+		assert!(near >= high * 0.8 && near < high);
+		for _ in 0..10 {
+			assert_eq!(c.update(0.08, 2.0), high);
+		}
+		assert_eq!(c.update(0.0, 1.0), high);
+		assert_eq!(c.update(0.0, 1.0), high);
+		assert_eq!(c.update(0.0, 1.0), 0.1);
+	}
+
+	#[test]
+	fn config_bounds_are_checked() {
+		assert!(RedundancyController::new(AdaptiveConfig { min_redundancy: 0.5, max_redundancy: 0.2, ..Default::default() }).is_err());
+		assert!(RedundancyController::new(AdaptiveConfig { block_shards: 200, ..Default::default() }).is_err());
+		assert!(RedundancyController::new(AdaptiveConfig { target_residual: 0.0, ..Default::default() }).is_err());
+	}
+}
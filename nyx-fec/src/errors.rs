@@ -0,0 +1,27 @@
+﻿use thiserror::Error;
+
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+	#[error("config: {0}")]
+	Config(String),
+	#[error("protocol: {0}")]
+	Protocol(String),
+	#[error("not enough shards to reconstruct")]
+	TooFewShards,
+}
+
+impl Error {
+	pub fn config(msg: impl Into<String>) -> Self { Self::Config(msg.into()) }
+	pub fn protocol(msg: impl Into<String>) -> Self { Self::Protocol(msg.into()) }
+}
+
+impl From<reed_solomon_erasure::Error> for Error {
+	fn from(e: reed_solomon_erasure::Error) -> Self {
+		match e {
+			reed_solomon_erasure::Error::TooFewShardsPresent => Self::TooFewShards,
+			other => Self::Protocol(other.to_string()),
+		}
+	}
+}
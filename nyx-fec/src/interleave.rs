@@ -0,0 +1,273 @@
+﻿#![forbid(unsafe_code)]
+
+//! Interleaving of Reed-Solomon shards across time slots and paths.
+//!
+//! [`Interleaver`] holds `depth` complete blocks and sends them slot by slot,
+//! one shard of each block per round, so a burst of up to `depth`
+//! consecutive losses costs every block at most one shard. Each shard is
+//! also assigned a path from the caller's picker, normally
+//! `WeightedScheduler::next_path` in nyx-stream; with the scheduler's
+//! interleaved ring a block's shards are spread over all paths by weight,
+//! and losing a whole path costs each block only that path's share.
+//! The price is latency: data shards wait until `depth` blocks are complete.
+//!
+//! [`Deinterleaver`] feeds arriving shards to an [`RsDecoder`] and releases
+//! data shards in (block, index) order. A block that cannot be recovered is
+//! skipped once it leaves the decoder's window. Block numbers come from the
+//! peer, so gaps are jumped over rather than walked, and they never wrap.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use crate::errors::{Error, Result};
+use crate::reed_solomon::{RsConfig, RsDecoder, Shard, ShardHeader};
+
+/// Blocks interleaved together by default.
+pub const DEFAULT_DEPTH: usize = 4;
+
+/// Sender side: buffers blocks and emits their shards interleaved.
+#[derive(Debug)]
+pub struct Interleaver<P> {
+	config: RsConfig,
+	depth: usize,
+	current: Vec<(ShardHeader, Shard)>,
+	ready: Vec<Vec<(P, ShardHeader, Shard)>>,
+}
+
+impl<P: Copy> Interleaver<P> {
+	pub fn new(config: RsConfig, depth: usize) -> Result<Self> {
+		config.validate()?;
+		if depth == 0 {
+			return Err(Error::config("interleaving depth must be positive"));
+		}
+		Ok(Self { config, depth, current: Vec::new(), ready: Vec::with_capacity(depth) })
+	}
+
+	pub fn depth(&self) -> usize { self.depth }
+
+	/// Take one shard as emitted by [`crate::RsEncoder`]. Once `depth` blocks are
+	/// complete, their shards are emitted with the path each was assigned.
+	pub fn push(&mut self, header: ShardHeader, shard: &Shard, mut pick_path: impl FnMut() -> P, emit: impl FnMut(P, ShardHeader, &Shard)) {
+		if self.current.first().is_some_and(|(h, _)| h.block != header.block) {
+			// The previous block ended without its parity.
+			self.close_block(&mut pick_path);
+		}
+		self.current.push((header, *shard));
+		if header.index as usize + 1 == self.config.total_shards() {
+			self.close_block(&mut pick_path);
+		}
+		if self.ready.len() >= self.depth {
+			self.emit_group(emit);
+		}
+	}
+
+	/// Emit everything held, interleaving whatever blocks there are.
+	pub fn flush(&mut self, pick_path: impl FnMut() -> P, emit: impl FnMut(P, ShardHeader, &Shard)) {
+		self.close_block(pick_path);
+		self.emit_group(emit);
+	}
+
+	fn close_block(&mut self, mut pick_path: impl FnMut() -> P) {
+		if !self.current.is_empty() {
+			let block = self.current.drain(..).map(|(h, s)| (pick_path(), h, s)).collect();
+			self.ready.push(block);
+		}
+	}
+
+	fn emit_group(&mut self, mut emit: impl FnMut(P, ShardHeader, &Shard)) {
+		let slots = self.ready.iter().map(Vec::len).max().unwrap_or(0);
+		for slot in 0..slots {
+			for block in &self.ready {
+				if let Some((p, h, s)) = block.get(slot) {
+					emit(*p, *h, s);
+				}
+			}
+		}
+		self.ready.clear();
+	}
+}
+
+/// Receiver side: decodes and restores the original shard order.
+#[derive(Debug)]
+pub struct Deinterleaver<P> {
+	data_shards: usize,
+	decoder: RsDecoder,
+	/// Decoded data shards not yet released.
+	pending: BTreeMap<(u32, u8), Shard>,
+	/// Lengths of short blocks, learned from their parity.
+	lengths: BTreeMap<u32, usize>,
+	/// Next shard to release; past the last block once it reaches `u32::MAX + 1`.
+	next: (u64, u8),
+	per_path: HashMap<P, u64>,
+}
+
+impl<P: Copy + Eq + Hash> Deinterleaver<P> {
+	/// `depth` must match the sender's; the decoder window leaves room for
+	/// shards of two interleaved groups to be in flight.
+	pub fn new(config: RsConfig, depth: usize) -> Result<Self> {
+		let decoder = RsDecoder::with_window(config, 2 * depth.max(1))?;
+		Ok(Self { data_shards: config.data_shards, decoder, pending: BTreeMap::new(), lengths: BTreeMap::new(), next: (0, 0), per_path: HashMap::new() })
+	}
+
+	pub fn decoder(&self) -> &RsDecoder { &self.decoder }
+
+	/// Shards received on `path` so far.
+	pub fn received_on(&self, path: P) -> u64 { self.per_path.get(&path).copied().unwrap_or(0) }
+
+	/// Take in a shard received on `path` and release every data shard that
+	/// is now next in order.
+	pub fn push(&mut self, path: P, header: ShardHeader, shard: &Shard, emit: impl FnMut(ShardHeader, &Shard)) -> Result<()> {
+		*self.per_path.entry(path).or_default() += 1;
+		if header.index as usize >= self.data_shards && u64::from(header.block) >= self.next.0 {
+			self.lengths.insert(header.block, header.data_shards as usize);
+		}
+		let (pending, next) = (&mut self.pending, self.next);
+		self.decoder.push(header, shard, |h, s| {
+			// Shards recovered after being skipped on flush stay skipped.
+			if (u64::from(h.block), h.index) >= next {
+				pending.insert((h.block, h.index), *s);
+			}
+		})?;
+		self.release(false, emit);
+		Ok(())
+	}
+
+	/// Release everything decoded so far, skipping what is missing.
+	pub fn flush(&mut self, emit: impl FnMut(ShardHeader, &Shard)) { self.release(true, emit); }
+
+	fn release(&mut self, flush: bool, mut emit: impl FnMut(ShardHeader, &Shard)) {
+		let window_start = self.decoder.window_start();
+		loop {
+			let (block, index) = self.next;
+			let Ok(b) = u32::try_from(block) else { break };
+			let len = self.lengths.get(&b).copied().unwrap_or(self.data_shards);
+			if index as usize >= len {
+				self.lengths.remove(&b);
+				self.next = (block + 1, 0);
+				continue;
+			}
+			if let Some(s) = self.pending.remove(&(b, index)) {
+				emit(ShardHeader { block: b, index, data_shards: 0 }, &s);
+				self.next.1 += 1;
+				continue;
+			}
+			let recoverable = block >= window_start;
+			if recoverable && (!flush || self.pending.is_empty()) {
+				// Still recoverable, or nothing left to flush.
+				break;
+			}
+			// This shard is lost: go straight to the next one held, or to the
+			// window if that comes first.
+			let held = self.pending.keys().next().map(|&(b, i)| (u64::from(b), i));
+			let target = match held {
+				Some(h) if recoverable || h < (window_start, 0) => h,
+				_ => (window_start, 0),
+			};
+			self.skip_to(target);
+		}
+	}
+
+	fn skip_to(&mut self, target: (u64, u8)) {
+		debug_assert!(target > self.next);
+		self.lengths = match u32::try_from(target.0) {
+			Ok(b) => self.lengths.split_off(&b),
+			Err(_) => BTreeMap::new(),
+		};
+		self.next = target;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::reed_solomon::{RsEncoder, SHARD_SIZE};
+
+	type Wire = Vec<(u8, ShardHeader, Shard)>;
+
+	fn send(cfg: RsConfig, depth: usize, paths: u8, shards: usize) -> (Vec<Shard>, Wire) {
+		let mut enc = RsEncoder::new(cfg).unwrap();
+		let mut il = Interleaver::new(cfg, depth).unwrap();
+		let mut rr = 0u8;
+		let mut pick = move || {
+			rr = (rr + 1) % paths;
+			rr
+		};
+		let (mut data, mut wire) = (Vec::new(), Vec::new());
+		for i in 0..shards {
+			let s = [i as u8; SHARD_SIZE];
+			data.push(s);
+			enc.push(&s, |h, s| il.push(h, s, &mut pick, |p, h, s| wire.push((p, h, *s)))).unwrap();
+		}
+		enc.flush(|h, s| il.push(h, s, &mut pick, |p, h, s| wire.push((p, h, *s))));
+		il.flush(&mut pick, |p, h, s| wire.push((p, h, *s)));
+		(data, wire)
+	}
+
+	fn receive(cfg: RsConfig, depth: usize, wire: Wire) -> Vec<Shard> {
+		let mut de = Deinterleaver::new(cfg, depth).unwrap();
+		let mut out = Vec::new();
+		for (p, h, s) in wire {
+			de.push(p, h, &s, |_, s| out.push(*s)).unwrap();
+		}
+		de.flush(|_, s| out.push(*s));
+		out
+	}
+
+	#[test]
+	fn shards_are_spread_over_slots_and_paths() {
+		let cfg = RsConfig::new(4, 2);
+		let (_, wire) = send(cfg, 3, 2, 12);
+		// Three blocks of six, one shard of each block per round.
+		let blocks: Vec<u32> = wire.iter().map(|(_, h, _)| h.block).collect();
+		assert_eq!(&blocks[..6], &[0, 1, 2, 0, 1, 2]);
+		for b in 0..3 {
+			let on_path0 = wire.iter().filter(|(p, h, _)| h.block == b && *p == 0).count();
+			assert_eq!(on_path0, 3);
+		}
+	}
+
+	#[test]
+	fn burst_of_depth_is_recovered_in_order() {
+		let cfg = RsConfig::new(4, 1);
+		let (data, mut wire) = send(cfg, 4, 1, 16);
+		wire.drain(5..9);
+		assert_eq!(receive(cfg, 4, wire), data);
+	}
+
+	#[test]
+	fn short_final_block_and_lost_block() {
+		let cfg = RsConfig::new(4, 2);
+		let (data, wire) = send(cfg, 2, 1, 10);
+		assert_eq!(receive(cfg, 2, wire.clone()), data);
+		// Block 0 loses three shards, one more than its parity; the rest arrives.
+		let wire: Wire = wire.into_iter().filter(|(_, h, _)| !(h.block == 0 && h.index < 3)).collect();
+		assert_eq!(receive(cfg, 2, wire), [&data[3..4], &data[4..]].concat());
+	}
+
+	#[test]
+	fn peer_chosen_block_numbers_are_jumped_not_walked() {
+		let cfg = RsConfig::new(4, 2);
+		let mut de = Deinterleaver::new(cfg, 2).unwrap();
+		let mut out = Vec::new();
+		let s = [7u8; SHARD_SIZE];
+		// Far-ahead blocks push the window past everything before them.
+		for block in [100_000_000, 4_000_000_000, 4_000_000_001, 4_000_000_002, 4_000_000_003, 4_000_000_004] {
+			for index in 0..4 {
+				de.push(0, ShardHeader { block, index, data_shards: 0 }, &s, |h, _| out.push((h.block, h.index))).unwrap();
+			}
+		}
+		// The last block numbers release in order and nothing wraps to block 0.
+		for block in [u32::MAX - 1, u32::MAX] {
+			for index in (0..6).rev() {
+				let data_shards = if index >= 4 { 4 } else { 0 };
+				de.push(0, ShardHeader { block, index, data_shards }, &s, |h, _| out.push((h.block, h.index))).unwrap();
+			}
+		}
+		de.push(0, ShardHeader { block: 0, index: 0, data_shards: 0 }, &s, |h, _| out.push((h.block, h.index))).unwrap();
+		de.flush(|h, _| out.push((h.block, h.index)));
+		assert_eq!(out.len(), 32);
+		assert!(out.windows(2).all(|w| w[0] < w[1]));
+		assert_eq!(out[24..], [(u32::MAX - 1, 0), (u32::MAX - 1, 1), (u32::MAX - 1, 2), (u32::MAX - 1, 3), (u32::MAX, 0), (u32::MAX, 1), (u32::MAX, 2), (u32::MAX, 3)]);
+		assert!(de.next.0 > u64::from(u32::MAX));
+	}
+}
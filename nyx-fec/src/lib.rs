@@ -1 +1,21 @@
+#![forbid(unsafe_code)]
 
+//! Forward error correction for fixed 1280-byte Nyx packets.
+
+pub mod adaptive;
+pub mod errors;
+pub mod interleave;
+pub mod padding;
+#[cfg(feature = "raptorq")]
+pub mod raptorq;
+pub mod reed_solomon;
+pub mod scheme;
+pub mod timing;
+
+pub use adaptive::{AdaptiveConfig, RedundancyController};
+pub use errors::{Error, Result};
+pub use interleave::{Deinterleaver, Interleaver};
+pub use padding::{Padder, Unpadder, CELL_SIZE};
+pub use reed_solomon::{RsCodec, RsConfig, RsDecoder, RsEncoder, Shard, ShardHeader, SHARD_SIZE};
+pub use scheme::FecScheme;
+pub use timing::{TimingConfig, TimingMode, TimingQueue};
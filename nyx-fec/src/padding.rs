@@ -1 +1,293 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Fixed-size cells: frames packed into 1280-byte packets (spec §5.3).
+//!
+//! [`Padder`] packs frames back to back into cells, splitting a frame that
+//! does not fit across as many cells as it needs, and fills the unused tail
+//! of each cell from a keystream private to the link, so padding from
+//! different links is independent (§21). [`Unpadder`] takes the cells back
+//! apart.
+//!
+//! A cell is a run of records: a 4-byte header, then that many frame bytes.
+//! The header is the chunk length (big-endian u16), a flags byte with `MORE`
+//! if the frame continues in the next cell, `CONT` if the chunk continues a
+//! frame from the previous cell and the chunk's position in its frame
+//! (modulo 64) in the low bits, and the frame's sequence number modulo 256.
+//! A zero length ends the records; everything after the end marker, or
+//! after the last record if no room for a marker remains, is keystream.
+
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+use crate::errors::{Error, Result};
+use crate::reed_solomon::{Shard, SHARD_SIZE};
+
+pub const CELL_SIZE: usize = SHARD_SIZE;
+pub type Cell = Shard;
+
+const RECORD_HEADER: usize = 4;
+const FLAG_MORE: u8 = 0x80;
+const FLAG_CONT: u8 = 0x40;
+const POS_MASK: u8 = 0x3f;
+/// Largest frame an [`Unpadder`] reassembles by default, as for the stream codec.
+pub const DEFAULT_MAX_FRAME_LEN: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PaddingStats {
+	pub frames: u64,
+	pub cells: u64,
+	/// Frame bytes carried.
+	pub payload_bytes: u64,
+	/// Record headers, end markers and keystream fill.
+	pub overhead_bytes: u64,
+}
+
+impl PaddingStats {
+	/// Share of cell bytes that carry frame data.
+	pub fn efficiency(&self) -> f64 {
+		if self.cells == 0 {
+			return 0.0;
+		}
+		self.payload_bytes as f64 / (self.cells * CELL_SIZE as u64) as f64
+	}
+}
+
+/// Packs frames into cells for one link.
+pub struct Padder {
+	keystream: StdRng,
+	cell: Cell,
+	used: usize,
+	/// Record headers in the cell under construction.
+	headers: usize,
+	frame_seq: u8,
+	stats: PaddingStats,
+}
+
+impl Padder {
+	/// Padder whose fill comes from a keystream seeded with `link_seed`; use
+	/// a different seed for every link.
+	pub fn new(link_seed: [u8; 32]) -> Self { Self { keystream: StdRng::from_seed(link_seed), cell: [0u8; CELL_SIZE], used: 0, headers: 0, frame_seq: 0, stats: PaddingStats::default() } }
+
+	/// Padder with a freshly drawn keystream seed.
+	pub fn random() -> Self { Self::new(rand::random()) }
+
+	pub fn stats(&self) -> PaddingStats { self.stats }
+
+	/// Bytes already packed into the cell under construction.
+	pub fn pending(&self) -> usize { self.used }
+
+	/// Pack one frame, emitting every cell it fills.
+	pub fn push(&mut self, frame: &[u8], mut emit: impl FnMut(&Cell)) -> Result<()> {
+		if frame.is_empty() {
+			return Err(Error::protocol("cannot pad an empty frame"));
+		}
+		let mut rest = frame;
+		let mut pos = 0u8;
+		while !rest.is_empty() {
+			if CELL_SIZE - self.used <= RECORD_HEADER {
+				self.finish(&mut emit);
+			}
+			let n = rest.len().min(CELL_SIZE - self.used - RECORD_HEADER);
+			let mut flags = pos & POS_MASK;
+			if n < rest.len() {
+				flags |= FLAG_MORE;
+			}
+			if pos > 0 {
+				flags |= FLAG_CONT;
+			}
+			let [hi, lo] = (n as u16).to_be_bytes();
+			self.cell[self.used..self.used + RECORD_HEADER].copy_from_slice(&[hi, lo, flags, self.frame_seq]);
+			self.headers += 1;
+			self.cell[self.used + RECORD_HEADER..self.used + RECORD_HEADER + n].copy_from_slice(&rest[..n]);
+			self.used += RECORD_HEADER + n;
+			self.stats.payload_bytes += n as u64;
+			rest = &rest[n..];
+			pos = pos.wrapping_add(1);
+		}
+		self.stats.frames += 1;
+		self.frame_seq = self.frame_seq.wrapping_add(1);
+		if CELL_SIZE - self.used <= RECORD_HEADER {
+			self.finish(&mut emit);
+		}
+		Ok(())
+	}
+
+	/// A cell carrying no frames, for slots with nothing to send.
+	pub fn idle_cell(&mut self) -> Cell {
+		let mut cell = [0u8; CELL_SIZE];
+		self.keystream.fill_bytes(&mut cell[RECORD_HEADER..]);
+		cell
+	}
+
+	/// Pad and emit the cell under construction, if it holds anything.
+	pub fn flush(&mut self, mut emit: impl FnMut(&Cell)) {
+		if self.used > 0 {
+			self.finish(&mut emit);
+		}
+	}
+
+	fn finish(&mut self, emit: &mut impl FnMut(&Cell)) {
+		let mut fill = self.used;
+		if CELL_SIZE - fill >= RECORD_HEADER {
+			self.cell[fill..fill + RECORD_HEADER].fill(0);
+			fill += RECORD_HEADER;
+		}
+		self.keystream.fill_bytes(&mut self.cell[fill..]);
+		self.stats.cells += 1;
+		self.stats.overhead_bytes += (CELL_SIZE - self.used + self.headers * RECORD_HEADER) as u64;
+		emit(&self.cell);
+		self.used = 0;
+		self.headers = 0;
+		#[cfg(feature = "telemetry")]
+		{
+			metrics::counter!("nyx_padding_cells_total").increment(1);
+			metrics::gauge!("nyx_padding_efficiency").set(self.stats.efficiency());
+		}
+	}
+}
+
+/// Reassembles frames from cells received in order.
+#[derive(Debug)]
+pub struct Unpadder {
+	partial: Vec<u8>,
+	/// Sequence number and next chunk position of the partial frame.
+	expect: (u8, u8),
+	max_frame: usize,
+	/// Frames dropped because a cell was missing or malformed.
+	dropped: u64,
+}
+
+impl Default for Unpadder {
+	fn default() -> Self { Self::new() }
+}
+
+impl Unpadder {
+	pub fn new() -> Self { Self::with_max_frame(DEFAULT_MAX_FRAME_LEN) }
+
+	pub fn with_max_frame(max_frame: usize) -> Self { Self { partial: Vec::new(), expect: (0, 0), max_frame, dropped: 0 } }
+
+	pub fn dropped(&self) -> u64 { self.dropped }
+
+	/// Emit every frame completed by `cell`. A chunk that does not continue
+	/// the frame in progress means a cell went missing; the broken frame is
+	/// dropped and counted, and unpacking goes on with the next one. Losing a
+	/// multiple of 64 cells inside one frame goes unnoticed here and is left
+	/// to the frame codec.
+	pub fn push(&mut self, cell: &Cell, mut emit: impl FnMut(&[u8])) -> Result<()> {
+		let mut at = 0;
+		while CELL_SIZE - at >= RECORD_HEADER {
+			let len = u16::from_be_bytes([cell[at], cell[at + 1]]) as usize;
+			let (flags, seq) = (cell[at + 2], cell[at + 3]);
+			if len == 0 {
+				break;
+			}
+			at += RECORD_HEADER;
+			if len > CELL_SIZE - at {
+				self.drop_partial();
+				return Err(Error::protocol("cell record overruns the cell"));
+			}
+			let chunk = &cell[at..at + len];
+			at += len;
+			let cont = flags & FLAG_CONT != 0;
+			let pos = flags & POS_MASK;
+			if cont == self.partial.is_empty() || (cont && (seq, pos) != self.expect) {
+				// The start of this frame, part of it, or the end of the last one was lost.
+				self.drop_partial();
+				if cont {
+					continue;
+				}
+			}
+			if self.partial.len() + len > self.max_frame {
+				self.drop_partial();
+				return Err(Error::protocol("padded frame exceeds the maximum frame size"));
+			}
+			self.partial.extend_from_slice(chunk);
+			self.expect = (seq, (pos + 1) & POS_MASK);
+			if flags & FLAG_MORE == 0 {
+				emit(&self.partial);
+				self.partial.clear();
+			}
+		}
+		Ok(())
+	}
+
+	fn drop_partial(&mut self) {
+		if !self.partial.is_empty() {
+			self.partial.clear();
+			self.dropped += 1;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn pack(p: &mut Padder, frames: &[Vec<u8>]) -> Vec<Cell> {
+		let mut cells = Vec::new();
+		for f in frames {
+			p.push(f, |c| cells.push(*c)).unwrap();
+		}
+		p.flush(|c| cells.push(*c));
+		cells
+	}
+
+	fn unpack(cells: &[Cell]) -> (Vec<Vec<u8>>, u64) {
+		let mut u = Unpadder::new();
+		let mut out = Vec::new();
+		for c in cells {
+			u.push(c, |f| out.push(f.to_vec())).unwrap();
+		}
+		(out, u.dropped())
+	}
+
+	#[test]
+	fn small_and_large_frames_round_trip() {
+		let frames: Vec<Vec<u8>> = [10usize, 300, 1278, 1279, 4000, 1, 600].iter().enumerate().map(|(i, &n)| vec![i as u8 + 1; n]).collect();
+		let mut p = Padder::new([7; 32]);
+		let cells = pack(&mut p, &frames);
+		let total: usize = frames.iter().map(Vec::len).sum();
+		assert_eq!(cells.len(), total.div_ceil(CELL_SIZE - 2 * RECORD_HEADER));
+		assert_eq!(unpack(&cells), (frames, 0));
+		let s = p.stats();
+		assert_eq!((s.frames, s.cells, s.payload_bytes), (7, cells.len() as u64, total as u64));
+		assert_eq!(s.payload_bytes + s.overhead_bytes, s.cells * CELL_SIZE as u64);
+		assert!(s.efficiency() > 0.9, "{}", s.efficiency());
+		assert!(p.push(&[], |_| {}).is_err());
+	}
+
+	#[test]
+	fn fill_is_per_link_keystream() {
+		let frames = vec![vec![0xaa; 100]];
+		let a = pack(&mut Padder::new([1; 32]), &frames);
+		let b = pack(&mut Padder::new([2; 32]), &frames);
+		assert_eq!(a[0][..104], b[0][..104]);
+		assert_eq!(a[0][104..106], [0, 0]);
+		assert_ne!(a[0][108..], b[0][108..]);
+		// Not a constant or zero fill.
+		assert!(a[0][108..].iter().filter(|&&x| x == 0).count() < 50);
+		assert_eq!(unpack(&a).0, frames);
+	}
+
+	#[test]
+	fn lost_cell_drops_only_the_broken_frames() {
+		let frames: Vec<Vec<u8>> = vec![vec![1; 500], vec![2; 2000], vec![3; 500], vec![4; 1500], vec![5; 100]];
+		let cells = pack(&mut Padder::random(), &frames);
+		let ids = |out: Vec<Vec<u8>>| -> Vec<u8> {
+			for f in &out {
+				assert!(frames.contains(f), "corrupt frame of {} bytes", f.len());
+			}
+			out.iter().map(|f| f[0]).collect()
+		};
+		// Cell 1 holds the middle of frame 2 and the start of frame 3.
+		let (out, dropped) = unpack(&[&cells[..1], &cells[2..]].concat());
+		assert_eq!((ids(out), dropped), (vec![1, 4, 5], 1));
+		// Starting mid-stream skips the tail of the frame in progress.
+		let (out, dropped) = unpack(&cells[2..]);
+		assert_eq!((ids(out), dropped), (vec![4, 5], 0));
+
+		let mut bad = cells[0];
+		bad[..2].copy_from_slice(&1300u16.to_be_bytes());
+		assert!(Unpadder::new().push(&bad, |_| {}).is_err());
+	}
+}
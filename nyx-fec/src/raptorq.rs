@@ -1 +1,271 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! RFC 6330 RaptorQ fountain coding with 1280-byte symbols.
+//!
+//! Unlike the fixed-rate Reed-Solomon blocks, RaptorQ produces as many
+//! repair symbols as the sender asks for: [`RaptorQEncoder`] emits the
+//! source symbols once and then further repair symbols per source block
+//! on demand, and [`RaptorQDecoder`] recovers the object from any
+//! slightly-more-than-K symbols of each block. The 12-byte OTI (object
+//! transmission information) must reach the receiver before any symbol is
+//! useful; each symbol carries a 4-byte payload ID (block, ESI).
+
+use ::raptorq::{partition, Decoder, Encoder, EncodingPacket, ObjectTransmissionInformation, PayloadId};
+
+use crate::errors::{Error, Result};
+use crate::reed_solomon::{Shard, SHARD_SIZE};
+
+/// Symbol size in bytes: one Nyx packet.
+pub const SYMBOL_SIZE: u16 = SHARD_SIZE as u16;
+/// Encoded length of the object transmission information.
+pub const OTI_LEN: usize = 12;
+/// Encoded length of a symbol's payload ID.
+pub const PAYLOAD_ID_LEN: usize = 4;
+/// Largest object a decoder accepts, bounding what a peer's OTI can make us allocate.
+pub const MAX_OBJECT_LEN: u64 = 16 << 20;
+/// Encoding symbol IDs are 24-bit.
+const MAX_ESI: u32 = 1 << 24;
+
+/// One encoding symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RqSymbol {
+	/// Source block number.
+	pub block: u8,
+	/// Encoding symbol ID; below the block's symbol count for source symbols.
+	pub esi: u32,
+	pub data: Shard,
+}
+
+impl RqSymbol {
+	/// Payload ID followed by the symbol.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut out = Vec::with_capacity(PAYLOAD_ID_LEN + SHARD_SIZE);
+		out.extend_from_slice(&PayloadId::new(self.block, self.esi).serialize());
+		out.extend_from_slice(&self.data);
+		out
+	}
+
+	pub fn from_bytes(b: &[u8]) -> Result<Self> {
+		if b.len() != PAYLOAD_ID_LEN + SHARD_SIZE {
+			return Err(Error::protocol("RaptorQ symbol must be 4 + 1280 bytes"));
+		}
+		let id = PayloadId::deserialize(b[..PAYLOAD_ID_LEN].try_into().expect("4 bytes"));
+		let data = b[PAYLOAD_ID_LEN..].try_into().expect("1280 bytes");
+		Ok(Self { block: id.source_block_number(), esi: id.encoding_symbol_id(), data })
+	}
+
+	fn from_packet(p: EncodingPacket) -> Self {
+		let (id, data) = p.split();
+		let data = data.as_slice().try_into().expect("symbols are SYMBOL_SIZE bytes");
+		Self { block: id.source_block_number(), esi: id.encoding_symbol_id(), data }
+	}
+}
+
+/// Parameters for an object of `len` bytes. Both ends derive them the same
+/// way, so a received OTI can be checked against this.
+fn parameters(len: u64) -> ObjectTransmissionInformation { ObjectTransmissionInformation::with_defaults(len, SYMBOL_SIZE) }
+
+/// K' column of RFC 6330 Table 2 (§5.6): the block sizes the code is
+/// defined for, ascending.
+const EXTENDED_SYMBOLS: [u32; 477] = [
+	10, 12, 18, 20, 26, 30, 32, 36, 42, 46, 48, 49, 55, 60, 62, 69,
+	75, 84, 88, 91, 95, 97, 101, 114, 119, 125, 127, 138, 140, 149, 153, 160,
+	166, 168, 179, 181, 185, 187, 200, 213, 217, 225, 236, 242, 248, 257, 263, 269,
+	280, 295, 301, 305, 324, 337, 341, 347, 355, 362, 368, 372, 380, 385, 393, 405,
+	418, 428, 434, 447, 453, 466, 478, 486, 491, 497, 511, 526, 532, 542, 549, 557,
+	563, 573, 580, 588, 594, 600, 606, 619, 633, 640, 648, 666, 675, 685, 693, 703,
+	718, 728, 736, 747, 759, 778, 792, 802, 811, 821, 835, 845, 860, 870, 891, 903,
+	913, 926, 938, 950, 963, 977, 989, 1002, 1020, 1032, 1050, 1074, 1085, 1099, 1111, 1136,
+	1152, 1169, 1183, 1205, 1220, 1236, 1255, 1269, 1285, 1306, 1347, 1361, 1389, 1404, 1420, 1436,
+	1461, 1477, 1502, 1522, 1539, 1561, 1579, 1600, 1616, 1649, 1673, 1698, 1716, 1734, 1759, 1777,
+	1800, 1824, 1844, 1863, 1887, 1906, 1926, 1954, 1979, 2005, 2040, 2070, 2103, 2125, 2152, 2195,
+	2217, 2247, 2278, 2315, 2339, 2367, 2392, 2416, 2447, 2473, 2502, 2528, 2565, 2601, 2640, 2668,
+	2701, 2737, 2772, 2802, 2831, 2875, 2906, 2938, 2979, 3015, 3056, 3101, 3151, 3186, 3224, 3265,
+	3299, 3344, 3387, 3423, 3466, 3502, 3539, 3579, 3616, 3658, 3697, 3751, 3792, 3840, 3883, 3924,
+	3970, 4015, 4069, 4112, 4165, 4207, 4252, 4318, 4365, 4418, 4468, 4513, 4567, 4626, 4681, 4731,
+	4780, 4838, 4901, 4954, 5008, 5063, 5116, 5172, 5225, 5279, 5334, 5391, 5449, 5506, 5566, 5637,
+	5694, 5763, 5823, 5896, 5975, 6039, 6102, 6169, 6233, 6296, 6363, 6427, 6518, 6589, 6655, 6730,
+	6799, 6878, 6956, 7033, 7108, 7185, 7281, 7360, 7445, 7520, 7596, 7675, 7770, 7855, 7935, 8030,
+	8111, 8194, 8290, 8377, 8474, 8559, 8654, 8744, 8837, 8928, 9019, 9111, 9206, 9303, 9400, 9497,
+	9601, 9708, 9813, 9916, 10017, 10120, 10241, 10351, 10458, 10567, 10676, 10787, 10899, 11015, 11130, 11245,
+	11358, 11475, 11590, 11711, 11829, 11956, 12087, 12208, 12333, 12460, 12593, 12726, 12857, 13002, 13143, 13284,
+	13417, 13558, 13695, 13833, 13974, 14115, 14272, 14415, 14560, 14713, 14862, 15011, 15170, 15325, 15496, 15651,
+	15808, 15977, 16161, 16336, 16505, 16674, 16851, 17024, 17195, 17376, 17559, 17742, 17929, 18116, 18309, 18503,
+	18694, 18909, 19126, 19325, 19539, 19740, 19939, 20152, 20355, 20564, 20778, 20988, 21199, 21412, 21629, 21852,
+	22073, 22301, 22536, 22779, 23010, 23252, 23491, 23730, 23971, 24215, 24476, 24721, 24976, 25230, 25493, 25756,
+	26022, 26291, 26566, 26838, 27111, 27392, 27682, 27959, 28248, 28548, 28845, 29138, 29434, 29731, 30037, 30346,
+	30654, 30974, 31285, 31605, 31948, 32272, 32601, 32932, 33282, 33623, 33961, 34302, 34654, 35031, 35395, 35750,
+	36112, 36479, 36849, 37227, 37606, 37992, 38385, 38787, 39176, 39576, 39980, 40398, 40816, 41226, 41641, 42067,
+	42490, 42916, 43388, 43840, 44279, 44729, 45183, 45638, 46104, 46574, 47047, 47523, 48007, 48489, 48976, 49470,
+	49978, 50511, 51017, 51530, 52062, 52586, 53114, 53650, 54188, 54735, 55289, 55843, 56403,
+];
+
+/// Extended source block size K': the smallest K' in Table 2 that is at
+/// least K (RFC 6330 §5.3.1). K never exceeds the table here, as
+/// `MAX_OBJECT_LEN` keeps blocks far below K'_max = 56403.
+fn extended_source_block_symbols(k: u32) -> u32 { EXTENDED_SYMBOLS[EXTENDED_SYMBOLS.partition_point(|&e| e < k)] }
+
+/// Source symbols in each block (RFC 6330 §4.4.1.2).
+fn block_symbols(oti: &ObjectTransmissionInformation) -> Vec<u32> {
+	let kt = oti.transfer_length().div_ceil(oti.symbol_size() as u64) as u32;
+	let (kl, ks, zl, zs) = partition(kt, oti.source_blocks());
+	std::iter::repeat_n(kl, zl as usize).chain(std::iter::repeat_n(ks, zs as usize)).collect()
+}
+
+pub struct RaptorQEncoder {
+	inner: Encoder,
+	/// Extended symbol count K' per block; repair ESIs start there.
+	extended: Vec<u32>,
+	/// Next repair symbol index per source block.
+	next_repair: Vec<u32>,
+}
+
+impl RaptorQEncoder {
+	pub fn new(data: &[u8]) -> Result<Self> {
+		if data.is_empty() || data.len() as u64 > MAX_OBJECT_LEN {
+			return Err(Error::config(format!("RaptorQ objects must be 1..={MAX_OBJECT_LEN} bytes")));
+		}
+		let oti = parameters(data.len() as u64);
+		let extended: Vec<u32> = block_symbols(&oti).into_iter().map(extended_source_block_symbols).collect();
+		let next_repair = vec![0; extended.len()];
+		Ok(Self { inner: Encoder::new(data, oti), extended, next_repair })
+	}
+
+	/// Object transmission information the decoder is built from.
+	pub fn oti(&self) -> [u8; OTI_LEN] { self.inner.get_config().serialize() }
+
+	pub fn source_blocks(&self) -> usize { self.next_repair.len() }
+
+	/// All source symbols, block by block.
+	pub fn source_symbols(&self) -> Vec<RqSymbol> {
+		self.inner.get_block_encoders().iter().flat_map(|b| b.source_packets()).map(RqSymbol::from_packet).collect()
+	}
+
+	/// `per_block` further repair symbols for every block. Each call continues
+	/// where the last stopped, so no repair symbol is sent twice.
+	pub fn repair_symbols(&mut self, per_block: u32) -> Vec<RqSymbol> {
+		(0..self.source_blocks()).flat_map(|b| self.repair_symbols_for(b as u8, per_block)).collect()
+	}
+
+	/// `n` further repair symbols for one block; none if the block does not exist.
+	pub fn repair_symbols_for(&mut self, block: u8, n: u32) -> Vec<RqSymbol> {
+		let Some(enc) = self.inner.get_block_encoders().get(block as usize) else { return Vec::new() };
+		let next = &mut self.next_repair[block as usize];
+		let n = n.min(MAX_ESI - self.extended[block as usize] - *next);
+		let out = enc.repair_packets(*next, n).into_iter().map(RqSymbol::from_packet).collect();
+		*next += n;
+		out
+	}
+}
+
+pub struct RaptorQDecoder {
+	inner: Decoder,
+	/// Source symbols K per block.
+	symbols: Vec<u32>,
+	/// Extended symbol count K' per block.
+	extended: Vec<u32>,
+	done: bool,
+}
+
+impl RaptorQDecoder {
+	/// Decoder for the object described by a received OTI. Only parameters
+	/// this module would have chosen for the object's length are accepted.
+	pub fn new(oti: &[u8; OTI_LEN]) -> Result<Self> {
+		let config = ObjectTransmissionInformation::deserialize(oti);
+		let len = config.transfer_length();
+		if len == 0 || len > MAX_OBJECT_LEN || config.serialize() != parameters(len).serialize() {
+			return Err(Error::protocol("unexpected RaptorQ transmission parameters"));
+		}
+		let symbols = block_symbols(&config);
+		let extended = symbols.iter().map(|&k| extended_source_block_symbols(k)).collect();
+		Ok(Self { inner: Decoder::new(config), symbols, extended, done: false })
+	}
+
+	pub fn is_complete(&self) -> bool { self.done }
+
+	/// Feed one symbol; returns the object once, when it becomes decodable.
+	pub fn push(&mut self, sym: &RqSymbol) -> Result<Option<Vec<u8>>> {
+		let b = sym.block as usize;
+		let (Some(&k), Some(&k_ext)) = (self.symbols.get(b), self.extended.get(b)) else {
+			return Err(Error::protocol(format!("no RaptorQ source block {b}")));
+		};
+		// ESIs between K and K' name padding symbols, which are never sent.
+		if (k..k_ext).contains(&sym.esi) || sym.esi >= MAX_ESI {
+			return Err(Error::protocol(format!("invalid encoding symbol id {}", sym.esi)));
+		}
+		if self.done {
+			return Ok(None);
+		}
+		let out = self.inner.decode(EncodingPacket::new(PayloadId::new(sym.block, sym.esi), sym.data.to_vec()));
+		self.done = out.is_some();
+		Ok(out)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn object(len: usize) -> Vec<u8> { (0..len).map(|i| (i * 31 % 251) as u8).collect() }
+
+	#[test]
+	fn extended_block_size_follows_table_2() {
+		assert!(EXTENDED_SYMBOLS.windows(2).all(|w| w[0] < w[1]));
+		for (k, k_ext) in [(1, 10), (10, 10), (11, 12), (21, 26), (101, 101), (102, 114), (56_403, 56_403)] {
+			assert_eq!(extended_source_block_symbols(k), k_ext, "K = {k}");
+		}
+	}
+
+	#[test]
+	fn repair_symbols_replace_lost_source_symbols() {
+		let data = object(20 * SHARD_SIZE + 77);
+		let mut enc = RaptorQEncoder::new(&data).unwrap();
+		let mut dec = RaptorQDecoder::new(&enc.oti()).unwrap();
+		// Lose every third source symbol, then top up with fresh repair symbols.
+		let mut got = None;
+		for s in enc.source_symbols().iter().enumerate().filter(|(i, _)| i % 3 != 0).map(|(_, s)| s) {
+			assert!(dec.push(s).unwrap().is_none());
+		}
+		for round in 0..4 {
+			for s in enc.repair_symbols(3) {
+				if let Some(out) = dec.push(&RqSymbol::from_bytes(&s.to_bytes()).unwrap()).unwrap() {
+					got = Some(out);
+				}
+			}
+			if got.is_some() {
+				assert!(round >= 2, "7 lost symbols need at least 7 repair symbols");
+				break;
+			}
+		}
+		assert_eq!(got.unwrap(), data);
+		assert!(dec.is_complete());
+	}
+
+	#[test]
+	fn repair_ids_continue_across_calls() {
+		let mut enc = RaptorQEncoder::new(&object(5000)).unwrap();
+		let k = enc.source_symbols().len() as u32;
+		let first = enc.repair_symbols(2);
+		let second = enc.repair_symbols(2);
+		assert!(first[0].esi >= k);
+		assert_eq!(second[0].esi, first[1].esi + 1);
+		assert_ne!(first[0].data, second[0].data);
+	}
+
+	#[test]
+	fn foreign_parameters_and_symbols_are_rejected() {
+		assert!(RaptorQEncoder::new(&[]).is_err());
+		let enc = RaptorQEncoder::new(&object(3000)).unwrap();
+		let mut oti = enc.oti();
+		oti[6] ^= 0x01; // symbol size 1024
+		assert!(RaptorQDecoder::new(&oti).is_err());
+		assert!(RaptorQDecoder::new(&ObjectTransmissionInformation::with_defaults(MAX_OBJECT_LEN + 1, SYMBOL_SIZE).serialize()).is_err());
+
+		let mut dec = RaptorQDecoder::new(&enc.oti()).unwrap();
+		let data = [0u8; SHARD_SIZE];
+		assert!(dec.push(&RqSymbol { block: 1, esi: 0, data }).is_err());
+		// 3 source symbols extend to K' = 10; ESIs 3..10 are never sent.
+		assert!(dec.push(&RqSymbol { block: 0, esi: 3, data }).is_err());
+		assert!(RqSymbol::from_bytes(&[0; 100]).is_err());
+	}
+}
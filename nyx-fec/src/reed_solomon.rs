@@ -0,0 +1,466 @@
+﻿#![forbid(unsafe_code)]
+
+//! Systematic Reed-Solomon erasure coding over GF(2^8) (spec §5.3).
+//!
+//! Packets travel in blocks of up to `data_shards` 1280-byte data shards
+//! followed by `parity_shards` repair shards; any `data_shards` of them
+//! recover the block. The default 10+3 gives the spec's 30% redundancy. Any
+//! split with at most 255 shards in total is allowed, RS(255,223) included.
+//!
+//! [`RsEncoder`] emits each data shard as soon as it is pushed and keeps only
+//! the running parity, never the block. A short final block is encoded as if
+//! padded with zero shards, which are not sent; its parity shards carry the
+//! real length. [`RsDecoder`] releases data shards on arrival and keeps
+//! copies only until their block is complete or leaves its window.
+
+use std::collections::BTreeMap;
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use crate::errors::{Error, Result};
+
+pub const SHARD_SIZE: usize = 1280;
+pub const DEFAULT_DATA_SHARDS: usize = 10;
+pub const DEFAULT_PARITY_SHARDS: usize = 3;
+/// A GF(2^8) codeword has at most 255 symbols.
+pub const MAX_TOTAL_SHARDS: usize = 255;
+/// Blocks an [`RsDecoder`] keeps open by default.
+pub const DEFAULT_DECODER_WINDOW: usize = 16;
+
+pub type Shard = [u8; SHARD_SIZE];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RsConfig {
+	pub data_shards: usize,
+	pub parity_shards: usize,
+}
+
+impl Default for RsConfig {
+	fn default() -> Self { Self::new(DEFAULT_DATA_SHARDS, DEFAULT_PARITY_SHARDS) }
+}
+
+impl RsConfig {
+	/// The classic RS(255,223) code.
+	pub const RS_255_223: Self = Self::new(223, 32);
+
+	pub const fn new(data_shards: usize, parity_shards: usize) -> Self { Self { data_shards, parity_shards } }
+
+	/// Fewest parity shards giving at least `redundancy` (parity / data).
+	pub fn with_redundancy(data_shards: usize, redundancy: f64) -> Self {
+		// The epsilon keeps 10 × 0.3 at 3 rather than 4.
+		let parity = (data_shards as f64 * redundancy.max(0.0) - 1e-9).ceil().max(1.0) as usize;
+		Self::new(data_shards, parity)
+	}
+
+	pub fn total_shards(&self) -> usize { self.data_shards + self.parity_shards }
+
+	/// Parity overhead relative to the data.
+	pub fn redundancy(&self) -> f64 { self.parity_shards as f64 / self.data_shards.max(1) as f64 }
+
+	pub fn validate(&self) -> Result<()> {
+		if self.data_shards == 0 || self.parity_shards == 0 {
+			return Err(Error::config("data and parity shard counts must be positive"));
+		}
+		if self.total_shards() > MAX_TOTAL_SHARDS {
+			return Err(Error::config(format!("at most {MAX_TOTAL_SHARDS} shards per block")));
+		}
+		Ok(())
+	}
+}
+
+/// Where a shard belongs, carried next to it on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardHeader {
+	pub block: u32,
+	/// `0..data_shards` for data shards, parity after that.
+	pub index: u8,
+	/// Data shards in the block. A block can be cut short after its data
+	/// shards were sent, so only parity shards carry it; data shards carry 0.
+	pub data_shards: u8,
+}
+
+impl ShardHeader {
+	pub const LEN: usize = 6;
+
+	pub fn to_bytes(&self) -> [u8; Self::LEN] {
+		let mut out = [0u8; Self::LEN];
+		out[..4].copy_from_slice(&self.block.to_be_bytes());
+		out[4] = self.index;
+		out[5] = self.data_shards;
+		out
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+		let b: [u8; Self::LEN] = bytes.try_into().map_err(|_| Error::protocol("shard header must be 6 bytes"))?;
+		Ok(Self { block: u32::from_be_bytes([b[0], b[1], b[2], b[3]]), index: b[4], data_shards: b[5] })
+	}
+}
+
+/// Block-at-a-time coding.
+#[derive(Debug, Clone)]
+pub struct RsCodec {
+	config: RsConfig,
+	rs: ReedSolomon,
+}
+
+impl RsCodec {
+	pub fn new(config: RsConfig) -> Result<Self> {
+		config.validate()?;
+		Ok(Self { config, rs: ReedSolomon::new(config.data_shards, config.parity_shards)? })
+	}
+
+	pub fn config(&self) -> RsConfig { self.config }
+
+	/// Parity shards for a block of up to `data_shards` shards.
+	pub fn encode(&self, data: &[Shard]) -> Result<Vec<Shard>> {
+		self.check_block_len(data.len())?;
+		let mut parity = vec![[0u8; SHARD_SIZE]; self.config.parity_shards];
+		for (i, shard) in data.iter().enumerate() {
+			self.rs.encode_single_sep(i, shard, &mut parity)?;
+		}
+		Ok(parity)
+	}
+
+	/// Fill in the missing data shards of a block of `data.len()` shards.
+	/// Needs at least `data.len()` shards present across `data` and `parity`.
+	pub fn reconstruct(&self, data: &mut [Option<Shard>], parity: &[Option<Shard>]) -> Result<()> {
+		self.check_block_len(data.len())?;
+		if parity.len() != self.config.parity_shards {
+			return Err(Error::protocol("wrong number of parity slots"));
+		}
+		if data.iter().all(Option::is_some) {
+			return Ok(());
+		}
+		if data.iter().flatten().count() + parity.iter().flatten().count() < data.len() {
+			return Err(Error::TooFewShards);
+		}
+		let mut shards: Vec<(Shard, bool)> = Vec::with_capacity(self.config.total_shards());
+		for i in 0..self.config.data_shards {
+			shards.push(match data.get(i) {
+				Some(Some(s)) => (*s, true),
+				Some(None) => ([0u8; SHARD_SIZE], false),
+				// Padding of a short block: known zeros.
+				None => ([0u8; SHARD_SIZE], true),
+			});
+		}
+		shards.extend(parity.iter().map(|p| p.map_or(([0u8; SHARD_SIZE], false), |s| (s, true))));
+		self.rs.reconstruct_data(&mut shards)?;
+		for (slot, (shard, _)) in data.iter_mut().zip(shards) {
+			slot.get_or_insert(shard);
+		}
+		Ok(())
+	}
+
+	fn check_block_len(&self, len: usize) -> Result<()> {
+		if len == 0 || len > self.config.data_shards {
+			return Err(Error::protocol(format!("block must hold 1..={} data shards", self.config.data_shards)));
+		}
+		Ok(())
+	}
+}
+
+/// Streaming encoder: data shards go out immediately, parity at block end.
+#[derive(Debug, Clone)]
+pub struct RsEncoder {
+	codec: RsCodec,
+	/// Widened so that running past `u32::MAX` is detected rather than wrapped.
+	block: u64,
+	next_index: usize,
+	parity: Vec<Shard>,
+}
+
+impl RsEncoder {
+	pub fn new(config: RsConfig) -> Result<Self> {
+		let codec = RsCodec::new(config)?;
+		Ok(Self { parity: vec![[0u8; SHARD_SIZE]; config.parity_shards], codec, block: 0, next_index: 0 })
+	}
+
+	pub fn config(&self) -> RsConfig { self.codec.config }
+
+	/// Block the next data shard goes into; `None` once every block number
+	/// has been used. Block numbers never wrap.
+	pub fn block(&self) -> Option<u32> { u32::try_from(self.block).ok() }
+
+	/// Data shards pushed into the current block so far.
+	pub fn pending(&self) -> usize { self.next_index }
+
+	/// Emit `shard` as the next data shard, then the block's parity if it is
+	/// now full. Fails once the block numbers are exhausted.
+	pub fn push(&mut self, shard: &Shard, mut emit: impl FnMut(ShardHeader, &Shard)) -> Result<()> {
+		let block = self.block().ok_or_else(|| Error::protocol("block numbers exhausted"))?;
+		// The first shard of a block overwrites the parity, later ones add to it.
+		self.codec.rs.encode_single_sep(self.next_index, shard, &mut self.parity).expect("index below data_shards");
+		emit(ShardHeader { block, index: self.next_index as u8, data_shards: 0 }, shard);
+		self.next_index += 1;
+		if self.next_index == self.codec.config.data_shards {
+			self.finish(&mut emit);
+		}
+		Ok(())
+	}
+
+	/// Close a partial block by emitting its parity; does nothing between blocks.
+	pub fn flush(&mut self, mut emit: impl FnMut(ShardHeader, &Shard)) {
+		if self.next_index > 0 {
+			self.finish(&mut emit);
+		}
+	}
+
+	fn finish(&mut self, emit: &mut impl FnMut(ShardHeader, &Shard)) {
+		let data_shards = self.next_index as u8;
+		// A started block always has a valid number.
+		let block = self.block as u32;
+		for (j, p) in self.parity.iter().enumerate() {
+			emit(ShardHeader { block, index: (self.codec.config.data_shards + j) as u8, data_shards }, p);
+		}
+		self.block += 1;
+		self.next_index = 0;
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecoderStats {
+	pub data_received: u64,
+	pub parity_received: u64,
+	pub recovered: u64,
+	/// Shards seen before or arriving after their block was complete.
+	pub redundant: u64,
+	/// Shards for blocks that already left the window.
+	pub stale: u64,
+	/// Blocks evicted with data still missing.
+	pub blocks_lost: u64,
+}
+
+#[derive(Debug)]
+struct BlockState {
+	data: Vec<Option<Shard>>,
+	parity: Vec<Option<Shard>>,
+	data_shards: Option<usize>,
+	complete: bool,
+}
+
+/// Streaming decoder over a window of recent blocks.
+#[derive(Debug)]
+pub struct RsDecoder {
+	codec: RsCodec,
+	window: usize,
+	blocks: BTreeMap<u32, BlockState>,
+	/// Blocks below this left the window; `u32::MAX + 1` once the last
+	/// block number has.
+	floor: u64,
+	stats: DecoderStats,
+}
+
+impl RsDecoder {
+	pub fn new(config: RsConfig) -> Result<Self> { Self::with_window(config, DEFAULT_DECODER_WINDOW) }
+
+	pub fn with_window(config: RsConfig, window: usize) -> Result<Self> {
+		Ok(Self { codec: RsCodec::new(config)?, window: window.max(1), blocks: BTreeMap::new(), floor: 0, stats: DecoderStats::default() })
+	}
+
+	pub fn stats(&self) -> DecoderStats { self.stats }
+
+	/// Oldest block still accepted; anything earlier has left the window.
+	pub fn window_start(&self) -> u64 { self.floor }
+
+	/// Take in one shard and emit every data shard that became available:
+	/// the shard itself if it carries data, or those rebuilt with its help.
+	/// Emitted headers are data headers; order follows arrival, not index.
+	pub fn push(&mut self, header: ShardHeader, shard: &Shard, mut emit: impl FnMut(ShardHeader, &Shard)) -> Result<()> {
+		let RsConfig { data_shards: k_max, parity_shards: m } = self.codec.config;
+		let index = header.index as usize;
+		if index >= k_max + m {
+			return Err(Error::protocol("shard index beyond block"));
+		}
+		let is_parity = index >= k_max;
+		if is_parity && !(1..=k_max).contains(&(header.data_shards as usize)) {
+			return Err(Error::protocol("parity shard carries an invalid block length"));
+		}
+		if u64::from(header.block) < self.floor {
+			self.stats.stale += 1;
+			return Ok(());
+		}
+		self.make_room(header.block);
+		let Self { codec, blocks, stats, .. } = self;
+		let state = blocks.entry(header.block).or_insert_with(|| BlockState { data: vec![None; k_max], parity: vec![None; m], data_shards: None, complete: false });
+		if state.complete {
+			stats.redundant += 1;
+			return Ok(());
+		}
+		if is_parity {
+			stats.parity_received += 1;
+			state.data_shards = Some(header.data_shards as usize);
+			state.parity[index - k_max] = Some(*shard);
+		} else {
+			if state.data[index].is_some() {
+				stats.redundant += 1;
+				return Ok(());
+			}
+			stats.data_received += 1;
+			state.data[index] = Some(*shard);
+			emit(header, shard);
+		}
+
+		let k = state.data_shards.unwrap_or(k_max);
+		let have = state.data[..k].iter().flatten().count();
+		if have < k && state.data_shards.is_some() && have + state.parity.iter().flatten().count() >= k {
+			let missing: Vec<usize> = (0..k).filter(|i| state.data[*i].is_none()).collect();
+			codec.reconstruct(&mut state.data[..k], &state.parity)?;
+			for i in missing {
+				stats.recovered += 1;
+				emit(ShardHeader { block: header.block, index: i as u8, data_shards: 0 }, state.data[i].as_ref().expect("reconstructed"));
+			}
+		} else if have < k {
+			return Ok(());
+		}
+		// Everything is out; keep a marker so late shards are not re-emitted.
+		*state = BlockState { data: Vec::new(), parity: Vec::new(), data_shards: Some(k), complete: true };
+		Ok(())
+	}
+
+	/// Evict the oldest blocks so that `block` fits in the window.
+	fn make_room(&mut self, block: u32) {
+		if self.blocks.contains_key(&block) {
+			return;
+		}
+		while self.blocks.len() >= self.window {
+			let Some((old, state)) = self.blocks.pop_first() else { break };
+			if !state.complete {
+				self.stats.blocks_lost += 1;
+			}
+			self.floor = self.floor.max(u64::from(old) + 1);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn shard(seed: u8) -> Shard {
+		let mut s = [0u8; SHARD_SIZE];
+		for (i, b) in s.iter_mut().enumerate() {
+			*b = seed.wrapping_mul(31).wrapping_add(i as u8);
+		}
+		s
+	}
+
+	#[test]
+	fn default_config_is_thirty_percent() {
+		let cfg = RsConfig::default();
+		assert_eq!(cfg.total_shards(), 13);
+		assert!((cfg.redundancy() - 0.3).abs() < 1e-12);
+		assert_eq!(RsConfig::with_redundancy(10, 0.3), cfg);
+		assert_eq!(RsConfig::with_redundancy(20, 0.3).parity_shards, 6);
+		assert!(RsConfig::RS_255_223.validate().is_ok());
+		assert!(RsConfig::new(250, 10).validate().is_err());
+		assert!(RsConfig::new(10, 0).validate().is_err());
+	}
+
+	#[test]
+	fn block_recovers_from_any_parity_count_of_erasures() {
+		let codec = RsCodec::new(RsConfig::default()).unwrap();
+		let data: Vec<Shard> = (0..10).map(shard).collect();
+		let parity = codec.encode(&data).unwrap();
+		let mut got: Vec<Option<Shard>> = data.iter().copied().map(Some).collect();
+		let par: Vec<Option<Shard>> = parity.iter().copied().map(Some).collect();
+		got[0] = None;
+		got[5] = None;
+		got[9] = None;
+		codec.reconstruct(&mut got, &par).unwrap();
+		assert_eq!(got.iter().map(|s| s.unwrap()).collect::<Vec<_>>(), data);
+
+		// Parity losses count too: two data and one parity shard missing.
+		let mut par = par;
+		par[1] = None;
+		got[2] = None;
+		got[7] = None;
+		codec.reconstruct(&mut got, &par).unwrap();
+		assert_eq!(got.into_iter().map(Option::unwrap).collect::<Vec<_>>(), data);
+
+		let mut too_few: Vec<Option<Shard>> = data.iter().copied().map(Some).collect();
+		too_few[..4].fill(None);
+		assert!(matches!(codec.reconstruct(&mut too_few, &parity.iter().copied().map(Some).collect::<Vec<_>>()), Err(Error::TooFewShards)));
+	}
+
+	#[test]
+	fn short_block_is_zero_padded() {
+		let codec = RsCodec::new(RsConfig::default()).unwrap();
+		let data: Vec<Shard> = (0..4).map(shard).collect();
+		let parity = codec.encode(&data).unwrap();
+		let mut padded = data.clone();
+		padded.resize(10, [0u8; SHARD_SIZE]);
+		assert_eq!(parity, codec.encode(&padded).unwrap());
+		let mut got = vec![None, Some(data[1]), None, None];
+		codec.reconstruct(&mut got, &parity.iter().copied().map(Some).collect::<Vec<_>>()).unwrap();
+		assert_eq!(got.into_iter().map(Option::unwrap).collect::<Vec<_>>(), data);
+	}
+
+	#[test]
+	fn streaming_round_trip_with_losses() {
+		let cfg = RsConfig::new(4, 2);
+		let mut enc = RsEncoder::new(cfg).unwrap();
+		let mut wire = Vec::new();
+		for i in 0..10 {
+			enc.push(&shard(i), |h, s| wire.push((h, *s))).unwrap();
+		}
+		enc.flush(|h, s| wire.push((h, *s)));
+		// 2 full blocks of 4 + 2 parity, then 2 data + 2 parity.
+		assert_eq!(wire.len(), 16);
+		assert_eq!((wire[15].0.block, wire[15].0.data_shards), (2, 2));
+
+		let mut dec = RsDecoder::new(cfg).unwrap();
+		let mut out = Vec::new();
+		for (n, (h, s)) in wire.iter().enumerate() {
+			// Drop two shards of every block.
+			if [0, 3, 7, 9, 12, 14].contains(&n) {
+				continue;
+			}
+			dec.push(*h, s, |h, s| out.push((h.block, h.index, *s))).unwrap();
+		}
+		out.sort_by_key(|(b, i, _)| (*b, *i));
+		assert_eq!(out.into_iter().map(|(_, _, s)| s).collect::<Vec<_>>(), (0..10).map(shard).collect::<Vec<_>>());
+		let stats = dec.stats();
+		assert_eq!((stats.recovered, stats.blocks_lost), (5, 0));
+	}
+
+	#[test]
+	fn decoder_window_drops_old_blocks() {
+		let cfg = RsConfig::new(2, 1);
+		let mut dec = RsDecoder::with_window(cfg, 2).unwrap();
+		let h = |block, index, data_shards| ShardHeader { block, index, data_shards };
+		let s = shard(1);
+		for block in 0..3 {
+			dec.push(h(block, 0, 0), &s, |_, _| {}).unwrap();
+		}
+		dec.push(h(0, 1, 0), &s, |_, _| panic!("block 0 left the window")).unwrap();
+		assert_eq!((dec.stats().stale, dec.stats().blocks_lost), (1, 1));
+		assert!(dec.push(h(3, 2, 0), &s, |_, _| {}).is_err());
+		assert!(dec.push(h(3, 9, 0), &s, |_, _| {}).is_err());
+		assert_eq!(ShardHeader::from_bytes(&h(7, 2, 1).to_bytes()).unwrap(), h(7, 2, 1));
+	}
+
+	#[test]
+	fn block_numbers_do_not_wrap() {
+		let cfg = RsConfig::new(2, 1);
+		let mut enc = RsEncoder::new(cfg).unwrap();
+		enc.block = u64::from(u32::MAX);
+		let mut wire = Vec::new();
+		for i in 0..2 {
+			enc.push(&shard(i), |h, s| wire.push((h, *s))).unwrap();
+		}
+		assert_eq!(enc.block(), None);
+		assert!(enc.push(&shard(2), |_, _| panic!("wrapped")).is_err());
+		assert!(wire.iter().all(|(h, _)| h.block == u32::MAX));
+
+		let mut dec = RsDecoder::with_window(cfg, 1).unwrap();
+		dec.push(ShardHeader { block: u32::MAX - 1, ..wire[0].0 }, &wire[0].1, |_, _| {}).unwrap();
+		let mut out = 0;
+		for (h, s) in &wire {
+			dec.push(*h, s, |_, _| out += 1).unwrap();
+		}
+		assert_eq!((out, dec.window_start()), (2, u64::from(u32::MAX)));
+		// Low block numbers after the last one are stale, not a new cycle.
+		dec.push(ShardHeader { block: 0, ..wire[0].0 }, &wire[0].1, |_, _| panic!("wrapped")).unwrap();
+		assert_eq!(dec.stats().stale, 1);
+	}
+}
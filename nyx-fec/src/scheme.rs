@@ -0,0 +1,82 @@
+﻿#![forbid(unsafe_code)]
+
+//! Negotiable FEC schemes.
+//!
+//! Like cipher suites, each scheme is advertised as an optional capability ID
+//! and the responder picks the first scheme in its own preference order that
+//! the peer also advertised. A peer that advertises no scheme is a v0.1
+//! stack and gets Reed-Solomon.
+
+use crate::errors::{Error, Result};
+
+/// Capability ID of systematic Reed-Solomon over 1280-byte shards.
+pub const CAP_FEC_REED_SOLOMON: u32 = 0x0200;
+/// Capability ID of RFC 6330 RaptorQ with 1280-byte symbols.
+pub const CAP_FEC_RAPTORQ: u32 = 0x0201;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FecScheme {
+	#[default]
+	ReedSolomon,
+	RaptorQ,
+}
+
+impl FecScheme {
+	pub const ALL: [FecScheme; 2] = [FecScheme::ReedSolomon, FecScheme::RaptorQ];
+
+	pub fn capability_id(&self) -> u32 {
+		match self {
+			Self::ReedSolomon => CAP_FEC_REED_SOLOMON,
+			Self::RaptorQ => CAP_FEC_RAPTORQ,
+		}
+	}
+
+	pub fn from_capability_id(id: u32) -> Option<Self> { Self::ALL.into_iter().find(|s| s.capability_id() == id) }
+
+	/// Whether this build can run the scheme; RaptorQ needs the `raptorq` feature.
+	pub fn is_supported(&self) -> bool {
+		match self {
+			Self::ReedSolomon => true,
+			Self::RaptorQ => cfg!(feature = "raptorq"),
+		}
+	}
+
+	/// Supported schemes, preferring RaptorQ when it is built in.
+	pub fn supported() -> Vec<FecScheme> { [Self::RaptorQ, Self::ReedSolomon].into_iter().filter(Self::is_supported).collect() }
+}
+
+/// Capability IDs to advertise for the given preference list; schemes this
+/// build cannot run are left out.
+pub fn advertise(prefs: &[FecScheme]) -> Vec<u32> { prefs.iter().filter(|s| s.is_supported()).map(|s| s.capability_id()).collect() }
+
+/// Choose a scheme given our preference order and the capability IDs the peer advertised.
+pub fn negotiate(local_prefs: &[FecScheme], peer_caps: &[u32]) -> Result<FecScheme> {
+	let local: Vec<FecScheme> = local_prefs.iter().copied().filter(FecScheme::is_supported).collect();
+	let peer: Vec<FecScheme> = peer_caps.iter().filter_map(|&id| FecScheme::from_capability_id(id)).collect();
+	if peer.is_empty() {
+		// Legacy peer: only Reed-Solomon is implied.
+		return if local.contains(&FecScheme::default()) { Ok(FecScheme::default()) } else { Err(Error::protocol("no common FEC scheme")) };
+	}
+	local.into_iter().find(|s| peer.contains(s)).ok_or_else(|| Error::protocol("no common FEC scheme"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn legacy_peer_gets_reed_solomon() {
+		assert_eq!(negotiate(&FecScheme::supported(), &[0x0001, 0x0100]).unwrap(), FecScheme::ReedSolomon);
+		assert!(negotiate(&[FecScheme::RaptorQ], &[0x0001]).is_err());
+		assert_eq!(FecScheme::from_capability_id(CAP_FEC_RAPTORQ), Some(FecScheme::RaptorQ));
+	}
+
+	#[test]
+	fn responder_preference_wins_among_supported() {
+		let peer = [CAP_FEC_REED_SOLOMON, CAP_FEC_RAPTORQ];
+		let expected = if cfg!(feature = "raptorq") { FecScheme::RaptorQ } else { FecScheme::ReedSolomon };
+		assert_eq!(negotiate(&[FecScheme::RaptorQ, FecScheme::ReedSolomon], &peer).unwrap(), expected);
+		assert_eq!(negotiate(&[FecScheme::ReedSolomon, FecScheme::RaptorQ], &peer).unwrap(), FecScheme::ReedSolomon);
+		assert_eq!(advertise(&FecScheme::ALL).len(), FecScheme::supported().len());
+	}
+}
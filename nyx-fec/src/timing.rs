@@ -1 +1,319 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Timing obfuscation for outgoing cells (spec §5.3, design doc §4.4).
+//!
+//! [`TimingQueue`] sits between the padding layer and the transport and
+//! decides when each cell leaves:
+//!
+//! - [`TimingMode::Jitter`] holds every cell for an independent random delay,
+//!   Gaussian (mean ± σ) or exponential, capped at `max_delay`. Cells leave in
+//!   release order, so one may overtake another.
+//! - [`TimingMode::ConstantRate`] sends one cell per `interval` and fills
+//!   idle slots with padding cells, so the link rate never changes. A caller
+//!   that falls behind loses the missed slots instead of bursting to catch up.
+//!
+//! At most `capacity` cells are held; when full, [`DropPolicy`] decides which
+//! cell is lost. [`TimingQueue::next`] is cancel-safe, so it can sit in a
+//! `select!` next to the channel that feeds [`TimingQueue::push`].
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Exp, Normal};
+use tokio::time::Instant;
+
+use crate::errors::{Error, Result};
+use crate::padding::{Cell, Padder};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Jitter {
+	Gaussian { mean: Duration, sigma: Duration },
+	Exponential { mean: Duration },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimingMode {
+	Jitter(Jitter),
+	ConstantRate { interval: Duration },
+}
+
+/// What to do with a cell pushed into a full queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+	/// Refuse the new cell.
+	#[default]
+	RejectNew,
+	/// Make room by dropping the cell queued longest.
+	DropOldest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimingConfig {
+	pub mode: TimingMode,
+	/// Cells held at most; memory stays under `capacity` × 1280 bytes.
+	pub capacity: usize,
+	/// Upper bound on a jitter delay.
+	pub max_delay: Duration,
+	pub drop_policy: DropPolicy,
+}
+
+impl Default for TimingConfig {
+	fn default() -> Self {
+		Self {
+			mode: TimingMode::Jitter(Jitter::Gaussian { mean: Duration::from_millis(20), sigma: Duration::from_millis(5) }),
+			capacity: 256,
+			max_delay: Duration::from_millis(200),
+			drop_policy: DropPolicy::RejectNew,
+		}
+	}
+}
+
+impl TimingConfig {
+	pub fn validate(&self) -> Result<()> {
+		if self.capacity == 0 {
+			return Err(Error::config("timing queue capacity must be positive"));
+		}
+		match self.mode {
+			TimingMode::ConstantRate { interval } if interval.is_zero() => Err(Error::config("constant-rate interval must be positive")),
+			TimingMode::Jitter(Jitter::Exponential { mean }) if mean.is_zero() => Err(Error::config("exponential jitter needs a positive mean")),
+			_ => Ok(()),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimingStats {
+	pub enqueued: u64,
+	/// Cells carrying data that left the queue.
+	pub sent: u64,
+	/// Padding cells sent in idle constant-rate slots.
+	pub idle: u64,
+	pub dropped: u64,
+	/// Constant-rate slots skipped because the caller fell behind.
+	pub missed_slots: u64,
+	pub max_queued: usize,
+}
+
+enum Sampler {
+	Gaussian(Normal<f64>),
+	Exponential(Exp<f64>),
+}
+
+enum Queue {
+	/// Keyed by release time, then arrival order.
+	Jitter { sampler: Sampler, cells: BTreeMap<(Instant, u64), Cell> },
+	ConstantRate { interval: Duration, next_slot: Option<Instant>, cells: VecDeque<Cell>, padder: Box<Padder> },
+}
+
+pub struct TimingQueue {
+	config: TimingConfig,
+	queue: Queue,
+	rng: StdRng,
+	seq: u64,
+	stats: TimingStats,
+}
+
+impl TimingQueue {
+	pub fn new(config: TimingConfig) -> Result<Self> { Self::with_rng(config, StdRng::from_entropy()) }
+
+	/// Deterministic delays and padding for tests and simulations.
+	pub fn with_seed(config: TimingConfig, seed: u64) -> Result<Self> { Self::with_rng(config, StdRng::seed_from_u64(seed)) }
+
+	fn with_rng(config: TimingConfig, mut rng: StdRng) -> Result<Self> {
+		config.validate()?;
+		let queue = match config.mode {
+			TimingMode::Jitter(j) => {
+				let sampler = match j {
+					Jitter::Gaussian { mean, sigma } => Sampler::Gaussian(Normal::new(mean.as_secs_f64(), sigma.as_secs_f64()).map_err(|e| Error::config(e.to_string()))?),
+					Jitter::Exponential { mean } => Sampler::Exponential(Exp::new(1.0 / mean.as_secs_f64()).map_err(|e| Error::config(e.to_string()))?),
+				};
+				Queue::Jitter { sampler, cells: BTreeMap::new() }
+			}
+			TimingMode::ConstantRate { interval } => Queue::ConstantRate { interval, next_slot: None, cells: VecDeque::new(), padder: Box::new(Padder::new(rng.gen())) },
+		};
+		Ok(Self { config, queue, rng, seq: 0, stats: TimingStats::default() })
+	}
+
+	pub fn config(&self) -> &TimingConfig { &self.config }
+
+	pub fn stats(&self) -> TimingStats { self.stats }
+
+	pub fn len(&self) -> usize {
+		match &self.queue {
+			Queue::Jitter { cells, .. } => cells.len(),
+			Queue::ConstantRate { cells, .. } => cells.len(),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+	/// Queue a cell; returns false if it was refused under [`DropPolicy::RejectNew`].
+	pub fn push(&mut self, cell: Cell) -> bool {
+		if self.len() >= self.config.capacity {
+			self.stats.dropped += 1;
+			match self.config.drop_policy {
+				DropPolicy::RejectNew => return false,
+				DropPolicy::DropOldest => self.drop_oldest(),
+			}
+		}
+		let seq = self.seq;
+		self.seq += 1;
+		match &mut self.queue {
+			Queue::Jitter { sampler, cells } => {
+				let secs = match sampler {
+					Sampler::Gaussian(n) => n.sample(&mut self.rng),
+					Sampler::Exponential(e) => e.sample(&mut self.rng),
+				};
+				let delay = Duration::from_secs_f64(secs.max(0.0)).min(self.config.max_delay);
+				cells.insert((Instant::now() + delay, seq), cell);
+			}
+			Queue::ConstantRate { cells, .. } => cells.push_back(cell),
+		}
+		self.stats.enqueued += 1;
+		self.stats.max_queued = self.stats.max_queued.max(self.len());
+		true
+	}
+
+	fn drop_oldest(&mut self) {
+		match &mut self.queue {
+			Queue::Jitter { cells, .. } => {
+				if let Some(&key) = cells.keys().min_by_key(|(_, seq)| *seq) {
+					cells.remove(&key);
+				}
+			}
+			Queue::ConstantRate { cells, .. } => {
+				cells.pop_front();
+			}
+		}
+	}
+
+	/// Wait for the next cell to send. In jitter mode this pends while the
+	/// queue is empty; in constant-rate mode it returns a padding cell for an
+	/// empty slot.
+	pub async fn next(&mut self) -> Cell {
+		match &mut self.queue {
+			Queue::Jitter { cells, .. } => {
+				let Some(&(at, _)) = cells.keys().next() else { return std::future::pending().await };
+				tokio::time::sleep_until(at).await;
+				self.stats.sent += 1;
+				cells.pop_first().expect("queue is not empty").1
+			}
+			Queue::ConstantRate { interval, next_slot, cells, padder } => {
+				let now = Instant::now();
+				let slot = match *next_slot {
+					Some(s) if s + *interval < now => {
+						// Fell behind: skip the missed slots rather than burst.
+						self.stats.missed_slots += ((now - s).as_nanos() / interval.as_nanos()) as u64;
+						now
+					}
+					Some(s) => s,
+					None => now,
+				};
+				tokio::time::sleep_until(slot).await;
+				*next_slot = Some(slot + *interval);
+				match cells.pop_front() {
+					Some(c) => {
+						self.stats.sent += 1;
+						c
+					}
+					None => {
+						self.stats.idle += 1;
+						padder.idle_cell()
+					}
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::padding::{Unpadder, CELL_SIZE};
+
+	fn cell(tag: u8) -> Cell { [tag; CELL_SIZE] }
+
+	fn jitter(j: Jitter, capacity: usize, drop_policy: DropPolicy) -> TimingQueue {
+		TimingQueue::with_seed(TimingConfig { mode: TimingMode::Jitter(j), capacity, max_delay: Duration::from_millis(100), drop_policy }, 9).unwrap()
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn jitter_delays_stay_within_bounds() {
+		let mut q = jitter(Jitter::Gaussian { mean: Duration::from_millis(30), sigma: Duration::from_millis(10) }, 1000, DropPolicy::RejectNew);
+		let start = Instant::now();
+		for i in 0..500 {
+			assert!(q.push(cell(i as u8)));
+		}
+		let mut delays = Vec::new();
+		while !q.is_empty() {
+			q.next().await;
+			delays.push((Instant::now() - start).as_secs_f64());
+		}
+		let mean = delays.iter().sum::<f64>() / delays.len() as f64;
+		let sd = (delays.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / delays.len() as f64).sqrt();
+		assert!((mean - 0.030).abs() < 0.003, "mean {mean}");
+		assert!((sd - 0.010).abs() < 0.003, "sd {sd}");
+		assert!(delays.windows(2).all(|w| w[0] <= w[1]));
+		assert_eq!(q.stats().sent, 500);
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn exponential_delay_is_capped() {
+		let mut q = jitter(Jitter::Exponential { mean: Duration::from_millis(50) }, 1000, DropPolicy::RejectNew);
+		let start = Instant::now();
+		for _ in 0..200 {
+			q.push(cell(0));
+		}
+		while !q.is_empty() {
+			q.next().await;
+		}
+		assert!(Instant::now() - start <= Duration::from_millis(101));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn full_queue_applies_drop_policy() {
+		let j = Jitter::Gaussian { mean: Duration::from_millis(10), sigma: Duration::ZERO };
+		let mut q = jitter(j, 2, DropPolicy::RejectNew);
+		assert!(q.push(cell(1)) && q.push(cell(2)));
+		assert!(!q.push(cell(3)));
+		assert_eq!((q.next().await[0], q.next().await[0]), (1, 2));
+
+		let mut q = jitter(j, 2, DropPolicy::DropOldest);
+		assert!(q.push(cell(1)) && q.push(cell(2)) && q.push(cell(3)));
+		assert_eq!((q.next().await[0], q.next().await[0]), (2, 3));
+		assert_eq!((q.stats().dropped, q.stats().max_queued), (1, 2));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn constant_rate_fills_idle_slots() {
+		let cfg = TimingConfig { mode: TimingMode::ConstantRate { interval: Duration::from_millis(10) }, ..Default::default() };
+		let mut q = TimingQueue::with_seed(cfg, 1).unwrap();
+		let start = Instant::now();
+		let mut padder = Padder::new([3; 32]);
+		padder.push(b"hello", |c| assert!(q.push(*c))).unwrap();
+		padder.flush(|c| assert!(q.push(*c)));
+		let mut unpad = Unpadder::new();
+		let mut sent = Vec::new();
+		for _ in 0..5 {
+			let c = q.next().await;
+			let mut frames = Vec::new();
+			unpad.push(&c, |f| frames.push(f.to_vec())).unwrap();
+			sent.push(((Instant::now() - start).as_millis(), frames));
+		}
+		// One cell per slot; idle slots carry padding cells that unpack to nothing.
+		let expected: Vec<(u128, Vec<Vec<u8>>)> = vec![(0, vec![b"hello".to_vec()]), (10, vec![]), (20, vec![]), (30, vec![]), (40, vec![])];
+		assert_eq!(sent, expected);
+		assert_eq!((q.stats().sent, q.stats().idle), (1, 4));
+
+		// A stalled caller skips slots instead of bursting.
+		tokio::time::sleep(Duration::from_millis(95)).await;
+		let before = Instant::now();
+		q.next().await;
+		q.next().await;
+		assert_eq!(Instant::now() - before, Duration::from_millis(10));
+		// Nine slots (50 to 130 ms) passed unserved: one goes out late at 135 ms, eight are skipped.
+		assert_eq!(q.stats().missed_slots, 8);
+	}
+}
@@ -1 +1,234 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! RSA accumulator for publishing cMix batch membership (spec v1.0 §4).
+//!
+//! Each element is hashed to a 256-bit prime `p`; the accumulator is
+//! `A = g^(p_1 · … · p_n) mod N`. A membership witness for `p` is the same
+//! product without `p`, so `w^p = A`. Adding `q` turns `A` into `A^q` and every
+//! existing witness into `w^q`, which holders can apply themselves.
+//!
+//! The group is the RSA-2048 modulus shared with [`crate::vdf`]; nobody can
+//! forge a witness without its factorisation (strong RSA assumption).
+//! Two elements that hash to the same prime share every witness, so the
+//! primes are wide enough that finding such a collision costs about 2^128
+//! hash evaluations, the same margin as the rest of the protocol.
+
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+
+use crate::errors::{Error, Result};
+use crate::vdf::{is_probable_prime, RSA_2048_HEX};
+
+/// Bit length of the primes elements are mapped to.
+pub const ELEMENT_PRIME_BITS: usize = 256;
+pub const GENERATOR: u32 = 3;
+
+const CTX_PRIME: &str = "nyx-accumulator v1 hash to prime";
+
+/// The RSA-2048 challenge modulus.
+pub fn default_modulus() -> BigUint { BigUint::parse_bytes(RSA_2048_HEX.as_bytes(), 16).expect("valid hex") }
+
+/// Map an element to its representative prime.
+pub fn hash_to_prime(element: &[u8]) -> BigUint {
+	let mut xof = blake3::Hasher::new_derive_key(CTX_PRIME).update(element).finalize_xof();
+	let mut buf = [0u8; ELEMENT_PRIME_BITS / 8];
+	loop {
+		xof.fill(&mut buf);
+		buf[0] |= 0x80;
+		buf[buf.len() - 1] |= 1;
+		let candidate = BigUint::from_bytes_be(&buf);
+		if is_probable_prime(&candidate) {
+			return candidate;
+		}
+	}
+}
+
+/// Membership witness for one element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Witness(pub BigUint);
+
+impl Witness {
+	/// Follow the accumulator across the addition of `element`.
+	pub fn update_on_add(&mut self, modulus: &BigUint, element: &[u8]) { self.0 = self.0.modpow(&hash_to_prime(element), modulus); }
+
+	pub fn to_bytes(&self, modulus: &BigUint) -> Vec<u8> { encode(&self.0, modulus) }
+
+	pub fn from_bytes(bytes: &[u8], modulus: &BigUint) -> Result<Self> { decode_value(bytes, modulus).map(Self) }
+}
+
+/// Check `w^H(element) = A` against a published accumulator value.
+pub fn verify_membership(modulus: &BigUint, value: &BigUint, element: &[u8], witness: &Witness) -> bool {
+	if witness.0.is_zero() || witness.0 >= *modulus {
+		return false;
+	}
+	witness.0.modpow(&hash_to_prime(element), modulus) == *value
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RsaAccumulator {
+	modulus: BigUint,
+	value: BigUint,
+	/// Primes of all added elements, in insertion order.
+	members: Vec<BigUint>,
+}
+
+impl Default for RsaAccumulator {
+	fn default() -> Self { Self::new() }
+}
+
+impl RsaAccumulator {
+	/// Empty accumulator over RSA-2048.
+	pub fn new() -> Self { Self::with_modulus(default_modulus()).expect("valid modulus") }
+
+	/// Only use moduli whose factorisation nobody holds.
+	pub fn with_modulus(modulus: BigUint) -> Result<Self> {
+		if modulus.bits() < 256 {
+			return Err(Error::config("accumulator modulus must be at least 256 bits"));
+		}
+		Ok(Self { value: BigUint::from(GENERATOR), modulus, members: Vec::new() })
+	}
+
+	pub fn modulus(&self) -> &BigUint { &self.modulus }
+
+	pub fn value(&self) -> &BigUint { &self.value }
+
+	/// Fixed-length big-endian accumulator value for publication.
+	pub fn value_bytes(&self) -> Vec<u8> { encode(&self.value, &self.modulus) }
+
+	pub fn len(&self) -> usize { self.members.len() }
+
+	pub fn is_empty(&self) -> bool { self.members.is_empty() }
+
+	pub fn add(&mut self, element: &[u8]) {
+		let p = hash_to_prime(element);
+		self.value = self.value.modpow(&p, &self.modulus);
+		self.members.push(p);
+	}
+
+	/// Add several elements with a single exponentiation.
+	pub fn extend<'a>(&mut self, elements: impl IntoIterator<Item = &'a [u8]>) {
+		let primes: Vec<BigUint> = elements.into_iter().map(hash_to_prime).collect();
+		let product = primes.iter().fold(BigUint::one(), |acc, p| acc * p);
+		self.value = self.value.modpow(&product, &self.modulus);
+		self.members.extend(primes);
+	}
+
+	pub fn contains(&self, element: &[u8]) -> bool { self.members.contains(&hash_to_prime(element)) }
+
+	/// Witness for `element`, or `None` if it was never added. Costs one
+	/// exponentiation by the product of all other members.
+	pub fn witness(&self, element: &[u8]) -> Option<Witness> {
+		let p = hash_to_prime(element);
+		let skip = self.members.iter().position(|m| *m == p)?;
+		let product = self.members.iter().enumerate().filter(|(i, _)| *i != skip).fold(BigUint::one(), |acc, (_, m)| acc * m);
+		Some(Witness(BigUint::from(GENERATOR).modpow(&product, &self.modulus)))
+	}
+
+	/// Witnesses for all members, in insertion order. Splits the members in
+	/// halves and raises `g` by the product of the other half at each level
+	/// (RootFactor), so the total exponent size is `n log n` primes rather
+	/// than the `n²` of calling [`Self::witness`] per member.
+	pub fn witnesses(&self) -> Vec<Witness> {
+		let mut out = Vec::with_capacity(self.members.len());
+		if !self.members.is_empty() {
+			root_factor(BigUint::from(GENERATOR), &self.members, &self.modulus, &mut out);
+		}
+		out
+	}
+
+	pub fn verify(&self, element: &[u8], witness: &Witness) -> bool { verify_membership(&self.modulus, &self.value, element, witness) }
+}
+
+fn root_factor(g: BigUint, primes: &[BigUint], modulus: &BigUint, out: &mut Vec<Witness>) {
+	if let [_] = primes {
+		out.push(Witness(g));
+		return;
+	}
+	let (left, right) = primes.split_at(primes.len() / 2);
+	let product = |half: &[BigUint]| half.iter().fold(BigUint::one(), |acc, p| acc * p);
+	root_factor(g.modpow(&product(right), modulus), left, modulus, out);
+	root_factor(g.modpow(&product(left), modulus), right, modulus, out);
+}
+
+fn encode(v: &BigUint, modulus: &BigUint) -> Vec<u8> {
+	let len = modulus.bits().div_ceil(8) as usize;
+	let bytes = v.to_bytes_be();
+	let mut out = vec![0u8; len.saturating_sub(bytes.len())];
+	out.extend_from_slice(&bytes);
+	out
+}
+
+/// Parse a fixed-length group element, such as a published accumulator value.
+pub fn decode_value(bytes: &[u8], modulus: &BigUint) -> Result<BigUint> {
+	if bytes.len() != modulus.bits().div_ceil(8) as usize {
+		return Err(Error::protocol("accumulator element has the wrong length"));
+	}
+	let v = BigUint::from_bytes_be(bytes);
+	if v.is_zero() || v >= *modulus { Err(Error::protocol("accumulator element out of range")) } else { Ok(v) }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn witnesses_verify_for_members_only() {
+		let mut acc = RsaAccumulator::new();
+		for e in [b"a".as_slice(), b"b", b"c"] {
+			acc.add(e);
+		}
+		let w = acc.witness(b"b").unwrap();
+		assert!(acc.verify(b"b", &w));
+		assert!(!acc.verify(b"a", &w));
+		assert!(acc.witness(b"d").is_none());
+		assert!(!acc.contains(b"d"));
+	}
+
+	#[test]
+	fn witness_update_tracks_additions() {
+		let mut acc = RsaAccumulator::new();
+		acc.add(b"first");
+		let mut w = acc.witness(b"first").unwrap();
+		acc.add(b"second");
+		assert!(!acc.verify(b"first", &w));
+		w.update_on_add(acc.modulus(), b"second");
+		assert!(acc.verify(b"first", &w));
+		assert_eq!(w, acc.witness(b"first").unwrap());
+	}
+
+	#[test]
+	fn batch_extend_matches_sequential_adds() {
+		let mut one = RsaAccumulator::new();
+		let mut many = RsaAccumulator::new();
+		let items: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 8]).collect();
+		for i in &items {
+			one.add(i);
+		}
+		many.extend(items.iter().map(Vec::as_slice));
+		assert_eq!(one, many);
+		let bytes = many.witness(&items[3]).unwrap().to_bytes(many.modulus());
+		assert_eq!(bytes.len(), 256);
+		assert!(many.verify(&items[3], &Witness::from_bytes(&bytes, many.modulus()).unwrap()));
+	}
+
+	#[test]
+	fn all_witnesses_match_single_ones() {
+		let mut acc = RsaAccumulator::new();
+		let items: Vec<Vec<u8>> = (0..7u8).map(|i| vec![i; 8]).collect();
+		acc.extend(items.iter().map(Vec::as_slice));
+		let all = acc.witnesses();
+		assert_eq!(all.len(), items.len());
+		for (item, w) in items.iter().zip(&all) {
+			assert_eq!(*w, acc.witness(item).unwrap());
+		}
+		assert!(RsaAccumulator::new().witnesses().is_empty());
+	}
+
+	#[test]
+	fn element_primes_are_256_bit() {
+		let p = hash_to_prime(b"x");
+		assert_eq!(p.bits(), 256);
+		assert!(is_probable_prime(&p));
+		assert_ne!(p, hash_to_prime(b"y"));
+	}
+}
@@ -1 +1,193 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Sender anonymity estimation.
+//!
+//! Two independent sources of uncertainty are tracked for a global passive
+//! observer:
+//!
+//! - **Mixing.** In a round, an output could have come from any of the `k`
+//!   packets that arrived at the mix: the real inputs of a cMix batch (local
+//!   padding dummies do not count, since they never crossed a link), or
+//!   `(arrival rate + own cover rate) × mean delay` packets for per-packet
+//!   exponential delays. Cover packets are indistinguishable from real ones
+//!   and do count, whether they arrived or were emitted by this node. The
+//!   permutation is uniform, so a round contributes `log2 k` bits; Shannon
+//!   entropy averages over recent rounds, min-entropy takes the worst one.
+//! - **Routing.** The entropy of the per-hop node selection distribution,
+//!   times the number of hops.
+//!
+//! The two are independent, so the joint entropies add. `2^shannon` is
+//! reported as the effective anonymity set size.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Shannon entropy in bits of a distribution given by non-negative weights.
+pub fn shannon_entropy(weights: &[f64]) -> f64 {
+	let total: f64 = weights.iter().filter(|w| **w > 0.0).sum();
+	if total <= 0.0 {
+		return 0.0;
+	}
+	weights.iter().filter(|w| **w > 0.0).map(|w| w / total).map(|p| -p * p.log2()).sum::<f64>().max(0.0)
+}
+
+/// Min-entropy in bits, `-log2 max p`: the adversary's best single guess.
+pub fn min_entropy(weights: &[f64]) -> f64 {
+	let total: f64 = weights.iter().filter(|w| **w > 0.0).sum();
+	let max = weights.iter().cloned().fold(0.0, f64::max);
+	if total <= 0.0 || max <= 0.0 { 0.0 } else { -(max / total).log2() }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnonymityConfig {
+	/// Mixing rounds kept for the estimate.
+	pub window: usize,
+	/// Below this min-entropy the estimate is flagged as degraded.
+	pub alert_min_entropy_bits: f64,
+}
+
+impl Default for AnonymityConfig {
+	fn default() -> Self { Self { window: 64, alert_min_entropy_bits: 4.0 } }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct AnonymityMetrics {
+	pub shannon_bits: f64,
+	pub min_entropy_bits: f64,
+	/// `2^shannon_bits`.
+	pub anonymity_set_size: f64,
+	pub mixing_shannon_bits: f64,
+	pub mixing_min_entropy_bits: f64,
+	pub routing_shannon_bits: f64,
+	pub routing_min_entropy_bits: f64,
+	/// Rounds in the current window.
+	pub rounds: usize,
+	/// Min-entropy is below the alert threshold.
+	pub degraded: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnonymityEstimator {
+	config: AnonymityConfig,
+	/// Candidate count per recent mixing round.
+	rounds: VecDeque<f64>,
+	routing_shannon: f64,
+	routing_min: f64,
+	cover_pps: f64,
+}
+
+impl Default for AnonymityEstimator {
+	fn default() -> Self { Self::new(AnonymityConfig::default()) }
+}
+
+impl AnonymityEstimator {
+	pub fn new(config: AnonymityConfig) -> Self { Self { rounds: VecDeque::with_capacity(config.window), config, routing_shannon: 0.0, routing_min: 0.0, cover_pps: 0.0 } }
+
+	/// A cMix batch with `real_inputs` packets that arrived over the network.
+	pub fn record_batch(&mut self, real_inputs: usize) { self.push_round(real_inputs as f64); }
+
+	/// A window of per-packet mixing: packets (real and cover) arriving at
+	/// `arrival_pps` and held for `mean_delay` on average, plus this node's
+	/// own cover emitted over the same delay.
+	pub fn record_poisson(&mut self, arrival_pps: f64, mean_delay: Duration) {
+		let pps = if arrival_pps.is_finite() { arrival_pps.max(0.0) + self.cover_pps } else { 0.0 };
+		self.push_round(pps * mean_delay.as_secs_f64());
+	}
+
+	/// Rate of cover packets this node emits alongside its mixed output.
+	pub fn set_cover_rate(&mut self, pps: f64) { self.cover_pps = if pps.is_finite() { pps.max(0.0) } else { 0.0 }; }
+
+	/// Node selection weights for one hop (e.g. path builder weights over the
+	/// directory) and the route length.
+	pub fn set_route_distribution(&mut self, weights: &[f64], hops: usize) {
+		self.routing_shannon = shannon_entropy(weights) * hops as f64;
+		self.routing_min = min_entropy(weights) * hops as f64;
+	}
+
+	fn push_round(&mut self, k: f64) {
+		if self.rounds.len() == self.config.window.max(1) {
+			self.rounds.pop_front();
+		}
+		// Fewer than one candidate means no mixing at all.
+		self.rounds.push_back(k.max(1.0));
+	}
+
+	pub fn metrics(&self) -> AnonymityMetrics {
+		let (mix_shannon, mix_min) = if self.rounds.is_empty() {
+			(0.0, 0.0)
+		} else {
+			let bits = self.rounds.iter().map(|k| k.log2());
+			(bits.clone().sum::<f64>() / self.rounds.len() as f64, bits.fold(f64::INFINITY, f64::min))
+		};
+		let shannon = mix_shannon + self.routing_shannon;
+		let min = mix_min + self.routing_min;
+		AnonymityMetrics {
+			shannon_bits: shannon,
+			min_entropy_bits: min,
+			anonymity_set_size: shannon.exp2(),
+			mixing_shannon_bits: mix_shannon,
+			mixing_min_entropy_bits: mix_min,
+			routing_shannon_bits: self.routing_shannon,
+			routing_min_entropy_bits: self.routing_min,
+			rounds: self.rounds.len(),
+			degraded: min < self.config.alert_min_entropy_bits,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn entropy_of_uniform_and_skewed_distributions() {
+		assert!((shannon_entropy(&[1.0; 8]) - 3.0).abs() < 1e-12);
+		assert!((min_entropy(&[1.0; 8]) - 3.0).abs() < 1e-12);
+		let skewed = [4.0, 1.0, 1.0, 1.0, 1.0];
+		assert!(min_entropy(&skewed) < shannon_entropy(&skewed));
+		assert_eq!(shannon_entropy(&[]), 0.0);
+		assert_eq!(min_entropy(&[0.0, 0.0]), 0.0);
+	}
+
+	#[test]
+	fn batches_and_routes_add_up() {
+		let mut e = AnonymityEstimator::default();
+		e.record_batch(64);
+		e.record_batch(16);
+		e.set_route_distribution(&[1.0; 4], 3);
+		let m = e.metrics();
+		assert!((m.mixing_shannon_bits - 5.0).abs() < 1e-12);
+		assert!((m.mixing_min_entropy_bits - 4.0).abs() < 1e-12);
+		assert!((m.shannon_bits - 11.0).abs() < 1e-12);
+		assert!((m.anonymity_set_size - 2048.0).abs() < 1e-6);
+		assert!(!m.degraded);
+	}
+
+	#[test]
+	fn lone_packets_are_flagged_degraded() {
+		let mut e = AnonymityEstimator::new(AnonymityConfig { window: 2, ..Default::default() });
+		e.record_poisson(100.0, Duration::from_millis(500));
+		assert!(!e.metrics().degraded);
+		e.record_batch(1);
+		assert_eq!(e.metrics().min_entropy_bits, 0.0);
+		assert!(e.metrics().degraded);
+		e.record_batch(32);
+		e.record_batch(32);
+		assert_eq!(e.metrics().rounds, 2);
+		assert!(!e.metrics().degraded);
+	}
+
+	#[test]
+	fn own_cover_widens_poisson_rounds() {
+		let mut e = AnonymityEstimator::new(AnonymityConfig { window: 1, ..Default::default() });
+		e.record_poisson(0.0, Duration::from_secs(1));
+		assert_eq!(e.metrics().mixing_shannon_bits, 0.0);
+		e.set_cover_rate(16.0);
+		e.record_poisson(0.0, Duration::from_secs(1));
+		assert!((e.metrics().mixing_shannon_bits - 4.0).abs() < 1e-12);
+		e.record_poisson(48.0, Duration::from_millis(500));
+		assert!((e.metrics().mixing_shannon_bits - 5.0).abs() < 1e-12);
+	}
+}
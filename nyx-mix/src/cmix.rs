@@ -1 +1,491 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! cMix-style batch mixing (spec v1.0 §4).
+//!
+//! Packets are collected until `batch_size` have arrived or `batch_timeout`
+//! has passed since the first one; a partial batch is topped up with
+//! [`SphinxPacket::dummy`] packets so every released batch has the same size
+//! and padding cannot be told apart from real inputs. Sealing a batch:
+//!
+//! 1. commits to the inputs (`C_in`), to a fresh secret nonce (`C_n`), and
+//!    accumulates the inputs in an RSA accumulator (`A`);
+//! 2. evaluates a delay function on `H(C_in ‖ C_n ‖ A)`;
+//! 3. derives the shuffle seed from the nonce and the delay output, and
+//!    applies a Fisher–Yates permutation driven by a BLAKE3 XOF.
+//!
+//! The batch leaves only after step 2 finishes, so the release time is
+//! bounded below by the delay, and the permutation cannot be known before
+//! then. Anyone can check the delay proof from the published commitments
+//! ([`BatchProof::verify_delay`]), including that it ran for at least
+//! [`MIN_VDF_ITERATIONS`]; an auditor given the inputs and the nonce
+//! can replay the whole shuffle ([`verify_batch`]). A sender holding a
+//! witness from [`Batch::inclusion_witness`] (or, for every packet at once,
+//! [`Batch::inclusion_witnesses`]) checks that its packet was in the batch
+//! with [`BatchProof::verify_inclusion`].
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use nyx_core::cmix::{MixConfig, MIN_VDF_ITERATIONS};
+use rand::RngCore;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::accumulator::{self, RsaAccumulator, Witness};
+use crate::errors::{Error, Result};
+use crate::sphinx::{SphinxPacket, PACKET_LEN};
+use crate::vdf::{Vdf, WesolowskiDelay};
+
+const CTX_INPUTS: &str = "nyx-cmix v1 input commitment";
+const CTX_NONCE: &str = "nyx-cmix v1 nonce commitment";
+const CTX_CHALLENGE: &str = "nyx-cmix v1 delay challenge";
+const CTX_SHUFFLE: &str = "nyx-cmix v1 shuffle seed";
+
+/// Result of a delay function evaluation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DelayOutput {
+	/// Iterations `T` the output was computed with.
+	pub iterations: u64,
+	pub output: Vec<u8>,
+	/// Empty for functions verified by recomputation.
+	pub proof: Vec<u8>,
+}
+
+/// A sequential function whose evaluation takes a tunable amount of time.
+/// Outputs are verified against the iteration count they record, so a
+/// verifier does not need to know the evaluator's calibration.
+pub trait DelayFunction: Send + Sync + fmt::Debug {
+	fn evaluate(&self, challenge: &[u8; 32]) -> DelayOutput;
+	fn verify(&self, challenge: &[u8; 32], out: &DelayOutput) -> bool;
+}
+
+/// Iterated BLAKE3. Inherently sequential, but verification costs as much as
+/// evaluation; [`WesolowskiDelay`] verifies cheaply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashChainDelay {
+	iterations: u64,
+}
+
+impl HashChainDelay {
+	pub fn new(iterations: u64) -> Self { Self { iterations: iterations.max(1) } }
+
+	/// Pick the iteration count that takes about `target` on this machine.
+	pub fn calibrated(target: Duration) -> Self {
+		const PROBE: u64 = 1 << 14;
+		let start = Instant::now();
+		chain(&[0u8; 32], PROBE);
+		let per_iter = start.elapsed().as_secs_f64().max(1e-9) / PROBE as f64;
+		Self::new(((target.as_secs_f64() / per_iter) as u64).max(MIN_VDF_ITERATIONS))
+	}
+
+	pub fn iterations(&self) -> u64 { self.iterations }
+}
+
+impl DelayFunction for HashChainDelay {
+	fn evaluate(&self, challenge: &[u8; 32]) -> DelayOutput { DelayOutput { iterations: self.iterations, output: chain(challenge, self.iterations).to_vec(), proof: Vec::new() } }
+
+	fn verify(&self, challenge: &[u8; 32], out: &DelayOutput) -> bool { out.proof.is_empty() && out.output == chain(challenge, out.iterations) }
+}
+
+fn chain(seed: &[u8; 32], iterations: u64) -> [u8; 32] {
+	let mut h = *seed;
+	for _ in 0..iterations {
+		h = *blake3::hash(&h).as_bytes();
+	}
+	h
+}
+
+/// Public part of a sealed batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchProof {
+	pub input_commitment: [u8; 32],
+	pub nonce_commitment: [u8; 32],
+	/// RSA accumulator over the batch inputs.
+	pub accumulator: Vec<u8>,
+	pub delay: DelayOutput,
+}
+
+impl BatchProof {
+	pub fn challenge(&self) -> [u8; 32] { challenge(&self.input_commitment, &self.nonce_commitment, &self.accumulator) }
+
+	/// Delay iterations `T` the batch waited for.
+	pub fn iterations(&self) -> u64 { self.delay.iterations }
+
+	/// Check that the delay was evaluated for this batch and ran for at least
+	/// [`MIN_VDF_ITERATIONS`].
+	pub fn verify_delay(&self, delay: &dyn DelayFunction) -> bool { self.iterations() >= MIN_VDF_ITERATIONS && delay.verify(&self.challenge(), &self.delay) }
+
+	/// Check that `packet` was one of the batch inputs.
+	pub fn verify_inclusion(&self, packet: &[u8], witness: &Witness) -> bool {
+		let modulus = accumulator::default_modulus();
+		let Ok(value) = accumulator::decode_value(&self.accumulator, &modulus) else { return false };
+		accumulator::verify_membership(&modulus, &value, packet, witness)
+	}
+}
+
+/// A shuffled batch, ready to forward.
+#[derive(Debug, Clone)]
+pub struct Batch {
+	pub packets: Vec<Vec<u8>>,
+	pub proof: BatchProof,
+	nonce: [u8; 32],
+	accumulator: RsaAccumulator,
+	/// `order[k]` is the input index of `packets[k]`.
+	order: Vec<usize>,
+	dummies: usize,
+}
+
+impl Batch {
+	/// Shuffle nonce; reveal only to auditors, since it together with the
+	/// inputs links every output to its input.
+	pub fn audit_nonce(&self) -> [u8; 32] { self.nonce }
+
+	/// Inputs that arrived over the network, i.e. excluding local padding.
+	pub fn real_inputs(&self) -> usize { self.packets.len() - self.dummies }
+
+	/// Membership witness for an input packet, to hand back to its sender.
+	pub fn inclusion_witness(&self, packet: &[u8]) -> Option<Witness> { self.accumulator.witness(packet) }
+
+	/// Membership witnesses for all packets, aligned with `packets`; much
+	/// cheaper than [`Self::inclusion_witness`] per packet.
+	pub fn inclusion_witnesses(&self) -> Vec<Witness> {
+		let by_input = self.accumulator.witnesses();
+		self.order.iter().map(|&i| by_input[i].clone()).collect()
+	}
+}
+
+/// A full (or timed-out, padded) batch waiting for its delay evaluation.
+#[derive(Debug)]
+pub struct PendingBatch {
+	inputs: Vec<Vec<u8>>,
+	dummies: usize,
+}
+
+impl PendingBatch {
+	/// Packets in arrival order, dummies last.
+	pub fn inputs(&self) -> &[Vec<u8>] { &self.inputs }
+
+	pub fn dummies(&self) -> usize { self.dummies }
+
+	/// Evaluate the delay and shuffle. Blocks for the duration of the delay.
+	pub fn seal(self, delay: &dyn DelayFunction) -> Batch {
+		let mut nonce = [0u8; 32];
+		rand::thread_rng().fill_bytes(&mut nonce);
+		self.seal_with_nonce(delay, nonce)
+	}
+
+	fn seal_with_nonce(self, delay: &dyn DelayFunction, nonce: [u8; 32]) -> Batch {
+		let input_commitment = input_commitment(&self.inputs);
+		let nonce_commitment = blake3::derive_key(CTX_NONCE, &nonce);
+		let accumulator = accumulate(&self.inputs);
+		let acc_value = accumulator.value_bytes();
+		let out = delay.evaluate(&challenge(&input_commitment, &nonce_commitment, &acc_value));
+		let perm = permutation(&shuffle_seed(&nonce, &out.output), self.inputs.len());
+		let mut slots: Vec<Option<Vec<u8>>> = self.inputs.into_iter().map(Some).collect();
+		let packets = perm.iter().map(|&i| slots[i].take().expect("permutation is a bijection")).collect();
+		Batch { packets, proof: BatchProof { input_commitment, nonce_commitment, accumulator: acc_value, delay: out }, nonce, accumulator, order: perm, dummies: self.dummies }
+	}
+}
+
+/// Replay a batch's shuffle from its inputs and nonce. Delays shorter than
+/// [`MIN_VDF_ITERATIONS`] are rejected.
+pub fn verify_batch(delay: &dyn DelayFunction, inputs: &[Vec<u8>], batch: &Batch, nonce: &[u8; 32]) -> Result<()> {
+	let proof = &batch.proof;
+	if input_commitment(inputs) != proof.input_commitment || blake3::derive_key(CTX_NONCE, nonce) != proof.nonce_commitment {
+		return Err(Error::Integrity);
+	}
+	if accumulate(inputs).value_bytes() != proof.accumulator {
+		return Err(Error::Integrity);
+	}
+	if !proof.verify_delay(delay) || batch.packets.len() != inputs.len() {
+		return Err(Error::Integrity);
+	}
+	let perm = permutation(&shuffle_seed(nonce, &proof.delay.output), inputs.len());
+	if perm.iter().zip(&batch.packets).all(|(&i, p)| inputs[i] == *p) { Ok(()) } else { Err(Error::Integrity) }
+}
+
+fn input_commitment(inputs: &[Vec<u8>]) -> [u8; 32] {
+	let mut h = blake3::Hasher::new_derive_key(CTX_INPUTS);
+	h.update(&(inputs.len() as u64).to_be_bytes());
+	for p in inputs {
+		h.update(p);
+	}
+	*h.finalize().as_bytes()
+}
+
+fn accumulate(inputs: &[Vec<u8>]) -> RsaAccumulator {
+	let mut acc = RsaAccumulator::new();
+	acc.extend(inputs.iter().map(Vec::as_slice));
+	acc
+}
+
+fn challenge(input_commitment: &[u8; 32], nonce_commitment: &[u8; 32], accumulator: &[u8]) -> [u8; 32] {
+	let mut h = blake3::Hasher::new_derive_key(CTX_CHALLENGE);
+	h.update(input_commitment);
+	h.update(nonce_commitment);
+	h.update(accumulator);
+	*h.finalize().as_bytes()
+}
+
+fn shuffle_seed(nonce: &[u8; 32], delay_output: &[u8]) -> [u8; 32] {
+	let mut h = blake3::Hasher::new_derive_key(CTX_SHUFFLE);
+	h.update(nonce);
+	h.update(delay_output);
+	*h.finalize().as_bytes()
+}
+
+/// `perm[k]` is the input index emitted at position `k`.
+fn permutation(seed: &[u8; 32], n: usize) -> Vec<usize> {
+	let mut xof = blake3::Hasher::new_keyed(seed).finalize_xof();
+	let mut next = || {
+		let mut b = [0u8; 8];
+		xof.fill(&mut b);
+		u64::from_le_bytes(b)
+	};
+	let mut perm: Vec<usize> = (0..n).collect();
+	for i in (1..n).rev() {
+		// Rejection sampling keeps the index unbiased.
+		let bound = i as u64 + 1;
+		let zone = u64::MAX - u64::MAX % bound;
+		let j = loop {
+			let x = next();
+			if x < zone {
+				break (x % bound) as usize;
+			}
+		};
+		perm.swap(i, j);
+	}
+	perm
+}
+
+/// Counters since the batcher was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CmixStats {
+	pub batches: u64,
+	pub packets: u64,
+	pub dummies: u64,
+}
+
+/// Collects packets into fixed-size batches.
+#[derive(Debug)]
+pub struct CmixBatcher {
+	batch_size: usize,
+	timeout: Duration,
+	delay: Arc<dyn DelayFunction>,
+	pending: Vec<Vec<u8>>,
+	first_at: Option<Instant>,
+	stats: CmixStats,
+}
+
+impl CmixBatcher {
+	pub fn new(config: &MixConfig, delay: Arc<dyn DelayFunction>) -> Result<Self> {
+		config.validate().map_err(|e| Error::config(e.to_string()))?;
+		Ok(Self { batch_size: config.batch_size, timeout: Duration::from_millis(config.batch_timeout_ms), delay, pending: Vec::with_capacity(config.batch_size), first_at: None, stats: CmixStats::default() })
+	}
+
+	/// Batcher using an RSA-2048 Wesolowski VDF calibrated to `vdf_delay_ms`.
+	pub fn from_config(config: &MixConfig) -> Result<Self> {
+		Self::new(config, Arc::new(WesolowskiDelay::calibrated(Vdf::rsa2048(), Duration::from_millis(config.vdf_delay_ms))))
+	}
+
+	pub fn batch_size(&self) -> usize { self.batch_size }
+
+	pub fn delay(&self) -> &Arc<dyn DelayFunction> { &self.delay }
+
+	pub fn pending(&self) -> usize { self.pending.len() }
+
+	pub fn stats(&self) -> CmixStats { self.stats }
+
+	/// Queue a packet. Fails on a wrong-size packet or when a full batch has
+	/// not been taken yet.
+	pub fn push(&mut self, packet: Vec<u8>, now: Instant) -> Result<()> {
+		if packet.len() != PACKET_LEN {
+			return Err(Error::protocol(format!("cmix packet must be {PACKET_LEN} bytes, got {}", packet.len())));
+		}
+		if self.pending.len() >= self.batch_size {
+			return Err(Error::protocol("cmix batch full"));
+		}
+		self.first_at.get_or_insert(now);
+		self.pending.push(packet);
+		Ok(())
+	}
+
+	/// When the current partial batch times out.
+	pub fn deadline(&self) -> Option<Instant> { self.first_at.map(|t| t + self.timeout) }
+
+	pub fn is_ready(&self, now: Instant) -> bool { self.pending.len() >= self.batch_size || self.deadline().is_some_and(|d| now >= d) }
+
+	/// Take the batch if it is full or has timed out.
+	pub fn take_ready(&mut self, now: Instant) -> Option<PendingBatch> { if self.is_ready(now) { self.flush() } else { None } }
+
+	/// Take whatever is queued, padded to the batch size. `None` when empty.
+	pub fn flush(&mut self) -> Option<PendingBatch> {
+		if self.pending.is_empty() {
+			return None;
+		}
+		let mut inputs = std::mem::replace(&mut self.pending, Vec::with_capacity(self.batch_size));
+		self.first_at = None;
+		let real = inputs.len();
+		let mut rng = rand::thread_rng();
+		inputs.resize_with(self.batch_size, || SphinxPacket::dummy(&mut rng).as_bytes().to_vec());
+		let dummies = self.batch_size - real;
+		self.stats.batches += 1;
+		self.stats.packets += real as u64;
+		self.stats.dummies += dummies as u64;
+		#[cfg(feature = "telemetry")]
+		{
+			metrics::counter!("nyx_cmix_batches_total").increment(1);
+			metrics::counter!("nyx_cmix_dummy_packets_total").increment(dummies as u64);
+		}
+		Some(PendingBatch { inputs, dummies })
+	}
+
+	/// Drive the batcher: read packets from `rx`, emit sealed batches on `tx`.
+	/// Delay evaluation runs on the blocking pool. Returns when either side
+	/// closes; a partial batch is flushed when `rx` closes.
+	pub async fn run(mut self, mut rx: mpsc::Receiver<Vec<u8>>, tx: mpsc::Sender<Batch>) {
+		loop {
+			let deadline = self.deadline().map(tokio::time::Instant::from_std);
+			let mut closed = false;
+			tokio::select! {
+				p = rx.recv() => match p {
+					Some(p) => {
+						if let Err(e) = self.push(p, tokio::time::Instant::now().into_std()) {
+							debug!("cmix: dropping packet: {e}");
+						}
+					}
+					None => closed = true,
+				},
+				_ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {}
+			}
+			let ready = if closed { self.flush() } else { self.take_ready(tokio::time::Instant::now().into_std()) };
+			if let Some(pending) = ready {
+				let delay = self.delay.clone();
+				let batch = match tokio::task::spawn_blocking(move || pending.seal(delay.as_ref())).await {
+					Ok(b) => b,
+					Err(e) => {
+						warn!("cmix: sealing task failed: {e}");
+						return;
+					}
+				};
+				if tx.send(batch).await.is_err() {
+					return;
+				}
+			}
+			if closed {
+				return;
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn cfg(batch_size: usize) -> MixConfig { MixConfig { batch_size, batch_timeout_ms: 50, ..Default::default() } }
+
+	fn packet(i: u8) -> Vec<u8> { vec![i; PACKET_LEN] }
+
+	#[test]
+	fn full_batch_is_shuffled_and_verifiable() {
+		let delay = Arc::new(HashChainDelay::new(MIN_VDF_ITERATIONS));
+		let mut b = CmixBatcher::new(&cfg(16), delay.clone()).unwrap();
+		let t0 = Instant::now();
+		for i in 0..16 {
+			assert!(!b.is_ready(t0));
+			b.push(packet(i), t0).unwrap();
+		}
+		assert!(b.push(packet(99), t0).is_err());
+		let pending = b.take_ready(t0).unwrap();
+		let inputs = pending.inputs().to_vec();
+		let batch = pending.seal(delay.as_ref());
+		assert_eq!(batch.packets.len(), 16);
+		assert_ne!(batch.packets, inputs);
+		assert!(batch.proof.verify_delay(delay.as_ref()));
+		assert_eq!(batch.proof.iterations(), MIN_VDF_ITERATIONS);
+		verify_batch(delay.as_ref(), &inputs, &batch, &batch.audit_nonce()).unwrap();
+		let w = batch.inclusion_witness(&inputs[5]).unwrap();
+		assert!(batch.proof.verify_inclusion(&inputs[5], &w));
+		assert!(!batch.proof.verify_inclusion(&packet(99), &w));
+		assert!(batch.inclusion_witness(&packet(99)).is_none());
+		let all = batch.inclusion_witnesses();
+		assert_eq!(all.len(), 16);
+		assert!(batch.packets.iter().zip(&all).all(|(p, w)| batch.proof.verify_inclusion(p, w)));
+
+		let mut swapped = batch.clone();
+		swapped.packets.swap(0, 1);
+		assert!(verify_batch(delay.as_ref(), &inputs, &swapped, &batch.audit_nonce()).is_err());
+		let mut relabelled = batch.clone();
+		relabelled.proof.delay.iterations += 1;
+		assert!(!relabelled.proof.verify_delay(delay.as_ref()));
+	}
+
+	#[test]
+	fn delay_below_the_network_minimum_is_rejected() {
+		let short = Arc::new(HashChainDelay::new(MIN_VDF_ITERATIONS - 1));
+		let mut b = CmixBatcher::new(&cfg(2), short.clone()).unwrap();
+		let t0 = Instant::now();
+		b.push(packet(1), t0).unwrap();
+		b.push(packet(2), t0).unwrap();
+		let pending = b.take_ready(t0).unwrap();
+		let inputs = pending.inputs().to_vec();
+		let batch = pending.seal(short.as_ref());
+		// The output itself is sound; only its length disqualifies it.
+		assert!(short.verify(&batch.proof.challenge(), &batch.proof.delay));
+		assert!(!batch.proof.verify_delay(short.as_ref()));
+		assert!(verify_batch(short.as_ref(), &inputs, &batch, &batch.audit_nonce()).is_err());
+		assert_eq!(HashChainDelay::calibrated(Duration::ZERO).iterations(), MIN_VDF_ITERATIONS);
+	}
+
+	#[test]
+	fn timeout_pads_partial_batch() {
+		let mut b = CmixBatcher::new(&cfg(8), Arc::new(HashChainDelay::new(1))).unwrap();
+		let t0 = Instant::now();
+		b.push(packet(1), t0).unwrap();
+		b.push(packet(2), t0 + Duration::from_millis(10)).unwrap();
+		assert!(b.take_ready(t0 + Duration::from_millis(49)).is_none());
+		let pending = b.take_ready(t0 + Duration::from_millis(50)).unwrap();
+		assert_eq!(pending.inputs().len(), 8);
+		assert_eq!(pending.dummies(), 6);
+		// Padding is shaped like real Sphinx traffic: a valid group element up front.
+		for d in &pending.inputs()[2..] {
+			assert!(crate::sphinx::tests::on_curve(d[..32].try_into().unwrap()));
+		}
+		assert_eq!(pending.seal(&HashChainDelay::new(1)).real_inputs(), 2);
+		assert_eq!(b.stats(), CmixStats { batches: 1, packets: 2, dummies: 6 });
+		assert!(b.deadline().is_none());
+		assert!(b.push(vec![0; 100], t0).is_err());
+	}
+
+	#[test]
+	fn permutation_is_a_deterministic_bijection() {
+		let p = permutation(&[7; 32], 100);
+		assert_eq!(p, permutation(&[7; 32], 100));
+		assert_ne!(p, permutation(&[8; 32], 100));
+		let mut sorted = p.clone();
+		sorted.sort_unstable();
+		assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn run_releases_on_size_and_timeout() {
+		let b = CmixBatcher::new(&cfg(4), Arc::new(HashChainDelay::new(10))).unwrap();
+		let (in_tx, in_rx) = mpsc::channel(16);
+		let (out_tx, mut out_rx) = mpsc::channel(4);
+		let task = tokio::spawn(b.run(in_rx, out_tx));
+		for i in 0..5 {
+			in_tx.send(packet(i)).await.unwrap();
+		}
+		assert_eq!(out_rx.recv().await.unwrap().packets.len(), 4);
+		// The fifth packet goes out padded once the timeout fires.
+		let start = tokio::time::Instant::now();
+		let padded = out_rx.recv().await.unwrap();
+		assert!(start.elapsed() >= Duration::from_millis(49));
+		assert_eq!(padded.packets.iter().filter(|p| **p == packet(4)).count(), 1);
+		drop(in_tx);
+		task.await.unwrap();
+		assert!(out_rx.recv().await.is_none());
+	}
+}
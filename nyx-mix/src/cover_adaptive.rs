@@ -1 +1,195 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Adaptive cover rate control (spec v1.0 §5–6).
+//!
+//! Link utilization `U = (real + cover packets) / (capacity × window)` is
+//! measured over fixed windows (default 1 s). At the end of each window the
+//! controller picks the cover rate that would put `U` back inside
+//! `[target_low, target_high]` (default `[0.2, 0.6]`) given the real traffic
+//! just observed, and moves λ part of the way there (`gain`) to avoid
+//! oscillating on bursty traffic. Inside the band λ is left alone.
+//!
+//! In low-power mode (screen off) the applied λ is scaled by
+//! `low_power_ratio` (spec: `cover_ratio = 0.1`); the base rate keeps
+//! tracking what full-power mode would need, so leaving low-power mode
+//! restores the right rate immediately.
+
+use std::time::{Duration, Instant};
+
+use crate::errors::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveCoverConfig {
+	/// Link capacity in packets per second; `U = 1.0` at this rate.
+	pub capacity_pps: f64,
+	pub target_low: f64,
+	pub target_high: f64,
+	pub window: Duration,
+	pub min_lambda: f64,
+	pub max_lambda: f64,
+	/// Fraction of the correction applied per window, in `(0, 1]`.
+	pub gain: f64,
+	pub low_power_ratio: f64,
+}
+
+impl Default for AdaptiveCoverConfig {
+	fn default() -> Self {
+		Self { capacity_pps: 1000.0, target_low: 0.2, target_high: 0.6, window: Duration::from_secs(1), min_lambda: 1.0, max_lambda: 1000.0, gain: 0.5, low_power_ratio: 0.1 }
+	}
+}
+
+impl AdaptiveCoverConfig {
+	pub fn validate(&self) -> Result<()> {
+		let ok = self.capacity_pps > 0.0
+			&& 0.0 <= self.target_low
+			&& self.target_low < self.target_high
+			&& self.target_high <= 1.0
+			&& !self.window.is_zero()
+			&& 0.0 <= self.min_lambda
+			&& self.min_lambda <= self.max_lambda
+			&& self.gain > 0.0
+			&& self.gain <= 1.0
+			&& (0.0..=1.0).contains(&self.low_power_ratio);
+		if ok { Ok(()) } else { Err(Error::config("invalid adaptive cover config")) }
+	}
+}
+
+/// Rate and utilization metrics from the last completed window.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AdaptiveCoverMetrics {
+	/// λ currently applied (after low-power scaling).
+	pub lambda: f64,
+	pub utilization: f64,
+	pub real_pps: f64,
+	pub cover_pps: f64,
+	pub windows: u64,
+	/// Completed windows whose utilization was inside the target band.
+	pub windows_in_band: u64,
+}
+
+#[derive(Debug)]
+pub struct AdaptiveCoverController {
+	config: AdaptiveCoverConfig,
+	/// Rate before low-power scaling.
+	base_lambda: f64,
+	low_power: bool,
+	window_start: Instant,
+	real: u64,
+	cover: u64,
+	metrics: AdaptiveCoverMetrics,
+}
+
+impl AdaptiveCoverController {
+	pub fn new(config: AdaptiveCoverConfig, now: Instant) -> Result<Self> {
+		config.validate()?;
+		// Start at the rate that alone would reach the lower bound.
+		let base_lambda = (config.target_low * config.capacity_pps).clamp(config.min_lambda, config.max_lambda);
+		let mut c = Self { config, base_lambda, low_power: false, window_start: now, real: 0, cover: 0, metrics: AdaptiveCoverMetrics::default() };
+		c.metrics.lambda = c.lambda();
+		Ok(c)
+	}
+
+	pub fn config(&self) -> &AdaptiveCoverConfig { &self.config }
+
+	/// λ to apply to the cover generator.
+	pub fn lambda(&self) -> f64 { if self.low_power { self.base_lambda * self.config.low_power_ratio } else { self.base_lambda } }
+
+	pub fn set_low_power(&mut self, on: bool) {
+		self.low_power = on;
+		self.metrics.lambda = self.lambda();
+	}
+
+	pub fn is_low_power(&self) -> bool { self.low_power }
+
+	pub fn record_real(&mut self, packets: u64) { self.real += packets; }
+
+	pub fn record_cover(&mut self, packets: u64) { self.cover += packets; }
+
+	/// Close the window if it has elapsed. Returns the new λ when a window closed.
+	pub fn on_tick(&mut self, now: Instant) -> Option<f64> {
+		let elapsed = now.saturating_duration_since(self.window_start);
+		if elapsed < self.config.window {
+			return None;
+		}
+		let secs = elapsed.as_secs_f64();
+		let real_pps = self.real as f64 / secs;
+		let cover_pps = self.cover as f64 / secs;
+		let u = (real_pps + cover_pps) / self.config.capacity_pps;
+
+		let cfg = &self.config;
+		// Adapt as if running at full rate, so low-power mode does not push the
+		// base rate up to make up for its own scaling.
+		let scale = if self.low_power { cfg.low_power_ratio } else { 1.0 };
+		let full_cover_pps = if scale > 0.0 { cover_pps / scale } else { self.base_lambda };
+		let u_full = (real_pps + full_cover_pps) / cfg.capacity_pps;
+		let desired = if u_full < cfg.target_low {
+			Some(cfg.target_low * cfg.capacity_pps - real_pps)
+		} else if u_full > cfg.target_high {
+			Some(cfg.target_high * cfg.capacity_pps - real_pps)
+		} else {
+			None
+		};
+		if let Some(desired) = desired {
+			let target = desired.clamp(cfg.min_lambda, cfg.max_lambda);
+			self.base_lambda += cfg.gain * (target - self.base_lambda);
+		}
+
+		let in_band = (cfg.target_low..=cfg.target_high).contains(&u);
+		self.metrics = AdaptiveCoverMetrics {
+			lambda: self.lambda(),
+			utilization: u,
+			real_pps,
+			cover_pps,
+			windows: self.metrics.windows + 1,
+			windows_in_band: self.metrics.windows_in_band + in_band as u64,
+		};
+		#[cfg(feature = "telemetry")]
+		{
+			metrics::gauge!("nyx_cover_utilization").set(u);
+			metrics::gauge!("nyx_cover_lambda").set(self.metrics.lambda);
+		}
+		self.window_start = now;
+		self.real = 0;
+		self.cover = 0;
+		Some(self.metrics.lambda)
+	}
+
+	pub fn metrics(&self) -> AdaptiveCoverMetrics { self.metrics }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn idle_link_is_filled_to_lower_bound() {
+		let t0 = Instant::now();
+		let mut c = AdaptiveCoverController::new(AdaptiveCoverConfig::default(), t0).unwrap();
+		assert_eq!(c.lambda(), 200.0);
+		c.record_cover(200);
+		assert_eq!(c.on_tick(t0 + Duration::from_millis(500)), None);
+		assert_eq!(c.on_tick(t0 + Duration::from_secs(1)), Some(200.0));
+		assert_eq!(c.metrics().windows_in_band, 1);
+	}
+
+	#[test]
+	fn heavy_real_traffic_backs_cover_off() {
+		let t0 = Instant::now();
+		let cfg = AdaptiveCoverConfig { gain: 1.0, ..Default::default() };
+		let mut c = AdaptiveCoverController::new(cfg, t0).unwrap();
+		c.record_real(550);
+		c.record_cover(200);
+		// U = 0.75 > 0.6: only 50 pps of cover fit.
+		assert_eq!(c.on_tick(t0 + Duration::from_secs(1)), Some(50.0));
+		assert!((c.metrics().utilization - 0.75).abs() < 1e-9);
+	}
+
+	#[test]
+	fn low_power_scales_applied_rate() {
+		let t0 = Instant::now();
+		let mut c = AdaptiveCoverController::new(AdaptiveCoverConfig::default(), t0).unwrap();
+		c.set_low_power(true);
+		assert!((c.lambda() - 20.0).abs() < 1e-9);
+		assert!(AdaptiveCoverConfig { target_low: 0.7, ..Default::default() }.validate().is_err());
+	}
+}
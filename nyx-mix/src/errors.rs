@@ -0,0 +1,20 @@
+﻿use thiserror::Error;
+
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+	#[error("config: {0}")]
+	Config(String),
+	#[error("protocol: {0}")]
+	Protocol(String),
+	#[error("replay detected")]
+	Replay,
+	#[error("integrity check failed")]
+	Integrity,
+}
+
+impl Error {
+	pub fn config(msg: impl Into<String>) -> Self { Self::Config(msg.into()) }
+	pub fn protocol(msg: impl Into<String>) -> Self { Self::Protocol(msg.into()) }
+}
@@ -1 +1,277 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! LARMix++ latency-aware route selection.
+//!
+//! Routes are chosen hop by hop. Given the previous hop (or the local node for
+//! the first hop), candidate `j` is drawn with probability
+//!
+//! ```text
+//! p_j ∝ exp(-bias · rtt(prev, j) / mean_rtt)
+//! ```
+//!
+//! where `rtt` comes from probe measurements ([`RttTable`]). `bias = 0` is
+//! uniform selection (maximum anonymity); larger values concentrate traffic on
+//! fast links. The anonymity cost of a route is the entropy lost relative to
+//! uniform selection over the same candidates, in bits, and
+//! [`LarmixRouter::estimate`] / [`LarmixRouter::calibrate_bias`] expose the
+//! latency/anonymity trade-off so a deployment can pick the smallest bias
+//! that meets its latency budget.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::errors::{Error, Result};
+use crate::sphinx::NodeId;
+
+/// Weight given to a new probe sample in the RTT EWMA.
+pub const RTT_EWMA_ALPHA: f64 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Ewma {
+	ms: f64,
+	samples: u64,
+}
+
+/// Smoothed probe RTTs from the local node and between mix pairs.
+#[derive(Debug, Clone)]
+pub struct RttTable {
+	local: HashMap<NodeId, Ewma>,
+	links: HashMap<(NodeId, NodeId), Ewma>,
+	/// Assumed RTT for links without samples.
+	default_rtt: Duration,
+}
+
+impl Default for RttTable {
+	fn default() -> Self { Self::new(Duration::from_millis(100)) }
+}
+
+impl RttTable {
+	pub fn new(default_rtt: Duration) -> Self { Self { local: HashMap::new(), links: HashMap::new(), default_rtt } }
+
+	fn update(e: &mut Ewma, rtt: Duration) {
+		let ms = rtt.as_secs_f64() * 1e3;
+		e.ms = if e.samples == 0 { ms } else { (1.0 - RTT_EWMA_ALPHA) * e.ms + RTT_EWMA_ALPHA * ms };
+		e.samples += 1;
+	}
+
+	/// Record a probe RTT. `from = None` means the local node.
+	pub fn record(&mut self, from: Option<NodeId>, to: NodeId, rtt: Duration) {
+		let e = match from {
+			None => self.local.entry(to).or_insert(Ewma { ms: 0.0, samples: 0 }),
+			// Links are symmetric; store under a canonical key.
+			Some(a) => self.links.entry(if a <= to { (a, to) } else { (to, a) }).or_insert(Ewma { ms: 0.0, samples: 0 }),
+		};
+		Self::update(e, rtt);
+	}
+
+	/// Smoothed RTT in milliseconds, or the default when never probed.
+	pub fn rtt_ms(&self, from: Option<&NodeId>, to: &NodeId) -> f64 {
+		let e = match from {
+			None => self.local.get(to),
+			Some(a) => self.links.get(&if a <= to { (*a, *to) } else { (*to, *a) }),
+		};
+		e.map_or(self.default_rtt.as_secs_f64() * 1e3, |e| e.ms)
+	}
+
+	pub fn samples(&self, from: Option<&NodeId>, to: &NodeId) -> u64 {
+		match from {
+			None => self.local.get(to),
+			Some(a) => self.links.get(&if a <= to { (*a, *to) } else { (*to, *a) }),
+		}
+		.map_or(0, |e| e.samples)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LarmixConfig {
+	/// Anonymity/latency trade-off; 0 = uniform, typical values 0..8.
+	pub bias: f64,
+}
+
+impl Default for LarmixConfig {
+	fn default() -> Self { Self { bias: 2.0 } }
+}
+
+/// A selected route with its latency and anonymity figures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LarmixRoute {
+	pub hops: Vec<NodeId>,
+	/// Added one-way latency, estimated as half the sum of link RTTs.
+	pub expected_latency: Duration,
+	/// Entropy of the selection distributions actually used, in bits.
+	pub entropy_bits: f64,
+	/// Entropy of uniform selection over the same candidates.
+	pub uniform_entropy_bits: f64,
+}
+
+impl LarmixRoute {
+	/// Anonymity given up for latency, in bits.
+	pub fn anonymity_cost_bits(&self) -> f64 { (self.uniform_entropy_bits - self.entropy_bits).max(0.0) }
+}
+
+/// Averages over many sampled routes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TradeoffEstimate {
+	pub bias: f64,
+	pub mean_latency: Duration,
+	pub mean_entropy_bits: f64,
+	pub mean_anonymity_cost_bits: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LarmixRouter {
+	config: LarmixConfig,
+}
+
+impl LarmixRouter {
+	pub fn new(config: LarmixConfig) -> Result<Self> {
+		if !(config.bias.is_finite() && config.bias >= 0.0) {
+			return Err(Error::config("larmix bias must be a non-negative number"));
+		}
+		Ok(Self { config })
+	}
+
+	pub fn config(&self) -> LarmixConfig { self.config }
+
+	/// Selection probabilities for the next hop after `prev`.
+	pub fn hop_distribution(&self, rtts: &RttTable, prev: Option<&NodeId>, candidates: &[NodeId]) -> Vec<f64> {
+		if candidates.is_empty() {
+			return Vec::new();
+		}
+		let lat: Vec<f64> = candidates.iter().map(|c| rtts.rtt_ms(prev, c)).collect();
+		let mean = lat.iter().sum::<f64>() / lat.len() as f64;
+		let scale = if mean > 0.0 { mean } else { 1.0 };
+		// Shift by the minimum so exp() cannot underflow for every candidate.
+		let min = lat.iter().copied().fold(f64::INFINITY, f64::min);
+		let w: Vec<f64> = lat.iter().map(|l| (-self.config.bias * (l - min) / scale).exp()).collect();
+		let total: f64 = w.iter().sum();
+		w.into_iter().map(|x| x / total).collect()
+	}
+
+	/// Choose `hops` distinct nodes from `nodes`.
+	pub fn select<R: Rng + ?Sized>(&self, rtts: &RttTable, nodes: &[NodeId], hops: usize, rng: &mut R) -> Result<LarmixRoute> {
+		if hops == 0 || hops > nodes.len() {
+			return Err(Error::config(format!("cannot pick {hops} hops from {} nodes", nodes.len())));
+		}
+		let mut candidates = nodes.to_vec();
+		let mut route = LarmixRoute { hops: Vec::with_capacity(hops), expected_latency: Duration::ZERO, entropy_bits: 0.0, uniform_entropy_bits: 0.0 };
+		let mut latency_ms = 0.0;
+		for _ in 0..hops {
+			let prev = route.hops.last().copied();
+			let p = self.hop_distribution(rtts, prev.as_ref(), &candidates);
+			route.entropy_bits += p.iter().filter(|&&x| x > 0.0).map(|&x| -x * x.log2()).sum::<f64>();
+			route.uniform_entropy_bits += (candidates.len() as f64).log2();
+			let mut x = rng.gen::<f64>();
+			let idx = p.iter().position(|&pi| { x -= pi; x < 0.0 }).unwrap_or(candidates.len() - 1);
+			let next = candidates.swap_remove(idx);
+			latency_ms += rtts.rtt_ms(prev.as_ref(), &next) / 2.0;
+			route.hops.push(next);
+		}
+		route.expected_latency = Duration::from_secs_f64(latency_ms / 1e3);
+		Ok(route)
+	}
+
+	/// Monte Carlo estimate of latency and anonymity cost at the configured bias.
+	pub fn estimate<R: Rng + ?Sized>(&self, rtts: &RttTable, nodes: &[NodeId], hops: usize, samples: usize, rng: &mut R) -> Result<TradeoffEstimate> {
+		let samples = samples.max(1);
+		let (mut lat, mut ent, mut cost) = (0.0, 0.0, 0.0);
+		for _ in 0..samples {
+			let r = self.select(rtts, nodes, hops, rng)?;
+			lat += r.expected_latency.as_secs_f64();
+			ent += r.entropy_bits;
+			cost += r.anonymity_cost_bits();
+		}
+		let n = samples as f64;
+		Ok(TradeoffEstimate { bias: self.config.bias, mean_latency: Duration::from_secs_f64(lat / n), mean_entropy_bits: ent / n, mean_anonymity_cost_bits: cost / n })
+	}
+
+	/// Smallest bias in `[0, max_bias]` whose estimated mean latency meets
+	/// `target`, found by bisection. Returns the estimate at that bias, or an
+	/// error if even `max_bias` misses the target.
+	pub fn calibrate_bias<R: Rng + ?Sized>(rtts: &RttTable, nodes: &[NodeId], hops: usize, target: Duration, max_bias: f64, samples: usize, rng: &mut R) -> Result<TradeoffEstimate> {
+		let at = |bias: f64, rng: &mut R| Self::new(LarmixConfig { bias })?.estimate(rtts, nodes, hops, samples, rng);
+		let uniform = at(0.0, rng)?;
+		if uniform.mean_latency <= target {
+			return Ok(uniform);
+		}
+		let mut best = at(max_bias, rng)?;
+		if best.mean_latency > target {
+			return Err(Error::config(format!("latency target {target:?} unreachable (best {:?} at bias {max_bias})", best.mean_latency)));
+		}
+		let (mut lo, mut hi) = (0.0, max_bias);
+		for _ in 0..20 {
+			let mid = (lo + hi) / 2.0;
+			let e = at(mid, rng)?;
+			if e.mean_latency <= target {
+				hi = mid;
+				best = e;
+			} else {
+				lo = mid;
+			}
+		}
+		Ok(best)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::{rngs::StdRng, SeedableRng};
+
+	/// 20 nodes: the first 5 are 10 ms apart, the rest 80 ms from everything.
+	fn topology() -> (Vec<NodeId>, RttTable) {
+		let nodes: Vec<NodeId> = (0..20u8).map(|i| [i; 32]).collect();
+		let mut t = RttTable::default();
+		for (i, a) in nodes.iter().enumerate() {
+			t.record(None, *a, Duration::from_millis(if i < 5 { 10 } else { 80 }));
+			for (j, b) in nodes.iter().enumerate().skip(i + 1) {
+				t.record(Some(*a), *b, Duration::from_millis(if i < 5 && j < 5 { 10 } else { 80 }));
+			}
+		}
+		(nodes, t)
+	}
+
+	#[test]
+	fn rtt_table_smooths_and_defaults() {
+		let mut t = RttTable::new(Duration::from_millis(50));
+		let (a, b) = ([1; 32], [2; 32]);
+		assert_eq!(t.rtt_ms(Some(&a), &b), 50.0);
+		t.record(Some(a), b, Duration::from_millis(100));
+		t.record(Some(b), a, Duration::from_millis(200));
+		assert!((t.rtt_ms(Some(&a), &b) - 120.0).abs() < 1e-9);
+		assert_eq!(t.samples(Some(&b), &a), 2);
+	}
+
+	#[test]
+	fn zero_bias_is_uniform_and_costs_nothing() {
+		let (nodes, t) = topology();
+		let r = LarmixRouter::new(LarmixConfig { bias: 0.0 }).unwrap();
+		let p = r.hop_distribution(&t, None, &nodes);
+		assert!(p.iter().all(|x| (x - 0.05).abs() < 1e-12));
+		let route = r.select(&t, &nodes, 5, &mut StdRng::seed_from_u64(1)).unwrap();
+		assert!(route.anonymity_cost_bits() < 1e-9);
+	}
+
+	#[test]
+	fn bias_trades_anonymity_for_latency() {
+		let (nodes, t) = topology();
+		let mut rng = StdRng::seed_from_u64(9);
+		let est = |bias: f64, rng: &mut StdRng| LarmixRouter::new(LarmixConfig { bias }).unwrap().estimate(&t, &nodes, 5, 400, rng).unwrap();
+		let (e0, e2, e8) = (est(0.0, &mut rng), est(2.0, &mut rng), est(8.0, &mut rng));
+		assert!(e0.mean_latency > e2.mean_latency && e2.mean_latency > e8.mean_latency);
+		assert!(e0.mean_anonymity_cost_bits < e2.mean_anonymity_cost_bits && e2.mean_anonymity_cost_bits < e8.mean_anonymity_cost_bits);
+	}
+
+	#[test]
+	fn calibrates_to_latency_target() {
+		let (nodes, t) = topology();
+		let mut rng = StdRng::seed_from_u64(5);
+		let target = Duration::from_millis(50);
+		let e = LarmixRouter::calibrate_bias(&t, &nodes, 5, target, 16.0, 300, &mut rng).unwrap();
+		assert!(e.mean_latency <= target && e.bias > 0.0);
+		assert!(LarmixRouter::calibrate_bias(&t, &nodes, 5, Duration::from_millis(1), 16.0, 50, &mut rng).is_err());
+		assert!(LarmixRouter::new(LarmixConfig { bias: -1.0 }).is_err());
+	}
+}
@@ -1 +1,14 @@
+#![forbid(unsafe_code)]
 
+pub mod accumulator;
+pub mod anonymity;
+pub mod cmix;
+pub mod cover;
+pub mod cover_adaptive;
+pub mod errors;
+pub mod larmix;
+pub mod sphinx;
+pub mod vdf;
+pub mod vdf_calib;
+
+pub use errors::{Error, Result};
@@ -0,0 +1,522 @@
+﻿#![forbid(unsafe_code)]
+
+//! Sphinx-style layered onion packets.
+//!
+//! A packet is `α (32) | β (MAX_HOPS × ROUTING_LEN) | γ (16) | δ (PAYLOAD_LEN)`
+//! and is exactly [`PACKET_LEN`] bytes at every hop, so neither the position
+//! on the route nor the route length is visible on the wire.
+//!
+//! - `α` is the X25519 group element. Each hop derives its shared secret from
+//!   `α` and then re-blinds it, so successive `α` values are unlinkable.
+//! - `β` holds the per-hop routing blocks, encrypted with a keystream; filler
+//!   keeps its length constant as blocks are peeled off.
+//! - `γ` authenticates `β` for the current hop.
+//! - `δ` is the payload under one LIONESS-style wide-block layer per hop, so
+//!   tampering anywhere on the path garbles the whole payload and the final
+//!   hop's zero-tag check fails.
+//!
+//! All symmetric primitives are BLAKE3 (`derive_key`, keyed hash, XOF).
+//! Hops record a replay tag per processed packet; see [`ReplayCache`].
+
+use std::collections::{HashSet, VecDeque};
+
+use rand::{CryptoRng, RngCore};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroize;
+
+use crate::errors::{Error, Result};
+
+pub type NodeId = [u8; 32];
+
+pub const MIN_HOPS: usize = 3;
+pub const MAX_HOPS: usize = 7;
+pub const GROUP_LEN: usize = 32;
+pub const MAC_LEN: usize = 16;
+/// `type (1) | next hop (32) | next γ (16)`.
+pub const ROUTING_LEN: usize = 1 + 32 + MAC_LEN;
+pub const BETA_LEN: usize = MAX_HOPS * ROUTING_LEN;
+pub const HEADER_LEN: usize = GROUP_LEN + BETA_LEN + MAC_LEN;
+/// One full 1280-byte cell.
+pub const PACKET_LEN: usize = 1280;
+pub const PAYLOAD_LEN: usize = PACKET_LEN - HEADER_LEN;
+/// Zero prefix checked by the final hop.
+const PAYLOAD_TAG_LEN: usize = 16;
+/// Largest application payload: `PAYLOAD_LEN` minus the zero tag and a u16 length.
+pub const MAX_MESSAGE_LEN: usize = PAYLOAD_LEN - PAYLOAD_TAG_LEN - 2;
+
+const TYPE_RELAY: u8 = 0x01;
+const TYPE_DELIVER: u8 = 0x02;
+
+const CTX_RHO: &str = "nyx-sphinx v1 header stream";
+const CTX_MU: &str = "nyx-sphinx v1 header mac";
+const CTX_BLIND: &str = "nyx-sphinx v1 blinding";
+const CTX_TAG: &str = "nyx-sphinx v1 replay tag";
+const CTX_PI: [&str; 4] = ["nyx-sphinx v1 lioness k1", "nyx-sphinx v1 lioness k2", "nyx-sphinx v1 lioness k3", "nyx-sphinx v1 lioness k4"];
+
+/// A hop on a route: its identifier and X25519 mix key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hop {
+	pub node_id: NodeId,
+	pub public_key: [u8; 32],
+}
+
+/// A mix node's long-term Sphinx key.
+pub struct NodeKey {
+	secret: StaticSecret,
+}
+
+impl NodeKey {
+	pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self { Self { secret: StaticSecret::random_from_rng(rng) } }
+
+	pub fn from_bytes(bytes: [u8; 32]) -> Self { Self { secret: StaticSecret::from(bytes) } }
+
+	pub fn public_key(&self) -> [u8; 32] { PublicKey::from(&self.secret).to_bytes() }
+}
+
+impl core::fmt::Debug for NodeKey {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result { f.debug_struct("NodeKey").field("public", &self.public_key()).finish_non_exhaustive() }
+}
+
+/// A fixed-size Sphinx packet.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SphinxPacket(Box<[u8; PACKET_LEN]>);
+
+impl SphinxPacket {
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+		let arr: [u8; PACKET_LEN] = bytes.try_into().map_err(|_| Error::protocol(format!("sphinx packet must be {PACKET_LEN} bytes")))?;
+		Ok(Self(Box::new(arr)))
+	}
+
+	pub fn as_bytes(&self) -> &[u8; PACKET_LEN] { &self.0 }
+
+	/// A packet no hop can open, shaped exactly like a real one. `α` is a
+	/// genuine X25519 public key, so it lies on the curve with its top bit
+	/// clear; `β`, `γ` and `δ` are random, as they appear in real packets.
+	/// Used for cover traffic and batch padding.
+	pub fn dummy<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+		let mut p = Box::new([0u8; PACKET_LEN]);
+		p[..GROUP_LEN].copy_from_slice(&PublicKey::from(&StaticSecret::random_from_rng(&mut *rng)).to_bytes());
+		rng.fill_bytes(&mut p[GROUP_LEN..]);
+		Self(p)
+	}
+
+	fn alpha(&self) -> [u8; GROUP_LEN] { self.0[..GROUP_LEN].try_into().expect("fixed layout") }
+	fn beta(&self) -> &[u8] { &self.0[GROUP_LEN..GROUP_LEN + BETA_LEN] }
+	fn gamma(&self) -> &[u8] { &self.0[GROUP_LEN + BETA_LEN..HEADER_LEN] }
+	fn delta(&self) -> &[u8] { &self.0[HEADER_LEN..] }
+
+	fn assemble(alpha: &[u8; GROUP_LEN], beta: &[u8], gamma: &[u8; MAC_LEN], delta: &[u8]) -> Self {
+		let mut p = Box::new([0u8; PACKET_LEN]);
+		p[..GROUP_LEN].copy_from_slice(alpha);
+		p[GROUP_LEN..GROUP_LEN + BETA_LEN].copy_from_slice(beta);
+		p[GROUP_LEN + BETA_LEN..HEADER_LEN].copy_from_slice(gamma);
+		p[HEADER_LEN..].copy_from_slice(delta);
+		Self(p)
+	}
+}
+
+impl core::fmt::Debug for SphinxPacket {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result { write!(f, "SphinxPacket({} bytes)", PACKET_LEN) }
+}
+
+/// Outcome of processing a packet at one hop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessResult {
+	Forward { next_hop: NodeId, packet: SphinxPacket },
+	Deliver { payload: Vec<u8> },
+}
+
+/// Builds packets for a fixed route of `MIN_HOPS..=MAX_HOPS` hops.
+#[derive(Debug, Clone)]
+pub struct SphinxBuilder {
+	route: Vec<Hop>,
+}
+
+impl SphinxBuilder {
+	pub fn new(route: &[Hop]) -> Result<Self> {
+		if !(MIN_HOPS..=MAX_HOPS).contains(&route.len()) {
+			return Err(Error::config(format!("route must have {MIN_HOPS}..={MAX_HOPS} hops, got {}", route.len())));
+		}
+		Ok(Self { route: route.to_vec() })
+	}
+
+	pub fn route(&self) -> &[Hop] { &self.route }
+
+	pub fn build(&self, message: &[u8]) -> Result<SphinxPacket> { self.build_with_rng(message, &mut rand::rngs::OsRng) }
+
+	pub fn build_with_rng<R: RngCore + CryptoRng>(&self, message: &[u8], rng: &mut R) -> Result<SphinxPacket> { Ok(self.build_layers_with_rng(message, rng)?.swap_remove(0)) }
+
+	/// The packet as it arrives at every hop: `[0]` is sent to the first hop
+	/// and `[i]` is what hop `i - 1` forwards, and so what a cMix hop batches
+	/// and proves inclusion of.
+	pub fn build_layers(&self, message: &[u8]) -> Result<Vec<SphinxPacket>> { self.build_layers_with_rng(message, &mut rand::rngs::OsRng) }
+
+	pub fn build_layers_with_rng<R: RngCore + CryptoRng>(&self, message: &[u8], rng: &mut R) -> Result<Vec<SphinxPacket>> {
+		if message.len() > MAX_MESSAGE_LEN {
+			return Err(Error::protocol(format!("message exceeds {MAX_MESSAGE_LEN} bytes")));
+		}
+		let r = self.route.len();
+		let x = StaticSecret::random_from_rng(&mut *rng);
+
+		// Per-hop α and shared secrets. Hop i sees α_i = b_{i-1}·…·b_0·x·G and
+		// computes s_i = y_i·α_i; we reach the same point by applying x and the
+		// blinding factors to Y_i in turn.
+		let mut alphas = Vec::with_capacity(r);
+		let mut secrets: Vec<[u8; 32]> = Vec::with_capacity(r);
+		let mut blinds: Vec<StaticSecret> = Vec::with_capacity(r);
+		let mut alpha = PublicKey::from(&x).to_bytes();
+		for hop in &self.route {
+			let mut s = x.diffie_hellman(&PublicKey::from(hop.public_key)).to_bytes();
+			for b in &blinds {
+				s = b.diffie_hellman(&PublicKey::from(s)).to_bytes();
+			}
+			if s == [0u8; 32] {
+				return Err(Error::protocol("low-order hop key"));
+			}
+			let b = blinding_factor(&alpha, &s);
+			alphas.push(alpha);
+			alpha = b.diffie_hellman(&PublicKey::from(alpha)).to_bytes();
+			blinds.push(b);
+			secrets.push(s);
+		}
+
+		// Filler: what hop r-1 will see at the tail of β after r-1 shifts.
+		let mut filler: Vec<u8> = Vec::new();
+		for s in &secrets[..r - 1] {
+			filler.extend_from_slice(&[0u8; ROUTING_LEN]);
+			let rho = rho(s);
+			let start = BETA_LEN + ROUTING_LEN - filler.len();
+			xor(&mut filler, &rho[start..]);
+		}
+
+		// Innermost β: deliver block, zero padding, then filler.
+		let mut beta = vec![0u8; BETA_LEN];
+		beta[0] = TYPE_DELIVER;
+		beta[1..33].copy_from_slice(&self.route[r - 1].node_id);
+		let head = BETA_LEN - filler.len();
+		xor(&mut beta[..head], &rho(&secrets[r - 1])[..head]);
+		beta[head..].copy_from_slice(&filler);
+		let mut gamma = mac(&secrets[r - 1], &beta);
+		let mut headers = vec![(Vec::new(), [0u8; MAC_LEN]); r];
+		headers[r - 1] = (beta.clone(), gamma);
+
+		for i in (0..r - 1).rev() {
+			let mut next = vec![0u8; BETA_LEN];
+			next[0] = TYPE_RELAY;
+			next[1..33].copy_from_slice(&self.route[i + 1].node_id);
+			next[33..ROUTING_LEN].copy_from_slice(&gamma);
+			next[ROUTING_LEN..].copy_from_slice(&beta[..BETA_LEN - ROUTING_LEN]);
+			xor(&mut next, &rho(&secrets[i])[..BETA_LEN]);
+			beta = next;
+			gamma = mac(&secrets[i], &beta);
+			headers[i] = (beta.clone(), gamma);
+		}
+
+		// Payload: zero tag | u16 length | message | zero pad, then wrap from the inside out.
+		let mut delta = vec![0u8; PAYLOAD_LEN];
+		delta[PAYLOAD_TAG_LEN..PAYLOAD_TAG_LEN + 2].copy_from_slice(&(message.len() as u16).to_be_bytes());
+		delta[PAYLOAD_TAG_LEN + 2..PAYLOAD_TAG_LEN + 2 + message.len()].copy_from_slice(message);
+		let mut packets = Vec::with_capacity(r);
+		for i in (0..r).rev() {
+			lioness_encrypt(&secrets[i], &mut delta);
+			packets.push(SphinxPacket::assemble(&alphas[i], &headers[i].0, &headers[i].1, &delta));
+		}
+		packets.reverse();
+		for s in secrets.iter_mut() {
+			s.zeroize();
+		}
+		Ok(packets)
+	}
+}
+
+/// Replay tags of packets already processed by this node.
+///
+/// Tags must be retained for as long as the node key is valid; rotate the key
+/// and call [`ReplayCache::clear`] together. A bounded cache
+/// ([`ReplayCache::with_capacity`]) forgets the oldest tags first, so replays
+/// older than `capacity` packets go undetected; size it to the key lifetime.
+#[derive(Debug, Default)]
+pub struct ReplayCache {
+	seen: HashSet<[u8; 32]>,
+	order: VecDeque<[u8; 32]>,
+	capacity: Option<usize>,
+}
+
+impl ReplayCache {
+	pub fn new() -> Self { Self::default() }
+
+	pub fn with_capacity(capacity: usize) -> Self { Self { capacity: Some(capacity.max(1)), ..Self::default() } }
+
+	/// Returns false if `tag` was already recorded.
+	pub fn insert(&mut self, tag: [u8; 32]) -> bool {
+		if !self.seen.insert(tag) {
+			return false;
+		}
+		if let Some(cap) = self.capacity {
+			self.order.push_back(tag);
+			if self.order.len() > cap {
+				if let Some(old) = self.order.pop_front() {
+					self.seen.remove(&old);
+				}
+			}
+		}
+		true
+	}
+
+	pub fn len(&self) -> usize { self.seen.len() }
+
+	pub fn is_empty(&self) -> bool { self.seen.is_empty() }
+
+	pub fn clear(&mut self) {
+		self.seen.clear();
+		self.order.clear();
+	}
+}
+
+/// Peel one layer off `packet` with `key`.
+pub fn process_at_hop(key: &NodeKey, replay: &mut ReplayCache, packet: &SphinxPacket) -> Result<ProcessResult> {
+	let alpha = packet.alpha();
+	let mut s = key.secret.diffie_hellman(&PublicKey::from(alpha)).to_bytes();
+	if s == [0u8; 32] {
+		return Err(Error::protocol("low-order group element"));
+	}
+
+	if !constant_time_eq(&mac(&s, packet.beta()), packet.gamma()) {
+		return Err(Error::Integrity);
+	}
+	// Only record authenticated packets, so garbage cannot fill the cache.
+	if !replay.insert(derive(CTX_TAG, &s)) {
+		return Err(Error::Replay);
+	}
+
+	let mut ext = vec![0u8; BETA_LEN + ROUTING_LEN];
+	ext[..BETA_LEN].copy_from_slice(packet.beta());
+	xor(&mut ext, &rho(&s));
+
+	let mut delta = packet.delta().to_vec();
+	lioness_decrypt(&s, &mut delta);
+
+	let result = match ext[0] {
+		TYPE_RELAY => {
+			let next_hop: NodeId = ext[1..33].try_into().expect("fixed layout");
+			let gamma: [u8; MAC_LEN] = ext[33..ROUTING_LEN].try_into().expect("fixed layout");
+			let next_alpha = blinding_factor(&alpha, &s).diffie_hellman(&PublicKey::from(alpha)).to_bytes();
+			let packet = SphinxPacket::assemble(&next_alpha, &ext[ROUTING_LEN..], &gamma, &delta);
+			Ok(ProcessResult::Forward { next_hop, packet })
+		}
+		TYPE_DELIVER => {
+			if delta[..PAYLOAD_TAG_LEN].iter().any(|&b| b != 0) {
+				return Err(Error::Integrity);
+			}
+			let len = u16::from_be_bytes([delta[PAYLOAD_TAG_LEN], delta[PAYLOAD_TAG_LEN + 1]]) as usize;
+			if len > MAX_MESSAGE_LEN {
+				return Err(Error::protocol("invalid payload length"));
+			}
+			Ok(ProcessResult::Deliver { payload: delta[PAYLOAD_TAG_LEN + 2..PAYLOAD_TAG_LEN + 2 + len].to_vec() })
+		}
+		t => Err(Error::protocol(format!("unknown routing type {t:#04x}"))),
+	};
+	s.zeroize();
+	result
+}
+
+fn derive(ctx: &str, s: &[u8; 32]) -> [u8; 32] { blake3::derive_key(ctx, s) }
+
+fn rho(s: &[u8; 32]) -> Vec<u8> {
+	let mut out = vec![0u8; BETA_LEN + ROUTING_LEN];
+	blake3::Hasher::new_keyed(&derive(CTX_RHO, s)).finalize_xof().fill(&mut out);
+	out
+}
+
+fn mac(s: &[u8; 32], data: &[u8]) -> [u8; MAC_LEN] {
+	let h = blake3::keyed_hash(&derive(CTX_MU, s), data);
+	h.as_bytes()[..MAC_LEN].try_into().expect("mac length")
+}
+
+fn blinding_factor(alpha: &[u8; 32], s: &[u8; 32]) -> StaticSecret {
+	let mut h = blake3::Hasher::new_derive_key(CTX_BLIND);
+	h.update(alpha);
+	h.update(s);
+	StaticSecret::from(*h.finalize().as_bytes())
+}
+
+fn xor(dst: &mut [u8], src: &[u8]) {
+	for (d, s) in dst.iter_mut().zip(src) {
+		*d ^= s;
+	}
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool { a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0 }
+
+// LIONESS over BLAKE3: the block is L (32 bytes) | R. The stream step keys the
+// XOF with `L ^ k`, the hash step is a keyed hash of R.
+fn lioness_keys(s: &[u8; 32]) -> [[u8; 32]; 4] { CTX_PI.map(|ctx| derive(ctx, s)) }
+
+fn lioness_stream(l: &[u8], k: &[u8; 32], r: &mut [u8]) {
+	let mut key = *k;
+	xor(&mut key, l);
+	let mut ks = vec![0u8; r.len()];
+	blake3::Hasher::new_keyed(&key).finalize_xof().fill(&mut ks);
+	xor(r, &ks);
+}
+
+fn lioness_hash(k: &[u8; 32], r: &[u8], l: &mut [u8]) { xor(l, blake3::keyed_hash(k, r).as_bytes()); }
+
+fn lioness_encrypt(s: &[u8; 32], block: &mut [u8]) {
+	let [k1, k2, k3, k4] = lioness_keys(s);
+	let (l, r) = block.split_at_mut(32);
+	lioness_stream(l, &k1, r);
+	lioness_hash(&k2, r, l);
+	lioness_stream(l, &k3, r);
+	lioness_hash(&k4, r, l);
+}
+
+fn lioness_decrypt(s: &[u8; 32], block: &mut [u8]) {
+	let [k1, k2, k3, k4] = lioness_keys(s);
+	let (l, r) = block.split_at_mut(32);
+	lioness_hash(&k4, r, l);
+	lioness_stream(l, &k3, r);
+	lioness_hash(&k2, r, l);
+	lioness_stream(l, &k1, r);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use rand::rngs::OsRng;
+
+	fn nodes(n: usize) -> (Vec<NodeKey>, Vec<Hop>) {
+		let keys: Vec<NodeKey> = (0..n).map(|_| NodeKey::generate(&mut OsRng)).collect();
+		let hops = keys.iter().enumerate().map(|(i, k)| Hop { node_id: [i as u8 + 1; 32], public_key: k.public_key() }).collect();
+		(keys, hops)
+	}
+
+	fn route_through(keys: &[NodeKey], hops: &[Hop], mut packet: SphinxPacket) -> Result<Vec<u8>> {
+		let mut caches: Vec<ReplayCache> = keys.iter().map(|_| ReplayCache::new()).collect();
+		let mut at = 0;
+		loop {
+			match process_at_hop(&keys[at], &mut caches[at], &packet)? {
+				ProcessResult::Forward { next_hop, packet: p } => {
+					at = hops.iter().position(|h| h.node_id == next_hop).expect("next hop on route");
+					packet = p;
+				}
+				ProcessResult::Deliver { payload } => {
+					assert_eq!(at, hops.len() - 1);
+					return Ok(payload);
+				}
+			}
+		}
+	}
+
+	/// Whether `u` is the x-coordinate of a point on Curve25519 (rather than its twist).
+	pub(crate) fn on_curve(u: &[u8; 32]) -> bool {
+		use num_bigint::BigUint;
+		let p = (BigUint::from(1u8) << 255u32) - 19u32;
+		let u = BigUint::from_bytes_le(u) % &p;
+		let rhs = (&u * &u * &u + BigUint::from(486_662u32) * &u * &u + &u) % &p;
+		let e = (&p - 1u32) >> 1u32;
+		rhs.modpow(&e, &p) <= BigUint::from(1u8)
+	}
+
+	#[test]
+	fn dummies_have_well_formed_headers() {
+		let (keys, hops) = nodes(MIN_HOPS);
+		let real = SphinxBuilder::new(&hops).unwrap().build(b"x").unwrap();
+		assert!(on_curve(&real.alpha()));
+		for _ in 0..64 {
+			let d = SphinxPacket::dummy(&mut OsRng);
+			let alpha = d.alpha();
+			assert_eq!(alpha[31] & 0x80, 0);
+			assert!(on_curve(&alpha));
+			// A hop treats it like any packet meant for someone else: the MAC fails.
+			assert!(matches!(process_at_hop(&keys[0], &mut ReplayCache::new(), &d), Err(Error::Integrity)));
+		}
+	}
+
+	#[test]
+	fn delivers_over_every_route_length() {
+		for n in MIN_HOPS..=MAX_HOPS {
+			let (keys, hops) = nodes(n);
+			let msg = vec![n as u8; 100 * n];
+			let pkt = SphinxBuilder::new(&hops).unwrap().build(&msg).unwrap();
+			assert_eq!(route_through(&keys, &hops, pkt).unwrap(), msg);
+		}
+		let (keys, hops) = nodes(3);
+		let max = vec![7u8; MAX_MESSAGE_LEN];
+		let pkt = SphinxBuilder::new(&hops).unwrap().build(&max).unwrap();
+		assert_eq!(route_through(&keys, &hops, pkt).unwrap(), max);
+	}
+
+	#[test]
+	fn layers_match_what_each_hop_forwards() {
+		let (keys, hops) = nodes(4);
+		let layers = SphinxBuilder::new(&hops).unwrap().build_layers(b"layers").unwrap();
+		assert_eq!(layers.len(), 4);
+		for (i, key) in keys.iter().enumerate().take(3) {
+			match process_at_hop(key, &mut ReplayCache::new(), &layers[i]).unwrap() {
+				ProcessResult::Forward { packet, .. } => assert_eq!(packet, layers[i + 1]),
+				other => panic!("hop {i} did not forward: {other:?}"),
+			}
+		}
+		assert_eq!(route_through(&keys[3..], &hops[3..], layers[3].clone()).unwrap(), b"layers");
+	}
+
+	#[test]
+	fn rejects_bad_route_lengths_and_oversized_messages() {
+		let (_, hops) = nodes(8);
+		assert!(SphinxBuilder::new(&hops[..2]).is_err());
+		assert!(SphinxBuilder::new(&hops).is_err());
+		let b = SphinxBuilder::new(&hops[..3]).unwrap();
+		assert!(b.build(&vec![0u8; MAX_MESSAGE_LEN + 1]).is_err());
+	}
+
+	#[test]
+	fn replay_is_detected() {
+		let (keys, hops) = nodes(3);
+		let pkt = SphinxBuilder::new(&hops).unwrap().build(b"once").unwrap();
+		let mut cache = ReplayCache::new();
+		assert!(process_at_hop(&keys[0], &mut cache, &pkt).is_ok());
+		assert!(matches!(process_at_hop(&keys[0], &mut cache, &pkt), Err(Error::Replay)));
+	}
+
+	#[test]
+	fn tampering_is_detected() {
+		let (keys, hops) = nodes(4);
+		let builder = SphinxBuilder::new(&hops).unwrap();
+
+		let mut header = builder.build(b"hi").unwrap().as_bytes().to_vec();
+		header[GROUP_LEN + 5] ^= 1;
+		let pkt = SphinxPacket::from_bytes(&header).unwrap();
+		assert!(matches!(process_at_hop(&keys[0], &mut ReplayCache::new(), &pkt), Err(Error::Integrity)));
+
+		let mut payload = builder.build(b"hi").unwrap().as_bytes().to_vec();
+		payload[PACKET_LEN - 1] ^= 1;
+		let pkt = SphinxPacket::from_bytes(&payload).unwrap();
+		assert!(matches!(route_through(&keys, &hops, pkt), Err(Error::Integrity)));
+	}
+
+	#[test]
+	fn hops_see_unlinkable_fixed_size_packets() {
+		let (keys, hops) = nodes(3);
+		let pkt = SphinxBuilder::new(&hops).unwrap().build(b"x").unwrap();
+		let ProcessResult::Forward { next_hop, packet: p1 } = process_at_hop(&keys[0], &mut ReplayCache::new(), &pkt).unwrap() else { panic!("expected forward") };
+		assert_eq!(next_hop, hops[1].node_id);
+		assert_eq!(p1.as_bytes().len(), PACKET_LEN);
+		// α is re-blinded and no header/payload bytes carry over unchanged.
+		assert_ne!(pkt.alpha(), p1.alpha());
+		assert_ne!(pkt.delta(), p1.delta());
+		assert_ne!(&pkt.beta()[ROUTING_LEN..], &p1.beta()[..BETA_LEN - ROUTING_LEN]);
+		// Wrong node key cannot authenticate the header.
+		assert!(process_at_hop(&keys[2], &mut ReplayCache::new(), &pkt).is_err());
+	}
+
+	#[test]
+	fn bounded_replay_cache_evicts_oldest() {
+		let mut c = ReplayCache::with_capacity(2);
+		assert!(c.insert([1; 32]) && c.insert([2; 32]));
+		assert!(!c.insert([2; 32]));
+		assert!(c.insert([3; 32]));
+		assert_eq!(c.len(), 2);
+		assert!(c.insert([1; 32]));
+	}
+}
@@ -1 +1,282 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Wesolowski verifiable delay function over an RSA group.
+//!
+//! `eval` computes `y = x^(2^T) mod N` by `T` sequential squarings; nobody
+//! without the factorisation of `N` is known to do it faster. `prove` returns
+//! `π = x^⌊2^T / ℓ⌋` for a 128-bit prime `ℓ` derived from `(x, y, T)`, and
+//! `verify` checks `π^ℓ · x^(2^T mod ℓ) = y` with two short exponentiations.
+//!
+//! Elements live in `Z_N* / {±1}` (stored as `min(v, N - v)`) so the order-2
+//! element `-1` cannot be used to forge proofs. The default modulus is the
+//! RSA-2048 challenge number, whose factors nobody is known to have.
+
+use num_bigint::BigUint;
+use num_integer::Integer;
+use num_traits::{One, Zero};
+
+use crate::cmix::{DelayFunction, DelayOutput};
+use crate::errors::{Error, Result};
+
+/// RSA Factoring Challenge modulus RSA-2048.
+pub const RSA_2048_HEX: &str = "c7970ceedcc3b0754490201a7aa613cd73911081c790f5f1a8726f463550bb5b7ff0db8e1ea1189ec72f93d1650011bd721aeeacc2acde32a04107f0648c2813a31f5b0b7765ff8b44b4b6ffc93384b646eb09c7cf5e8592d40ea33c80039f35b4f14a04b51f7bfd781be4d1673164ba8eb991c2c4d730bbbe35f592bdef524af7e8daefd26c66fc02c479af89d64d373f442709439de66ceb955f3ea37d5159f6135809f85334b5cb1813addc80cd05609f10ac6a95ad65872c909525bdad32bc729592642920f24c61dc5b3c3b7923e56b16a4d9d373d8721f24a3fc0f1b3131f55615172866bccc30f95054c824e733a5eb6817f7bc16399d48c6361cc7e5";
+
+/// Bit length of the Fiat–Shamir challenge prime.
+pub const CHALLENGE_PRIME_BITS: usize = 128;
+
+const CTX_INPUT: &str = "nyx-vdf v1 hash to group";
+const CTX_PRIME: &str = "nyx-vdf v1 hash to prime";
+
+/// Proof `π` for one evaluation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof(pub BigUint);
+
+/// The group and the operations on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vdf {
+	modulus: BigUint,
+	byte_len: usize,
+}
+
+impl Default for Vdf {
+	fn default() -> Self { Self::rsa2048() }
+}
+
+impl Vdf {
+	pub fn rsa2048() -> Self { Self::new(BigUint::parse_bytes(RSA_2048_HEX.as_bytes(), 16).expect("valid hex")).expect("valid modulus") }
+
+	/// Group modulo `modulus`. Only use moduli whose factorisation nobody holds.
+	pub fn new(modulus: BigUint) -> Result<Self> {
+		if modulus.bits() < 256 || modulus.is_even() {
+			return Err(Error::config("vdf modulus must be odd and at least 256 bits"));
+		}
+		let byte_len = modulus.bits().div_ceil(8) as usize;
+		Ok(Self { modulus, byte_len })
+	}
+
+	pub fn modulus(&self) -> &BigUint { &self.modulus }
+
+	/// Fixed big-endian length of encoded elements.
+	pub fn element_len(&self) -> usize { self.byte_len }
+
+	/// Map a challenge to a group element.
+	pub fn hash_to_group(&self, challenge: &[u8]) -> BigUint {
+		let mut h = blake3::Hasher::new_derive_key(CTX_INPUT);
+		h.update(challenge);
+		// 128 extra bits make the reduction bias negligible.
+		let mut wide = vec![0u8; self.byte_len + 16];
+		h.finalize_xof().fill(&mut wide);
+		let x = self.normalize(BigUint::from_bytes_be(&wide) % &self.modulus);
+		if x.is_zero() { BigUint::from(2u32) } else { x }
+	}
+
+	/// `x^(2^t)`.
+	pub fn eval(&self, x: &BigUint, t: u64) -> BigUint {
+		let mut y = x % &self.modulus;
+		for _ in 0..t {
+			y = &y * &y % &self.modulus;
+		}
+		self.normalize(y)
+	}
+
+	/// Proof for `y = eval(x, t)`; costs about as much as `eval`.
+	pub fn prove(&self, x: &BigUint, y: &BigUint, t: u64) -> Proof {
+		let l = self.challenge_prime(x, y, t);
+		// Long division of 2^t by ℓ, one bit at a time: π accumulates x^⌊2^t/ℓ⌋.
+		let x = x % &self.modulus;
+		let two = BigUint::from(2u32);
+		let mut r = BigUint::one();
+		let mut pi = BigUint::one();
+		for _ in 0..t {
+			let r2 = &r * &two;
+			pi = &pi * &pi % &self.modulus;
+			if r2 >= l {
+				pi = &pi * &x % &self.modulus;
+				r = r2 - &l;
+			} else {
+				r = r2;
+			}
+		}
+		Proof(self.normalize(pi))
+	}
+
+	/// `eval` followed by `prove`.
+	pub fn eval_and_prove(&self, x: &BigUint, t: u64) -> (BigUint, Proof) {
+		let y = self.eval(x, t);
+		let proof = self.prove(x, &y, t);
+		(y, proof)
+	}
+
+	pub fn verify(&self, x: &BigUint, y: &BigUint, t: u64, proof: &Proof) -> bool {
+		if !self.is_canonical(x) || !self.is_canonical(y) || !self.is_canonical(&proof.0) {
+			return false;
+		}
+		let l = self.challenge_prime(x, y, t);
+		let r = BigUint::from(2u32).modpow(&BigUint::from(t), &l);
+		let lhs = proof.0.modpow(&l, &self.modulus) * x.modpow(&r, &self.modulus) % &self.modulus;
+		self.normalize(lhs) == *y
+	}
+
+	/// Fixed-length big-endian encoding.
+	pub fn encode(&self, v: &BigUint) -> Vec<u8> {
+		let bytes = v.to_bytes_be();
+		let mut out = vec![0u8; self.byte_len.saturating_sub(bytes.len())];
+		out.extend_from_slice(&bytes);
+		out
+	}
+
+	pub fn decode(&self, bytes: &[u8]) -> Result<BigUint> {
+		if bytes.len() != self.byte_len {
+			return Err(Error::protocol(format!("vdf element must be {} bytes", self.byte_len)));
+		}
+		let v = BigUint::from_bytes_be(bytes);
+		if self.is_canonical(&v) { Ok(v) } else { Err(Error::protocol("vdf element out of range")) }
+	}
+
+	fn normalize(&self, v: BigUint) -> BigUint {
+		let neg = &self.modulus - &v;
+		if neg < v { neg } else { v }
+	}
+
+	fn is_canonical(&self, v: &BigUint) -> bool { !v.is_zero() && *v < self.modulus && (v << 1usize) < self.modulus }
+
+	/// Fiat–Shamir prime ℓ bound to the statement.
+	fn challenge_prime(&self, x: &BigUint, y: &BigUint, t: u64) -> BigUint {
+		let mut h = blake3::Hasher::new_derive_key(CTX_PRIME);
+		h.update(&self.encode(x));
+		h.update(&self.encode(y));
+		h.update(&t.to_be_bytes());
+		let mut xof = h.finalize_xof();
+		let mut buf = [0u8; CHALLENGE_PRIME_BITS / 8];
+		loop {
+			xof.fill(&mut buf);
+			buf[0] |= 0x80;
+			buf[buf.len() - 1] |= 1;
+			let candidate = BigUint::from_bytes_be(&buf);
+			if is_probable_prime(&candidate) {
+				return candidate;
+			}
+		}
+	}
+}
+
+const SMALL_PRIMES: [u32; 24] = [3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97];
+
+/// Miller–Rabin with fixed bases; candidates come from a hash, not an adversary.
+pub(crate) fn is_probable_prime(n: &BigUint) -> bool {
+	let one = BigUint::one();
+	let two = BigUint::from(2u32);
+	if *n < two {
+		return false;
+	}
+	for p in SMALL_PRIMES.iter().chain(&[2]) {
+		let p = BigUint::from(*p);
+		if *n == p {
+			return true;
+		}
+		if (n % &p).is_zero() {
+			return false;
+		}
+	}
+	let n_minus_1 = n - &one;
+	let s = n_minus_1.trailing_zeros().unwrap_or(0);
+	let d = &n_minus_1 >> s;
+	'witness: for a in SMALL_PRIMES.iter().take(20) {
+		let mut x = BigUint::from(*a).modpow(&d, n);
+		if x == one || x == n_minus_1 {
+			continue;
+		}
+		for _ in 1..s {
+			x = &x * &x % n;
+			if x == n_minus_1 {
+				continue 'witness;
+			}
+		}
+		return false;
+	}
+	true
+}
+
+/// Wesolowski VDF as the cMix batch delay.
+#[derive(Debug, Clone)]
+pub struct WesolowskiDelay {
+	vdf: Vdf,
+	iterations: u64,
+}
+
+impl WesolowskiDelay {
+	pub fn new(vdf: Vdf, iterations: u64) -> Self { Self { vdf, iterations: iterations.max(1) } }
+
+	pub fn vdf(&self) -> &Vdf { &self.vdf }
+
+	pub fn iterations(&self) -> u64 { self.iterations }
+}
+
+impl DelayFunction for WesolowskiDelay {
+	fn evaluate(&self, challenge: &[u8; 32]) -> DelayOutput {
+		let x = self.vdf.hash_to_group(challenge);
+		let (y, proof) = self.vdf.eval_and_prove(&x, self.iterations);
+		DelayOutput { iterations: self.iterations, output: self.vdf.encode(&y), proof: self.vdf.encode(&proof.0) }
+	}
+
+	fn verify(&self, challenge: &[u8; 32], out: &DelayOutput) -> bool {
+		let (Ok(y), Ok(pi)) = (self.vdf.decode(&out.output), self.vdf.decode(&out.proof)) else { return false };
+		self.vdf.verify(&self.vdf.hash_to_group(challenge), &y, out.iterations, &Proof(pi))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rsa2048_modulus_is_well_formed() {
+		let vdf = Vdf::rsa2048();
+		assert_eq!(vdf.modulus().bits(), 2048);
+		assert_eq!(vdf.element_len(), 256);
+	}
+
+	#[test]
+	fn eval_prove_verify_roundtrip() {
+		let vdf = Vdf::rsa2048();
+		let x = vdf.hash_to_group(b"batch");
+		let (y, proof) = vdf.eval_and_prove(&x, 200);
+		assert!(vdf.verify(&x, &y, 200, &proof));
+		assert!(!vdf.verify(&x, &y, 199, &proof));
+		assert!(!vdf.verify(&vdf.hash_to_group(b"other"), &y, 200, &proof));
+		let forged = Proof(vdf.normalize(&proof.0 * 2u32 % vdf.modulus()));
+		assert!(!vdf.verify(&x, &y, 200, &forged));
+		// The negated output is not canonical and must not verify.
+		assert!(!vdf.verify(&x, &(vdf.modulus() - &y), 200, &proof));
+	}
+
+	#[test]
+	fn eval_matches_direct_exponentiation() {
+		let vdf = Vdf::rsa2048();
+		let x = vdf.hash_to_group(b"x");
+		let direct = x.modpow(&(BigUint::one() << 64usize), vdf.modulus());
+		assert_eq!(vdf.eval(&x, 64), vdf.normalize(direct));
+	}
+
+	#[test]
+	fn primality_test_agrees_on_known_values() {
+		assert!(is_probable_prime(&BigUint::from(2u32)));
+		assert!(is_probable_prime(&BigUint::from(340_282_366_920_938_463_463_374_607_431_768_211_297u128)));
+		assert!(!is_probable_prime(&BigUint::from(3_215_031_751u64)));
+		assert!(!is_probable_prime(&(BigUint::from(u64::MAX) * BigUint::from(3u32))));
+	}
+
+	#[test]
+	fn delay_function_roundtrip() {
+		let d = WesolowskiDelay::new(Vdf::rsa2048(), 50);
+		let out = d.evaluate(&[1; 32]);
+		assert_eq!(out.output.len(), 256);
+		assert!(d.verify(&[1; 32], &out));
+		assert!(!d.verify(&[2; 32], &out));
+		assert_eq!(out.iterations, 50);
+		// Verified against the recorded T, whatever the verifier's own.
+		assert!(WesolowskiDelay::new(Vdf::rsa2048(), 51).verify(&[1; 32], &out));
+		assert!(!d.verify(&[1; 32], &DelayOutput { iterations: 51, ..out.clone() }));
+		assert!(!d.verify(&[1; 32], &DelayOutput { proof: vec![0; 256], ..out }));
+	}
+}
@@ -1 +1,86 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! VDF iteration calibration.
+//!
+//! Times a short probe of squarings and of proof generation on this machine
+//! and converts a target delay into an iteration count. A batch release pays
+//! for both, so [`Calibration::iterations_for`] budgets for the proof too.
+
+use std::time::{Duration, Instant};
+
+use nyx_core::cmix::MIN_VDF_ITERATIONS;
+
+use crate::vdf::{Vdf, WesolowskiDelay};
+
+/// Iterations used by [`Calibration::measure`].
+pub const DEFAULT_PROBE_ITERATIONS: u64 = 1 << 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+	/// Sequential modular squarings per second.
+	pub squarings_per_sec: f64,
+	/// Proof generation time relative to evaluation time.
+	pub prove_ratio: f64,
+}
+
+impl Calibration {
+	pub fn measure(vdf: &Vdf) -> Self { Self::measure_with(vdf, DEFAULT_PROBE_ITERATIONS) }
+
+	pub fn measure_with(vdf: &Vdf, probe: u64) -> Self {
+		let probe = probe.max(1);
+		let x = vdf.hash_to_group(b"nyx-vdf calibration");
+		let start = Instant::now();
+		let y = vdf.eval(&x, probe);
+		let eval = start.elapsed().as_secs_f64().max(1e-9);
+		let start = Instant::now();
+		vdf.prove(&x, &y, probe);
+		let prove = start.elapsed().as_secs_f64();
+		Self { squarings_per_sec: probe as f64 / eval, prove_ratio: prove / eval }
+	}
+
+	/// Iterations whose evaluation alone takes about `target`.
+	pub fn eval_iterations_for(&self, target: Duration) -> u64 { ((target.as_secs_f64() * self.squarings_per_sec) as u64).max(1) }
+
+	/// Iterations whose evaluation plus proof take about `target`.
+	pub fn iterations_for(&self, target: Duration) -> u64 { ((target.as_secs_f64() * self.squarings_per_sec / (1.0 + self.prove_ratio)) as u64).max(1) }
+
+	/// Expected evaluation plus proof time for `iterations`.
+	pub fn estimate(&self, iterations: u64) -> Duration { Duration::from_secs_f64(iterations as f64 * (1.0 + self.prove_ratio) / self.squarings_per_sec) }
+}
+
+impl WesolowskiDelay {
+	/// Delay over `vdf` calibrated to take about `target` per batch, and never
+	/// fewer than [`MIN_VDF_ITERATIONS`] iterations.
+	pub fn calibrated(vdf: Vdf, target: Duration) -> Self {
+		let cal = Calibration::measure(&vdf);
+		let iterations = cal.iterations_for(target).max(MIN_VDF_ITERATIONS);
+		tracing::debug!(squarings_per_sec = cal.squarings_per_sec, iterations, "vdf calibrated");
+		Self::new(vdf, iterations)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn iteration_count_scales_with_target() {
+		let cal = Calibration { squarings_per_sec: 100_000.0, prove_ratio: 1.0 };
+		assert_eq!(cal.eval_iterations_for(Duration::from_millis(100)), 10_000);
+		assert_eq!(cal.iterations_for(Duration::from_millis(100)), 5_000);
+		assert_eq!(cal.iterations_for(Duration::ZERO), 1);
+		assert_eq!(cal.estimate(5_000), Duration::from_millis(100));
+	}
+
+	#[test]
+	fn calibration_never_goes_below_the_network_minimum() {
+		assert_eq!(WesolowskiDelay::calibrated(Vdf::rsa2048(), Duration::ZERO).iterations(), MIN_VDF_ITERATIONS);
+	}
+
+	#[test]
+	fn measured_rate_is_sane() {
+		let cal = Calibration::measure_with(&Vdf::rsa2048(), 64);
+		assert!(cal.squarings_per_sec > 0.0 && cal.squarings_per_sec.is_finite());
+		assert!(cal.prove_ratio > 0.0);
+	}
+}
@@ -1 +1,60 @@
+use std::time::{Duration, Instant};
 
+use nyx_mix::cover::CoverGenerator;
+use nyx_mix::cover_adaptive::{AdaptiveCoverConfig, AdaptiveCoverController};
+
+/// Cover packets a Poisson generator emits within one window.
+fn emit_for(generator: &mut CoverGenerator, window: Duration) -> u64 {
+    let mut t = Duration::ZERO;
+    let mut n = 0;
+    while let Some(gap) = generator.next_delay() {
+        t += gap;
+        if t > window {
+            break;
+        }
+        generator.dummy_packet();
+        n += 1;
+    }
+    n
+}
+
+#[test]
+fn utilization_converges_into_band_as_real_traffic_changes() {
+    let cfg = AdaptiveCoverConfig::default();
+    let window = cfg.window;
+    let mut now = Instant::now();
+    let mut ctl = AdaptiveCoverController::new(cfg, now).unwrap();
+    let mut generator = CoverGenerator::with_seed(ctl.lambda(), 2024).unwrap();
+
+    // Idle, moderate, then heavy real traffic (packets per 1 s window).
+    for real in [20u64, 300, 520] {
+        for w in 0..10 {
+            ctl.record_real(real);
+            ctl.record_cover(emit_for(&mut generator, window));
+            now += window;
+            let lambda = ctl.on_tick(now).expect("window elapsed");
+            generator.set_lambda(lambda).unwrap();
+            if w >= 5 {
+                let u = ctl.metrics().utilization;
+                assert!((0.17..=0.63).contains(&u), "real={real} window={w} U={u}");
+            }
+        }
+    }
+    assert_eq!(ctl.metrics().windows, 30);
+    assert!(ctl.metrics().windows_in_band >= 20);
+    assert_eq!(generator.metrics().lambda, ctl.lambda());
+}
+
+#[test]
+fn real_traffic_above_band_drives_cover_to_minimum() {
+    let cfg = AdaptiveCoverConfig::default();
+    let min = cfg.min_lambda;
+    let mut now = Instant::now();
+    let mut ctl = AdaptiveCoverController::new(cfg, now).unwrap();
+    for _ in 0..20 {
+        ctl.record_real(900);
+        now += Duration::from_secs(1);
+        ctl.on_tick(now);
+    }
+    assert!((ctl.lambda() - min).abs() < 0.01);
+}
@@ -1 +1,23 @@
+use std::time::{Duration, Instant};
 
+use nyx_mix::cover_adaptive::{AdaptiveCoverConfig, AdaptiveCoverController};
+
+#[test]
+fn screen_off_applies_cover_ratio_and_restores_on_wake() {
+    let mut now = Instant::now();
+    let mut ctl = AdaptiveCoverController::new(AdaptiveCoverConfig::default(), now).unwrap();
+    let full = ctl.lambda();
+
+    ctl.set_low_power(true);
+    assert!((ctl.lambda() - full * 0.1).abs() < 1e-9);
+    // Windows spent at the reduced rate must not inflate the base rate.
+    for _ in 0..5 {
+        ctl.record_cover((ctl.lambda()) as u64);
+        now += Duration::from_secs(1);
+        ctl.on_tick(now);
+    }
+    assert!((ctl.lambda() - full * 0.1).abs() < 1.0);
+
+    ctl.set_low_power(false);
+    assert!((ctl.lambda() - full).abs() < 10.0);
+}
@@ -1 +1,31 @@
+use std::time::Instant;
 
+use criterion::{criterion_group, criterion_main, Criterion};
+use nyx_crypto::aead::AeadKey;
+use nyx_crypto::hpke;
+use nyx_stream::hpke_rekey_manager::{HpkeRekeyManager, RekeyPolicy};
+
+fn bench_rekey(c: &mut Criterion) {
+	let (sk_a, pk_a) = hpke::gen_keypair();
+	let (sk_b, pk_b) = hpke::gen_keypair();
+	let k0 = AeadKey::generate();
+	let mut a = HpkeRekeyManager::new(RekeyPolicy::default(), 1, (sk_a, pk_a.clone()), pk_b.clone(), k0.clone(), k0.clone());
+	let mut b = HpkeRekeyManager::new(RekeyPolicy::default(), 1, (sk_b, pk_b), pk_a, k0.clone(), k0);
+
+	c.bench_function("hpke_rekey_seal", |bench| {
+		let mut seq = 0u64;
+		bench.iter(|| { seq += 1; a.rekey_at(Instant::now(), seq).unwrap() })
+	});
+
+	c.bench_function("hpke_rekey_roundtrip", |bench| {
+		let mut seq = 0u64;
+		bench.iter(|| {
+			seq += 1;
+			let f = a.rekey_at(Instant::now(), seq).unwrap();
+			b.handle_crypto_frame(&f).unwrap()
+		})
+	});
+}
+
+criterion_group!(benches, bench_rekey);
+criterion_main!(benches);
@@ -5,6 +5,13 @@ use crate::multipath::{integration::IntegrationSettings, mpr::{MprState}, schedu
 use bytes::{Bytes, BytesMut};
 use std::{collections::BTreeMap, time::Duration};
 use tokio::{sync::{mpsc, oneshot}, time::{Instant, sleep}};
+#[cfg(feature = "hpke")]
+use crate::hpke_rekey_manager::{HpkeRekeyManager, RekeyMetrics};
+#[cfg(feature = "hpke")]
+use nyx_crypto::aead::{AeadAlgorithm, AeadCipher, AeadNonce};
+
+#[cfg(feature = "hpke")]
+const PAYLOAD_AEAD: AeadAlgorithm = AeadAlgorithm::ChaCha20Poly1305;
 
 #[derive(Debug, Clone)]
 pub struct AsyncStreamConfig {
@@ -32,6 +39,8 @@ enum Cmd {
 	Send { data: Bytes, ack: oneshot::Sender<()> },
 	Recv { reply: oneshot::Sender<Option<Bytes>> },
 	Close { ack: oneshot::Sender<()> },
+	#[cfg(feature = "hpke")]
+	RekeyMetrics { reply: oneshot::Sender<Option<RekeyMetrics>> },
 }
 
 #[derive(Debug)]
@@ -62,9 +71,29 @@ impl AsyncStream {
 		let _ = rx.await;
 		Ok(())
 	}
+
+	/// Rekey counters and epochs of this endpoint; `None` if it has no rekey manager.
+	#[cfg(feature = "hpke")]
+	pub async fn rekey_metrics(&self) -> Result<Option<RekeyMetrics>> {
+		let (tx, rx) = oneshot::channel();
+		self.tx.send(Cmd::RekeyMetrics { reply: tx }).await.map_err(|_| Error::ChannelClosed)?;
+		rx.await.map_err(|_| Error::ChannelClosed)
+	}
 }
 
-pub fn pair(cfg_a: AsyncStreamConfig, mut cfg_b: AsyncStreamConfig) -> (AsyncStream, AsyncStream) {
+pub fn pair(cfg_a: AsyncStreamConfig, cfg_b: AsyncStreamConfig) -> (AsyncStream, AsyncStream) {
+	spawn_pair(cfg_a, cfg_b, Protection::default(), Protection::default())
+}
+
+/// Like [`pair`], but each endpoint seals its payloads under its manager's
+/// transmit key and sends CRYPTO frames whenever the manager's policy asks for
+/// a rekey. Both managers must be built for the same stream id.
+#[cfg(feature = "hpke")]
+pub fn pair_with_rekey(cfg_a: AsyncStreamConfig, cfg_b: AsyncStreamConfig, rekey_a: HpkeRekeyManager, rekey_b: HpkeRekeyManager) -> (AsyncStream, AsyncStream) {
+	spawn_pair(cfg_a, cfg_b, Protection { rekey: Some(rekey_a) }, Protection { rekey: Some(rekey_b) })
+}
+
+fn spawn_pair(cfg_a: AsyncStreamConfig, mut cfg_b: AsyncStreamConfig, keys_a: Protection, keys_b: Protection) -> (AsyncStream, AsyncStream) {
 	// Ensure distinct stream ids (A->B uses A.stream_id, B->A uses B.stream_id)
 	if cfg_b.stream_id == cfg_a.stream_id { cfg_b.stream_id = cfg_a.stream_id + 1; }
 
@@ -76,8 +105,8 @@ pub fn pair(cfg_a: AsyncStreamConfig, mut cfg_b: AsyncStreamConfig) -> (AsyncStr
 	let (wire_ab_tx, wire_ab_rx) = mpsc::channel::<LinkMsg>(1024);
 	let (wire_ba_tx, wire_ba_rx) = mpsc::channel::<LinkMsg>(1024);
 
-	tokio::spawn(endpoint_task(cfg_a, cmd_a_rx, wire_ab_tx.clone(), wire_ba_rx));
-	tokio::spawn(endpoint_task(cfg_b, cmd_b_rx, wire_ba_tx.clone(), wire_ab_rx));
+	tokio::spawn(endpoint_task(cfg_a, keys_a, cmd_a_rx, wire_ab_tx.clone(), wire_ba_rx));
+	tokio::spawn(endpoint_task(cfg_b, keys_b, cmd_b_rx, wire_ba_tx.clone(), wire_ab_rx));
 
 	(AsyncStream { tx: cmd_a_tx }, AsyncStream { tx: cmd_b_tx })
 }
@@ -89,8 +118,78 @@ struct TxEntry {
 	last_path: PathId,
 }
 
+/// Payload protection of one endpoint. Without a rekey manager frames travel
+/// in the clear and CRYPTO frames are skipped.
+#[derive(Default)]
+struct Protection {
+	#[cfg(feature = "hpke")]
+	rekey: Option<HpkeRekeyManager>,
+}
+
+#[cfg_attr(not(feature = "hpke"), allow(unused_variables))]
+impl Protection {
+	/// CRYPTO frame announcing a fresh transmit key, if the rekey policy calls for one.
+	fn poll_rekey(&mut self, seq: u64) -> Result<Option<Frame>> {
+		#[cfg(feature = "hpke")]
+		if let Some(m) = self.rekey.as_mut() { return m.poll_rekey(seq); }
+		Ok(None)
+	}
+
+	fn seal(&mut self, stream_id: u32, seq: u64, data: Bytes) -> Result<Bytes> {
+		#[cfg(feature = "hpke")]
+		if let Some(m) = self.rekey.as_mut() {
+			let sealed = AeadCipher::with_algorithm(PAYLOAD_AEAD, m.tx_key()).seal(AeadNonce::new(stream_id, seq), &aad(stream_id, seq), &data)?;
+			m.on_bytes_sent(data.len());
+			return Ok(sealed.into());
+		}
+		Ok(data)
+	}
+
+	/// Payload of the next in-order frame. CRYPTO frames install the peer's
+	/// next receive key and deliver nothing.
+	fn receive(&mut self, frame: Frame) -> Result<Option<Bytes>> {
+		#[cfg(feature = "hpke")]
+		if let Some(m) = self.rekey.as_mut() {
+			if frame.header.ty == FrameType::Crypto {
+				m.handle_crypto_frame(&frame)?;
+				return Ok(None);
+			}
+			let (stream_id, seq) = (frame.header.stream_id, frame.header.seq);
+			let pt = m.open_at(std::time::Instant::now(), PAYLOAD_AEAD, AeadNonce::new(stream_id, seq), &aad(stream_id, seq), &frame.payload)?;
+			return Ok(Some(pt.into()));
+		}
+		Ok((frame.header.ty == FrameType::Data).then(|| frame.payload.into()))
+	}
+}
+
+#[cfg(feature = "hpke")]
+fn aad(stream_id: u32, seq: u64) -> [u8; 12] {
+	let mut aad = [0u8; 12];
+	aad[..4].copy_from_slice(&stream_id.to_be_bytes());
+	aad[4..].copy_from_slice(&seq.to_be_bytes());
+	aad
+}
+
+/// Encode `frame` and put it on the wire, or hold it back while simulating reordering.
+async fn transmit(frame: &Frame, path: PathId, reorder_window: Option<usize>, reorder_buf: &mut Vec<(BytesMut, PathId)>, wire_tx: &mpsc::Sender<LinkMsg>) {
+	let mut buf = BytesMut::new();
+	if FrameCodec::encode(frame, &mut buf).is_err() { return; }
+	if let Some(n) = reorder_window {
+		reorder_buf.push((buf, path));
+		if reorder_buf.len() >= n {
+			// Emit in reverse order
+			while let Some((b, path)) = reorder_buf.pop() {
+				let _ = wire_tx.send(LinkMsg::Wire { bytes: b, path: path.0 }).await;
+			}
+		}
+	} else {
+		let _ = wire_tx.send(LinkMsg::Wire { bytes: buf, path: path.0 }).await;
+	}
+}
+
 async fn endpoint_task(
 	cfg: AsyncStreamConfig,
+	mut keys: Protection,
 	mut cmds: mpsc::Receiver<Cmd>,
 	wire_tx: mpsc::Sender<LinkMsg>,
 	mut wire_rx: mpsc::Receiver<LinkMsg>,
@@ -100,7 +199,7 @@ async fn endpoint_task(
 	let mut flow = FlowController::new(cfg.max_inflight, cfg.max_inflight * 4);
 	let mut rtt = RttEstimator::new(cfg.retransmit_timeout);
 	let mut rx_queue: std::collections::VecDeque<Bytes> = Default::default();
-	let mut pending_rx: BTreeMap<u64, Bytes> = BTreeMap::new();
+	let mut pending_rx: BTreeMap<u64, Frame> = BTreeMap::new();
 	let mut expected_rx_seq: u64 = 1;
 	let closed_local = false;
 	let mut closed_remote = false;
@@ -136,25 +235,20 @@ async fn endpoint_task(
 						if closed_local { let _ = ack.send(()); continue; }
 						while !flow.can_send(inflight.len()) { sleep(Duration::from_millis(1)).await; }
 						if let Some(limit) = cfg.max_frame_len { if data.len() > limit { let _ = ack.send(()); continue; } }
-						let frame = Frame::data(cfg.stream_id, next_seq, data);
+						// A due key update takes the next seq so the peer installs it before this frame
+						if let Ok(Some(update)) = keys.poll_rekey(next_seq) {
+							next_seq += 1;
+							let path = mpr.as_mut().map(|s| s.pick_path()).unwrap_or(PathId(0));
+							transmit(&update, path, cfg.reorder_window, &mut reorder_buf, &wire_tx).await;
+							inflight.insert(update.header.seq, TxEntry { frame: update, last_sent: Instant::now(), retries: 0, last_path: path });
+						}
+						let Ok(payload) = keys.seal(cfg.stream_id, next_seq, data) else { let _ = ack.send(()); continue; };
+						let frame = Frame::data(cfg.stream_id, next_seq, payload);
 						next_seq += 1;
 						// Decide path for this frame now
 						let selected_path = mpr.as_mut().map(|s| s.pick_path()).unwrap_or(PathId(0));
 						// Encode and send (or buffer) over the simulated wire
-						let mut buf = BytesMut::new();
-						if FrameCodec::encode(&frame, &mut buf).is_ok() {
-							if let Some(n) = cfg.reorder_window {
-								reorder_buf.push((buf, selected_path));
-								if reorder_buf.len() >= n {
-									// Emit in reverse order
-									while let Some((b, path)) = reorder_buf.pop() {
-										let _ = wire_tx.send(LinkMsg::Wire { bytes: b, path: path.0 }).await;
-									}
-								}
-							} else {
-								let _ = wire_tx.send(LinkMsg::Wire { bytes: buf, path: selected_path.0 }).await;
-							}
-						}
+						transmit(&frame, selected_path, cfg.reorder_window, &mut reorder_buf, &wire_tx).await;
 						inflight.insert(frame.header.seq, TxEntry { frame, last_sent: Instant::now(), retries: 0, last_path: selected_path });
 						let _ = ack.send(());
 					}
@@ -186,6 +280,8 @@ async fn endpoint_task(
 						let _ = ack.send(());
 						break;
 					}
+					#[cfg(feature = "hpke")]
+					Cmd::RekeyMetrics { reply } => { let _ = reply.send(keys.rekey.as_ref().map(|m| m.metrics())); }
 				}
 			}
 			// Link receive path
@@ -195,14 +291,19 @@ async fn endpoint_task(
 						// Decode one frame per wire message
 						match FrameCodec::decode(&mut bytes) {
 							Ok(Some(frame)) => match frame.header.ty {
-							FrameType::Data => {
-								// Queue payload out-of-order and ACK
-								pending_rx.insert(frame.header.seq, Bytes::from(frame.payload));
-								while let Some(b) = pending_rx.remove(&expected_rx_seq) {
-									rx_queue.push_back(b);
+							FrameType::Data | FrameType::Crypto => {
+								// Queue out-of-order, deliver (or install key updates) in sequence, and ACK
+								let seq = frame.header.seq;
+								if seq >= expected_rx_seq { pending_rx.insert(seq, frame); }
+								while let Some(f) = pending_rx.remove(&expected_rx_seq) {
 									expected_rx_seq += 1;
+									match keys.receive(f) {
+										Ok(Some(b)) => rx_queue.push_back(b),
+										Ok(None) => {}
+										Err(_) => closed_remote = true,
+									}
 								}
-								let ack = Frame { header: FrameHeader { stream_id: cfg.stream_id, seq: frame.header.seq, ty: FrameType::Ack }, payload: vec![] };
+								let ack = Frame { header: FrameHeader { stream_id: cfg.stream_id, seq, ty: FrameType::Ack }, payload: vec![] };
 								let mut buf = BytesMut::new();
 								if FrameCodec::encode(&ack, &mut buf).is_ok() { let _ = wire_tx.send(LinkMsg::Wire { bytes: buf, path }).await; }
 							}
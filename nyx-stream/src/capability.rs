@@ -1 +1,150 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Capability negotiation carried in the first CRYPTO frame.
+//!
+//! Wire format is a CBOR array of `{id: u32, flags: u8, data: bytes}`; see
+//! `spec/Capability_Negotiation_Policy_EN.md`. Cipher suites are advertised as
+//! optional capabilities and selected with [`negotiate_cipher_suite`]; with the
+//! `fec` feature, FEC schemes are chosen per connection the same way.
+
+use nyx_crypto::suite::{self, CipherSuiteId};
+#[cfg(feature = "fec")]
+use nyx_fec::scheme::{self, FecScheme};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::errors::{Error, Result};
+
+pub const CAP_CORE: u32 = 0x0001;
+pub const CAP_PLUGIN_FRAMEWORK: u32 = 0x0002;
+
+/// Capability IDs understood by this stack.
+pub const LOCAL_CAP_IDS: &[u32] = &[
+	CAP_CORE,
+	CAP_PLUGIN_FRAMEWORK,
+	suite::CAP_SUITE_CHACHA20POLY1305_SHA256,
+	suite::CAP_SUITE_ASCON128A_BLAKE3,
+];
+
+pub const FLAG_REQUIRED: u8 = 0x01;
+/// Upper bound on the number of capabilities accepted from a peer.
+pub const MAX_CAPS: usize = 64;
+/// Upper bound on the encoded capability list accepted from a peer.
+pub const MAX_CAPS_LEN: usize = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Capability {
+	pub id: u32,
+	pub flags: u8,
+	#[serde(with = "serde_bytes")]
+	pub data: Vec<u8>,
+}
+
+impl Capability {
+	pub fn required(id: u32) -> Self { Self { id, flags: FLAG_REQUIRED, data: Vec::new() } }
+	pub fn optional(id: u32) -> Self { Self { id, flags: 0, data: Vec::new() } }
+	pub fn is_required(&self) -> bool { self.flags & FLAG_REQUIRED != 0 }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CapabilityError {
+	/// Close with `ERR_UNSUPPORTED_CAP` (0x07) carrying this id.
+	#[error("unsupported required capability: 0x{0:08x}")]
+	Unsupported(u32),
+	#[error("no common cipher suite")]
+	NoCommonSuite,
+	#[error("no common FEC scheme")]
+	NoCommonFecScheme,
+}
+
+pub fn encode_caps(caps: &[Capability]) -> Result<Vec<u8>> {
+	let mut out = Vec::with_capacity(caps.len() * 8);
+	ciborium::ser::into_writer(caps, &mut out).map_err(Error::CborSer)?;
+	Ok(out)
+}
+
+pub fn decode_caps(bytes: &[u8]) -> Result<Vec<Capability>> {
+	if bytes.len() > MAX_CAPS_LEN { return Err(Error::protocol("capability list too large")); }
+	let caps: Vec<Capability> = ciborium::de::from_reader(bytes).map_err(Error::Cbor)?;
+	if caps.len() > MAX_CAPS { return Err(Error::protocol("too many capabilities")); }
+	Ok(caps)
+}
+
+/// Fail on the first required peer capability we do not support.
+pub fn negotiate(local_supported: &[u32], peer_caps: &[Capability]) -> core::result::Result<(), CapabilityError> {
+	match peer_caps.iter().find(|c| c.is_required() && !local_supported.contains(&c.id)) {
+		Some(c) => Err(CapabilityError::Unsupported(c.id)),
+		None => Ok(()),
+	}
+}
+
+/// Capabilities to advertise: core (required) plus our cipher suites in preference order.
+pub fn local_capabilities(suite_prefs: &[CipherSuiteId]) -> Vec<Capability> {
+	let mut caps = vec![Capability::required(CAP_CORE)];
+	#[cfg(feature = "plugin")]
+	caps.push(Capability::optional(CAP_PLUGIN_FRAMEWORK));
+	caps.extend(suite::advertise(suite_prefs).into_iter().map(Capability::optional));
+	caps
+}
+
+/// Select the cipher suite from the peer's advertised capabilities using our preference order.
+pub fn negotiate_cipher_suite(local_prefs: &[CipherSuiteId], peer_caps: &[Capability]) -> core::result::Result<CipherSuiteId, CapabilityError> {
+	let ids: Vec<u32> = peer_caps.iter().map(|c| c.id).collect();
+	suite::negotiate(local_prefs, &ids).map_err(|_| CapabilityError::NoCommonSuite)
+}
+
+/// FEC scheme capabilities to advertise alongside [`local_capabilities`].
+#[cfg(feature = "fec")]
+pub fn fec_capabilities(prefs: &[FecScheme]) -> Vec<Capability> { scheme::advertise(prefs).into_iter().map(Capability::optional).collect() }
+
+/// Select the FEC scheme (Reed-Solomon or RaptorQ) for the connection.
+#[cfg(feature = "fec")]
+pub fn negotiate_fec_scheme(local_prefs: &[FecScheme], peer_caps: &[Capability]) -> core::result::Result<FecScheme, CapabilityError> {
+	let ids: Vec<u32> = peer_caps.iter().map(|c| c.id).collect();
+	scheme::negotiate(local_prefs, &ids).map_err(|_| CapabilityError::NoCommonFecScheme)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cbor_roundtrip() {
+		let caps = vec![Capability::required(CAP_CORE), Capability { id: 0x1234, flags: 0, data: vec![1, 2] }];
+		let bytes = encode_caps(&caps).unwrap();
+		assert_eq!(decode_caps(&bytes).unwrap(), caps);
+		assert!(decode_caps(&[0xff; 8]).is_err());
+	}
+
+	#[test]
+	fn unknown_required_fails_unknown_optional_ignored() {
+		let peer = vec![Capability::required(CAP_CORE), Capability::optional(0xdead)];
+		assert!(negotiate(LOCAL_CAP_IDS, &peer).is_ok());
+		let peer = vec![Capability::required(0xbeef)];
+		assert_eq!(negotiate(LOCAL_CAP_IDS, &peer), Err(CapabilityError::Unsupported(0xbeef)));
+	}
+
+	#[test]
+	fn cipher_suite_selected_from_caps() {
+		let iot = [CipherSuiteId::Ascon128aBlake3];
+		let peer = local_capabilities(&CipherSuiteId::ALL);
+		assert_eq!(negotiate_cipher_suite(&iot, &peer), Ok(CipherSuiteId::Ascon128aBlake3));
+		let legacy = vec![Capability::required(CAP_CORE)];
+		assert_eq!(negotiate_cipher_suite(&CipherSuiteId::ALL, &legacy), Ok(CipherSuiteId::ChaCha20Poly1305Sha256));
+		assert_eq!(negotiate_cipher_suite(&iot, &legacy), Err(CapabilityError::NoCommonSuite));
+	}
+
+	#[cfg(feature = "fec")]
+	#[test]
+	fn fec_scheme_selected_from_caps() {
+		let mut peer = local_capabilities(&CipherSuiteId::ALL);
+		peer.extend(fec_capabilities(&FecScheme::ALL));
+		assert_eq!(negotiate_fec_scheme(&[FecScheme::ReedSolomon], &peer), Ok(FecScheme::ReedSolomon));
+		#[cfg(feature = "raptorq")]
+		assert_eq!(negotiate_fec_scheme(&[FecScheme::RaptorQ, FecScheme::ReedSolomon], &peer), Ok(FecScheme::RaptorQ));
+		let legacy = vec![Capability::required(CAP_CORE)];
+		assert_eq!(negotiate_fec_scheme(&FecScheme::supported(), &legacy), Ok(FecScheme::ReedSolomon));
+		let rs_only = fec_capabilities(&[FecScheme::ReedSolomon]);
+		assert_eq!(negotiate_fec_scheme(&[FecScheme::RaptorQ], &rs_only), Err(CapabilityError::NoCommonFecScheme));
+	}
+}
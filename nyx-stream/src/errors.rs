@@ -16,6 +16,8 @@ pub enum Error {
 	Config(String),
 	#[error("protocol: {0}")]
 	Protocol(String),
+	#[error("crypto: {0}")]
+	Crypto(#[from] nyx_crypto::Error),
 	#[error("timeout")]
 	Timeout,
 	#[error("channel closed")]
@@ -3,7 +3,7 @@ use serde::{Serialize, Deserialize};
 use crate::errors::{Result, Error};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-pub enum FrameType { Data, Ack, Close }
+pub enum FrameType { Data, Ack, Close, Crypto }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct FrameHeader {
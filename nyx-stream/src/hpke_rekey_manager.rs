@@ -1 +1,258 @@
-﻿
\ No newline at end of file
+﻿#![forbid(unsafe_code)]
+
+//! Rekey manager for long-lived streams.
+//!
+//! Tracks bytes and time under the current transmit key and, once the policy
+//! triggers (spec §7.2: 1 GiB or 10 min by default), produces a CRYPTO frame
+//! carrying a fresh HPKE-sealed key. Incoming CRYPTO frames install the peer's
+//! next receive key; stale or replayed epochs are rejected. The previous
+//! receive key stays usable for `rx_grace` so that frames sealed before the
+//! update but delivered after it (reordering, other paths) still open.
+
+use std::time::{Duration, Instant};
+
+use nyx_crypto::aead::{AeadAlgorithm, AeadCipher, AeadKey, AeadNonce};
+use nyx_crypto::hpke::{PrivateKey, PublicKey};
+
+use crate::errors::{Error, Result};
+use crate::frame::Frame;
+use crate::hpke_rekey::RekeyMessage;
+
+#[derive(Debug, Clone)]
+pub struct RekeyPolicy {
+	/// Rekey after this many bytes have been protected with one key.
+	pub max_bytes: u64,
+	/// Rekey after a key has been in use for this long.
+	pub max_age: Duration,
+	/// Never rekey more often than this, even if a limit is hit.
+	pub min_interval: Duration,
+	/// How long the previous receive key is still accepted after an update.
+	pub rx_grace: Duration,
+}
+
+impl Default for RekeyPolicy {
+	fn default() -> Self {
+		Self { max_bytes: 1 << 30, max_age: Duration::from_secs(600), min_interval: Duration::from_secs(1), rx_grace: Duration::from_secs(5) }
+	}
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RekeyMetrics {
+	pub rekeys_sent: u64,
+	pub rekeys_received: u64,
+	/// Updates that failed to decrypt or decode.
+	pub rekey_failures: u64,
+	/// Updates rejected because their epoch was not newer than the current one.
+	pub stale_rejected: u64,
+	pub bytes_since_rekey: u64,
+	pub tx_epoch: u32,
+	pub rx_epoch: u32,
+}
+
+pub struct HpkeRekeyManager {
+	policy: RekeyPolicy,
+	stream_id: u32,
+	local_sk: PrivateKey,
+	local_pk: PublicKey,
+	peer_pk: PublicKey,
+	tx_key: AeadKey,
+	tx_epoch: u32,
+	rx_key: AeadKey,
+	rx_epoch: u32,
+	/// Previous receive key and the instant it stops being accepted.
+	prev_rx: Option<(AeadKey, Instant)>,
+	bytes_since_rekey: u64,
+	last_rekey: Instant,
+	metrics: RekeyMetrics,
+}
+
+impl HpkeRekeyManager {
+	/// `tx_key`/`rx_key` are the epoch-0 keys established by the handshake.
+	pub fn new(policy: RekeyPolicy, stream_id: u32, local: (PrivateKey, PublicKey), peer_pk: PublicKey, tx_key: AeadKey, rx_key: AeadKey) -> Self {
+		Self {
+			policy,
+			stream_id,
+			local_sk: local.0,
+			local_pk: local.1,
+			peer_pk,
+			tx_key,
+			tx_epoch: 0,
+			rx_key,
+			rx_epoch: 0,
+			prev_rx: None,
+			bytes_since_rekey: 0,
+			last_rekey: Instant::now(),
+			metrics: RekeyMetrics::default(),
+		}
+	}
+
+	pub fn tx_key(&self) -> &AeadKey { &self.tx_key }
+	pub fn rx_key(&self) -> &AeadKey { &self.rx_key }
+	pub fn tx_epoch(&self) -> u32 { self.tx_epoch }
+	pub fn rx_epoch(&self) -> u32 { self.rx_epoch }
+
+	/// Keys a received frame may be sealed with: the current one, then the
+	/// previous one while its grace window lasts.
+	pub fn rx_keys_at(&self, now: Instant) -> impl Iterator<Item = &AeadKey> {
+		let prev = self.prev_rx.as_ref().filter(|(_, until)| now < *until).map(|(k, _)| k);
+		std::iter::once(&self.rx_key).chain(prev)
+	}
+
+	/// Open a received frame, falling back to the previous key within the grace window.
+	pub fn open_at(&self, now: Instant, alg: AeadAlgorithm, nonce: AeadNonce, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+		let mut last = None;
+		for key in self.rx_keys_at(now) {
+			match AeadCipher::with_algorithm(alg, key).open(nonce, aad, ciphertext) {
+				Ok(pt) => return Ok(pt),
+				Err(e) => last = Some(e),
+			}
+		}
+		Err(last.expect("at least the current key is tried").into())
+	}
+	pub fn policy(&self) -> &RekeyPolicy { &self.policy }
+
+	/// Account bytes protected under the current transmit key.
+	pub fn on_bytes_sent(&mut self, n: usize) {
+		self.bytes_since_rekey = self.bytes_since_rekey.saturating_add(n as u64);
+	}
+
+	pub fn should_rekey(&self) -> bool { self.should_rekey_at(Instant::now()) }
+
+	pub fn should_rekey_at(&self, now: Instant) -> bool {
+		let age = now.saturating_duration_since(self.last_rekey);
+		if age < self.policy.min_interval { return false; }
+		self.bytes_since_rekey >= self.policy.max_bytes || age >= self.policy.max_age
+	}
+
+	/// Returns a CRYPTO frame if the policy says it is time to rekey.
+	pub fn poll_rekey(&mut self, seq: u64) -> Result<Option<Frame>> { self.poll_rekey_at(Instant::now(), seq) }
+
+	pub fn poll_rekey_at(&mut self, now: Instant, seq: u64) -> Result<Option<Frame>> {
+		if !self.should_rekey_at(now) { return Ok(None); }
+		self.rekey_at(now, seq).map(Some)
+	}
+
+	/// Generate a new transmit key and the CRYPTO frame announcing it.
+	/// The new key is used for every frame sent after this one.
+	pub fn rekey_at(&mut self, now: Instant, seq: u64) -> Result<Frame> {
+		let epoch = self.tx_epoch.checked_add(1).ok_or_else(|| Error::protocol("rekey: epoch exhausted"))?;
+		let key = AeadKey::generate();
+		let msg = RekeyMessage::seal((&self.local_sk, &self.local_pk), &self.peer_pk, self.stream_id, epoch, &key)?;
+		let frame = msg.to_frame(self.stream_id, seq)?;
+		self.tx_key = key;
+		self.tx_epoch = epoch;
+		self.bytes_since_rekey = 0;
+		self.last_rekey = now;
+		self.metrics.rekeys_sent += 1;
+		Ok(frame)
+	}
+
+	/// Install the peer's next receive key from a CRYPTO frame. Returns the new epoch.
+	pub fn handle_crypto_frame(&mut self, frame: &Frame) -> Result<u32> { self.handle_crypto_frame_at(Instant::now(), frame) }
+
+	pub fn handle_crypto_frame_at(&mut self, now: Instant, frame: &Frame) -> Result<u32> {
+		let msg = match RekeyMessage::from_frame(frame) {
+			Ok(m) => m,
+			Err(e) => { self.metrics.rekey_failures += 1; return Err(e); }
+		};
+		if msg.epoch <= self.rx_epoch {
+			self.metrics.stale_rejected += 1;
+			return Err(Error::protocol(format!("rekey: stale epoch {} (current {})", msg.epoch, self.rx_epoch)));
+		}
+		let key = match msg.open(&self.local_sk, &self.peer_pk, self.stream_id) {
+			Ok(k) => k,
+			Err(e) => { self.metrics.rekey_failures += 1; return Err(e); }
+		};
+		let prev = std::mem::replace(&mut self.rx_key, key);
+		self.prev_rx = Some((prev, now + self.policy.rx_grace));
+		self.rx_epoch = msg.epoch;
+		self.metrics.rekeys_received += 1;
+		Ok(msg.epoch)
+	}
+
+	pub fn metrics(&self) -> RekeyMetrics {
+		RekeyMetrics { bytes_since_rekey: self.bytes_since_rekey, tx_epoch: self.tx_epoch, rx_epoch: self.rx_epoch, ..self.metrics.clone() }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use nyx_crypto::hpke;
+
+	fn pair(policy: RekeyPolicy) -> (HpkeRekeyManager, HpkeRekeyManager) {
+		let (sk_a, pk_a) = hpke::gen_keypair();
+		let (sk_b, pk_b) = hpke::gen_keypair();
+		let ab = AeadKey([1u8; 32]);
+		let ba = AeadKey([2u8; 32]);
+		let a = HpkeRekeyManager::new(policy.clone(), 5, (sk_a, pk_a.clone()), pk_b.clone(), ab.clone(), ba.clone());
+		let b = HpkeRekeyManager::new(policy, 5, (sk_b, pk_b), pk_a, ba, ab);
+		(a, b)
+	}
+
+	#[test]
+	fn byte_limit_triggers_rekey_and_peer_installs_key() {
+		let policy = RekeyPolicy { max_bytes: 1000, max_age: Duration::from_secs(3600), min_interval: Duration::ZERO, ..Default::default() };
+		let (mut a, mut b) = pair(policy);
+		assert!(a.poll_rekey(1).unwrap().is_none());
+		a.on_bytes_sent(1500);
+		let frame = a.poll_rekey(2).unwrap().expect("rekey due");
+		assert_eq!(a.tx_epoch(), 1);
+		assert_eq!(b.handle_crypto_frame(&frame).unwrap(), 1);
+		assert_eq!(b.rx_key().as_bytes(), a.tx_key().as_bytes());
+		assert_eq!(a.metrics().rekeys_sent, 1);
+		assert_eq!(a.metrics().bytes_since_rekey, 0);
+		assert_eq!(b.metrics().rekeys_received, 1);
+	}
+
+	#[test]
+	fn age_limit_respects_min_interval() {
+		let policy = RekeyPolicy { max_bytes: u64::MAX, max_age: Duration::from_secs(60), min_interval: Duration::from_secs(10), ..Default::default() };
+		let (mut a, _b) = pair(policy);
+		let t0 = Instant::now();
+		assert!(!a.should_rekey_at(t0 + Duration::from_secs(30)));
+		assert!(a.should_rekey_at(t0 + Duration::from_secs(61)));
+		a.rekey_at(t0 + Duration::from_secs(61), 1).unwrap();
+		// Byte pressure right after a rekey is held back by min_interval
+		a.policy.max_bytes = 1;
+		a.on_bytes_sent(10);
+		assert!(!a.should_rekey_at(t0 + Duration::from_secs(65)));
+		assert!(a.should_rekey_at(t0 + Duration::from_secs(72)));
+	}
+
+	#[test]
+	fn reordered_frame_under_old_key_opens_within_grace() {
+		let (mut a, mut b) = pair(RekeyPolicy { rx_grace: Duration::from_secs(2), ..Default::default() });
+		let alg = AeadAlgorithm::ChaCha20Poly1305;
+		let old = AeadCipher::with_algorithm(alg, a.tx_key()).seal(AeadNonce::new(0, 7), b"hdr", b"late").unwrap();
+		let t0 = Instant::now();
+		let update = a.rekey_at(t0, 8).unwrap();
+		let new = AeadCipher::with_algorithm(alg, a.tx_key()).seal(AeadNonce::new(0, 9), b"hdr", b"fresh").unwrap();
+
+		// The update overtakes the frame sealed before it.
+		b.handle_crypto_frame_at(t0, &update).unwrap();
+		assert_eq!(b.open_at(t0, alg, AeadNonce::new(0, 9), b"hdr", &new).unwrap(), b"fresh");
+		assert_eq!(b.open_at(t0 + Duration::from_secs(1), alg, AeadNonce::new(0, 7), b"hdr", &old).unwrap(), b"late");
+		assert!(b.open_at(t0 + Duration::from_secs(3), alg, AeadNonce::new(0, 7), b"hdr", &old).is_err());
+	}
+
+	#[test]
+	fn replayed_update_is_rejected() {
+		let (mut a, mut b) = pair(RekeyPolicy::default());
+		let f1 = a.rekey_at(Instant::now(), 1).unwrap();
+		b.handle_crypto_frame(&f1).unwrap();
+		assert!(b.handle_crypto_frame(&f1).is_err());
+		assert_eq!(b.metrics().stale_rejected, 1);
+		assert_eq!(b.rx_epoch(), 1);
+	}
+
+	#[test]
+	fn update_from_foreign_sender_fails() {
+		let (mut a, _) = pair(RekeyPolicy::default());
+		let (_, mut c) = pair(RekeyPolicy::default());
+		let f = a.rekey_at(Instant::now(), 1).unwrap();
+		assert!(c.handle_crypto_frame(&f).is_err());
+		assert_eq!(c.metrics().rekey_failures, 1);
+		assert_eq!(c.rx_epoch(), 0);
+	}
+}
@@ -9,6 +9,11 @@ pub mod multipath;
 pub mod async_stream;
 pub mod frame_codec;
 pub mod congestion;
+pub mod capability;
+#[cfg(feature = "hpke")]
+pub mod hpke_rekey;
+#[cfg(feature = "hpke")]
+pub mod hpke_rekey_manager;
 
 pub use errors::{Error, Result};
 pub use frame::{Frame, FrameHeader, FrameType};
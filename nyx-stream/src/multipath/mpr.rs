@@ -0,0 +1,27 @@
+﻿#![forbid(unsafe_code)]
+
+use std::time::Duration;
+
+use crate::multipath::scheduler::{LossStats, PathId, WeightedScheduler, PathMetric};
+
+#[derive(Debug, Clone, Default)]
+pub struct MprConfig {
+	pub enabled: bool,
+}
+
+#[derive(Debug)]
+pub struct MprState {
+	pub sched: Option<WeightedScheduler>,
+}
+
+impl MprState {
+	pub fn disabled() -> Self { Self { sched: None } }
+	pub fn new(paths: &[(PathId, PathMetric)]) -> Self { Self { sched: Some(WeightedScheduler::new(paths)) } }
+	pub fn pick_path(&mut self) -> PathId { self.sched.as_mut().map(|s| s.next_path()).unwrap_or(PathId(0)) }
+	pub fn on_rtt_sample(&mut self, path: PathId, sample: Duration) { if let Some(s) = self.sched.as_mut() { s.observe_rtt(path, sample); } }
+	pub fn on_loss(&mut self, path: PathId) { if let Some(s) = self.sched.as_mut() { s.observe_loss(path); } }
+	pub fn on_delivery(&mut self, path: PathId) { if let Some(s) = self.sched.as_mut() { s.observe_delivery(path); } }
+	/// Loss seen across all paths; feeds the adaptive FEC redundancy controller.
+	pub fn loss(&self) -> LossStats { self.sched.as_ref().map(|s| s.aggregate_loss()).unwrap_or_default() }
+}
+
@@ -13,6 +13,41 @@ pub struct PathMetric {
 	pub weight: u32,
 }
 
+/// Measured loss on a path: EWMA loss rate and mean length of loss bursts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LossStats {
+	pub rate: f64,
+	pub mean_burst: f64,
+}
+
+impl Default for LossStats {
+	fn default() -> Self { Self { rate: 0.0, mean_burst: 1.0 } }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct LossTrack {
+	stats: LossStats,
+	/// Losses since the last delivery.
+	run: u32,
+}
+
+impl LossTrack {
+	const ALPHA: f64 = 1.0 / 64.0;
+
+	fn record(&mut self, lost: bool) {
+		self.stats.rate += Self::ALPHA * (f64::from(u8::from(lost)) - self.stats.rate);
+		if lost {
+			self.run += 1;
+		} else if self.run > 0 {
+			self.stats.mean_burst += 0.25 * (self.run as f64 - self.stats.mean_burst);
+			self.run = 0;
+		}
+	}
+
+	/// A burst still in progress counts as soon as it outgrows the mean.
+	fn current(&self) -> LossStats { LossStats { rate: self.stats.rate, mean_burst: self.stats.mean_burst.max(self.run as f64) } }
+}
+
 #[derive(Debug)]
 pub struct WeightedScheduler {
 	base_weights: HashMap<PathId, f64>,
@@ -20,6 +55,7 @@ pub struct WeightedScheduler {
 	rtt_ewma_ns: HashMap<PathId, f64>,
 	order: Vec<PathId>,
 	loss_penalty: HashMap<PathId, f64>,
+	loss: HashMap<PathId, LossTrack>,
 	ring: Vec<PathId>,
 	idx: usize,
 }
@@ -37,7 +73,8 @@ impl WeightedScheduler {
 			order.push(id);
 		}
 	let loss_penalty = order.iter().map(|&id| (id, 1.0)).collect();
-	let mut s = Self { base_weights, weights, rtt_ewma_ns, order, loss_penalty, ring: Vec::new(), idx: 0 };
+	let loss = order.iter().map(|&id| (id, LossTrack::default())).collect();
+	let mut s = Self { base_weights, weights, rtt_ewma_ns, order, loss_penalty, loss, ring: Vec::new(), idx: 0 };
 		s.rebuild_ring();
 		s
 	}
@@ -62,12 +99,36 @@ impl WeightedScheduler {
 
 	/// Observe a loss (timeout or retransmit trigger) for a path to penalize its share.
 	pub fn observe_loss(&mut self, path: PathId) {
+		self.loss.entry(path).or_default().record(true);
 		let p = self.loss_penalty.entry(path).or_insert(1.0);
 		*p = (*p * 0.9).max(0.5); // lower-bound
 		self.recompute_weights();
 		self.rebuild_ring();
 	}
 
+	/// Observe a packet delivered on a path; together with [`Self::observe_loss`]
+	/// this drives the loss rate and burst length reported by [`Self::loss`].
+	pub fn observe_delivery(&mut self, path: PathId) { self.loss.entry(path).or_default().record(false); }
+
+	/// Measured loss on one path.
+	pub fn loss(&self, path: PathId) -> Option<LossStats> { self.loss.get(&path).map(LossTrack::current) }
+
+	/// Loss across all paths, weighted by each path's share of the traffic.
+	/// This is what FEC blocks spread over the paths experience.
+	pub fn aggregate_loss(&self) -> LossStats {
+		let total: f64 = self.weights.values().sum();
+		if self.loss.is_empty() || total <= 0.0 { return LossStats::default(); }
+		let mut agg = LossStats { rate: 0.0, mean_burst: 0.0 };
+		for (id, track) in &self.loss {
+			let share = self.weights.get(id).copied().unwrap_or(0.0) / total;
+			let l = track.current();
+			agg.rate += share * l.rate;
+			agg.mean_burst += share * l.mean_burst;
+		}
+		agg.mean_burst = agg.mean_burst.max(1.0);
+		agg
+	}
+
 	fn recompute_weights(&mut self) {
 		if self.rtt_ewma_ns.is_empty() { return; }
 		let min_rtt = self.rtt_ewma_ns.values().copied().fold(f64::INFINITY, f64::min);
@@ -196,5 +257,27 @@ mod tests {
 		let c2b = picks.iter().filter(|&&p| p==2).count();
 		assert!(c2b > c1b);
 	}
-}
 
+	#[test]
+	fn loss_rate_and_burst_length_are_measured() {
+		let paths = vec![
+			(PathId(1), PathMetric{ rtt: Duration::from_millis(10), loss: 0.0, weight: 1 }),
+			(PathId(2), PathMetric{ rtt: Duration::from_millis(10), loss: 0.0, weight: 1 }),
+		];
+		let mut s = WeightedScheduler::new(&paths);
+		// Path 1 loses 3 packets in a row out of every 20, path 2 nothing.
+		for i in 0..2000 {
+			if i % 20 < 3 { s.observe_loss(PathId(1)); } else { s.observe_delivery(PathId(1)); }
+			s.observe_delivery(PathId(2));
+		}
+		let l1 = s.loss(PathId(1)).unwrap();
+		assert!((l1.rate - 0.15).abs() < 0.05, "{l1:?}");
+		assert!((l1.mean_burst - 3.0).abs() < 0.01, "{l1:?}");
+		assert_eq!(s.loss(PathId(2)).unwrap().rate, 0.0);
+		let agg = s.aggregate_loss();
+		assert!(agg.rate > 0.0 && agg.rate < l1.rate);
+		// A long burst in progress shows up before it ends.
+		for _ in 0..6 { s.observe_loss(PathId(1)); }
+		assert_eq!(s.loss(PathId(1)).unwrap().mean_burst, 6.0);
+	}
+}
@@ -0,0 +1,97 @@
+//! Simulated-loss harness: RaptorQ blocks over a Gilbert-Elliott channel with
+//! redundancy chosen by the adaptive controller from the loss the multipath
+//! scheduler measures on the path.
+
+use nyx_fec::adaptive::{AdaptiveConfig, RedundancyController};
+use nyx_fec::raptorq::{RaptorQDecoder, RaptorQEncoder};
+use nyx_fec::SHARD_SIZE;
+use nyx_stream::multipath::scheduler::{PathId, PathMetric, WeightedScheduler};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
+
+const K: usize = 10;
+const BLOCKS_PER_PHASE: usize = 300;
+const TARGET_RESIDUAL: f64 = 0.01;
+const PATH: PathId = PathId(1);
+
+/// Two-state bursty loss: every packet in the bad state is lost.
+struct GilbertElliott {
+	to_bad: f64,
+	to_good: f64,
+	bad: bool,
+}
+
+impl GilbertElliott {
+	fn new(loss: f64, mean_burst: f64) -> Self {
+		let to_good = 1.0 / mean_burst;
+		Self { to_bad: loss * to_good / (1.0 - loss), to_good, bad: false }
+	}
+
+	fn lost(&mut self, rng: &mut StdRng) -> bool {
+		self.bad = if self.bad { !rng.gen_bool(self.to_good) } else { rng.gen_bool(self.to_bad) };
+		self.bad
+	}
+}
+
+struct Phase {
+	redundancy_at_end: f64,
+	residual: f64,
+	raw: f64,
+}
+
+fn run_phase(channel: &mut GilbertElliott, ctl: &mut RedundancyController, sched: &mut WeightedScheduler, rng: &mut StdRng, enc: &mut RaptorQEncoder, data: &[u8]) -> Phase {
+	let (mut sent, mut lost_raw, mut lost_after_fec) = (0usize, 0usize, 0usize);
+	for _ in 0..BLOCKS_PER_PHASE {
+		let repair = (ctl.redundancy() * K as f64).ceil() as u32;
+		// Every block carries the same object; fresh repair symbols each time.
+		let mut symbols = enc.source_symbols();
+		symbols.extend(enc.repair_symbols(repair));
+
+		let mut received = Vec::new();
+		let mut source_lost = 0;
+		for (i, s) in symbols.into_iter().enumerate() {
+			let lost = channel.lost(rng);
+			sent += 1;
+			if lost {
+				sched.observe_loss(PATH);
+				lost_raw += 1;
+				source_lost += usize::from(i < K);
+			} else {
+				sched.observe_delivery(PATH);
+				received.push(s);
+			}
+		}
+		if source_lost > 0 {
+			let mut dec = RaptorQDecoder::new(&enc.oti()).unwrap();
+			let decoded = received.iter().any(|s| dec.push(s).unwrap().is_some_and(|out| out == data));
+			if !decoded {
+				lost_after_fec += source_lost;
+			}
+		}
+		let loss = sched.aggregate_loss();
+		ctl.update(loss.rate, loss.mean_burst);
+	}
+	Phase { redundancy_at_end: ctl.redundancy(), residual: lost_after_fec as f64 / (BLOCKS_PER_PHASE * K) as f64, raw: lost_raw as f64 / sent as f64 }
+}
+
+#[test]
+fn adaptive_raptorq_redundancy_adjusts_both_directions() {
+	let mut rng = StdRng::seed_from_u64(0x4e79);
+	let data: Vec<u8> = (0..K * SHARD_SIZE).map(|i| (i % 251) as u8).collect();
+	let mut ctl = RedundancyController::new(AdaptiveConfig { target_residual: 1e-3, ..Default::default() }).unwrap();
+	let mut sched = WeightedScheduler::new(&[(PATH, PathMetric { rtt: Duration::from_millis(20), loss: 0.0, weight: 1 })]);
+	let mut enc = RaptorQEncoder::new(&data).unwrap();
+
+	let calm = run_phase(&mut GilbertElliott::new(0.005, 1.0), &mut ctl, &mut sched, &mut rng, &mut enc, &data);
+	let storm = run_phase(&mut GilbertElliott::new(0.10, 2.0), &mut ctl, &mut sched, &mut rng, &mut enc, &data);
+	let after = run_phase(&mut GilbertElliott::new(0.005, 1.0), &mut ctl, &mut sched, &mut rng, &mut enc, &data);
+
+	assert!(storm.raw > 0.05, "channel should be lossy: {}", storm.raw);
+	assert!(storm.redundancy_at_end > calm.redundancy_at_end + 0.3, "{} -> {}", calm.redundancy_at_end, storm.redundancy_at_end);
+	assert!(after.redundancy_at_end < storm.redundancy_at_end, "{} -> {}", storm.redundancy_at_end, after.redundancy_at_end);
+	assert!(after.redundancy_at_end <= 0.3);
+	for (name, p) in [("calm", &calm), ("storm", &storm), ("after", &after)] {
+		assert!(p.residual < TARGET_RESIDUAL, "{name}: residual {} at raw loss {}", p.residual, p.raw);
+	}
+}
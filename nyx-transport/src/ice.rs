@@ -1 +1,627 @@
-﻿
\ No newline at end of file
+﻿//! ICE (RFC 8445) NAT traversal over the single Nyx UDP socket.
+//!
+//! This is the hole-punching procedure of spec §5.4: each side gathers
+//! host, server-reflexive and relay candidates, exchanges them through the
+//! rendezvous server, and then both run paced STUN connectivity checks over
+//! every candidate pair in parallel. The controlling side nominates the
+//! first pair that answers; once nominated, all other checks are cancelled.
+//!
+//! If both sides start in the same role, the ICE-CONTROLLING/CONTROLLED
+//! tiebreakers decide who switches (RFC 8445 §7.3.1.1): the side with the
+//! larger value controls, the other answers or receives 487 Role Conflict and
+//! takes the opposite role.
+//!
+//! Relay candidates are addresses on a forwarding relay that was set up
+//! out of band and delivers to this socket. Locally they share the host base,
+//! so only the remote side checks them.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use rand::distributions::{Alphanumeric, DistString};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{self, Instant, MissedTickBehavior};
+use tracing::{debug, trace};
+
+use crate::stun_server::{Attribute, Class, StunMessage, TransactionId, BINDING};
+
+const LOCAL_PREFERENCE: u32 = 65_535;
+const COMPONENT_ID: u32 = 1;
+const INCOMING_QUEUE: usize = 64;
+
+/// Datagram socket the agent runs its checks on.
+#[async_trait]
+pub trait IceSocket: Send + Sync + 'static {
+	async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize>;
+	async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+	fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+#[async_trait]
+impl IceSocket for UdpSocket {
+	async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> { UdpSocket::send_to(self, buf, target).await }
+
+	async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> { UdpSocket::recv_from(self, buf).await }
+
+	fn local_addr(&self) -> io::Result<SocketAddr> { UdpSocket::local_addr(self) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandidateKind {
+	Host,
+	PeerReflexive,
+	ServerReflexive,
+	Relay,
+}
+
+impl CandidateKind {
+	/// Type preference from RFC 8445 §5.1.2.2.
+	pub fn preference(self) -> u32 {
+		match self {
+			Self::Host => 126,
+			Self::PeerReflexive => 110,
+			Self::ServerReflexive => 100,
+			Self::Relay => 0,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Candidate {
+	pub kind: CandidateKind,
+	pub addr: SocketAddr,
+	/// Address the agent actually sends from for this candidate.
+	pub base: SocketAddr,
+	pub priority: u32,
+}
+
+impl Candidate {
+	pub fn new(kind: CandidateKind, addr: SocketAddr, base: SocketAddr) -> Self { Self { kind, addr, base, priority: priority(kind) } }
+}
+
+fn priority(kind: CandidateKind) -> u32 { (kind.preference() << 24) | (LOCAL_PREFERENCE << 8) | (256 - COMPONENT_ID) }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IceRole {
+	Controlling,
+	Controlled,
+}
+
+impl IceRole {
+	fn other(self) -> Self {
+		match self {
+			Self::Controlling => Self::Controlled,
+			Self::Controlled => Self::Controlling,
+		}
+	}
+}
+
+/// Short-term credentials exchanged alongside the candidates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IceCredentials {
+	pub ufrag: String,
+	pub pwd: String,
+}
+
+impl IceCredentials {
+	pub fn random() -> Self {
+		let mut rng = rand::thread_rng();
+		Self { ufrag: Alphanumeric.sample_string(&mut rng, 8), pwd: Alphanumeric.sample_string(&mut rng, 24) }
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CandidatePair {
+	pub local: Candidate,
+	pub remote: Candidate,
+	pub priority: u64,
+}
+
+impl CandidatePair {
+	/// Pair priority from RFC 8445 §6.1.2.3.
+	pub fn new(local: Candidate, remote: Candidate, role: IceRole) -> Self {
+		let (g, d) = match role {
+			IceRole::Controlling => (u64::from(local.priority), u64::from(remote.priority)),
+			IceRole::Controlled => (u64::from(remote.priority), u64::from(local.priority)),
+		};
+		Self { local, remote, priority: (g.min(d) << 32) + 2 * g.max(d) + u64::from(g > d) }
+	}
+}
+
+/// Pair every local candidate with every remote one of the same family.
+///
+/// Local reflexive and relay candidates are replaced by their base, since
+/// that is where the checks are sent from, and the resulting duplicates are
+/// pruned so that only the highest-priority copy remains.
+pub fn form_pairs(local: &[Candidate], remote: &[Candidate], role: IceRole) -> Vec<CandidatePair> {
+	let mut pairs: Vec<CandidatePair> = local
+		.iter()
+		.map(|l| if l.kind == CandidateKind::Host { *l } else { Candidate::new(CandidateKind::Host, l.base, l.base) })
+		.flat_map(|l| remote.iter().filter(move |r| r.addr.is_ipv4() == l.addr.is_ipv4()).map(move |r| CandidatePair::new(l, *r, role)))
+		.collect();
+	pairs.sort_by_key(|p| std::cmp::Reverse(p.priority));
+	let mut seen = Vec::with_capacity(pairs.len());
+	pairs.retain(|p| {
+		let key = (p.local.addr, p.remote.addr);
+		!seen.contains(&key) && {
+			seen.push(key);
+			true
+		}
+	});
+	pairs
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairState {
+	Waiting,
+	InProgress,
+	Succeeded,
+	Failed,
+	Cancelled,
+}
+
+#[derive(Debug, Clone)]
+pub struct IceConfig {
+	pub stun_servers: Vec<SocketAddr>,
+	pub relay_candidates: Vec<SocketAddr>,
+	/// Interval between starting two checks (Ta).
+	pub pacing: Duration,
+	/// Initial STUN retransmission timeout, doubled on every retry.
+	pub rto: Duration,
+	/// How long a single check or STUN query may take.
+	pub check_timeout: Duration,
+	/// How long `connect` waits for a nominated pair.
+	pub connect_timeout: Duration,
+	pub max_pairs: usize,
+}
+
+impl Default for IceConfig {
+	fn default() -> Self {
+		Self {
+			stun_servers: Vec::new(),
+			relay_candidates: Vec::new(),
+			pacing: Duration::from_millis(20),
+			rto: Duration::from_millis(100),
+			check_timeout: Duration::from_secs(3),
+			connect_timeout: Duration::from_secs(10),
+			max_pairs: 100,
+		}
+	}
+}
+
+/// Result of a successful `connect`.
+#[derive(Debug, Clone)]
+pub struct IceOutcome {
+	pub pair: CandidatePair,
+	pub rtt: Duration,
+	/// Final state of every pair in the check list.
+	pub checks: Vec<(CandidatePair, PairState)>,
+}
+
+struct Response {
+	msg: StunMessage,
+	raw: Vec<u8>,
+	src: SocketAddr,
+}
+
+#[derive(Debug)]
+struct IncomingCheck {
+	src: SocketAddr,
+	priority: u32,
+	use_candidate: bool,
+}
+
+type Pending = Arc<Mutex<HashMap<TransactionId, oneshot::Sender<Response>>>>;
+
+/// Shared between the check tasks and the reader, which both resolve role conflicts.
+type SharedRole = Arc<Mutex<IceRole>>;
+
+/// Removes a transaction from the pending table when the query ends or is cancelled.
+struct PendingGuard<'a>(&'a Pending, TransactionId);
+
+impl Drop for PendingGuard<'_> {
+	fn drop(&mut self) { self.0.lock().expect("pending lock").remove(&self.1); }
+}
+
+/// What a check task needs, cheap to clone into each one.
+struct Checker<S> {
+	socket: Arc<S>,
+	pending: Pending,
+	cfg: IceConfig,
+	role: SharedRole,
+	tiebreaker: u64,
+}
+
+impl<S> Clone for Checker<S> {
+	fn clone(&self) -> Self { Self { socket: self.socket.clone(), pending: self.pending.clone(), cfg: self.cfg.clone(), role: self.role.clone(), tiebreaker: self.tiebreaker } }
+}
+
+impl<S> Checker<S> {
+	fn role(&self) -> IceRole { *self.role.lock().expect("role lock") }
+
+	/// Leave `from` after a 487 answer, unless another check already did.
+	fn switch_role(&self, from: IceRole) {
+		let mut role = self.role.lock().expect("role lock");
+		if *role == from {
+			*role = from.other();
+			debug!(role = ?*role, "role conflict, switching");
+		}
+	}
+
+	/// Resolve an incoming check that claims our own role (RFC 8445 §7.3.1.1).
+	/// Returns true if the check must be refused with 487.
+	fn conflicts(&self, msg: &StunMessage) -> bool {
+		let claimed = msg.attrs.iter().find_map(|a| match a {
+			Attribute::IceControlling(t) => Some((IceRole::Controlling, *t)),
+			Attribute::IceControlled(t) => Some((IceRole::Controlled, *t)),
+			_ => None,
+		});
+		let mut role = self.role.lock().expect("role lock");
+		let Some((theirs, tiebreaker)) = claimed.filter(|(theirs, _)| *theirs == *role) else { return false };
+		// The larger tiebreaker ends up controlling.
+		if (theirs == IceRole::Controlling) == (self.tiebreaker >= tiebreaker) {
+			return true;
+		}
+		*role = role.other();
+		debug!(role = ?*role, "role conflict, switching");
+		false
+	}
+}
+
+impl<S: IceSocket> Checker<S> {
+	/// Send `msg` to `dst`, retransmitting until a response with the same transaction id arrives.
+	async fn transact(&self, dst: SocketAddr, msg: &StunMessage, key: Option<&[u8]>) -> io::Result<Response> {
+		let (tx, mut rx) = oneshot::channel();
+		self.pending.lock().expect("pending lock").insert(msg.txid, tx);
+		let _guard = PendingGuard(&self.pending, msg.txid);
+		let raw = msg.encode(key);
+		let deadline = Instant::now() + self.cfg.check_timeout;
+		let mut rto = self.cfg.rto;
+		loop {
+			if let Err(e) = self.socket.send_to(&raw, dst).await {
+				trace!(%dst, error = %e, "stun send failed");
+			}
+			let wait = rto.min(deadline.saturating_duration_since(Instant::now()));
+			match time::timeout(wait, &mut rx).await {
+				Ok(Ok(resp)) => return Ok(resp),
+				Ok(Err(_)) => return Err(io::Error::other("ice agent closed")),
+				Err(_) if Instant::now() >= deadline => return Err(io::Error::new(io::ErrorKind::TimedOut, format!("no STUN response from {dst}"))),
+				Err(_) => rto *= 2,
+			}
+		}
+	}
+
+	async fn binding(&self, server: SocketAddr) -> io::Result<SocketAddr> {
+		let resp = self.transact(server, &StunMessage::binding_request(), None).await?;
+		match resp.msg.class {
+			Class::Success => resp.msg.xor_mapped_address().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "binding response without mapped address")),
+			_ => Err(io::Error::other(format!("binding request rejected by {server}"))),
+		}
+	}
+
+	/// One connectivity check; returns the round-trip time on success and
+	/// `Interrupted` if the peer reported a role conflict.
+	async fn check(&self, pair: CandidatePair, local: &IceCredentials, remote: &IceCredentials, nominate: bool) -> io::Result<Duration> {
+		let role = self.role();
+		let mut req = StunMessage::binding_request()
+			.with(Attribute::Username(format!("{}:{}", remote.ufrag, local.ufrag)))
+			.with(Attribute::Priority(priority(CandidateKind::PeerReflexive)))
+			.with(match role {
+				IceRole::Controlling => Attribute::IceControlling(self.tiebreaker),
+				IceRole::Controlled => Attribute::IceControlled(self.tiebreaker),
+			});
+		if nominate {
+			req = req.with(Attribute::UseCandidate);
+		}
+		let start = Instant::now();
+		let resp = self.transact(pair.remote.addr, &req, Some(remote.pwd.as_bytes())).await?;
+		if resp.msg.class == Class::Error && resp.msg.error_code() == Some(487) && resp.msg.verify(&resp.raw, remote.pwd.as_bytes()) {
+			self.switch_role(role);
+			return Err(io::Error::new(io::ErrorKind::Interrupted, "role conflict"));
+		}
+		if resp.msg.class != Class::Success || !resp.msg.verify(&resp.raw, remote.pwd.as_bytes()) {
+			return Err(io::Error::new(io::ErrorKind::PermissionDenied, "check rejected"));
+		}
+		// Responses must come back from the address the check went to (RFC 8445 §7.2.5.2.1).
+		if resp.src != pair.remote.addr {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "asymmetric check response"));
+		}
+		Ok(start.elapsed())
+	}
+}
+
+struct Entry {
+	pair: CandidatePair,
+	state: PairState,
+	nominate: bool,
+	rtt: Option<Duration>,
+}
+
+/// An ICE agent bound to one socket.
+///
+/// The agent answers authenticated checks from the peer as soon as it is
+/// created, so it must be constructed before candidates are exchanged.
+pub struct IceAgent<S: IceSocket> {
+	checker: Checker<S>,
+	host: SocketAddr,
+	local: IceCredentials,
+	incoming: tokio::sync::Mutex<mpsc::Receiver<IncomingCheck>>,
+	reader: JoinHandle<()>,
+}
+
+impl<S: IceSocket> IceAgent<S> {
+	/// Wrap `socket` and start answering checks. Must be called within a Tokio runtime.
+	pub fn new(socket: S, role: IceRole, cfg: IceConfig) -> io::Result<Self> {
+		let host = host_addr(socket.local_addr()?, &cfg.stun_servers);
+		let socket = Arc::new(socket);
+		let local = IceCredentials::random();
+		let pending: Pending = Arc::default();
+		let (tx, rx) = mpsc::channel(INCOMING_QUEUE);
+		let checker = Checker { socket, pending, cfg, role: Arc::new(Mutex::new(role)), tiebreaker: rand::random() };
+		let reader = tokio::spawn(read_loop(checker.clone(), local.clone(), tx));
+		Ok(Self { checker, host, local, incoming: tokio::sync::Mutex::new(rx), reader })
+	}
+
+	pub fn credentials(&self) -> &IceCredentials { &self.local }
+
+	/// Current role; differs from the initial one after a lost role conflict.
+	pub fn role(&self) -> IceRole { self.checker.role() }
+
+	/// Gather host, server-reflexive (queried in parallel) and configured relay candidates.
+	pub async fn gather(&self) -> Vec<Candidate> {
+		let mut out = vec![Candidate::new(CandidateKind::Host, self.host, self.host)];
+		let servers = self.checker.cfg.stun_servers.iter().filter(|s| s.is_ipv4() == self.host.is_ipv4());
+		for (server, res) in servers.clone().zip(join_all(servers.map(|&s| self.checker.binding(s))).await) {
+			match res {
+				Ok(mapped) if !out.iter().any(|c| c.addr == mapped) => out.push(Candidate::new(CandidateKind::ServerReflexive, mapped, self.host)),
+				Ok(_) => {}
+				Err(e) => debug!(%server, error = %e, "server-reflexive gathering failed"),
+			}
+		}
+		out.extend(self.checker.cfg.relay_candidates.iter().map(|&r| Candidate::new(CandidateKind::Relay, r, self.host)));
+		out
+	}
+
+	/// Run connectivity checks against the peer's candidates until a pair is nominated.
+	pub async fn connect(&self, remote: &IceCredentials, local: &[Candidate], remote_candidates: &[Candidate]) -> io::Result<IceOutcome> {
+		let mut role = self.checker.role();
+		let cfg = &self.checker.cfg;
+		let mut entries: Vec<Entry> = form_pairs(local, remote_candidates, role).into_iter().take(cfg.max_pairs).map(|pair| Entry { pair, state: PairState::Waiting, nominate: false, rtt: None }).collect();
+		let mut triggered = VecDeque::new();
+		let mut checks = JoinSet::new();
+		let mut nominating = false;
+		let mut pace = time::interval(cfg.pacing);
+		pace.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		let deadline = time::sleep(cfg.connect_timeout);
+		tokio::pin!(deadline);
+		let mut incoming = self.incoming.lock().await;
+
+		let spawn = |checks: &mut JoinSet<_>, i: usize, pair: CandidatePair, nominate: bool| {
+			let checker = self.checker.clone();
+			let (local, remote) = (self.local.clone(), remote.clone());
+			checks.spawn(async move { (i, nominate, checker.check(pair, &local, &remote, nominate).await) });
+		};
+
+		let nominated = loop {
+			// Pair priorities depend on the role, which a conflict may have changed.
+			if self.checker.role() != role {
+				role = self.checker.role();
+				for e in &mut entries {
+					e.pair = CandidatePair::new(e.pair.local, e.pair.remote, role);
+				}
+			}
+			tokio::select! {
+				_ = &mut deadline => break None,
+				_ = pace.tick() => {
+					// Covers both a failed nomination and becoming controlling after checks already succeeded.
+					if role == IceRole::Controlling && !nominating {
+						if let Some(j) = entries.iter().position(|e| e.state == PairState::Succeeded) {
+							nominating = true;
+							spawn(&mut checks, j, entries[j].pair, true);
+						}
+					}
+					let next = std::iter::from_fn(|| triggered.pop_front()).find(|&i: &usize| entries[i].state == PairState::Waiting).or_else(|| entries.iter().position(|e| e.state == PairState::Waiting));
+					if let Some(i) = next {
+						entries[i].state = PairState::InProgress;
+						spawn(&mut checks, i, entries[i].pair, false);
+					}
+				}
+				Some(joined) = checks.join_next() => {
+					let Ok((i, nominate, res)) = joined else { continue };
+					match res {
+						Ok(rtt) => {
+							trace!(pair = ?entries[i].pair, ?rtt, nominate, "check succeeded");
+							entries[i].state = PairState::Succeeded;
+							entries[i].rtt = Some(rtt);
+							if nominate || (role == IceRole::Controlled && entries[i].nominate) {
+								break Some(i);
+							}
+							if role == IceRole::Controlling && !nominating {
+								nominating = true;
+								spawn(&mut checks, i, entries[i].pair, true);
+							}
+						}
+						Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+							trace!(pair = ?entries[i].pair, "check hit a role conflict, retrying");
+							nominating &= !nominate;
+							entries[i].state = PairState::Waiting;
+							triggered.push_back(i);
+						}
+						Err(e) if nominate => {
+							// Fail the pair so the next tick nominates a different one.
+							debug!(pair = ?entries[i].pair, error = %e, "nomination failed");
+							entries[i].state = PairState::Failed;
+							nominating = false;
+						}
+						Err(e) => {
+							trace!(pair = ?entries[i].pair, error = %e, "check failed");
+							entries[i].state = PairState::Failed;
+						}
+					}
+				}
+				Some(check) = incoming.recv() => {
+					// The reader may have resolved a conflict just before queueing this check.
+					let role = self.checker.role();
+					let i = match entries.iter().position(|e| e.pair.remote.addr == check.src) {
+						Some(i) => i,
+						None => {
+							// Unknown source: learn it as a peer-reflexive candidate (RFC 8445 §7.3.1.3).
+							let remote = Candidate { kind: CandidateKind::PeerReflexive, addr: check.src, base: check.src, priority: check.priority };
+							let host = Candidate::new(CandidateKind::Host, self.host, self.host);
+							entries.push(Entry { pair: CandidatePair::new(host, remote, role), state: PairState::Waiting, nominate: false, rtt: None });
+							entries.len() - 1
+						}
+					};
+					if check.use_candidate && role == IceRole::Controlled {
+						entries[i].nominate = true;
+						if entries[i].state == PairState::Succeeded {
+							break Some(i);
+						}
+					}
+					if matches!(entries[i].state, PairState::Waiting | PairState::Failed) {
+						entries[i].state = PairState::Waiting;
+						triggered.push_back(i);
+					}
+				}
+			}
+		};
+
+		// Close every other path: in-flight checks are dropped along with their transactions.
+		checks.shutdown().await;
+		for e in &mut entries {
+			if matches!(e.state, PairState::Waiting | PairState::InProgress) {
+				e.state = PairState::Cancelled;
+			}
+		}
+		let checks = entries.iter().map(|e| (e.pair, e.state)).collect();
+		match nominated {
+			Some(i) => Ok(IceOutcome { pair: entries[i].pair, rtt: entries[i].rtt.unwrap_or_default(), checks }),
+			None => Err(io::Error::new(io::ErrorKind::TimedOut, "no candidate pair succeeded")),
+		}
+	}
+
+	/// Stop answering checks and hand the socket back for data.
+	pub fn into_socket(self) -> Arc<S> {
+		self.reader.abort();
+		self.checker.socket.clone()
+	}
+}
+
+impl<S: IceSocket> Drop for IceAgent<S> {
+	fn drop(&mut self) { self.reader.abort(); }
+}
+
+/// Address to advertise as the host candidate. A wildcard bind is resolved
+/// to the interface that routes towards the first STUN server.
+fn host_addr(bound: SocketAddr, stun_servers: &[SocketAddr]) -> SocketAddr {
+	if !bound.ip().is_unspecified() {
+		return bound;
+	}
+	let route = stun_servers.iter().find(|s| s.is_ipv4() == bound.is_ipv4()).and_then(|&server| {
+		let probe = std::net::UdpSocket::bind(SocketAddr::new(bound.ip(), 0)).ok()?;
+		probe.connect(server).ok()?;
+		probe.local_addr().ok()
+	});
+	let ip = route.map(|a| a.ip()).unwrap_or(if bound.is_ipv4() { IpAddr::V4(Ipv4Addr::LOCALHOST) } else { IpAddr::V6(Ipv6Addr::LOCALHOST) });
+	SocketAddr::new(ip, bound.port())
+}
+
+/// Answer authenticated checks and route responses to their transactions.
+async fn read_loop<S: IceSocket>(checker: Checker<S>, local: IceCredentials, incoming: mpsc::Sender<IncomingCheck>) {
+	let Checker { socket, pending, .. } = &checker;
+	let prefix = format!("{}:", local.ufrag);
+	let mut buf = vec![0u8; 1500];
+	loop {
+		let (n, src) = match socket.recv_from(&mut buf).await {
+			Ok(r) => r,
+			Err(e) if matches!(e.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused) => continue,
+			Err(e) => {
+				debug!(error = %e, "ice socket closed");
+				return;
+			}
+		};
+		let raw = &buf[..n];
+		let Ok(msg) = StunMessage::decode(raw) else { continue };
+		match msg.class {
+			Class::Request if msg.method == BINDING => {
+				let authentic = msg.username().is_some_and(|u| u.starts_with(&prefix)) && msg.verify(raw, local.pwd.as_bytes());
+				if !authentic {
+					let resp = StunMessage::new(Class::Error, BINDING, msg.txid).with(Attribute::ErrorCode(401, "Unauthorized".into()));
+					let _ = socket.send_to(&resp.encode(None), src).await;
+					continue;
+				}
+				if checker.conflicts(&msg) {
+					let resp = StunMessage::new(Class::Error, BINDING, msg.txid).with(Attribute::ErrorCode(487, "Role Conflict".into()));
+					let _ = socket.send_to(&resp.encode(Some(local.pwd.as_bytes())), src).await;
+					continue;
+				}
+				let resp = StunMessage::new(Class::Success, BINDING, msg.txid).with(Attribute::XorMappedAddress(src));
+				let _ = socket.send_to(&resp.encode(Some(local.pwd.as_bytes())), src).await;
+				let check = IncomingCheck { src, priority: msg.priority().unwrap_or(0), use_candidate: msg.use_candidate() };
+				if incoming.try_send(check).is_err() {
+					trace!(%src, "incoming check queue full");
+				}
+			}
+			Class::Success | Class::Error => {
+				// Responses to transactions we never sent (or already gave up on) are ignored.
+				let waiter = pending.lock().expect("pending lock").remove(&msg.txid);
+				if let Some(waiter) = waiter {
+					let _ = waiter.send(Response { msg, raw: raw.to_vec(), src });
+				}
+			}
+			_ => {}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn addr(s: &str) -> SocketAddr { s.parse().unwrap() }
+
+	#[test]
+	fn candidate_priorities_follow_type_preference() {
+		let host = Candidate::new(CandidateKind::Host, addr("10.0.0.1:1"), addr("10.0.0.1:1"));
+		assert_eq!(host.priority, 126 << 24 | 65_535 << 8 | 255);
+		let srflx = Candidate::new(CandidateKind::ServerReflexive, addr("1.2.3.4:5"), host.addr);
+		let relay = Candidate::new(CandidateKind::Relay, addr("5.6.7.8:9"), addr("5.6.7.8:9"));
+		assert!(host.priority > priority(CandidateKind::PeerReflexive));
+		assert!(priority(CandidateKind::PeerReflexive) > srflx.priority);
+		assert!(srflx.priority > relay.priority);
+	}
+
+	#[test]
+	fn pair_priority_is_symmetric_between_roles() {
+		let a = Candidate::new(CandidateKind::Host, addr("10.0.0.1:1"), addr("10.0.0.1:1"));
+		let b = Candidate::new(CandidateKind::ServerReflexive, addr("1.2.3.4:5"), addr("10.0.0.2:1"));
+		let ours = CandidatePair::new(a, b, IceRole::Controlling);
+		let theirs = CandidatePair::new(b, a, IceRole::Controlled);
+		assert_eq!(ours.priority, theirs.priority);
+		assert_eq!(ours.priority, (u64::from(b.priority) << 32) + 2 * u64::from(a.priority) + 1);
+	}
+
+	#[test]
+	fn pairs_use_base_prune_duplicates_and_sort() {
+		let base = addr("10.0.0.1:1");
+		let local = [
+			Candidate::new(CandidateKind::Host, base, base),
+			Candidate::new(CandidateKind::ServerReflexive, addr("1.2.3.4:5"), base),
+			Candidate::new(CandidateKind::Relay, addr("5.6.7.8:9"), base),
+			Candidate::new(CandidateKind::Host, addr("[fd00::1]:1"), addr("[fd00::1]:1")),
+		];
+		let remote = [Candidate::new(CandidateKind::Relay, addr("9.9.9.9:9"), addr("9.9.9.9:9")), Candidate::new(CandidateKind::Host, addr("10.0.0.2:1"), addr("10.0.0.2:1"))];
+		let pairs = form_pairs(&local, &remote, IceRole::Controlling);
+		assert_eq!(pairs.len(), 2);
+		assert!(pairs.iter().all(|p| p.local.addr == base));
+		assert_eq!(pairs[0].remote.kind, CandidateKind::Host);
+		assert!(pairs[0].priority > pairs[1].priority);
+	}
+}
@@ -1 +1,18 @@
+#![forbid(unsafe_code)]
 
+//! Nyx transport layer: the UDP socket every path runs over,
+//! with a TCP fallback for networks that block UDP and ICE for NAT traversal.
+
+pub mod ice;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod stun_server;
+pub mod tcp_fallback;
+pub mod udp;
+
+pub use ice::{Candidate, CandidateKind, IceAgent, IceConfig, IceCredentials, IceOutcome, IceRole, IceSocket};
+#[cfg(feature = "quic")]
+pub use quic::{QuicConfig, QuicConnection, QuicEndpoint, QuicIdentity};
+pub use stun_server::StunServer;
+pub use tcp_fallback::{FallbackConfig, FallbackTransport, TransportKind};
+pub use udp::{Ecn, EcnCounts, Transmit, UdpConfig, UdpTransport, PORT_RANGE};
@@ -0,0 +1,349 @@
+﻿//! Nyx cells over QUIC DATAGRAM frames (RFC 9221).
+//!
+//! For networks that only let QUIC on 443 through. Every node presents a
+//! self-signed certificate for its keystore Ed25519 identity key, so the
+//! certificate is bound to the NodeID (`BLAKE3(public_key)`, spec §6).
+//! Dialers pin the NodeID they expect, listeners require a client
+//! certificate and expose the dialer's NodeID on the connection. No CA or
+//! hostname is involved.
+//!
+//! TLS runs on the pure-Rust provider in [`provider`]; see [`session`] for
+//! why quinn's own rustls glue is not used.
+//!
+//! Inbound handshakes run in their own tasks, at most [`MAX_HANDSHAKES`] at
+//! a time, so a slow or silent dialer cannot hold up the ones behind it.
+
+use std::fmt;
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use ed25519_dalek::{Signer as _, SigningKey};
+use nyx_crypto::keystore::{node_id_from_public_key, Keystore, NodeId};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{DigitallySignedStruct, DistinguishedName, SignatureScheme};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+use zeroize::Zeroizing;
+
+/// ALPN protocol id of the Nyx QUIC mapping.
+pub const ALPN: &[u8] = b"nyx/1";
+/// Initial path MTU: a 1280-byte cell plus DATAGRAM frame, short header
+/// and AEAD tag. Lower values cannot carry a cell at all.
+pub const INITIAL_MTU: u16 = 1350;
+/// Inbound handshakes in flight; further attempts are refused.
+pub const MAX_HANDSHAKES: usize = 256;
+
+pub mod provider;
+mod session;
+
+pub use session::HandshakeData;
+
+const SERVER_NAME: &str = "nyx";
+const ED25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+/// Node identity key and the self-signed certificate presented for it.
+#[derive(Clone)]
+pub struct QuicIdentity {
+	node_id: NodeId,
+	/// PKCS#8 wrapping of the identity seed; wiped on drop.
+	key_der: Zeroizing<Vec<u8>>,
+	cert: CertificateDer<'static>,
+}
+
+impl fmt::Debug for QuicIdentity {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.debug_struct("QuicIdentity").field("node_id", &hex(&self.node_id)).finish_non_exhaustive() }
+}
+
+impl QuicIdentity {
+	/// Present the keystore's Ed25519 identity, so peers pin this node's NodeID.
+	pub fn from_keystore(keystore: &Keystore) -> io::Result<Self> { Self::from_signing_key(keystore.identity()) }
+
+	/// Throwaway identity, for tests and clients that do not need to be recognised.
+	pub fn generate() -> io::Result<Self> { Self::from_signing_key(&SigningKey::generate(&mut rand::rngs::OsRng)) }
+
+	pub fn from_signing_key(key: &SigningKey) -> io::Result<Self> {
+		let node_id = node_id_from_public_key(key.verifying_key().as_bytes());
+		let mut params = rcgen::CertificateParams::new(vec![SERVER_NAME.to_string()]).map_err(io::Error::other)?;
+		// Deterministic serial; rcgen only derives one itself with ring enabled.
+		let mut serial = node_id[..20].to_vec();
+		serial[0] &= 0x7f;
+		params.serial_number = Some(serial.into());
+		let signer = rcgen::KeyPair::from_remote(Box::new(CertSigner(key.clone()))).map_err(io::Error::other)?;
+		let cert = params.self_signed(&signer).map_err(io::Error::other)?;
+		Ok(Self { node_id, key_der: provider::ed25519_pkcs8(&Zeroizing::new(key.to_bytes())), cert: cert.der().clone() })
+	}
+
+	/// The value peers pin.
+	pub fn node_id(&self) -> NodeId { self.node_id }
+
+	fn key(&self) -> PrivateKeyDer<'static> { PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.key_der.to_vec())) }
+}
+
+/// Lets rcgen self-sign with a dalek key instead of its ring backend.
+struct CertSigner(SigningKey);
+
+impl rcgen::RemoteKeyPair for CertSigner {
+	fn public_key(&self) -> &[u8] { self.0.as_ref().as_bytes() }
+	fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, rcgen::Error> { Ok(self.0.sign(msg).to_vec()) }
+	fn algorithm(&self) -> &'static rcgen::SignatureAlgorithm { &rcgen::PKCS_ED25519 }
+}
+
+#[derive(Debug, Clone)]
+pub struct QuicConfig {
+	pub bind: SocketAddr,
+	pub idle_timeout: Duration,
+	/// Spec §5.4 keep-alive period.
+	pub keep_alive: Duration,
+	/// Bytes of unread datagrams buffered per connection before the oldest are dropped.
+	pub datagram_buffer: usize,
+}
+
+impl Default for QuicConfig {
+	fn default() -> Self {
+		Self {
+			bind: SocketAddr::from((Ipv6Addr::UNSPECIFIED, 443)),
+			idle_timeout: Duration::from_secs(30),
+			keep_alive: Duration::from_secs(15),
+			datagram_buffer: 1 << 20,
+		}
+	}
+}
+
+/// A QUIC endpoint that both dials and accepts.
+#[derive(Debug)]
+pub struct QuicEndpoint {
+	endpoint: quinn::Endpoint,
+	identity: QuicIdentity,
+	transport: Arc<quinn::TransportConfig>,
+	provider: Arc<CryptoProvider>,
+	accepted: tokio::sync::Mutex<mpsc::Receiver<io::Result<QuicConnection>>>,
+	acceptor: JoinHandle<()>,
+}
+
+impl QuicEndpoint {
+	/// Must be called inside a Tokio runtime.
+	pub fn bind(cfg: &QuicConfig, identity: QuicIdentity) -> io::Result<Self> {
+		let provider = Arc::new(provider::provider());
+		let mut transport = quinn::TransportConfig::default();
+		transport
+			.max_idle_timeout(Some(cfg.idle_timeout.try_into().map_err(io::Error::other)?))
+			.keep_alive_interval(Some(cfg.keep_alive))
+			.initial_mtu(INITIAL_MTU)
+			.datagram_receive_buffer_size(Some(cfg.datagram_buffer))
+			.datagram_send_buffer_size(cfg.datagram_buffer)
+			// Cells only travel in datagrams.
+			.max_concurrent_bidi_streams(0u32.into())
+			.max_concurrent_uni_streams(0u32.into());
+		let transport = Arc::new(transport);
+
+		let mut tls = rustls::ServerConfig::builder_with_provider(provider.clone())
+			.with_protocol_versions(&[&rustls::version::TLS13])
+			.map_err(io::Error::other)?
+			.with_client_cert_verifier(Arc::new(AnyPeer(provider.clone())))
+			.with_single_cert(vec![identity.cert.clone()], identity.key())
+			.map_err(io::Error::other)?;
+		tls.alpn_protocols = vec![ALPN.to_vec()];
+		let mut server = quinn::ServerConfig::new(Arc::new(session::ServerConfig::new(tls)?), Arc::new(session::TokenKey::random()));
+		server.transport_config(transport.clone());
+
+		let endpoint = quinn::Endpoint::new(quinn::EndpointConfig::new(Arc::new(session::ResetKey::random())), Some(server), bind_socket(cfg.bind)?, Arc::new(quinn::TokioRuntime))?;
+		let (tx, accepted) = mpsc::channel(MAX_HANDSHAKES);
+		let acceptor = tokio::spawn(accept_loop(endpoint.clone(), tx));
+		Ok(Self { endpoint, identity, transport, provider, accepted: tokio::sync::Mutex::new(accepted), acceptor })
+	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> { self.endpoint.local_addr() }
+
+	pub fn identity(&self) -> &QuicIdentity { &self.identity }
+
+	/// Dial `addr`, accepting only the peer whose NodeID is `pin`.
+	pub async fn connect(&self, addr: SocketAddr, pin: NodeId) -> io::Result<QuicConnection> {
+		let mut tls = rustls::ClientConfig::builder_with_provider(self.provider.clone())
+			.with_protocol_versions(&[&rustls::version::TLS13])
+			.map_err(io::Error::other)?
+			.dangerous()
+			.with_custom_certificate_verifier(Arc::new(PinnedPeer { pin, provider: self.provider.clone() }))
+			.with_client_auth_cert(vec![self.identity.cert.clone()], self.identity.key())
+			.map_err(io::Error::other)?;
+		tls.alpn_protocols = vec![ALPN.to_vec()];
+		let mut client = quinn::ClientConfig::new(Arc::new(session::ClientConfig::new(tls)?));
+		client.transport_config(self.transport.clone());
+		let conn = self.endpoint.connect_with(client, addr, SERVER_NAME).map_err(io::Error::other)?.await?;
+		QuicConnection::new(conn, pin)
+	}
+
+	/// Next inbound connection, in order of handshake completion; `None`
+	/// once the endpoint is closed.
+	pub async fn accept(&self) -> Option<io::Result<QuicConnection>> { self.accepted.lock().await.recv().await }
+
+	pub fn close(&self) { self.endpoint.close(0u32.into(), b"shutdown"); }
+}
+
+impl Drop for QuicEndpoint {
+	fn drop(&mut self) { self.acceptor.abort(); }
+}
+
+/// Dual-stack when bound to `[::]`, as `quinn::Endpoint::server` would do.
+fn bind_socket(addr: SocketAddr) -> io::Result<std::net::UdpSocket> {
+	let socket = socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::DGRAM, Some(socket2::Protocol::UDP))?;
+	if addr.is_ipv6() {
+		if let Err(e) = socket.set_only_v6(false) {
+			tracing::debug!(error = %e, "QUIC socket is IPv6-only");
+		}
+	}
+	socket.bind(&addr.into())?;
+	Ok(socket.into())
+}
+
+async fn accept_loop(endpoint: quinn::Endpoint, tx: mpsc::Sender<io::Result<QuicConnection>>) {
+	let permits = Arc::new(Semaphore::new(MAX_HANDSHAKES));
+	// Dropping the set when this task is aborted cancels pending handshakes.
+	let mut handshakes = JoinSet::new();
+	while let Some(incoming) = endpoint.accept().await {
+		while handshakes.try_join_next().is_some() {}
+		let Ok(permit) = permits.clone().try_acquire_owned() else {
+			tracing::debug!(remote = %incoming.remote_address(), "too many QUIC handshakes in flight");
+			incoming.refuse();
+			continue;
+		};
+		let tx = tx.clone();
+		handshakes.spawn(async move {
+			let conn = incoming.await;
+			drop(permit);
+			let res = match conn {
+				Ok(conn) => {
+					let identity = conn.peer_identity().and_then(|any| any.downcast::<Vec<CertificateDer<'static>>>().ok()).and_then(|certs| certs.first().and_then(cert_node_id));
+					match identity {
+						Some(node_id) => QuicConnection::new(conn, node_id),
+						None => Err(io::Error::new(io::ErrorKind::InvalidData, "peer presented no Ed25519 identity")),
+					}
+				}
+				// A failed handshake is not worth reporting to the caller.
+				Err(e) => {
+					tracing::debug!(error = %e, "QUIC handshake failed");
+					return;
+				}
+			};
+			let _ = tx.send(res).await;
+		});
+	}
+}
+
+/// One peer connection carrying cells as unreliable datagrams.
+#[derive(Debug, Clone)]
+pub struct QuicConnection {
+	conn: quinn::Connection,
+	peer: NodeId,
+}
+
+impl QuicConnection {
+	fn new(conn: quinn::Connection, peer: NodeId) -> io::Result<Self> {
+		if conn.max_datagram_size().is_none() {
+			return Err(io::Error::new(io::ErrorKind::Unsupported, "peer does not accept QUIC datagrams"));
+		}
+		Ok(Self { conn, peer })
+	}
+
+	/// NodeID of the key the peer authenticated with.
+	pub fn peer_node_id(&self) -> NodeId { self.peer }
+
+	pub fn remote_address(&self) -> SocketAddr { self.conn.remote_address() }
+
+	/// Largest cell the current path MTU can carry.
+	pub fn max_datagram_size(&self) -> Option<usize> { self.conn.max_datagram_size() }
+
+	/// Queue one cell; datagrams are never retransmitted.
+	pub fn send(&self, cell: &[u8]) -> io::Result<()> {
+		self.conn.send_datagram(Bytes::copy_from_slice(cell)).map_err(|e| match e {
+			quinn::SendDatagramError::TooLarge => io::Error::new(io::ErrorKind::InvalidInput, e),
+			quinn::SendDatagramError::ConnectionLost(e) => e.into(),
+			e => io::Error::other(e),
+		})
+	}
+
+	pub async fn recv(&self) -> io::Result<Bytes> { Ok(self.conn.read_datagram().await?) }
+
+	pub fn close(&self) { self.conn.close(0u32.into(), b"close"); }
+}
+
+/// Raw Ed25519 key from a certificate, `None` for any other key type.
+fn ed25519_key(cert: &CertificateDer<'_>) -> Option<[u8; 32]> {
+	let cert = webpki::EndEntityCert::try_from(cert).ok()?;
+	let spki = cert.subject_public_key_info();
+	spki.strip_prefix(&ED25519_SPKI_PREFIX[..])?.try_into().ok()
+}
+
+fn cert_node_id(cert: &CertificateDer<'_>) -> Option<NodeId> { ed25519_key(cert).map(|pk| node_id_from_public_key(&pk)) }
+
+fn hex(bytes: &[u8]) -> String { bytes.iter().map(|b| format!("{b:02x}")).collect() }
+
+fn tls12_unsupported() -> Result<HandshakeSignatureValid, rustls::Error> { Err(rustls::Error::PeerIncompatible(rustls::PeerIncompatible::Tls12NotOffered)) }
+
+/// Dialer side: the server certificate must carry a key hashing to the pinned NodeID.
+#[derive(Debug)]
+struct PinnedPeer {
+	pin: NodeId,
+	provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedPeer {
+	fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _name: &ServerName<'_>, _ocsp: &[u8], _now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+		match cert_node_id(end_entity) {
+			Some(node_id) if node_id == self.pin => Ok(ServerCertVerified::assertion()),
+			_ => Err(rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure)),
+		}
+	}
+
+	fn verify_tls12_signature(&self, _: &[u8], _: &CertificateDer<'_>, _: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> { tls12_unsupported() }
+
+	fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+		verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+	}
+
+	fn supported_verify_schemes(&self) -> Vec<SignatureScheme> { vec![SignatureScheme::ED25519] }
+}
+
+/// Listener side: any Ed25519 identity may connect, but must prove it holds the key.
+#[derive(Debug)]
+struct AnyPeer(Arc<CryptoProvider>);
+
+impl ClientCertVerifier for AnyPeer {
+	fn root_hint_subjects(&self) -> &[DistinguishedName] { &[] }
+
+	fn verify_client_cert(&self, end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _now: UnixTime) -> Result<ClientCertVerified, rustls::Error> {
+		match ed25519_key(end_entity) {
+			Some(_) => Ok(ClientCertVerified::assertion()),
+			None => Err(rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure)),
+		}
+	}
+
+	fn verify_tls12_signature(&self, _: &[u8], _: &CertificateDer<'_>, _: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> { tls12_unsupported() }
+
+	fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+		verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+	}
+
+	fn supported_verify_schemes(&self) -> Vec<SignatureScheme> { vec![SignatureScheme::ED25519] }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn identity_is_the_keystore_node_id() {
+		let ks = Keystore::generate();
+		let a = QuicIdentity::from_keystore(&ks).unwrap();
+		let b = QuicIdentity::from_keystore(&ks).unwrap();
+		assert_eq!(a.node_id(), ks.node_id());
+		assert_eq!(a.cert, b.cert);
+		assert_eq!(cert_node_id(&a.cert), Some(ks.node_id()));
+		assert_ne!(QuicIdentity::generate().unwrap().node_id(), a.node_id());
+	}
+}
@@ -0,0 +1,313 @@
+﻿//! Pure-Rust rustls crypto provider for the QUIC transport.
+//!
+//! Implements exactly what the Nyx QUIC mapping needs and nothing else:
+//! TLS 1.3 with `TLS13_AES_128_GCM_SHA256` (also the suite RFC 9001 fixes
+//! for Initial packets), X25519 key exchange and Ed25519 certificates, all
+//! on RustCrypto/dalek primitives so the `quic` feature needs no ring or
+//! aws-lc (CONTRIBUTING: pure Rust policy).
+
+use std::sync::Arc;
+
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::aes::cipher::{generic_array::GenericArray, BlockEncrypt};
+use aes_gcm::aes::Aes128;
+use aes_gcm::Aes128Gcm;
+use ed25519_dalek::Signer as _;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rustls::crypto::cipher::{
+	make_tls13_aad, AeadKey, InboundOpaqueMessage, InboundPlainMessage, Iv, MessageDecrypter, MessageEncrypter, Nonce, OutboundOpaqueMessage, OutboundPlainMessage, PrefixedPayload,
+	Tls13AeadAlgorithm, UnsupportedOperationError,
+};
+use rustls::crypto::tls13::HkdfUsingHmac;
+use rustls::crypto::{hash, hmac as tls_hmac, ActiveKeyExchange, CipherSuiteCommon, CryptoProvider, GetRandomFailed, KeyProvider, SecureRandom, SharedSecret, SupportedKxGroup, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{alg_id, AlgorithmIdentifier, InvalidSignature, PrivateKeyDer, SignatureVerificationAlgorithm};
+use rustls::sign::{Signer, SigningKey};
+use rustls::{quic, CipherSuite, ConnectionTrafficSecrets, ContentType, Error, NamedGroup, ProtocolVersion, SignatureAlgorithm, SignatureScheme, SupportedCipherSuite, Tls13CipherSuite};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, Zeroizing};
+
+const TAG_LEN: usize = 16;
+const ED25519_PKCS8_PREFIX: [u8; 16] = [0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20];
+
+/// The provider every QUIC endpoint uses.
+pub fn provider() -> CryptoProvider {
+	CryptoProvider {
+		cipher_suites: vec![TLS13_AES_128_GCM_SHA256],
+		kx_groups: vec![&X25519],
+		signature_verification_algorithms: SIGNATURE_ALGORITHMS,
+		secure_random: &OsRandom,
+		key_provider: &Ed25519Keys,
+	}
+}
+
+/// PKCS#8 v1 encoding of an Ed25519 seed, the only key form [`provider`] loads.
+pub(crate) fn ed25519_pkcs8(seed: &[u8; 32]) -> Zeroizing<Vec<u8>> {
+	// Sized up front so no reallocation leaves a copy of the seed behind.
+	let mut der = Zeroizing::new(Vec::with_capacity(ED25519_PKCS8_PREFIX.len() + seed.len()));
+	der.extend_from_slice(&ED25519_PKCS8_PREFIX);
+	der.extend_from_slice(seed);
+	der
+}
+
+pub static TLS13_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite::Tls13(&TLS13_AES_128_GCM_SHA256_INTERNAL);
+
+static TLS13_AES_128_GCM_SHA256_INTERNAL: Tls13CipherSuite = Tls13CipherSuite {
+	common: CipherSuiteCommon { suite: CipherSuite::TLS13_AES_128_GCM_SHA256, hash_provider: &Sha256Hash, confidentiality_limit: 1 << 24 },
+	hkdf_provider: &HkdfUsingHmac(&HmacSha256),
+	aead_alg: &Aes128GcmAead,
+	quic: Some(&Aes128GcmAead),
+};
+
+struct Sha256Hash;
+
+impl hash::Hash for Sha256Hash {
+	fn start(&self) -> Box<dyn hash::Context> { Box::new(Sha256Context(Sha256::new())) }
+	fn hash(&self, data: &[u8]) -> hash::Output { hash::Output::new(&Sha256::digest(data)) }
+	fn output_len(&self) -> usize { 32 }
+	fn algorithm(&self) -> hash::HashAlgorithm { hash::HashAlgorithm::SHA256 }
+}
+
+struct Sha256Context(Sha256);
+
+impl hash::Context for Sha256Context {
+	fn fork_finish(&self) -> hash::Output { hash::Output::new(&self.0.clone().finalize()) }
+	fn fork(&self) -> Box<dyn hash::Context> { Box::new(Sha256Context(self.0.clone())) }
+	fn finish(self: Box<Self>) -> hash::Output { hash::Output::new(&self.0.finalize()) }
+	fn update(&mut self, data: &[u8]) { self.0.update(data); }
+}
+
+struct HmacSha256;
+
+impl tls_hmac::Hmac for HmacSha256 {
+	fn with_key(&self, key: &[u8]) -> Box<dyn tls_hmac::Key> { Box::new(HmacSha256Key(<Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length"))) }
+	fn hash_output_len(&self) -> usize { 32 }
+}
+
+struct HmacSha256Key(Hmac<Sha256>);
+
+impl tls_hmac::Key for HmacSha256Key {
+	fn sign_concat(&self, first: &[u8], middle: &[&[u8]], last: &[u8]) -> tls_hmac::Tag {
+		let mut mac = self.0.clone();
+		mac.update(first);
+		middle.iter().for_each(|m| mac.update(m));
+		mac.update(last);
+		tls_hmac::Tag::new(&mac.finalize().into_bytes())
+	}
+	fn tag_len(&self) -> usize { 32 }
+}
+
+/// AES-128-GCM for TLS records and QUIC packets, plus AES header protection.
+struct Aes128GcmAead;
+
+fn gcm(key: &AeadKey) -> Aes128Gcm { Aes128Gcm::new_from_slice(key.as_ref()).expect("16-byte AES-128 key") }
+
+impl Tls13AeadAlgorithm for Aes128GcmAead {
+	fn encrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageEncrypter> { Box::new(RecordCipher(gcm(&key), iv)) }
+	fn decrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageDecrypter> { Box::new(RecordCipher(gcm(&key), iv)) }
+	fn key_len(&self) -> usize { 16 }
+	fn extract_keys(&self, key: AeadKey, iv: Iv) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError> { Ok(ConnectionTrafficSecrets::Aes128Gcm { key, iv }) }
+}
+
+impl quic::Algorithm for Aes128GcmAead {
+	fn packet_key(&self, key: AeadKey, iv: Iv) -> Box<dyn quic::PacketKey> { Box::new(PacketCipher(gcm(&key), iv)) }
+	fn header_protection_key(&self, key: AeadKey) -> Box<dyn quic::HeaderProtectionKey> { Box::new(HeaderCipher(Aes128::new_from_slice(&key.as_ref()[..16]).expect("16-byte AES-128 key"))) }
+	fn aead_key_len(&self) -> usize { 16 }
+}
+
+struct RecordCipher(Aes128Gcm, Iv);
+
+impl MessageEncrypter for RecordCipher {
+	fn encrypt(&mut self, msg: OutboundPlainMessage<'_>, seq: u64) -> Result<OutboundOpaqueMessage, Error> {
+		let total_len = self.encrypted_payload_len(msg.payload.len());
+		let mut payload = PrefixedPayload::with_capacity(total_len);
+		payload.extend_from_chunks(&msg.payload);
+		payload.extend_from_slice(&msg.typ.to_array());
+		let nonce = Nonce::new(&self.1, seq).0;
+		let tag = self.0.encrypt_in_place_detached(&nonce.into(), &make_tls13_aad(total_len), payload.as_mut()).map_err(|_| Error::EncryptError)?;
+		payload.extend_from_slice(&tag);
+		Ok(OutboundOpaqueMessage::new(ContentType::ApplicationData, ProtocolVersion::TLSv1_2, payload))
+	}
+
+	fn encrypted_payload_len(&self, payload_len: usize) -> usize { payload_len + 1 + TAG_LEN }
+}
+
+impl MessageDecrypter for RecordCipher {
+	fn decrypt<'a>(&mut self, mut msg: InboundOpaqueMessage<'a>, seq: u64) -> Result<InboundPlainMessage<'a>, Error> {
+		let payload = &mut msg.payload;
+		let aad = make_tls13_aad(payload.len());
+		let Some(plain_len) = payload.len().checked_sub(TAG_LEN) else { return Err(Error::DecryptError) };
+		let (plain, tag) = payload.split_at_mut(plain_len);
+		let nonce = Nonce::new(&self.1, seq).0;
+		self.0.decrypt_in_place_detached(&nonce.into(), &aad, plain, GenericArray::from_slice(tag)).map_err(|_| Error::DecryptError)?;
+		payload.truncate(plain_len);
+		msg.into_tls13_unpadded_message()
+	}
+}
+
+struct PacketCipher(Aes128Gcm, Iv);
+
+impl quic::PacketKey for PacketCipher {
+	fn encrypt_in_place(&self, packet_number: u64, header: &[u8], payload: &mut [u8]) -> Result<quic::Tag, Error> {
+		let nonce = Nonce::new(&self.1, packet_number).0;
+		let tag = self.0.encrypt_in_place_detached(&nonce.into(), header, payload).map_err(|_| Error::EncryptError)?;
+		Ok(quic::Tag::from(&tag[..]))
+	}
+
+	fn decrypt_in_place<'a>(&self, packet_number: u64, header: &[u8], payload: &'a mut [u8]) -> Result<&'a [u8], Error> {
+		let Some(plain_len) = payload.len().checked_sub(TAG_LEN) else { return Err(Error::DecryptError) };
+		let (plain, tag) = payload.split_at_mut(plain_len);
+		let nonce = Nonce::new(&self.1, packet_number).0;
+		self.0.decrypt_in_place_detached(&nonce.into(), header, plain, GenericArray::from_slice(tag)).map_err(|_| Error::DecryptError)?;
+		Ok(&payload[..plain_len])
+	}
+
+	fn tag_len(&self) -> usize { TAG_LEN }
+	/// RFC 9001 §6.6 limits for AEAD_AES_128_GCM.
+	fn confidentiality_limit(&self) -> u64 { 1 << 23 }
+	fn integrity_limit(&self) -> u64 { 1 << 52 }
+}
+
+/// RFC 9001 §5.4.3: the mask is AES-ECB of the 16-byte ciphertext sample.
+struct HeaderCipher(Aes128);
+
+impl HeaderCipher {
+	fn xor_in_place(&self, sample: &[u8], first: &mut u8, packet_number: &mut [u8], masked: bool) -> Result<(), Error> {
+		if sample.len() != 16 || packet_number.len() > 4 {
+			return Err(Error::General("invalid QUIC header protection input".into()));
+		}
+		let mut mask = GenericArray::clone_from_slice(sample);
+		self.0.encrypt_block(&mut mask);
+		let bits = if *first & 0x80 != 0 { 0x0f } else { 0x1f };
+		let plain_first = if masked { *first ^ (mask[0] & bits) } else { *first };
+		let pn_len = (plain_first & 0x03) as usize + 1;
+		*first ^= mask[0] & bits;
+		packet_number.iter_mut().zip(&mask[1..]).take(pn_len).for_each(|(b, m)| *b ^= m);
+		Ok(())
+	}
+}
+
+impl quic::HeaderProtectionKey for HeaderCipher {
+	fn encrypt_in_place(&self, sample: &[u8], first: &mut u8, packet_number: &mut [u8]) -> Result<(), Error> { self.xor_in_place(sample, first, packet_number, false) }
+	fn decrypt_in_place(&self, sample: &[u8], first: &mut u8, packet_number: &mut [u8]) -> Result<(), Error> { self.xor_in_place(sample, first, packet_number, true) }
+	fn sample_len(&self) -> usize { 16 }
+}
+
+#[derive(Debug)]
+struct X25519Group;
+
+static X25519: X25519Group = X25519Group;
+
+impl SupportedKxGroup for X25519Group {
+	fn start(&self) -> Result<Box<dyn ActiveKeyExchange>, Error> {
+		let secret = x25519_dalek::StaticSecret::random_from_rng(rand::rngs::OsRng);
+		let public = x25519_dalek::PublicKey::from(&secret);
+		Ok(Box::new(X25519Exchange { secret, public }))
+	}
+	fn name(&self) -> NamedGroup { NamedGroup::X25519 }
+}
+
+struct X25519Exchange {
+	secret: x25519_dalek::StaticSecret,
+	public: x25519_dalek::PublicKey,
+}
+
+impl ActiveKeyExchange for X25519Exchange {
+	fn complete(self: Box<Self>, peer_pub_key: &[u8]) -> Result<SharedSecret, Error> {
+		let peer: [u8; 32] = peer_pub_key.try_into().map_err(|_| Error::from(rustls::PeerMisbehaved::InvalidKeyShare))?;
+		let shared = self.secret.diffie_hellman(&peer.into());
+		// A low-order peer key yields the all-zero secret.
+		if !shared.was_contributory() {
+			return Err(rustls::PeerMisbehaved::InvalidKeyShare.into());
+		}
+		Ok(SharedSecret::from(&shared.as_bytes()[..]))
+	}
+	fn pub_key(&self) -> &[u8] { self.public.as_bytes() }
+	fn group(&self) -> NamedGroup { NamedGroup::X25519 }
+}
+
+#[derive(Debug)]
+struct Ed25519Verify;
+
+static ED25519: Ed25519Verify = Ed25519Verify;
+static SIGNATURE_ALGORITHMS: WebPkiSupportedAlgorithms = WebPkiSupportedAlgorithms { all: &[&ED25519], mapping: &[(SignatureScheme::ED25519, &[&ED25519])] };
+
+impl SignatureVerificationAlgorithm for Ed25519Verify {
+	fn verify_signature(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), InvalidSignature> {
+		let key = public_key.try_into().ok().and_then(|k| ed25519_dalek::VerifyingKey::from_bytes(k).ok()).ok_or(InvalidSignature)?;
+		let sig = ed25519_dalek::Signature::from_slice(signature).map_err(|_| InvalidSignature)?;
+		key.verify_strict(message, &sig).map_err(|_| InvalidSignature)
+	}
+	fn public_key_alg_id(&self) -> AlgorithmIdentifier { alg_id::ED25519 }
+	fn signature_alg_id(&self) -> AlgorithmIdentifier { alg_id::ED25519 }
+}
+
+#[derive(Debug)]
+struct Ed25519Keys;
+
+impl KeyProvider for Ed25519Keys {
+	fn load_private_key(&self, mut key_der: PrivateKeyDer<'static>) -> Result<Arc<dyn SigningKey>, Error> {
+		let seed = match &key_der {
+			PrivateKeyDer::Pkcs8(der) => der.secret_pkcs8_der().strip_prefix(&ED25519_PKCS8_PREFIX[..]).and_then(|s| <[u8; 32]>::try_from(s).ok()).map(Zeroizing::new),
+			_ => None,
+		};
+		// The DER copy rustls handed over holds the seed too.
+		key_der.zeroize();
+		let seed = seed.ok_or_else(|| Error::General("only PKCS#8 Ed25519 keys are supported".into()))?;
+		Ok(Arc::new(Ed25519Signer(Arc::new(ed25519_dalek::SigningKey::from_bytes(&seed)))))
+	}
+}
+
+#[derive(Debug, Clone)]
+struct Ed25519Signer(Arc<ed25519_dalek::SigningKey>);
+
+impl SigningKey for Ed25519Signer {
+	fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> { offered.contains(&SignatureScheme::ED25519).then(|| Box::new(self.clone()) as Box<dyn Signer>) }
+	fn algorithm(&self) -> SignatureAlgorithm { SignatureAlgorithm::ED25519 }
+}
+
+impl Signer for Ed25519Signer {
+	fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> { Ok(self.0.sign(message).to_vec()) }
+	fn scheme(&self) -> SignatureScheme { SignatureScheme::ED25519 }
+}
+
+#[derive(Debug)]
+struct OsRandom;
+
+impl SecureRandom for OsRandom {
+	fn fill(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed> { rand::rngs::OsRng.try_fill_bytes(buf).map_err(|_| GetRandomFailed) }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rustls::quic::{Keys, Version};
+	use rustls::Side;
+
+	fn hex(s: &str) -> Vec<u8> { (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect() }
+
+	/// RFC 9369 Appendix A.3: server Initial protected with this provider's
+	/// AES-128-GCM packet key and AES header protection.
+	#[test]
+	fn initial_packet_matches_rfc9369_a3() {
+		let Keys { local, .. } = TLS13_AES_128_GCM_SHA256_INTERNAL.quic_suite().unwrap().keys(&hex("8394c8f03e515708"), Side::Server, Version::V2);
+
+		let mut header = hex("d16b3343cf0008f067a5502a4262b50040750001");
+		let plain = hex("02000000000600405a020000560303eefce7f7b37ba1d1632e96677825ddf73988cfc79825df566dc5430b9a045a1200130100002e00330024001d00209d3c940d89690b84d08a60993c144eca684d1081287c834d5311bcf32bb9da1a002b00020304");
+		let mut payload = plain.clone();
+		let tag = local.packet.encrypt_in_place(1, &header, &mut payload).unwrap();
+		let mut sealed = [&payload[..], tag.as_ref()].concat();
+		assert_eq!(local.packet.decrypt_in_place(1, &header, &mut sealed).unwrap(), &plain[..]);
+		let (first, rest) = header.split_at_mut(1);
+		let pn_start = rest.len() - 2;
+		local.header.encrypt_in_place(&payload[2..18], &mut first[0], &mut rest[pn_start..]).unwrap();
+
+		let mut packet = header;
+		packet.extend_from_slice(&payload);
+		packet.extend_from_slice(tag.as_ref());
+		assert_eq!(
+			packet,
+			hex("dc6b3343cf0008f067a5502a4262b5004075d92faaf16f05d8a4398c47089698baeea26b91eb761d9b89237bbf87263017915358230035f7fd3945d88965cf17f9af6e16886c61bfc703106fbaf3cb4cfa52382dd16a393e42757507698075b2c984c707f0a0812d8cd5a6881eaf21ceda98f4bd23f6fe1a3e2c43edd9ce7ca84bed8521e2e140")
+		);
+	}
+}
@@ -0,0 +1,319 @@
+﻿//! quinn crypto glue over rustls' QUIC API.
+//!
+//! quinn only ships its rustls integration together with ring or aws-lc, so
+//! this is the same mapping rebuilt on [`super::provider`]: TLS sessions,
+//! Initial keys, Retry integrity tags (RFC 9001 §5.8), the stateless-reset
+//! HMAC key and the address-validation token key.
+
+use std::any::Any;
+use std::io;
+use std::sync::Arc;
+
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use bytes::BytesMut;
+use hmac::{Hmac, Mac};
+use quinn_proto::crypto::{self, CryptoError, ExportKeyingMaterialError, KeyPair, Keys, UnsupportedVersion};
+use quinn_proto::transport_parameters::TransportParameters;
+use quinn_proto::{ConnectError, ConnectionId, Side, TransportError, TransportErrorCode};
+use rand::RngCore;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::quic::{Connection, HeaderProtectionKey, KeyChange, PacketKey, Secrets, Suite, Version};
+use sha2::Sha256;
+
+const RETRY_KEY_V1: [u8; 16] = [0xbe, 0x0c, 0x69, 0x0b, 0x9f, 0x66, 0x57, 0x5a, 0x1d, 0x76, 0x6b, 0x54, 0xe3, 0x68, 0xc8, 0x4e];
+const RETRY_NONCE_V1: [u8; 12] = [0x46, 0x15, 0x99, 0xd3, 0x5d, 0x63, 0x2b, 0xf2, 0x23, 0x98, 0x25, 0xbb];
+
+fn initial_suite(provider: &rustls::crypto::CryptoProvider) -> Option<Suite> {
+	provider.cipher_suites.iter().find_map(|cs| match (cs.suite(), cs.tls13()) {
+		(rustls::CipherSuite::TLS13_AES_128_GCM_SHA256, Some(suite)) => suite.quic_suite(),
+		_ => None,
+	})
+}
+
+/// Only QUIC v1; the endpoint never offers draft versions.
+fn version(v: u32) -> Result<Version, UnsupportedVersion> {
+	match v {
+		0x0000_0001 => Ok(Version::V1),
+		_ => Err(UnsupportedVersion),
+	}
+}
+
+fn params_bytes(params: &TransportParameters) -> Vec<u8> {
+	let mut bytes = Vec::new();
+	params.write(&mut bytes);
+	bytes
+}
+
+fn keys(keys: rustls::quic::Keys) -> Keys {
+	Keys {
+		header: KeyPair { local: Box::new(HeaderKey(keys.local.header)), remote: Box::new(HeaderKey(keys.remote.header)) },
+		packet: KeyPair { local: Box::new(PacketCipher(keys.local.packet)), remote: Box::new(PacketCipher(keys.remote.packet)) },
+	}
+}
+
+fn retry_tag(orig_dst_cid: &ConnectionId, packet: &[u8]) -> [u8; 16] {
+	let mut pseudo = Vec::with_capacity(1 + orig_dst_cid.len() + packet.len());
+	pseudo.push(orig_dst_cid.len() as u8);
+	pseudo.extend_from_slice(orig_dst_cid);
+	pseudo.extend_from_slice(packet);
+	let key = Aes128Gcm::new(&RETRY_KEY_V1.into());
+	key.encrypt_in_place_detached(&RETRY_NONCE_V1.into(), &pseudo, &mut []).expect("empty plaintext").into()
+}
+
+pub(crate) struct ClientConfig {
+	inner: Arc<rustls::ClientConfig>,
+	initial: Suite,
+}
+
+impl ClientConfig {
+	pub(crate) fn new(inner: rustls::ClientConfig) -> io::Result<Self> {
+		let initial = initial_suite(inner.crypto_provider()).ok_or_else(|| io::Error::other("provider lacks TLS13_AES_128_GCM_SHA256"))?;
+		Ok(Self { inner: Arc::new(inner), initial })
+	}
+}
+
+impl crypto::ClientConfig for ClientConfig {
+	fn start_session(self: Arc<Self>, v: u32, server_name: &str, params: &TransportParameters) -> Result<Box<dyn crypto::Session>, ConnectError> {
+		let version = version(v)?;
+		let name = ServerName::try_from(server_name).map_err(|_| ConnectError::InvalidServerName(server_name.into()))?.to_owned();
+		let conn = rustls::quic::ClientConnection::new(self.inner.clone(), version, name, params_bytes(params)).map_err(|_| ConnectError::UnsupportedVersion)?;
+		Ok(Box::new(TlsSession::new(Connection::Client(conn), self.initial)))
+	}
+}
+
+pub(crate) struct ServerConfig {
+	inner: Arc<rustls::ServerConfig>,
+	initial: Suite,
+}
+
+impl ServerConfig {
+	pub(crate) fn new(inner: rustls::ServerConfig) -> io::Result<Self> {
+		let initial = initial_suite(inner.crypto_provider()).ok_or_else(|| io::Error::other("provider lacks TLS13_AES_128_GCM_SHA256"))?;
+		Ok(Self { inner: Arc::new(inner), initial })
+	}
+}
+
+impl crypto::ServerConfig for ServerConfig {
+	fn initial_keys(&self, v: u32, dst_cid: &ConnectionId) -> Result<Keys, UnsupportedVersion> {
+		Ok(keys(self.initial.keys(dst_cid, rustls::Side::Server, version(v)?)))
+	}
+
+	fn retry_tag(&self, _version: u32, orig_dst_cid: &ConnectionId, packet: &[u8]) -> [u8; 16] { retry_tag(orig_dst_cid, packet) }
+
+	fn start_session(self: Arc<Self>, v: u32, params: &TransportParameters) -> Box<dyn crypto::Session> {
+		// Only reached for versions `initial_keys` accepted.
+		let version = version(v).expect("version checked by initial_keys");
+		let conn = rustls::quic::ServerConnection::new(self.inner.clone(), version, params_bytes(params)).expect("server config supports TLS 1.3");
+		Box::new(TlsSession::new(Connection::Server(conn), self.initial))
+	}
+}
+
+struct TlsSession {
+	inner: Connection,
+	initial: Suite,
+	got_handshake_data: bool,
+	next_secrets: Option<Secrets>,
+}
+
+impl TlsSession {
+	fn new(inner: Connection, initial: Suite) -> Self { Self { inner, initial, got_handshake_data: false, next_secrets: None } }
+
+	fn side(&self) -> Side {
+		match self.inner {
+			Connection::Client(_) => Side::Client,
+			Connection::Server(_) => Side::Server,
+		}
+	}
+}
+
+/// What `quinn::Connecting::handshake_data` yields for Nyx connections.
+pub struct HandshakeData {
+	pub protocol: Option<Vec<u8>>,
+}
+
+impl crypto::Session for TlsSession {
+	fn initial_keys(&self, dst_cid: &ConnectionId, side: Side) -> Keys {
+		let side = match side {
+			Side::Client => rustls::Side::Client,
+			Side::Server => rustls::Side::Server,
+		};
+		keys(self.initial.keys(dst_cid, side, Version::V1))
+	}
+
+	fn handshake_data(&self) -> Option<Box<dyn Any>> {
+		self.got_handshake_data.then(|| Box::new(HandshakeData { protocol: self.inner.alpn_protocol().map(<[u8]>::to_vec) }) as Box<dyn Any>)
+	}
+
+	/// `Vec<CertificateDer<'static>>`, as with quinn's own rustls session.
+	fn peer_identity(&self) -> Option<Box<dyn Any>> {
+		let certs = self.inner.peer_certificates()?;
+		Some(Box::new(certs.iter().map(|c| c.clone().into_owned()).collect::<Vec<CertificateDer<'static>>>()))
+	}
+
+	// 0-RTT is never enabled: cells carry their own replay protection upstream.
+	fn early_crypto(&self) -> Option<(Box<dyn crypto::HeaderKey>, Box<dyn crypto::PacketKey>)> { None }
+
+	fn early_data_accepted(&self) -> Option<bool> {
+		match self.inner {
+			Connection::Client(ref c) => Some(c.is_early_data_accepted()),
+			Connection::Server(_) => None,
+		}
+	}
+
+	fn is_handshaking(&self) -> bool { self.inner.is_handshaking() }
+
+	fn read_handshake(&mut self, buf: &[u8]) -> Result<bool, TransportError> {
+		self.inner.read_hs(buf).map_err(|e| match self.inner.alert() {
+			Some(alert) => TransportError { code: TransportErrorCode::crypto(alert.into()), frame: None, reason: e.to_string() },
+			None => TransportError { code: TransportErrorCode::PROTOCOL_VIOLATION, frame: None, reason: format!("TLS error: {e}") },
+		})?;
+		if !self.got_handshake_data {
+			// rustls has no explicit "ClientHello parsed" / "ALPN agreed" signal.
+			let have_server_name = matches!(self.inner, Connection::Server(ref s) if s.server_name().is_some());
+			if self.inner.alpn_protocol().is_some() || have_server_name || !self.is_handshaking() {
+				self.got_handshake_data = true;
+				return Ok(true);
+			}
+		}
+		Ok(false)
+	}
+
+	fn transport_parameters(&self) -> Result<Option<TransportParameters>, TransportError> {
+		match self.inner.quic_transport_parameters() {
+			None => Ok(None),
+			Some(buf) => Ok(Some(TransportParameters::read(self.side(), &mut io::Cursor::new(buf))?)),
+		}
+	}
+
+	fn write_handshake(&mut self, buf: &mut Vec<u8>) -> Option<Keys> {
+		let k = match self.inner.write_hs(buf)? {
+			KeyChange::Handshake { keys } => keys,
+			KeyChange::OneRtt { keys, next } => {
+				self.next_secrets = Some(next);
+				keys
+			}
+		};
+		Some(keys(k))
+	}
+
+	fn next_1rtt_keys(&mut self) -> Option<KeyPair<Box<dyn crypto::PacketKey>>> {
+		let k = self.next_secrets.as_mut()?.next_packet_keys();
+		Some(KeyPair { local: Box::new(PacketCipher(k.local)), remote: Box::new(PacketCipher(k.remote)) })
+	}
+
+	fn is_valid_retry(&self, orig_dst_cid: &ConnectionId, header: &[u8], payload: &[u8]) -> bool {
+		let Some(tag_start) = payload.len().checked_sub(16) else { return false };
+		let mut packet = header.to_vec();
+		packet.extend_from_slice(&payload[..tag_start]);
+		// Constant-time comparison is not needed: the tag is public.
+		retry_tag(orig_dst_cid, &packet)[..] == payload[tag_start..]
+	}
+
+	fn export_keying_material(&self, output: &mut [u8], label: &[u8], context: &[u8]) -> Result<(), ExportKeyingMaterialError> {
+		self.inner.export_keying_material(output, label, Some(context)).map(|_| ()).map_err(|_| ExportKeyingMaterialError)
+	}
+}
+
+struct HeaderKey(Box<dyn HeaderProtectionKey>);
+
+impl HeaderKey {
+	fn apply(&self, pn_offset: usize, packet: &mut [u8], encrypt: bool) {
+		let (header, sample) = packet.split_at_mut(pn_offset + 4);
+		let (first, rest) = header.split_at_mut(1);
+		let pn = &mut rest[pn_offset - 1..];
+		let sample = &sample[..self.0.sample_len()];
+		let res = if encrypt { self.0.encrypt_in_place(sample, &mut first[0], pn) } else { self.0.decrypt_in_place(sample, &mut first[0], pn) };
+		res.expect("sample and packet number lengths are fixed by quinn");
+	}
+}
+
+impl crypto::HeaderKey for HeaderKey {
+	fn decrypt(&self, pn_offset: usize, packet: &mut [u8]) { self.apply(pn_offset, packet, false) }
+	fn encrypt(&self, pn_offset: usize, packet: &mut [u8]) { self.apply(pn_offset, packet, true) }
+	fn sample_size(&self) -> usize { self.0.sample_len() }
+}
+
+struct PacketCipher(Box<dyn PacketKey>);
+
+impl crypto::PacketKey for PacketCipher {
+	fn encrypt(&self, packet: u64, buf: &mut [u8], header_len: usize) {
+		let (header, rest) = buf.split_at_mut(header_len);
+		let (payload, tag) = rest.split_at_mut(rest.len() - self.0.tag_len());
+		let t = self.0.encrypt_in_place(packet, header, payload).expect("payload within AEAD limits");
+		tag.copy_from_slice(t.as_ref());
+	}
+
+	fn decrypt(&self, packet: u64, header: &[u8], payload: &mut BytesMut) -> Result<(), CryptoError> {
+		let len = self.0.decrypt_in_place(packet, header, payload.as_mut()).map_err(|_| CryptoError)?.len();
+		payload.truncate(len);
+		Ok(())
+	}
+
+	fn tag_len(&self) -> usize { self.0.tag_len() }
+	fn confidentiality_limit(&self) -> u64 { self.0.confidentiality_limit() }
+	fn integrity_limit(&self) -> u64 { self.0.integrity_limit() }
+}
+
+/// HMAC-SHA256 key for stateless reset tokens.
+pub(crate) struct ResetKey(Hmac<Sha256>);
+
+impl ResetKey {
+	pub(crate) fn random() -> Self {
+		let mut key = [0u8; 64];
+		rand::rngs::OsRng.fill_bytes(&mut key);
+		Self(<Hmac<Sha256> as Mac>::new_from_slice(&key).expect("HMAC accepts any key length"))
+	}
+}
+
+impl crypto::HmacKey for ResetKey {
+	fn sign(&self, data: &[u8], out: &mut [u8]) { out.copy_from_slice(&self.0.clone().chain_update(data).finalize().into_bytes()) }
+	fn signature_len(&self) -> usize { 32 }
+	fn verify(&self, data: &[u8], signature: &[u8]) -> Result<(), CryptoError> { self.0.clone().chain_update(data).verify_slice(signature).map_err(|_| CryptoError) }
+}
+
+/// HKDF-SHA256 master key for Retry/NEW_TOKEN tokens; each token gets its
+/// own AES-256-GCM key, so the zero nonce is never reused.
+pub(crate) struct TokenKey(hkdf::Hkdf<Sha256>);
+
+impl TokenKey {
+	pub(crate) fn random() -> Self {
+		let mut secret = [0u8; 32];
+		rand::rngs::OsRng.fill_bytes(&mut secret);
+		Self(hkdf::Hkdf::new(None, &secret))
+	}
+}
+
+impl crypto::HandshakeTokenKey for TokenKey {
+	fn aead_from_hkdf(&self, random_bytes: &[u8]) -> Box<dyn crypto::AeadKey> {
+		let mut key = [0u8; 32];
+		self.0.expand(random_bytes, &mut key).expect("32 bytes is a valid HKDF-SHA256 length");
+		Box::new(TokenAead(Aes256Gcm::new(&key.into())))
+	}
+}
+
+struct TokenAead(Aes256Gcm);
+
+impl crypto::AeadKey for TokenAead {
+	fn seal(&self, data: &mut Vec<u8>, aad: &[u8]) -> Result<(), CryptoError> { self.0.encrypt_in_place(&[0u8; 12].into(), aad, data).map_err(|_| CryptoError) }
+
+	fn open<'a>(&self, data: &'a mut [u8], aad: &[u8]) -> Result<&'a mut [u8], CryptoError> {
+		let plain_len = data.len().checked_sub(16).ok_or(CryptoError)?;
+		let (plain, tag) = data.split_at_mut(plain_len);
+		self.0.decrypt_in_place_detached(&[0u8; 12].into(), aad, plain, aes_gcm::Tag::from_slice(tag)).map_err(|_| CryptoError)?;
+		Ok(plain)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// RFC 9001 Appendix A.4.
+	#[test]
+	fn retry_tag_matches_rfc9001_a4() {
+		let header = [0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50, 0x2a, 0x42, 0x62, 0xb5, 0x74, 0x6f, 0x6b, 0x65, 0x6e];
+		let odcid = ConnectionId::new(&[0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08]);
+		assert_eq!(retry_tag(&odcid, &header), [0x04, 0xa2, 0x65, 0xba, 0x2e, 0xff, 0x4d, 0x82, 0x90, 0x58, 0xfb, 0x3f, 0x0f, 0x24, 0x96, 0xba]);
+	}
+}
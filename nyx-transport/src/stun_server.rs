@@ -1 +1,380 @@
-﻿
\ No newline at end of file
+﻿//! STUN (RFC 5389) Binding messages and a minimal in-process server.
+//!
+//! The codec covers what ICE needs: XOR-MAPPED-ADDRESS, USERNAME,
+//! MESSAGE-INTEGRITY with short-term credentials, FINGERPRINT and the ICE
+//! attributes of RFC 8445. The server only answers Binding requests with
+//! the source address it observed, which is all server-reflexive
+//! candidate gathering requires.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+pub const MAGIC_COOKIE: u32 = 0x2112_a442;
+pub const BINDING: u16 = 0x0001;
+pub const HEADER_LEN: usize = 20;
+
+pub type TransactionId = [u8; 12];
+
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_USERNAME: u16 = 0x0006;
+const ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+const ATTR_ERROR_CODE: u16 = 0x0009;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const ATTR_PRIORITY: u16 = 0x0024;
+const ATTR_USE_CANDIDATE: u16 = 0x0025;
+const ATTR_FINGERPRINT: u16 = 0x8028;
+const ATTR_ICE_CONTROLLED: u16 = 0x8029;
+const ATTR_ICE_CONTROLLING: u16 = 0x802a;
+const FINGERPRINT_XOR: u32 = 0x5354_554e;
+const INTEGRITY_LEN: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+	Request,
+	Indication,
+	Success,
+	Error,
+}
+
+impl Class {
+	fn bits(self) -> u16 {
+		match self {
+			Self::Request => 0x0000,
+			Self::Indication => 0x0010,
+			Self::Success => 0x0100,
+			Self::Error => 0x0110,
+		}
+	}
+
+	fn from_type(ty: u16) -> Self {
+		match ty & 0x0110 {
+			0x0000 => Self::Request,
+			0x0010 => Self::Indication,
+			0x0100 => Self::Success,
+			_ => Self::Error,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Attribute {
+	MappedAddress(SocketAddr),
+	XorMappedAddress(SocketAddr),
+	Username(String),
+	ErrorCode(u16, String),
+	Priority(u32),
+	UseCandidate,
+	IceControlled(u64),
+	IceControlling(u64),
+	Unknown(u16, Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StunMessage {
+	pub class: Class,
+	pub method: u16,
+	pub txid: TransactionId,
+	pub attrs: Vec<Attribute>,
+	/// Offset of MESSAGE-INTEGRITY in the decoded datagram.
+	integrity_at: Option<usize>,
+}
+
+impl StunMessage {
+	pub fn new(class: Class, method: u16, txid: TransactionId) -> Self { Self { class, method, txid, attrs: Vec::new(), integrity_at: None } }
+
+	pub fn binding_request() -> Self { Self::new(Class::Request, BINDING, rand::random()) }
+
+	pub fn with(mut self, attr: Attribute) -> Self {
+		self.attrs.push(attr);
+		self
+	}
+
+	/// Cheap check that a datagram is STUN rather than a Nyx cell.
+	pub fn is_stun(buf: &[u8]) -> bool { buf.len() >= HEADER_LEN && buf[0] & 0xc0 == 0 && buf[4..8] == MAGIC_COOKIE.to_be_bytes() }
+
+	/// Encode, appending MESSAGE-INTEGRITY when `key` is given and always a FINGERPRINT.
+	pub fn encode(&self, key: Option<&[u8]>) -> Vec<u8> {
+		let mut buf = Vec::with_capacity(128);
+		let ty = (self.method & 0x000f) | ((self.method & 0x0070) << 1) | ((self.method & 0x0f80) << 2) | self.class.bits();
+		buf.extend_from_slice(&ty.to_be_bytes());
+		buf.extend_from_slice(&[0, 0]);
+		buf.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+		buf.extend_from_slice(&self.txid);
+		for attr in &self.attrs {
+			self.encode_attr(attr, &mut buf);
+		}
+		if let Some(key) = key {
+			set_len(&mut buf, 4 + INTEGRITY_LEN);
+			let mac = hmac_sha1(key, &buf);
+			put_attr(&mut buf, ATTR_MESSAGE_INTEGRITY, &mac);
+		}
+		set_len(&mut buf, 8);
+		let crc = crc32(&buf) ^ FINGERPRINT_XOR;
+		put_attr(&mut buf, ATTR_FINGERPRINT, &crc.to_be_bytes());
+		buf
+	}
+
+	fn encode_attr(&self, attr: &Attribute, buf: &mut Vec<u8>) {
+		match attr {
+			Attribute::MappedAddress(a) => put_attr(buf, ATTR_MAPPED_ADDRESS, &encode_addr(*a)),
+			Attribute::XorMappedAddress(a) => put_attr(buf, ATTR_XOR_MAPPED_ADDRESS, &encode_addr(xor_addr(*a, &self.txid))),
+			Attribute::Username(u) => put_attr(buf, ATTR_USERNAME, u.as_bytes()),
+			Attribute::ErrorCode(code, reason) => {
+				let mut v = vec![0, 0, (code / 100) as u8, (code % 100) as u8];
+				v.extend_from_slice(reason.as_bytes());
+				put_attr(buf, ATTR_ERROR_CODE, &v);
+			}
+			Attribute::Priority(p) => put_attr(buf, ATTR_PRIORITY, &p.to_be_bytes()),
+			Attribute::UseCandidate => put_attr(buf, ATTR_USE_CANDIDATE, &[]),
+			Attribute::IceControlled(t) => put_attr(buf, ATTR_ICE_CONTROLLED, &t.to_be_bytes()),
+			Attribute::IceControlling(t) => put_attr(buf, ATTR_ICE_CONTROLLING, &t.to_be_bytes()),
+			Attribute::Unknown(ty, v) => put_attr(buf, *ty, v),
+		}
+	}
+
+	/// Decode and validate the FINGERPRINT if present.
+	pub fn decode(buf: &[u8]) -> io::Result<Self> {
+		if !Self::is_stun(buf) {
+			return Err(invalid("not a STUN message"));
+		}
+		let ty = u16::from_be_bytes([buf[0], buf[1]]);
+		let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+		if !len.is_multiple_of(4) || buf.len() < HEADER_LEN + len {
+			return Err(invalid("bad STUN length"));
+		}
+		let buf = &buf[..HEADER_LEN + len];
+		let method = (ty & 0x000f) | ((ty >> 1) & 0x0070) | ((ty >> 2) & 0x0f80);
+		let txid: TransactionId = buf[8..20].try_into().expect("12-byte transaction id");
+		let mut msg = Self::new(Class::from_type(ty), method, txid);
+		let mut off = HEADER_LEN;
+		while off + 4 <= buf.len() {
+			let aty = u16::from_be_bytes([buf[off], buf[off + 1]]);
+			let alen = u16::from_be_bytes([buf[off + 2], buf[off + 3]]) as usize;
+			let v = buf.get(off + 4..off + 4 + alen).ok_or_else(|| invalid("truncated STUN attribute"))?;
+			match aty {
+				ATTR_MESSAGE_INTEGRITY => msg.integrity_at = Some(off),
+				ATTR_FINGERPRINT => {
+					let want = crc32(&buf[..off]) ^ FINGERPRINT_XOR;
+					if v != want.to_be_bytes() {
+						return Err(invalid("STUN fingerprint mismatch"));
+					}
+				}
+				// Attributes after MESSAGE-INTEGRITY (other than FINGERPRINT) are ignored.
+				_ if msg.integrity_at.is_some() => {}
+				_ => msg.attrs.push(decode_attr(aty, v, &txid)?),
+			}
+			off += 4 + alen.next_multiple_of(4);
+		}
+		Ok(msg)
+	}
+
+	/// Verify MESSAGE-INTEGRITY of the datagram this message was decoded from.
+	pub fn verify(&self, raw: &[u8], key: &[u8]) -> bool {
+		let Some(at) = self.integrity_at else { return false };
+		let Some(mac) = raw.get(at + 4..at + 4 + INTEGRITY_LEN) else { return false };
+		let mut covered = raw[..at].to_vec();
+		set_len(&mut covered, 4 + INTEGRITY_LEN);
+		let mut h = <Hmac<Sha1> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+		h.update(&covered);
+		h.verify_slice(mac).is_ok()
+	}
+
+	pub fn xor_mapped_address(&self) -> Option<SocketAddr> {
+		self.attrs.iter().find_map(|a| match a {
+			Attribute::XorMappedAddress(addr) | Attribute::MappedAddress(addr) => Some(*addr),
+			_ => None,
+		})
+	}
+
+	pub fn username(&self) -> Option<&str> {
+		self.attrs.iter().find_map(|a| match a {
+			Attribute::Username(u) => Some(u.as_str()),
+			_ => None,
+		})
+	}
+
+	pub fn priority(&self) -> Option<u32> {
+		self.attrs.iter().find_map(|a| match a {
+			Attribute::Priority(p) => Some(*p),
+			_ => None,
+		})
+	}
+
+	pub fn use_candidate(&self) -> bool { self.attrs.contains(&Attribute::UseCandidate) }
+
+	pub fn error_code(&self) -> Option<u16> {
+		self.attrs.iter().find_map(|a| match a {
+			Attribute::ErrorCode(code, _) => Some(*code),
+			_ => None,
+		})
+	}
+}
+
+fn invalid(msg: &str) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, msg.to_string()) }
+
+fn set_len(buf: &mut [u8], extra: usize) {
+	let len = (buf.len() - HEADER_LEN + extra) as u16;
+	buf[2..4].copy_from_slice(&len.to_be_bytes());
+}
+
+fn put_attr(buf: &mut Vec<u8>, ty: u16, v: &[u8]) {
+	buf.extend_from_slice(&ty.to_be_bytes());
+	buf.extend_from_slice(&(v.len() as u16).to_be_bytes());
+	buf.extend_from_slice(v);
+	buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+fn hmac_sha1(key: &[u8], data: &[u8]) -> [u8; INTEGRITY_LEN] {
+	let mut h = <Hmac<Sha1> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+	h.update(data);
+	h.finalize().into_bytes().into()
+}
+
+fn encode_addr(addr: SocketAddr) -> Vec<u8> {
+	let mut v = vec![0, if addr.is_ipv4() { 1 } else { 2 }];
+	v.extend_from_slice(&addr.port().to_be_bytes());
+	match addr.ip() {
+		IpAddr::V4(ip) => v.extend_from_slice(&ip.octets()),
+		IpAddr::V6(ip) => v.extend_from_slice(&ip.octets()),
+	}
+	v
+}
+
+fn decode_addr(v: &[u8]) -> io::Result<SocketAddr> {
+	let port = u16::from_be_bytes([*v.get(2).ok_or_else(|| invalid("short address"))?, *v.get(3).ok_or_else(|| invalid("short address"))?]);
+	match (v[1], v.len()) {
+		(1, 8) => Ok(SocketAddr::new(Ipv4Addr::from(<[u8; 4]>::try_from(&v[4..8]).expect("4 bytes")).into(), port)),
+		(2, 20) => Ok(SocketAddr::new(Ipv6Addr::from(<[u8; 16]>::try_from(&v[4..20]).expect("16 bytes")).into(), port)),
+		_ => Err(invalid("bad address family")),
+	}
+}
+
+/// XOR-MAPPED-ADDRESS obfuscation; applying it twice is the identity.
+fn xor_addr(addr: SocketAddr, txid: &TransactionId) -> SocketAddr {
+	let port = addr.port() ^ (MAGIC_COOKIE >> 16) as u16;
+	let ip = match addr.ip() {
+		IpAddr::V4(ip) => IpAddr::V4((u32::from(ip) ^ MAGIC_COOKIE).into()),
+		IpAddr::V6(ip) => {
+			let mut mask = [0u8; 16];
+			mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+			mask[4..].copy_from_slice(txid);
+			let mut o = ip.octets();
+			o.iter_mut().zip(mask).for_each(|(b, m)| *b ^= m);
+			IpAddr::V6(o.into())
+		}
+	};
+	SocketAddr::new(ip, port)
+}
+
+fn decode_attr(ty: u16, v: &[u8], txid: &TransactionId) -> io::Result<Attribute> {
+	let u32_at = |v: &[u8]| v.try_into().map(u32::from_be_bytes).map_err(|_| invalid("bad u32 attribute"));
+	let u64_at = |v: &[u8]| v.try_into().map(u64::from_be_bytes).map_err(|_| invalid("bad u64 attribute"));
+	Ok(match ty {
+		ATTR_MAPPED_ADDRESS => Attribute::MappedAddress(decode_addr(v)?),
+		ATTR_XOR_MAPPED_ADDRESS => Attribute::XorMappedAddress(xor_addr(decode_addr(v)?, txid)),
+		ATTR_USERNAME => Attribute::Username(String::from_utf8(v.to_vec()).map_err(|_| invalid("username is not UTF-8"))?),
+		ATTR_ERROR_CODE if v.len() >= 4 => Attribute::ErrorCode(u16::from(v[2] & 0x07) * 100 + u16::from(v[3]), String::from_utf8_lossy(&v[4..]).into_owned()),
+		ATTR_PRIORITY => Attribute::Priority(u32_at(v)?),
+		ATTR_USE_CANDIDATE => Attribute::UseCandidate,
+		ATTR_ICE_CONTROLLED => Attribute::IceControlled(u64_at(v)?),
+		ATTR_ICE_CONTROLLING => Attribute::IceControlling(u64_at(v)?),
+		_ => Attribute::Unknown(ty, v.to_vec()),
+	})
+}
+
+/// CRC-32 (IEEE), as required by FINGERPRINT.
+fn crc32(data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for &b in data {
+		crc ^= u32::from(b);
+		for _ in 0..8 {
+			crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+		}
+	}
+	!crc
+}
+
+/// Answers Binding requests with the observed source address until dropped.
+#[derive(Debug)]
+pub struct StunServer {
+	local_addr: SocketAddr,
+	task: JoinHandle<()>,
+}
+
+impl StunServer {
+	pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+		let socket = UdpSocket::bind(addr).await?;
+		let local_addr = socket.local_addr()?;
+		Ok(Self { local_addr, task: tokio::spawn(serve(socket)) })
+	}
+
+	pub fn local_addr(&self) -> SocketAddr { self.local_addr }
+}
+
+impl Drop for StunServer {
+	fn drop(&mut self) { self.task.abort(); }
+}
+
+async fn serve(socket: UdpSocket) {
+	let mut buf = [0u8; 1500];
+	loop {
+		let (n, src) = match socket.recv_from(&mut buf).await {
+			Ok(r) => r,
+			Err(e) => {
+				debug!(error = %e, "stun server receive failed");
+				continue;
+			}
+		};
+		match StunMessage::decode(&buf[..n]) {
+			Ok(req) if req.class == Class::Request && req.method == BINDING => {
+				let resp = StunMessage::new(Class::Success, BINDING, req.txid).with(Attribute::XorMappedAddress(src));
+				let _ = socket.send_to(&resp.encode(None), src).await;
+			}
+			Ok(_) => {}
+			Err(e) => debug!(%src, error = %e, "dropping malformed STUN message"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn binding_success_matches_rfc5769_layout() {
+		let msg = StunMessage::new(Class::Success, BINDING, [1; 12]).with(Attribute::XorMappedAddress("192.0.2.1:32853".parse().unwrap()));
+		let raw = msg.encode(None);
+		assert_eq!(&raw[..2], &[0x01, 0x01]);
+		// RFC 5769 §2.2: 192.0.2.1:32853 encodes as port 0xa147, address 0xe112a643.
+		assert_eq!(&raw[24..32], &[0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43]);
+		assert_eq!(StunMessage::decode(&raw).unwrap(), msg);
+	}
+
+	#[test]
+	fn integrity_and_fingerprint_roundtrip() {
+		let v6: SocketAddr = "[2001:db8::1]:4000".parse().unwrap();
+		let msg = StunMessage::binding_request()
+			.with(Attribute::Username("abcd:efgh".into()))
+			.with(Attribute::Priority(0x6e00_01ff))
+			.with(Attribute::UseCandidate)
+			.with(Attribute::IceControlling(42))
+			.with(Attribute::XorMappedAddress(v6));
+		let raw = msg.encode(Some(b"password"));
+		let decoded = StunMessage::decode(&raw).unwrap();
+		assert_eq!(decoded.attrs, msg.attrs);
+		assert!(decoded.verify(&raw, b"password"));
+		assert!(!decoded.verify(&raw, b"wrong"));
+		assert_eq!(decoded.xor_mapped_address(), Some(v6));
+
+		let mut bad = raw.clone();
+		bad[30] ^= 1;
+		assert!(StunMessage::decode(&bad).is_err());
+		assert!(!StunMessage::is_stun(&[0u8; 1280]));
+	}
+}
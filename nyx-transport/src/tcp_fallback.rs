@@ -1 +1,839 @@
-﻿
\ No newline at end of file
+﻿//! TCP fallback for networks that block UDP.
+//!
+//! Cells travel over TCP as `u16` length-prefixed frames on pooled
+//! connections. [`FallbackTransport`] listens for UDP and TCP on the same
+//! port and picks a path per peer happy-eyeballs style: a UDP probe goes out
+//! first and a TCP dial follows after [`FallbackConfig::tcp_delay`]; the first
+//! to succeed wins. Peers left on TCP are re-probed over UDP every
+//! [`FallbackConfig::upgrade_interval`] and moved back once UDP answers, with
+//! at most one re-probe task per peer.
+//!
+//! Accepted connections are keyed by their remote address as the kernel
+//! reports it, never by anything the dialer claims, so replies to
+//! [`Incoming::peer`] reuse the connection and one host cannot pose as
+//! another behind the same IP. Peer state is bounded by
+//! [`FallbackConfig::max_peers`]; idle entries are evicted first.
+//! Connections are bounded by [`FallbackConfig::max_connections`]: an
+//! evicted or idle connection has both of its tasks stopped and its socket
+//! closed, whichever side opened it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{AbortHandle, JoinHandle, JoinSet};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::udp::{RecvMeta, UdpConfig, UdpTransport};
+
+/// Largest frame carried over TCP: one Nyx cell.
+pub const MAX_FRAME: usize = 1280;
+
+const PROBE_LEN: usize = 12;
+const PROBE: &[u8; 4] = b"NYXp";
+const PROBE_ACK: &[u8; 4] = b"NYXa";
+const PROBE_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportKind {
+	Udp,
+	Tcp,
+}
+
+impl fmt::Display for TransportKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::Udp => "udp",
+			Self::Tcp => "tcp",
+		})
+	}
+}
+
+/// Write one length-prefixed frame.
+pub async fn write_frame<W: AsyncWrite + Unpin>(w: &mut W, payload: &[u8]) -> io::Result<()> {
+	if payload.is_empty() || payload.len() > MAX_FRAME {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame length out of range"));
+	}
+	let mut buf = Vec::with_capacity(2 + payload.len());
+	buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+	buf.extend_from_slice(payload);
+	w.write_all(&buf).await
+}
+
+/// Read one length-prefixed frame; `None` on a clean end of stream.
+pub async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> io::Result<Option<Vec<u8>>> {
+	let mut len = [0u8; 2];
+	match r.read_exact(&mut len).await {
+		Ok(_) => {}
+		Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+		Err(e) => return Err(e),
+	}
+	let len = u16::from_be_bytes(len) as usize;
+	if len == 0 || len > MAX_FRAME {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame length {len} out of range")));
+	}
+	let mut frame = vec![0u8; len];
+	r.read_exact(&mut frame).await?;
+	Ok(Some(frame))
+}
+
+#[derive(Debug, Clone)]
+pub struct FallbackConfig {
+	pub udp: UdpConfig,
+	/// Head start UDP gets before TCP is dialed (RFC 8305 connection attempt delay).
+	pub tcp_delay: Duration,
+	/// How long UDP probes are retried before UDP counts as blocked.
+	pub probe_timeout: Duration,
+	pub connect_timeout: Duration,
+	/// Period of UDP re-probes for peers on TCP.
+	pub upgrade_interval: Duration,
+	pub max_connections: usize,
+	/// Connections without traffic for this long are closed.
+	pub idle_timeout: Duration,
+	/// Depth of the receive and per-connection send queues.
+	pub queue: usize,
+	/// Peers whose path is remembered; idle ones are forgotten first.
+	pub max_peers: usize,
+}
+
+impl Default for FallbackConfig {
+	fn default() -> Self {
+		Self {
+			udp: UdpConfig::default(),
+			tcp_delay: Duration::from_millis(250),
+			probe_timeout: Duration::from_secs(1),
+			connect_timeout: Duration::from_secs(3),
+			upgrade_interval: Duration::from_secs(30),
+			max_connections: 64,
+			idle_timeout: Duration::from_secs(120),
+			queue: 256,
+			max_peers: 4096,
+		}
+	}
+}
+
+/// A cell received from `peer`: the peer's listening address for UDP and
+/// for connections we dialed, the connection's remote address for accepted
+/// ones. Either way, sending to `peer` reaches the sender.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Incoming {
+	pub peer: SocketAddr,
+	pub kind: TransportKind,
+	pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FallbackStats {
+	pub udp_peers: usize,
+	pub tcp_peers: usize,
+	pub tcp_connections: usize,
+	/// Peers moved to TCP.
+	pub fallbacks: u64,
+	/// Peers moved back to UDP.
+	pub upgrades: u64,
+}
+
+#[derive(Debug)]
+pub struct FallbackTransport {
+	inner: Arc<Inner>,
+	incoming: tokio::sync::Mutex<mpsc::Receiver<Incoming>>,
+	tasks: Vec<JoinHandle<()>>,
+}
+
+impl FallbackTransport {
+	/// Bind UDP and TCP on the same port. Must be called inside a Tokio runtime.
+	pub fn bind(cfg: FallbackConfig) -> io::Result<Self> {
+		let mut last = None;
+		for port in cfg.udp.ports.clone() {
+			let udp = match UdpTransport::bind(&UdpConfig { ports: port..=port, ..cfg.udp.clone() }) {
+				Ok(udp) => udp,
+				Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+					last = Some(e);
+					continue;
+				}
+				Err(e) => return Err(e),
+			};
+			let local = udp.local_addr()?;
+			match listen(local, cfg.udp.dual_stack) {
+				Ok(listener) => return Ok(Self::start(cfg, udp, listener)),
+				Err(e) if e.kind() == io::ErrorKind::AddrInUse => last = Some(e),
+				Err(e) => return Err(e),
+			}
+		}
+		Err(last.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty port range")))
+	}
+
+	fn start(cfg: FallbackConfig, udp: UdpTransport, listener: TcpListener) -> Self {
+		let (tx, incoming) = mpsc::channel(cfg.queue);
+		let pool = TcpPool::new(&cfg, tx.clone());
+		let inner = Arc::new(Inner {
+			cfg,
+			udp,
+			pool,
+			peers: Mutex::default(),
+			probes: Mutex::default(),
+			incoming: tx,
+			fallbacks: AtomicU64::new(0),
+			upgrades: AtomicU64::new(0),
+			#[cfg(test)]
+			drop_udp: std::sync::atomic::AtomicBool::new(false),
+		});
+		let tasks = vec![tokio::spawn(udp_loop(inner.clone())), tokio::spawn(accept_loop(inner.clone(), listener))];
+		Self { inner, incoming: tokio::sync::Mutex::new(incoming), tasks }
+	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> { self.inner.udp.local_addr() }
+
+	pub fn udp(&self) -> &UdpTransport { &self.inner.udp }
+
+	/// Path currently used for `peer`, if one was chosen.
+	pub fn kind(&self, peer: SocketAddr) -> Option<TransportKind> { self.inner.peers.lock().unwrap_or_else(|e| e.into_inner()).get(&canonical(peer)).and_then(|s| s.get()) }
+
+	pub fn stats(&self) -> FallbackStats {
+		let kinds: Vec<Option<TransportKind>> = self.inner.peers.lock().unwrap_or_else(|e| e.into_inner()).values().map(|s| s.get()).collect();
+		FallbackStats {
+			udp_peers: kinds.iter().filter(|k| **k == Some(TransportKind::Udp)).count(),
+			tcp_peers: kinds.iter().filter(|k| **k == Some(TransportKind::Tcp)).count(),
+			tcp_connections: self.inner.pool.len(),
+			fallbacks: self.inner.fallbacks.load(Ordering::Relaxed),
+			upgrades: self.inner.upgrades.load(Ordering::Relaxed),
+		}
+	}
+
+	/// Send one cell, choosing a path first if `peer` has none. Returns the
+	/// path used.
+	pub async fn send(&self, peer: SocketAddr, cell: &[u8]) -> io::Result<TransportKind> {
+		if cell.is_empty() || cell.len() > MAX_FRAME {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "cell length out of range"));
+		}
+		let peer = canonical(peer);
+		let kind = self.inner.path(peer).await?;
+		let res = match kind {
+			TransportKind::Udp => self.inner.udp.send_to(cell, peer).await.map(drop),
+			TransportKind::Tcp => self.inner.pool.send(peer, cell).await,
+		};
+		if res.is_err() {
+			self.inner.slot(peer).set(None);
+		}
+		res.map(|()| kind)
+	}
+
+	/// Report that UDP to `peer` stopped working (e.g. acknowledgements
+	/// ceased); moves the peer to TCP if it can be reached there.
+	pub async fn udp_failed(&self, peer: SocketAddr) -> io::Result<TransportKind> {
+		let peer = canonical(peer);
+		let slot = self.inner.slot(peer);
+		let _race = slot.connecting.lock().await;
+		if slot.get() == Some(TransportKind::Tcp) {
+			return Ok(TransportKind::Tcp);
+		}
+		match self.inner.pool.connect(peer).await {
+			Ok(()) => {
+				self.inner.fell_back(peer, &slot);
+				Ok(TransportKind::Tcp)
+			}
+			Err(e) => {
+				slot.set(None);
+				Err(e)
+			}
+		}
+	}
+
+	/// Next cell from any peer over either path.
+	pub async fn recv(&self) -> Option<Incoming> { self.incoming.lock().await.recv().await }
+}
+
+impl Drop for FallbackTransport {
+	fn drop(&mut self) {
+		for t in &self.tasks {
+			t.abort();
+		}
+	}
+}
+
+#[derive(Debug)]
+struct PeerSlot {
+	kind: Mutex<Option<TransportKind>>,
+	/// Serialises path selection for one peer.
+	connecting: tokio::sync::Mutex<()>,
+	/// An upgrade task is re-probing this peer.
+	upgrading: AtomicBool,
+	last_used: Mutex<Instant>,
+}
+
+impl Default for PeerSlot {
+	fn default() -> Self { Self { kind: Mutex::default(), connecting: tokio::sync::Mutex::default(), upgrading: AtomicBool::new(false), last_used: Mutex::new(Instant::now()) } }
+}
+
+impl PeerSlot {
+	fn get(&self) -> Option<TransportKind> { *self.kind.lock().unwrap_or_else(|e| e.into_inner()) }
+
+	fn set(&self, kind: Option<TransportKind>) { *self.kind.lock().unwrap_or_else(|e| e.into_inner()) = kind; }
+
+	fn idle_since(&self) -> Instant { *self.last_used.lock().unwrap_or_else(|e| e.into_inner()) }
+}
+
+#[derive(Debug)]
+struct Inner {
+	cfg: FallbackConfig,
+	udp: UdpTransport,
+	pool: TcpPool,
+	peers: Mutex<HashMap<SocketAddr, Arc<PeerSlot>>>,
+	probes: Mutex<HashMap<u64, oneshot::Sender<()>>>,
+	incoming: mpsc::Sender<Incoming>,
+	fallbacks: AtomicU64,
+	upgrades: AtomicU64,
+	/// Simulates a firewall dropping all inbound UDP.
+	#[cfg(test)]
+	drop_udp: std::sync::atomic::AtomicBool,
+}
+
+impl Inner {
+	fn slot(&self, peer: SocketAddr) -> Arc<PeerSlot> {
+		let mut peers = self.peers.lock().unwrap_or_else(|e| e.into_inner());
+		let now = Instant::now();
+		if peers.len() >= self.cfg.max_peers.max(1) && !peers.contains_key(&peer) {
+			// Forget peers that went quiet, then the least recently used.
+			peers.retain(|_, s| now.duration_since(s.idle_since()) < self.cfg.idle_timeout);
+			if peers.len() >= self.cfg.max_peers.max(1) {
+				if let Some(lru) = peers.iter().min_by_key(|(_, s)| s.idle_since()).map(|(a, _)| *a) {
+					peers.remove(&lru);
+				}
+			}
+		}
+		let slot = peers.entry(peer).or_default().clone();
+		*slot.last_used.lock().unwrap_or_else(|e| e.into_inner()) = now;
+		slot
+	}
+
+	/// Whether `slot` is still the one remembered for `peer`.
+	fn is_current(&self, peer: SocketAddr, slot: &Arc<PeerSlot>) -> bool {
+		self.peers.lock().unwrap_or_else(|e| e.into_inner()).get(&peer).is_some_and(|s| Arc::ptr_eq(s, slot))
+	}
+
+	async fn path(self: &Arc<Self>, peer: SocketAddr) -> io::Result<TransportKind> {
+		let slot = self.slot(peer);
+		if let Some(kind) = slot.get() {
+			return Ok(kind);
+		}
+		let _race = slot.connecting.lock().await;
+		if let Some(kind) = slot.get() {
+			return Ok(kind);
+		}
+		match self.race(peer).await? {
+			TransportKind::Udp => {
+				slot.set(Some(TransportKind::Udp));
+				Ok(TransportKind::Udp)
+			}
+			TransportKind::Tcp => {
+				self.fell_back(peer, &slot);
+				Ok(TransportKind::Tcp)
+			}
+		}
+	}
+
+	fn fell_back(self: &Arc<Self>, peer: SocketAddr, slot: &Arc<PeerSlot>) {
+		slot.set(Some(TransportKind::Tcp));
+		self.fallbacks.fetch_add(1, Ordering::Relaxed);
+		warn!(%peer, "UDP unavailable, using TCP fallback");
+		if !slot.upgrading.swap(true, Ordering::AcqRel) {
+			tokio::spawn(upgrade_loop(Arc::downgrade(self), peer, slot.clone(), self.cfg.upgrade_interval));
+		}
+	}
+
+	/// Happy-eyeballs race between a UDP probe and a delayed TCP dial.
+	async fn race(&self, peer: SocketAddr) -> io::Result<TransportKind> {
+		let udp = self.probe_udp(peer);
+		let tcp = async {
+			tokio::time::sleep(self.cfg.tcp_delay).await;
+			self.pool.connect(peer).await
+		};
+		tokio::pin!(udp, tcp);
+		let (mut udp_failed, mut tcp_err) = (false, None);
+		loop {
+			tokio::select! {
+				ok = &mut udp, if !udp_failed => {
+					if ok {
+						return Ok(TransportKind::Udp);
+					}
+					udp_failed = true;
+					if let Some(e) = tcp_err.take() {
+						return Err(e);
+					}
+				}
+				res = &mut tcp, if tcp_err.is_none() => match res {
+					Ok(()) => return Ok(TransportKind::Tcp),
+					Err(e) if udp_failed => return Err(e),
+					Err(e) => tcp_err = Some(e),
+				},
+			}
+		}
+	}
+
+	async fn probe_udp(&self, peer: SocketAddr) -> bool {
+		let nonce: u64 = rand::random();
+		let (tx, rx) = oneshot::channel();
+		self.probes.lock().unwrap_or_else(|e| e.into_inner()).insert(nonce, tx);
+		let mut probe = [0u8; PROBE_LEN];
+		probe[..4].copy_from_slice(PROBE);
+		probe[4..].copy_from_slice(&nonce.to_be_bytes());
+		let retries = async {
+			for _ in 0..PROBE_ATTEMPTS {
+				if let Err(e) = self.udp.send_to(&probe, peer).await {
+					debug!(%peer, error = %e, "UDP probe not sent");
+				}
+				tokio::time::sleep(self.cfg.probe_timeout / PROBE_ATTEMPTS).await;
+			}
+		};
+		let ok = tokio::select! {
+			r = rx => r.is_ok(),
+			_ = retries => false,
+		};
+		self.probes.lock().unwrap_or_else(|e| e.into_inner()).remove(&nonce);
+		ok
+	}
+
+	async fn on_datagram(&self, src: SocketAddr, d: &[u8]) -> bool {
+		if d.len() == PROBE_LEN && &d[..4] == PROBE {
+			let mut ack = [0u8; PROBE_LEN];
+			ack[..4].copy_from_slice(PROBE_ACK);
+			ack[4..].copy_from_slice(&d[4..]);
+			let _ = self.udp.send_to(&ack, src).await;
+			return true;
+		}
+		if d.len() == PROBE_LEN && &d[..4] == PROBE_ACK {
+			let nonce = u64::from_be_bytes(d[4..].try_into().expect("8-byte nonce"));
+			if let Some(tx) = self.probes.lock().unwrap_or_else(|e| e.into_inner()).remove(&nonce) {
+				let _ = tx.send(());
+			}
+			return true;
+		}
+		if d.is_empty() || d.len() > MAX_FRAME {
+			return true;
+		}
+		self.incoming.send(Incoming { peer: src, kind: TransportKind::Udp, data: d.to_vec() }).await.is_ok()
+	}
+}
+
+/// Re-probe UDP for a peer on TCP until it answers, the peer leaves TCP or
+/// its slot is evicted.
+async fn upgrade_loop(inner: Weak<Inner>, peer: SocketAddr, slot: Arc<PeerSlot>, interval: Duration) {
+	struct Done<'a>(&'a AtomicBool);
+	impl Drop for Done<'_> {
+		fn drop(&mut self) { self.0.store(false, Ordering::Release); }
+	}
+	let _done = Done(&slot.upgrading);
+	loop {
+		tokio::time::sleep(interval).await;
+		let Some(inner) = inner.upgrade() else { return };
+		if slot.get() != Some(TransportKind::Tcp) || !inner.is_current(peer, &slot) {
+			return;
+		}
+		if inner.probe_udp(peer).await {
+			slot.set(Some(TransportKind::Udp));
+			inner.upgrades.fetch_add(1, Ordering::Relaxed);
+			info!(%peer, "UDP reachable again, leaving TCP fallback");
+			return;
+		}
+	}
+}
+
+async fn udp_loop(inner: Arc<Inner>) {
+	let mut storage = vec![vec![0u8; 65_536]; 8];
+	let mut meta = [RecvMeta::default(); 8];
+	loop {
+		let mut bufs: Vec<&mut [u8]> = storage.iter_mut().map(|b| b.as_mut_slice()).collect();
+		let n = match inner.udp.recv_batch(&mut bufs, &mut meta).await {
+			Ok(n) => n,
+			Err(e) => {
+				debug!(error = %e, "udp receive failed");
+				continue;
+			}
+		};
+		#[cfg(test)]
+		if inner.drop_udp.load(Ordering::Relaxed) {
+			continue;
+		}
+		for (buf, m) in bufs.iter().zip(&meta[..n]) {
+			for d in m.datagrams(buf) {
+				if !inner.on_datagram(m.src, d).await {
+					return;
+				}
+			}
+		}
+	}
+}
+
+async fn accept_loop(inner: Arc<Inner>, listener: TcpListener) {
+	loop {
+		match listener.accept().await {
+			Ok((stream, addr)) => {
+				if let Err(e) = accepted(&inner, stream, canonical(addr)) {
+					debug!(%addr, error = %e, "TCP fallback connection dropped");
+				}
+			}
+			Err(e) => {
+				warn!(error = %e, "TCP fallback accept failed");
+				tokio::time::sleep(Duration::from_millis(100)).await;
+			}
+		}
+	}
+}
+
+/// Attach an accepted connection under its remote address. Nothing listens
+/// on UDP there, so the peer stays on TCP and is never re-probed.
+fn accepted(inner: &Inner, stream: TcpStream, addr: SocketAddr) -> io::Result<()> {
+	stream.set_nodelay(true)?;
+	inner.pool.attach(addr, stream);
+	inner.slot(addr).set(Some(TransportKind::Tcp));
+	Ok(())
+}
+
+#[derive(Debug)]
+struct Link {
+	id: u64,
+	tx: mpsc::Sender<Vec<u8>>,
+	/// Writer and reader, stopped with the link so its socket closes even
+	/// while the peer keeps it open or stops reading.
+	tasks: Vec<AbortHandle>,
+	last_used: Instant,
+}
+
+impl Drop for Link {
+	fn drop(&mut self) {
+		self.tasks.iter().for_each(AbortHandle::abort);
+	}
+}
+
+type Links = Arc<Mutex<HashMap<SocketAddr, Link>>>;
+
+/// One TCP connection per peer, shared by both directions.
+#[derive(Debug)]
+struct TcpPool {
+	connect_timeout: Duration,
+	max_connections: usize,
+	idle_timeout: Duration,
+	queue: usize,
+	links: Links,
+	next_id: AtomicU64,
+	incoming: mpsc::Sender<Incoming>,
+	tasks: Mutex<JoinSet<()>>,
+}
+
+impl TcpPool {
+	fn new(cfg: &FallbackConfig, incoming: mpsc::Sender<Incoming>) -> Self {
+		Self {
+			connect_timeout: cfg.connect_timeout,
+			max_connections: cfg.max_connections.max(1),
+			idle_timeout: cfg.idle_timeout,
+			queue: cfg.queue.max(1),
+			links: Links::default(),
+			next_id: AtomicU64::new(0),
+			incoming,
+			tasks: Mutex::default(),
+		}
+	}
+
+	fn len(&self) -> usize { self.links.lock().unwrap_or_else(|e| e.into_inner()).len() }
+
+	fn spawn(&self, fut: impl std::future::Future<Output = ()> + Send + 'static) -> AbortHandle {
+		let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+		while tasks.try_join_next().is_some() {}
+		tasks.spawn(fut)
+	}
+
+	fn sender(&self, peer: SocketAddr) -> Option<mpsc::Sender<Vec<u8>>> {
+		let mut links = self.links.lock().unwrap_or_else(|e| e.into_inner());
+		let link = links.get_mut(&peer)?;
+		if link.tx.is_closed() {
+			links.remove(&peer);
+			return None;
+		}
+		link.last_used = Instant::now();
+		Some(link.tx.clone())
+	}
+
+	async fn connect(&self, peer: SocketAddr) -> io::Result<()> {
+		if self.sender(peer).is_some() {
+			return Ok(());
+		}
+		let stream = tokio::time::timeout(self.connect_timeout, TcpStream::connect(peer)).await.map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+		stream.set_nodelay(true)?;
+		self.attach(peer, stream);
+		Ok(())
+	}
+
+	async fn send(&self, peer: SocketAddr, cell: &[u8]) -> io::Result<()> {
+		for _ in 0..2 {
+			let tx = match self.sender(peer) {
+				Some(tx) => tx,
+				None => {
+					self.connect(peer).await?;
+					self.sender(peer).ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?
+				}
+			};
+			if tx.send(cell.to_vec()).await.is_ok() {
+				return Ok(());
+			}
+			self.links.lock().unwrap_or_else(|e| e.into_inner()).remove(&peer);
+		}
+		Err(io::ErrorKind::BrokenPipe.into())
+	}
+
+	fn attach(&self, peer: SocketAddr, stream: TcpStream) {
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		let (tx, mut rx) = mpsc::channel::<Vec<u8>>(self.queue);
+		{
+			let mut links = self.links.lock().unwrap_or_else(|e| e.into_inner());
+			let now = Instant::now();
+			links.retain(|_, l| now.duration_since(l.last_used) < self.idle_timeout && !l.tx.is_closed());
+			if links.len() >= self.max_connections && !links.contains_key(&peer) {
+				if let Some(lru) = links.iter().min_by_key(|(_, l)| l.last_used).map(|(a, _)| *a) {
+					debug!(peer = %lru, "closing least recently used TCP fallback connection");
+					links.remove(&lru);
+				}
+			}
+			links.insert(peer, Link { id, tx, tasks: Vec::new(), last_used: now });
+		}
+		let (mut rd, mut wr) = stream.into_split();
+		let writer = self.spawn(async move {
+			while let Some(cell) = rx.recv().await {
+				if write_frame(&mut wr, &cell).await.is_err() {
+					break;
+				}
+			}
+		});
+		let (links, incoming, idle_timeout) = (self.links.clone(), self.incoming.clone(), self.idle_timeout);
+		let reader = self.spawn(async move {
+			'read: loop {
+				let mut read = std::pin::pin!(read_frame(&mut rd));
+				// Traffic either way keeps the connection; a frame in progress
+				// when it goes idle is dropped with it.
+				let frame = loop {
+					let last_used = links.lock().unwrap_or_else(|e| e.into_inner()).get(&peer).filter(|l| l.id == id).map(|l| l.last_used);
+					let Some(deadline) = last_used.map(|t| t + idle_timeout).filter(|d| *d > Instant::now()) else {
+						debug!(%peer, "closing idle TCP fallback connection");
+						break 'read;
+					};
+					if let Ok(frame) = tokio::time::timeout_at(deadline, &mut read).await {
+						break frame;
+					}
+				};
+				match frame {
+					Ok(Some(data)) => {
+						if let Some(link) = links.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&peer) {
+							link.last_used = Instant::now();
+						}
+						if incoming.send(Incoming { peer, kind: TransportKind::Tcp, data }).await.is_err() {
+							break;
+						}
+					}
+					Ok(None) => break,
+					Err(e) => {
+						debug!(%peer, error = %e, "TCP fallback read failed");
+						break;
+					}
+				}
+			}
+			let mut links = links.lock().unwrap_or_else(|e| e.into_inner());
+			if links.get(&peer).is_some_and(|l| l.id == id) {
+				links.remove(&peer);
+			}
+		});
+		match self.links.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&peer) {
+			Some(link) if link.id == id => link.tasks = vec![writer, reader],
+			// Evicted before its tasks were registered.
+			_ => [writer, reader].iter().for_each(AbortHandle::abort),
+		}
+	}
+}
+
+fn listen(addr: SocketAddr, dual_stack: bool) -> io::Result<TcpListener> {
+	let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+	if addr.is_ipv6() {
+		socket.set_only_v6(!dual_stack)?;
+	}
+	socket.set_reuse_address(true)?;
+	socket.bind(&addr.into())?;
+	socket.listen(1024)?;
+	socket.set_nonblocking(true)?;
+	TcpListener::from_std(socket.into())
+}
+
+fn canonical(addr: SocketAddr) -> SocketAddr { SocketAddr::new(addr.ip().to_canonical(), addr.port()) }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::{IpAddr, Ipv4Addr};
+
+	fn config() -> FallbackConfig {
+		FallbackConfig {
+			udp: UdpConfig { bind: IpAddr::V4(Ipv4Addr::LOCALHOST), ports: 0..=0, ..Default::default() },
+			tcp_delay: Duration::from_millis(50),
+			probe_timeout: Duration::from_millis(300),
+			upgrade_interval: Duration::from_millis(100),
+			..Default::default()
+		}
+	}
+
+	async fn recv(t: &FallbackTransport) -> Incoming { tokio::time::timeout(Duration::from_secs(5), t.recv()).await.expect("recv timed out").unwrap() }
+
+	#[tokio::test]
+	async fn frames_roundtrip_and_reject_bad_lengths() {
+		let (mut a, mut b) = tokio::io::duplex(8192);
+		write_frame(&mut a, &[7u8; MAX_FRAME]).await.unwrap();
+		write_frame(&mut a, b"x").await.unwrap();
+		assert!(write_frame(&mut a, &[0u8; MAX_FRAME + 1]).await.is_err());
+		assert_eq!(read_frame(&mut b).await.unwrap().unwrap(), vec![7u8; MAX_FRAME]);
+		assert_eq!(read_frame(&mut b).await.unwrap().unwrap(), b"x");
+		a.write_all(&[0x05, 0x01]).await.unwrap();
+		assert_eq!(read_frame(&mut b).await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+		let (a, mut b) = tokio::io::duplex(64);
+		drop(a);
+		assert!(read_frame(&mut b).await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn udp_wins_when_reachable() {
+		let a = FallbackTransport::bind(config()).unwrap();
+		let b = FallbackTransport::bind(config()).unwrap();
+		let dst = b.local_addr().unwrap();
+		assert_eq!(a.send(dst, &[1u8; MAX_FRAME]).await.unwrap(), TransportKind::Udp);
+		let got = recv(&b).await;
+		assert_eq!((got.peer, got.kind, got.data.len()), (a.local_addr().unwrap(), TransportKind::Udp, MAX_FRAME));
+		assert_eq!(a.stats(), FallbackStats { udp_peers: 1, ..Default::default() });
+	}
+
+	#[tokio::test]
+	async fn falls_back_to_tcp_and_upgrades_when_udp_returns() {
+		let a = FallbackTransport::bind(config()).unwrap();
+		let b = FallbackTransport::bind(config()).unwrap();
+		b.inner.drop_udp.store(true, Ordering::Relaxed);
+		let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+
+		assert_eq!(a.send(b_addr, b"over tcp").await.unwrap(), TransportKind::Tcp);
+		let got = recv(&b).await;
+		assert_eq!((got.peer.ip(), got.kind, got.data.as_slice()), (a_addr.ip(), TransportKind::Tcp, &b"over tcp"[..]));
+		// The reply reuses the inbound connection.
+		assert_eq!(b.send(got.peer, b"reply").await.unwrap(), TransportKind::Tcp);
+		assert_eq!(recv(&a).await.data, b"reply");
+		assert_eq!(b.stats().tcp_connections, 1);
+		assert_eq!(a.stats().fallbacks, 1);
+
+		b.inner.drop_udp.store(false, Ordering::Relaxed);
+		let deadline = Instant::now() + Duration::from_secs(5);
+		while a.kind(b_addr) != Some(TransportKind::Udp) {
+			assert!(Instant::now() < deadline, "never upgraded");
+			tokio::time::sleep(Duration::from_millis(20)).await;
+		}
+		assert_eq!(a.send(b_addr, b"back on udp").await.unwrap(), TransportKind::Udp);
+		assert_eq!(recv(&b).await.kind, TransportKind::Udp);
+		assert_eq!(a.stats().upgrades, 1);
+	}
+
+	#[tokio::test]
+	async fn reported_udp_failure_moves_peer_to_tcp() {
+		let a = FallbackTransport::bind(config()).unwrap();
+		let b = FallbackTransport::bind(config()).unwrap();
+		let dst = b.local_addr().unwrap();
+		assert_eq!(a.send(dst, b"1").await.unwrap(), TransportKind::Udp);
+		recv(&b).await;
+		b.inner.drop_udp.store(true, Ordering::Relaxed);
+		assert_eq!(a.udp_failed(dst).await.unwrap(), TransportKind::Tcp);
+		assert_eq!(a.send(dst, b"2").await.unwrap(), TransportKind::Tcp);
+		assert_eq!(recv(&b).await.kind, TransportKind::Tcp);
+		assert_eq!(a.stats(), FallbackStats { tcp_peers: 1, tcp_connections: 1, fallbacks: 1, ..Default::default() });
+	}
+
+	#[tokio::test]
+	async fn repeated_fallbacks_share_one_upgrade_task() {
+		let a = FallbackTransport::bind(FallbackConfig { upgrade_interval: Duration::from_secs(60), ..config() }).unwrap();
+		let b = FallbackTransport::bind(config()).unwrap();
+		b.inner.drop_udp.store(true, Ordering::Relaxed);
+		let dst = b.local_addr().unwrap();
+		for _ in 0..5 {
+			assert_eq!(a.udp_failed(dst).await.unwrap(), TransportKind::Tcp);
+			a.inner.slot(dst).set(Some(TransportKind::Udp));
+		}
+		tokio::task::yield_now().await;
+		let slot = a.inner.slot(dst);
+		assert!(slot.upgrading.load(Ordering::Acquire));
+		// The peer map, this handle and the single upgrade task.
+		assert_eq!(Arc::strong_count(&slot), 3);
+		assert_eq!(a.stats().fallbacks, 5);
+	}
+
+	#[tokio::test]
+	async fn accepted_connections_are_keyed_by_their_remote_address() {
+		let b = FallbackTransport::bind(config()).unwrap();
+		let mut raw = TcpStream::connect(b.local_addr().unwrap()).await.unwrap();
+		// Formerly a claimed listening port; now just a cell.
+		write_frame(&mut raw, &b.local_addr().unwrap().port().to_be_bytes()).await.unwrap();
+		let got = recv(&b).await;
+		assert_eq!((got.peer, got.kind), (raw.local_addr().unwrap(), TransportKind::Tcp));
+		assert_eq!(b.kind(got.peer), Some(TransportKind::Tcp));
+		assert_eq!(b.send(got.peer, b"back").await.unwrap(), TransportKind::Tcp);
+		assert_eq!(read_frame(&mut raw).await.unwrap().unwrap(), b"back");
+		assert_eq!(b.stats().fallbacks, 0);
+	}
+
+	#[tokio::test]
+	async fn evicted_connections_are_closed() {
+		let b = FallbackTransport::bind(FallbackConfig { max_connections: 1, ..config() }).unwrap();
+		let mut first = TcpStream::connect(b.local_addr().unwrap()).await.unwrap();
+		write_frame(&mut first, b"first").await.unwrap();
+		assert_eq!(recv(&b).await.data, b"first");
+		let mut second = TcpStream::connect(b.local_addr().unwrap()).await.unwrap();
+		write_frame(&mut second, b"second").await.unwrap();
+		assert_eq!(recv(&b).await.data, b"second");
+
+		// The first connection was evicted: its socket is closed, not just its
+		// write half, so nothing it sends is read any more.
+		let eof = tokio::time::timeout(Duration::from_secs(5), read_frame(&mut first)).await.expect("evicted socket left open");
+		assert!(matches!(eof, Ok(None) | Err(_)));
+		let _ = write_frame(&mut first, b"late").await;
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		write_frame(&mut second, b"again").await.unwrap();
+		let got = recv(&b).await;
+		assert_eq!((got.peer, got.data), (second.local_addr().unwrap(), b"again".to_vec()));
+		assert_eq!(b.inner.pool.len(), 1);
+	}
+
+	#[tokio::test]
+	async fn idle_inbound_connections_are_closed() {
+		let b = FallbackTransport::bind(FallbackConfig { idle_timeout: Duration::from_millis(100), ..config() }).unwrap();
+		let mut raw = TcpStream::connect(b.local_addr().unwrap()).await.unwrap();
+		write_frame(&mut raw, b"hello").await.unwrap();
+		recv(&b).await;
+		let eof = tokio::time::timeout(Duration::from_secs(5), read_frame(&mut raw)).await.expect("idle socket left open");
+		assert!(matches!(eof, Ok(None) | Err(_)));
+		assert_eq!(b.inner.pool.len(), 0);
+	}
+
+	#[tokio::test]
+	async fn peer_slots_are_bounded_and_idle_ones_go_first() {
+		let t = FallbackTransport::bind(FallbackConfig { max_peers: 2, idle_timeout: Duration::from_millis(50), ..config() }).unwrap();
+		let peer = |p: u16| SocketAddr::from((Ipv4Addr::LOCALHOST, p));
+		t.inner.slot(peer(1));
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		t.inner.slot(peer(2));
+		t.inner.slot(peer(3));
+		let known = |p: u16| t.inner.peers.lock().unwrap().contains_key(&peer(p));
+		assert!(!known(1) && known(2) && known(3));
+		tokio::time::sleep(Duration::from_millis(2)).await;
+		// Nothing idle: the least recently used goes.
+		t.inner.slot(peer(2));
+		t.inner.slot(peer(4));
+		assert!(known(2) && !known(3) && known(4));
+	}
+}
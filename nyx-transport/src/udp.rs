@@ -0,0 +1,570 @@
+﻿//! Primary UDP transport.
+//!
+//! One socket carries every path. On Linux datagrams are moved with
+//! `sendmmsg`/`recvmmsg`, large sends are handed to the kernel as a single
+//! GSO super-datagram and coalesced receives (GRO) are reported with their
+//! segment stride. ECN codepoints are set per transmit and read back per
+//! datagram so congestion control can react to CE marks. Other platforms
+//! fall back to one system call per datagram with the same API.
+
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tracing::{debug, warn};
+
+/// Recommended listening ports (spec §5.4).
+pub const PORT_RANGE: RangeInclusive<u16> = 43300..=43399;
+/// Maximum datagrams moved per system call.
+pub const BATCH_SIZE: usize = 32;
+/// Kernel limit on segments in one GSO send.
+pub const MAX_GSO_SEGMENTS: usize = 64;
+/// Largest payload one GSO send (or any UDP datagram) may carry.
+pub const MAX_UDP_PAYLOAD: usize = 65_507;
+/// Fraction of CE-marked datagrams above which a path counts as congested (spec §19).
+pub const CE_THRESHOLD: f64 = 0.05;
+
+/// ECN codepoint carried in the low two bits of the TOS / traffic class byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Ecn {
+	Ect1 = 0b01,
+	Ect0 = 0b10,
+	Ce = 0b11,
+}
+
+impl Ecn {
+	/// Decode from a TOS / traffic class byte; `None` is Not-ECT.
+	pub fn from_bits(tos: u8) -> Option<Self> {
+		match tos & 0b11 {
+			0b01 => Some(Self::Ect1),
+			0b10 => Some(Self::Ect0),
+			0b11 => Some(Self::Ce),
+			_ => None,
+		}
+	}
+
+	pub fn bits(self) -> u8 { self as u8 }
+}
+
+/// Received ECN codepoint counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EcnCounts {
+	pub not_ect: u64,
+	pub ect0: u64,
+	pub ect1: u64,
+	pub ce: u64,
+}
+
+impl EcnCounts {
+	pub fn total(&self) -> u64 { self.not_ect + self.ect0 + self.ect1 + self.ce }
+
+	/// Counts accumulated since `earlier` was taken.
+	pub fn since(&self, earlier: &EcnCounts) -> EcnCounts {
+		EcnCounts {
+			not_ect: self.not_ect.saturating_sub(earlier.not_ect),
+			ect0: self.ect0.saturating_sub(earlier.ect0),
+			ect1: self.ect1.saturating_sub(earlier.ect1),
+			ce: self.ce.saturating_sub(earlier.ce),
+		}
+	}
+
+	/// Share of ECN-capable datagrams that arrived CE-marked.
+	pub fn ce_fraction(&self) -> f64 {
+		let capable = self.ect0 + self.ect1 + self.ce;
+		if capable == 0 { 0.0 } else { self.ce as f64 / capable as f64 }
+	}
+
+	pub fn congested(&self) -> bool { self.ce_fraction() > CE_THRESHOLD }
+}
+
+#[derive(Debug, Default)]
+struct EcnCounters([AtomicU64; 4]);
+
+impl EcnCounters {
+	fn record(&self, ecn: Option<Ecn>) {
+		let i = ecn.map_or(0, |e| e.bits() as usize);
+		self.0[i].fetch_add(1, Ordering::Relaxed);
+	}
+
+	fn snapshot(&self) -> EcnCounts {
+		let get = |i: usize| self.0[i].load(Ordering::Relaxed);
+		EcnCounts { not_ect: get(0), ect1: get(1), ect0: get(2), ce: get(3) }
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct UdpConfig {
+	/// Local address; the IPv6 unspecified address binds dual-stack when allowed.
+	pub bind: IpAddr,
+	/// Ports tried in order; `0..=0` picks an ephemeral port.
+	pub ports: RangeInclusive<u16>,
+	/// Accept IPv4 traffic on an IPv6 socket (falls back to IPv4 when IPv6 is unavailable).
+	pub dual_stack: bool,
+	pub gso: bool,
+	pub gro: bool,
+	pub ecn: bool,
+	pub recv_buffer: Option<usize>,
+	pub send_buffer: Option<usize>,
+}
+
+impl Default for UdpConfig {
+	fn default() -> Self {
+		Self {
+			bind: IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+			ports: PORT_RANGE,
+			dual_stack: true,
+			gso: true,
+			gro: true,
+			ecn: true,
+			recv_buffer: None,
+			send_buffer: None,
+		}
+	}
+}
+
+/// Offloads that were successfully enabled on the socket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+	pub mmsg: bool,
+	pub gso: bool,
+	pub gro: bool,
+	pub ecn: bool,
+}
+
+/// One outgoing datagram, or with `segment_size` a run of equal-sized
+/// datagrams (the last may be shorter) to the same destination.
+#[derive(Debug, Clone, Copy)]
+pub struct Transmit<'a> {
+	pub dst: SocketAddr,
+	pub ecn: Option<Ecn>,
+	pub contents: &'a [u8],
+	pub segment_size: Option<usize>,
+}
+
+impl<'a> Transmit<'a> {
+	pub fn new(dst: SocketAddr, contents: &'a [u8]) -> Self { Self { dst, ecn: None, contents, segment_size: None } }
+}
+
+/// Metadata of one received buffer. With GRO `len` may span several
+/// datagrams of `stride` bytes each (the last may be shorter).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvMeta {
+	pub src: SocketAddr,
+	pub len: usize,
+	pub stride: usize,
+	pub ecn: Option<Ecn>,
+}
+
+impl Default for RecvMeta {
+	fn default() -> Self { Self { src: SocketAddr::from(([0, 0, 0, 0], 0)), len: 0, stride: 0, ecn: None } }
+}
+
+impl RecvMeta {
+	/// Split a received buffer into its datagrams.
+	pub fn datagrams<'b>(&self, buf: &'b [u8]) -> impl Iterator<Item = &'b [u8]> {
+		buf[..self.len].chunks(self.stride.max(1))
+	}
+}
+
+#[derive(Debug)]
+pub struct UdpTransport {
+	socket: UdpSocket,
+	caps: Capabilities,
+	v6: bool,
+	gso: AtomicBool,
+	ecn_rx: EcnCounters,
+}
+
+impl UdpTransport {
+	/// Bind on the first free port of `cfg.ports`. Must be called inside a
+	/// Tokio runtime.
+	pub fn bind(cfg: &UdpConfig) -> io::Result<Self> {
+		if cfg.ports.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty port range"));
+		}
+		let mut last = None;
+		for port in cfg.ports.clone() {
+			match bind_socket(cfg, port) {
+				Ok(socket) => return Self::from_socket(socket, cfg),
+				Err(e) if e.kind() == io::ErrorKind::AddrInUse => last = Some(e),
+				Err(e) => return Err(e),
+			}
+		}
+		Err(last.unwrap_or_else(|| io::ErrorKind::AddrInUse.into()))
+	}
+
+	fn from_socket(socket: Socket, cfg: &UdpConfig) -> io::Result<Self> {
+		if let Some(n) = cfg.recv_buffer {
+			socket.set_recv_buffer_size(n)?;
+		}
+		if let Some(n) = cfg.send_buffer {
+			socket.set_send_buffer_size(n)?;
+		}
+		let v6 = socket.local_addr()?.is_ipv6();
+		let caps = sys::enable_offloads(&socket, v6, cfg);
+		socket.set_nonblocking(true)?;
+		let socket = UdpSocket::from_std(socket.into())?;
+		debug!(addr = ?socket.local_addr()?, ?caps, "udp transport bound");
+		Ok(Self { socket, caps, v6, gso: AtomicBool::new(caps.gso), ecn_rx: EcnCounters::default() })
+	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> { self.socket.local_addr() }
+
+	pub fn capabilities(&self) -> Capabilities { Capabilities { gso: self.gso.load(Ordering::Relaxed), ..self.caps } }
+
+	/// Segments one [`Transmit`] may carry in a single system call.
+	pub fn max_gso_segments(&self) -> usize {
+		if self.gso.load(Ordering::Relaxed) { MAX_GSO_SEGMENTS } else { 1 }
+	}
+
+	/// ECN codepoints of every datagram received so far.
+	pub fn ecn_counts(&self) -> EcnCounts { self.ecn_rx.snapshot() }
+
+	pub async fn send_to(&self, buf: &[u8], dst: SocketAddr) -> io::Result<usize> { self.socket.send_to(buf, self.target(dst)?).await }
+
+	/// Send every transmit, batching consecutive ones that share ECN marking,
+	/// segment size and address family into one system call.
+	pub async fn send_batch(&self, txs: &[Transmit<'_>]) -> io::Result<()> {
+		for tx in txs {
+			self.target(tx.dst)?;
+			if tx.contents.len() > MAX_UDP_PAYLOAD || tx.segment_size == Some(0) {
+				return Err(io::Error::new(io::ErrorKind::InvalidInput, "transmit too large"));
+			}
+			if let Some(seg) = tx.segment_size {
+				if tx.contents.len().div_ceil(seg) > MAX_GSO_SEGMENTS {
+					return Err(io::Error::new(io::ErrorKind::InvalidInput, "too many segments"));
+				}
+			}
+		}
+		if !self.gso.load(Ordering::Relaxed) && txs.iter().any(|t| t.segment_size.is_some()) {
+			return self.send_all(&split_segments(txs)).await.map_err(|(_, e)| e);
+		}
+		match self.send_all(txs).await {
+			Ok(()) => Ok(()),
+			// Some NICs cannot checksum GSO sends; the kernel reports EIO.
+			Err((sent, e)) if sys::is_gso_error(&e) && txs[sent].segment_size.is_some() => {
+				warn!(error = %e, "UDP GSO rejected by the kernel, disabling");
+				self.gso.store(false, Ordering::Relaxed);
+				self.send_all(&split_segments(&txs[sent..])).await.map_err(|(_, e)| e)
+			}
+			Err((_, e)) => Err(e),
+		}
+	}
+
+	/// On failure returns how many transmits were sent before the error.
+	async fn send_all(&self, txs: &[Transmit<'_>]) -> Result<(), (usize, io::Error)> {
+		let gso = self.gso.load(Ordering::Relaxed);
+		let mut sent = 0;
+		while sent < txs.len() {
+			let key = BatchKey::of(&txs[sent], gso);
+			let n = txs[sent..].iter().take(BATCH_SIZE).take_while(|t| BatchKey::of(t, gso) == key).count();
+			let group = &txs[sent..sent + n];
+			match self.socket.async_io(Interest::WRITABLE, || sys::send(&self.socket, self.v6, group, key)).await {
+				Ok(k) => sent += k,
+				Err(e) => return Err((sent, e)),
+			}
+		}
+		Ok(())
+	}
+
+	/// Receive up to `bufs.len()` buffers, filling `meta` for each. Buffers
+	/// should hold 64 KiB when GRO is enabled or coalesced data is truncated.
+	pub async fn recv_batch(&self, bufs: &mut [&mut [u8]], meta: &mut [RecvMeta]) -> io::Result<usize> {
+		let n = self.socket.async_io(Interest::READABLE, || sys::recv(&self.socket, bufs, meta)).await?;
+		for m in &meta[..n] {
+			for _ in 0..m.len.div_ceil(m.stride.max(1)).max(1) {
+				self.ecn_rx.record(m.ecn);
+			}
+		}
+		Ok(n)
+	}
+
+	fn target(&self, dst: SocketAddr) -> io::Result<SocketAddr> {
+		match (self.v6, dst.ip()) {
+			(true, IpAddr::V4(ip)) => Ok(SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), dst.port())),
+			(false, IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+				Some(v4) => Ok(SocketAddr::new(IpAddr::V4(v4), dst.port())),
+				None => Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "IPv6 destination on an IPv4 socket")),
+			},
+			_ => Ok(dst),
+		}
+	}
+}
+
+/// What must be identical across datagrams sent in one system call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BatchKey {
+	ecn: Option<Ecn>,
+	segment: Option<u16>,
+	v4: bool,
+}
+
+impl BatchKey {
+	fn of(tx: &Transmit<'_>, gso: bool) -> Self {
+		let segment = tx.segment_size.filter(|&s| gso && s < tx.contents.len()).map(|s| s as u16);
+		Self { ecn: tx.ecn, segment, v4: tx.dst.ip().to_canonical().is_ipv4() }
+	}
+}
+
+fn split_segments<'a>(txs: &[Transmit<'a>]) -> Vec<Transmit<'a>> {
+	txs.iter()
+		.flat_map(|tx| {
+			let seg = tx.segment_size.unwrap_or(tx.contents.len()).max(1);
+			tx.contents.chunks(seg).map(move |c| Transmit { contents: c, segment_size: None, ..*tx })
+		})
+		.collect()
+}
+
+fn bind_socket(cfg: &UdpConfig, port: u16) -> io::Result<Socket> {
+	let v6_any = cfg.bind == IpAddr::V6(Ipv6Addr::UNSPECIFIED);
+	match open(SocketAddr::new(cfg.bind, port), cfg.dual_stack) {
+		Err(e) if v6_any && cfg.dual_stack && e.kind() != io::ErrorKind::AddrInUse => {
+			debug!(error = %e, "IPv6 unavailable, binding IPv4 only");
+			open(SocketAddr::from(([0, 0, 0, 0], port)), false)
+		}
+		r => r,
+	}
+}
+
+fn open(addr: SocketAddr, dual_stack: bool) -> io::Result<Socket> {
+	let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+	if addr.is_ipv6() {
+		socket.set_only_v6(!dual_stack)?;
+	}
+	socket.bind(&addr.into())?;
+	Ok(socket)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+	use std::io::{self, IoSlice, IoSliceMut};
+	use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
+	use std::os::fd::AsRawFd;
+
+	use nix::cmsg_space;
+	use nix::errno::Errno;
+	use nix::sys::socket::{
+		getsockopt, recvmmsg, sendmmsg, setsockopt, sockopt, ControlMessage, ControlMessageOwned, MsgFlags, MultiHeaders, SockaddrLike, SockaddrStorage,
+	};
+	use socket2::Socket;
+	use tokio::net::UdpSocket;
+
+	use super::{BatchKey, Capabilities, Ecn, RecvMeta, Transmit, UdpConfig, BATCH_SIZE};
+
+	pub(super) fn enable_offloads(socket: &Socket, v6: bool, cfg: &UdpConfig) -> Capabilities {
+		let gso = cfg.gso && getsockopt(socket, sockopt::UdpGsoSegment).is_ok();
+		let gro = cfg.gro && setsockopt(socket, sockopt::UdpGroSegment, &true).is_ok();
+		let ecn = cfg.ecn
+			&& if v6 {
+				// IPv4-mapped traffic on a dual-stack socket reports IP_TOS.
+				let _ = setsockopt(socket, sockopt::IpRecvTos, &true);
+				setsockopt(socket, sockopt::Ipv6RecvTClass, &true).is_ok()
+			} else {
+				setsockopt(socket, sockopt::IpRecvTos, &true).is_ok()
+			};
+		Capabilities { mmsg: true, gso, gro, ecn }
+	}
+
+	pub(super) fn is_gso_error(e: &io::Error) -> bool { e.raw_os_error() == Some(Errno::EIO as i32) }
+
+	pub(super) fn send(socket: &UdpSocket, v6: bool, txs: &[Transmit<'_>], key: BatchKey) -> io::Result<usize> {
+		let n = txs.len().min(BATCH_SIZE);
+		let addrs: Vec<Option<SockaddrStorage>> = txs[..n]
+			.iter()
+			.map(|t| {
+				let dst = match (v6, t.dst) {
+					(true, SocketAddr::V4(a)) => SocketAddr::V6(SocketAddrV6::new(a.ip().to_ipv6_mapped(), a.port(), 0, 0)),
+					(_, dst) => dst,
+				};
+				Some(SockaddrStorage::from(dst))
+			})
+			.collect();
+		let iovs: Vec<[IoSlice<'_>; 1]> = txs[..n].iter().map(|t| [IoSlice::new(t.contents)]).collect();
+		let tos = key.ecn.map_or(0, Ecn::bits);
+		let tclass = i32::from(tos);
+		let mut cmsgs = Vec::with_capacity(2);
+		if key.ecn.is_some() {
+			cmsgs.push(if key.v4 { ControlMessage::Ipv4Tos(&tos) } else { ControlMessage::Ipv6TClass(&tclass) });
+		}
+		if let Some(seg) = &key.segment {
+			cmsgs.push(ControlMessage::UdpGsoSegments(seg));
+		}
+		// The kernel walks the whole control buffer, so it must be sized exactly.
+		let space = match (key.ecn.is_some(), key.segment.is_some()) {
+			(false, false) => None,
+			(true, false) => Some(cmsg_space!(i32)),
+			(false, true) => Some(cmsg_space!(u16)),
+			(true, true) => Some(cmsg_space!(i32, u16)),
+		};
+		let mut headers = MultiHeaders::<SockaddrStorage>::preallocate(n, space);
+		let sent = sendmmsg(socket.as_raw_fd(), &mut headers, &iovs, &addrs, &cmsgs, MsgFlags::empty())?;
+		Ok(sent.count())
+	}
+
+	pub(super) fn recv(socket: &UdpSocket, bufs: &mut [&mut [u8]], meta: &mut [RecvMeta]) -> io::Result<usize> {
+		let n = bufs.len().min(meta.len()).min(BATCH_SIZE);
+		let mut iovs: Vec<[IoSliceMut<'_>; 1]> = bufs[..n].iter_mut().map(|b| [IoSliceMut::new(b)]).collect();
+		let mut headers = MultiHeaders::<SockaddrStorage>::preallocate(n, Some(cmsg_space!(i32, i32)));
+		let msgs = recvmmsg(socket.as_raw_fd(), &mut headers, &mut iovs, MsgFlags::empty(), None)?;
+		let mut count = 0;
+		for (msg, m) in msgs.zip(meta.iter_mut()) {
+			let mut stride = msg.bytes;
+			let mut ecn = None;
+			for cmsg in msg.cmsgs()? {
+				match cmsg {
+					ControlMessageOwned::Ipv4Tos(tos) => ecn = Ecn::from_bits(tos),
+					ControlMessageOwned::Ipv6TClass(tc) => ecn = Ecn::from_bits(tc as u8),
+					ControlMessageOwned::UdpGroSegments(s) => stride = s as usize,
+					_ => {}
+				}
+			}
+			let src = msg.address.and_then(to_socket_addr).unwrap_or(m.src);
+			*m = RecvMeta { src: SocketAddr::new(src.ip().to_canonical(), src.port()), len: msg.bytes, stride, ecn };
+			count += 1;
+		}
+		Ok(count)
+	}
+
+	fn to_socket_addr(addr: SockaddrStorage) -> Option<SocketAddr> {
+		match addr.family()? {
+			nix::sys::socket::AddressFamily::Inet => addr.as_sockaddr_in().map(|a| SocketAddr::V4(SocketAddrV4::from(*a))),
+			nix::sys::socket::AddressFamily::Inet6 => addr.as_sockaddr_in6().map(|a| SocketAddr::V6(SocketAddrV6::from(*a))),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+	use std::io;
+
+	use socket2::Socket;
+	use tokio::net::UdpSocket;
+
+	use super::{BatchKey, Capabilities, RecvMeta, Transmit, UdpConfig};
+
+	pub(super) fn enable_offloads(_socket: &Socket, _v6: bool, _cfg: &UdpConfig) -> Capabilities { Capabilities::default() }
+
+	pub(super) fn is_gso_error(_e: &io::Error) -> bool { false }
+
+	/// GSO is never enabled here, so every transmit is a single datagram.
+	pub(super) fn send(socket: &UdpSocket, v6: bool, txs: &[Transmit<'_>], _key: BatchKey) -> io::Result<usize> {
+		let tx = &txs[0];
+		let dst = match (v6, tx.dst) {
+			(true, std::net::SocketAddr::V4(a)) => std::net::SocketAddr::new(a.ip().to_ipv6_mapped().into(), a.port()),
+			(_, dst) => dst,
+		};
+		socket.try_send_to(tx.contents, dst)?;
+		Ok(1)
+	}
+
+	pub(super) fn recv(socket: &UdpSocket, bufs: &mut [&mut [u8]], meta: &mut [RecvMeta]) -> io::Result<usize> {
+		let (Some(buf), Some(m)) = (bufs.first_mut(), meta.first_mut()) else { return Ok(0) };
+		let (len, src) = socket.try_recv_from(buf)?;
+		*m = RecvMeta { src: std::net::SocketAddr::new(src.ip().to_canonical(), src.port()), len, stride: len, ecn: None };
+		Ok(1)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::Ipv4Addr;
+	use std::time::Duration;
+
+	fn loopback() -> UdpConfig { UdpConfig { bind: IpAddr::V4(Ipv4Addr::LOCALHOST), ports: 0..=0, ..Default::default() } }
+
+	async fn recv_datagrams(t: &UdpTransport, want: usize) -> Vec<(Vec<u8>, RecvMeta)> {
+		let mut storage = vec![vec![0u8; 65_536]; 8];
+		let mut out = Vec::new();
+		while out.len() < want {
+			let mut bufs: Vec<&mut [u8]> = storage.iter_mut().map(|b| b.as_mut_slice()).collect();
+			let mut meta = [RecvMeta::default(); 8];
+			let n = tokio::time::timeout(Duration::from_secs(5), t.recv_batch(&mut bufs, &mut meta)).await.expect("recv timed out").unwrap();
+			for (buf, m) in bufs.iter().zip(&meta[..n]) {
+				out.extend(m.datagrams(buf).map(|d| (d.to_vec(), *m)));
+			}
+		}
+		out
+	}
+
+	#[tokio::test]
+	async fn batch_roundtrip_preserves_order_and_source() {
+		let a = UdpTransport::bind(&loopback()).unwrap();
+		let b = UdpTransport::bind(&loopback()).unwrap();
+		let dst = b.local_addr().unwrap();
+		let payloads: Vec<Vec<u8>> = (0..BATCH_SIZE as u8 + 8).map(|i| vec![i; 1280]).collect();
+		let txs: Vec<Transmit> = payloads.iter().map(|p| Transmit::new(dst, p)).collect();
+		a.send_batch(&txs).await.unwrap();
+		let got = recv_datagrams(&b, payloads.len()).await;
+		assert_eq!(got.iter().map(|(d, _)| d.clone()).collect::<Vec<_>>(), payloads);
+		assert!(got.iter().all(|(_, m)| m.src == a.local_addr().unwrap()));
+	}
+
+	#[tokio::test]
+	async fn gso_transmit_arrives_as_separate_datagrams() {
+		let a = UdpTransport::bind(&loopback()).unwrap();
+		let b = UdpTransport::bind(&loopback()).unwrap();
+		let contents: Vec<u8> = (0..4 * 1280 + 100).map(|i| (i / 1280) as u8).collect();
+		let tx = Transmit { segment_size: Some(1280), ..Transmit::new(b.local_addr().unwrap(), &contents) };
+		a.send_batch(&[tx]).await.unwrap();
+		let got = recv_datagrams(&b, 5).await;
+		assert_eq!(got.iter().map(|(d, _)| d.len()).collect::<Vec<_>>(), [1280, 1280, 1280, 1280, 100]);
+		assert!(got.iter().enumerate().all(|(i, (d, _))| d.iter().all(|&x| x == i as u8)));
+
+		// A forced fallback yields the same datagrams.
+		a.gso.store(false, Ordering::Relaxed);
+		a.send_batch(&[tx]).await.unwrap();
+		assert_eq!(recv_datagrams(&b, 5).await.iter().map(|(d, _)| d.len()).sum::<usize>(), contents.len());
+	}
+
+	#[tokio::test]
+	async fn ecn_marks_are_reported_and_counted() {
+		let a = UdpTransport::bind(&loopback()).unwrap();
+		let b = UdpTransport::bind(&loopback()).unwrap();
+		if !b.capabilities().ecn {
+			return;
+		}
+		let dst = b.local_addr().unwrap();
+		let marks = [Some(Ecn::Ect0), Some(Ecn::Ce), None, Some(Ecn::Ect1), Some(Ecn::Ce)];
+		let txs: Vec<Transmit> = marks.iter().map(|&ecn| Transmit { ecn, ..Transmit::new(dst, b"nyx") }).collect();
+		a.send_batch(&txs).await.unwrap();
+		let got = recv_datagrams(&b, marks.len()).await;
+		assert_eq!(got.iter().map(|(_, m)| m.ecn).collect::<Vec<_>>(), marks);
+		let counts = b.ecn_counts();
+		assert_eq!(counts, EcnCounts { not_ect: 1, ect0: 1, ect1: 1, ce: 2 });
+		assert!(counts.congested());
+		let later = EcnCounts { ect0: counts.ect0 + 100, ..counts };
+		assert!(!later.since(&counts).congested());
+	}
+
+	#[tokio::test]
+	async fn binds_within_the_recommended_range() {
+		let cfg = UdpConfig { bind: IpAddr::V4(Ipv4Addr::LOCALHOST), ..Default::default() };
+		let a = UdpTransport::bind(&cfg).unwrap();
+		let b = UdpTransport::bind(&cfg).unwrap();
+		let (pa, pb) = (a.local_addr().unwrap().port(), b.local_addr().unwrap().port());
+		assert!(PORT_RANGE.contains(&pa) && PORT_RANGE.contains(&pb) && pa != pb);
+		let taken = UdpConfig { ports: pa..=pa, ..cfg };
+		assert_eq!(UdpTransport::bind(&taken).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+	}
+
+	#[tokio::test]
+	async fn dual_stack_accepts_ipv4_peers() {
+		let server = UdpTransport::bind(&UdpConfig { ports: 0..=0, ..Default::default() }).unwrap();
+		let port = server.local_addr().unwrap().port();
+		let client = UdpTransport::bind(&loopback()).unwrap();
+		client.send_to(b"v4", SocketAddr::from(([127, 0, 0, 1], port))).await.unwrap();
+		let got = recv_datagrams(&server, 1).await;
+		assert_eq!(got[0].0, b"v4");
+		assert_eq!(got[0].1.src, client.local_addr().unwrap());
+		// Replies to an IPv4 peer are mapped onto the IPv6 socket transparently.
+		server.send_batch(&[Transmit::new(got[0].1.src, b"ok")]).await.unwrap();
+		assert_eq!(recv_datagrams(&client, 1).await[0].0, b"ok");
+	}
+}
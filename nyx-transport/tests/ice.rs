@@ -1 +1,271 @@
+//! ICE between agents behind simulated NATs, using an in-process STUN server.
+//!
+//! Each `NatSocket` stands for a host with a private address (127.1.x.1,
+//! never routed) behind a NAT whose public mappings are real sockets on
+//! 127.0.0.1. Mapping and filtering follow the classic cone taxonomy.
 
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use nyx_transport::ice::{Candidate, CandidateKind, IceOutcome, PairState};
+use nyx_transport::stun_server::StunMessage;
+use nyx_transport::{IceAgent, IceConfig, IceRole, IceSocket, StunServer};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Nat {
+	FullCone,
+	AddressRestricted,
+	PortRestricted,
+	Symmetric,
+}
+
+struct Mapping {
+	socket: UdpSocket,
+	sent: Mutex<HashSet<SocketAddr>>,
+}
+
+type Inbound = (Vec<u8>, SocketAddr, Arc<Mapping>);
+
+struct NatSocket {
+	nat: Nat,
+	private: SocketAddr,
+	/// Keyed by destination for symmetric NATs, a single shared mapping otherwise.
+	mappings: tokio::sync::Mutex<HashMap<Option<SocketAddr>, Arc<Mapping>>>,
+	tx: mpsc::UnboundedSender<Inbound>,
+	rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<Inbound>>,
+}
+
+static NEXT_HOST: AtomicU8 = AtomicU8::new(1);
+
+impl NatSocket {
+	fn new(nat: Nat) -> Self {
+		let private = SocketAddr::from(([127, 1, NEXT_HOST.fetch_add(1, Ordering::Relaxed), 1], 40_000));
+		let (tx, rx) = mpsc::unbounded_channel();
+		Self { nat, private, mappings: Default::default(), tx, rx: tokio::sync::Mutex::new(rx) }
+	}
+
+	async fn mapping(&self, dst: SocketAddr) -> io::Result<Arc<Mapping>> {
+		let key = (self.nat == Nat::Symmetric).then_some(dst);
+		let mut mappings = self.mappings.lock().await;
+		if let Some(m) = mappings.get(&key) {
+			return Ok(m.clone());
+		}
+		let m = Arc::new(Mapping { socket: UdpSocket::bind("127.0.0.1:0").await?, sent: Mutex::default() });
+		let (fwd, tx) = (m.clone(), self.tx.clone());
+		tokio::spawn(async move {
+			let mut buf = [0u8; 1500];
+			while let Ok((n, src)) = fwd.socket.recv_from(&mut buf).await {
+				if tx.send((buf[..n].to_vec(), src, fwd.clone())).is_err() {
+					return;
+				}
+			}
+		});
+		mappings.insert(key, m.clone());
+		Ok(m)
+	}
+
+	fn admits(&self, mapping: &Mapping, src: SocketAddr) -> bool {
+		let sent = mapping.sent.lock().unwrap();
+		match self.nat {
+			Nat::FullCone => true,
+			Nat::AddressRestricted => sent.iter().any(|d| d.ip() == src.ip()),
+			Nat::PortRestricted | Nat::Symmetric => sent.contains(&src),
+		}
+	}
+}
+
+fn is_private(addr: SocketAddr) -> bool { matches!(addr, SocketAddr::V4(a) if a.ip().octets()[..2] == [127, 1]) }
+
+#[async_trait]
+impl IceSocket for NatSocket {
+	async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+		if is_private(target) {
+			// Another site's private address: unroutable from here.
+			return Ok(buf.len());
+		}
+		let m = self.mapping(target).await?;
+		m.sent.lock().unwrap().insert(target);
+		m.socket.send_to(buf, target).await
+	}
+
+	async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+		let mut rx = self.rx.lock().await;
+		loop {
+			let (data, src, m) = rx.recv().await.ok_or_else(|| io::Error::from(io::ErrorKind::BrokenPipe))?;
+			if self.admits(&m, src) {
+				buf[..data.len()].copy_from_slice(&data);
+				return Ok((data.len(), src));
+			}
+		}
+	}
+
+	fn local_addr(&self) -> io::Result<SocketAddr> { Ok(self.private) }
+}
+
+/// Drops nominations towards one address, as if that path broke right after its check.
+struct NoNomination {
+	inner: UdpSocket,
+	blocked: SocketAddr,
+}
+
+#[async_trait]
+impl IceSocket for NoNomination {
+	async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+		if target == self.blocked && StunMessage::decode(buf).is_ok_and(|m| m.use_candidate()) {
+			return Ok(buf.len());
+		}
+		self.inner.send_to(buf, target).await
+	}
+
+	async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> { self.inner.recv_from(buf).await }
+
+	fn local_addr(&self) -> io::Result<SocketAddr> { self.inner.local_addr() }
+}
+
+/// Relays between the first client and `target`, making `target` reachable at a second address.
+async fn forwarder(target: SocketAddr) -> SocketAddr {
+	let front = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+	let back = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+	let addr = front.local_addr().unwrap();
+	let client = Arc::new(Mutex::new(None));
+	let (rx_front, tx_back, seen) = (front.clone(), back.clone(), client.clone());
+	tokio::spawn(async move {
+		let mut buf = [0u8; 1500];
+		while let Ok((n, src)) = rx_front.recv_from(&mut buf).await {
+			*seen.lock().unwrap() = Some(src);
+			let _ = tx_back.send_to(&buf[..n], target).await;
+		}
+	});
+	tokio::spawn(async move {
+		let mut buf = [0u8; 1500];
+		while let Ok((n, _)) = back.recv_from(&mut buf).await {
+			let to = *client.lock().unwrap();
+			if let Some(to) = to {
+				let _ = front.send_to(&buf[..n], to).await;
+			}
+		}
+	});
+	addr
+}
+
+fn config(stun: &StunServer) -> IceConfig {
+	IceConfig { stun_servers: vec![stun.local_addr()], rto: Duration::from_millis(50), check_timeout: Duration::from_secs(1), connect_timeout: Duration::from_secs(3), ..Default::default() }
+}
+
+async fn run<A: IceSocket, B: IceSocket>(a: &IceAgent<A>, b: &IceAgent<B>) -> (io::Result<IceOutcome>, io::Result<IceOutcome>) {
+	let (ca, cb) = tokio::join!(a.gather(), b.gather());
+	tokio::join!(a.connect(b.credentials(), &ca, &cb), b.connect(a.credentials(), &cb, &ca))
+}
+
+fn assert_rest_closed(outcome: &IceOutcome) {
+	for (pair, state) in &outcome.checks {
+		if *pair != outcome.pair {
+			assert!(!matches!(state, PairState::Waiting | PairState::InProgress), "{pair:?} left {state:?}");
+		}
+	}
+}
+
+#[tokio::test]
+async fn hosts_without_nat_connect_directly() {
+	let stun = StunServer::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+	let a = IceAgent::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), IceRole::Controlling, config(&stun)).unwrap();
+	let b = IceAgent::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), IceRole::Controlled, config(&stun)).unwrap();
+
+	// The reflexive address equals the host one, so it is not a separate candidate.
+	assert_eq!(a.gather().await.len(), 1);
+
+	let (oa, ob) = run(&a, &b).await;
+	let (oa, ob) = (oa.unwrap(), ob.unwrap());
+	assert_eq!(oa.pair.remote.kind, CandidateKind::Host);
+	assert_eq!(oa.pair.local.addr, ob.pair.remote.addr);
+	assert_eq!(ob.pair.local.addr, oa.pair.remote.addr);
+	assert_rest_closed(&oa);
+	assert_rest_closed(&ob);
+}
+
+#[tokio::test]
+async fn restricted_cone_nats_punch_through_reflexive_candidates() {
+	let stun = StunServer::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+	let a = IceAgent::new(NatSocket::new(Nat::PortRestricted), IceRole::Controlling, config(&stun)).unwrap();
+	let b = IceAgent::new(NatSocket::new(Nat::AddressRestricted), IceRole::Controlled, config(&stun)).unwrap();
+
+	let cands = a.gather().await;
+	assert!(cands.iter().any(|c| c.kind == CandidateKind::ServerReflexive && c.base == cands[0].addr));
+
+	let (oa, ob) = run(&a, &b).await;
+	let (oa, ob) = (oa.unwrap(), ob.unwrap());
+	assert_eq!(oa.pair.remote.kind, CandidateKind::ServerReflexive);
+	assert_eq!(ob.pair.remote.kind, CandidateKind::ServerReflexive);
+	// Checks towards the private host addresses never got an answer.
+	assert!(oa.checks.iter().filter(|(p, _)| p.remote.kind == CandidateKind::Host).all(|(_, s)| *s != PairState::Succeeded));
+	assert_rest_closed(&oa);
+	assert_rest_closed(&ob);
+}
+
+#[tokio::test]
+async fn symmetric_nat_is_reached_through_a_peer_reflexive_candidate() {
+	let stun = StunServer::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+	let a = IceAgent::new(NatSocket::new(Nat::Symmetric), IceRole::Controlled, config(&stun)).unwrap();
+	let b = IceAgent::new(NatSocket::new(Nat::FullCone), IceRole::Controlling, config(&stun)).unwrap();
+
+	let (oa, ob) = run(&a, &b).await;
+	let (oa, ob) = (oa.unwrap(), ob.unwrap());
+	// The symmetric side's reflexive address only works for the STUN server;
+	// the full-cone side learns the real mapping from the incoming check.
+	assert_eq!(ob.pair.remote.kind, CandidateKind::PeerReflexive);
+	assert_eq!(oa.pair.remote.kind, CandidateKind::ServerReflexive);
+	assert_rest_closed(&oa);
+	assert_rest_closed(&ob);
+}
+
+#[tokio::test]
+async fn two_symmetric_nats_cannot_connect() {
+	let stun = StunServer::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+	let cfg = IceConfig { connect_timeout: Duration::from_millis(1500), ..config(&stun) };
+	let a = IceAgent::new(NatSocket::new(Nat::Symmetric), IceRole::Controlling, cfg.clone()).unwrap();
+	let b = IceAgent::new(NatSocket::new(Nat::Symmetric), IceRole::Controlled, cfg).unwrap();
+
+	let (oa, ob) = run(&a, &b).await;
+	assert_eq!(oa.unwrap_err().kind(), io::ErrorKind::TimedOut);
+	assert_eq!(ob.unwrap_err().kind(), io::ErrorKind::TimedOut);
+}
+
+#[tokio::test]
+async fn role_conflicts_are_settled_by_the_tiebreaker() {
+	let stun = StunServer::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+	for role in [IceRole::Controlling, IceRole::Controlled] {
+		let a = IceAgent::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), role, config(&stun)).unwrap();
+		let b = IceAgent::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), role, config(&stun)).unwrap();
+
+		let (oa, ob) = run(&a, &b).await;
+		let (oa, ob) = (oa.unwrap(), ob.unwrap());
+		assert_ne!(a.role(), b.role());
+		assert_eq!(oa.pair.local.addr, ob.pair.remote.addr);
+		assert_eq!(ob.pair.local.addr, oa.pair.remote.addr);
+	}
+}
+
+#[tokio::test]
+async fn a_failed_nomination_moves_on_to_the_next_pair() {
+	let stun = StunServer::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+	let b = IceAgent::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), IceRole::Controlled, config(&stun)).unwrap();
+	let cb = b.gather().await;
+	let direct = cb[0].addr;
+	let relayed = forwarder(direct).await;
+	let a = IceAgent::new(NoNomination { inner: UdpSocket::bind("127.0.0.1:0").await.unwrap(), blocked: direct }, IceRole::Controlling, config(&stun)).unwrap();
+	let ca = a.gather().await;
+
+	// The direct pair ranks first and passes its check, but cannot be nominated.
+	let remote = [cb.clone(), vec![Candidate::new(CandidateKind::Relay, relayed, relayed)]].concat();
+	let (oa, ob) = tokio::join!(a.connect(b.credentials(), &ca, &remote), b.connect(a.credentials(), &cb, &ca));
+	let (oa, ob) = (oa.unwrap(), ob.unwrap());
+	assert_eq!(oa.pair.remote.addr, relayed);
+	assert_eq!(ob.pair.remote.kind, CandidateKind::PeerReflexive);
+}
@@ -0,0 +1,78 @@
+//! Cells over QUIC DATAGRAM on loopback with NodeID-pinned self-signed identities.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use nyx_crypto::keystore::Keystore;
+use nyx_transport::quic::{QuicConfig, QuicEndpoint, QuicIdentity};
+use tokio::net::UdpSocket;
+
+fn loopback() -> QuicConfig { QuicConfig { bind: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)), ..Default::default() } }
+
+async fn within<T>(f: impl std::future::Future<Output = T>) -> T { tokio::time::timeout(Duration::from_secs(10), f).await.expect("timed out") }
+
+#[tokio::test]
+async fn cells_cross_a_pinned_connection_both_ways() {
+	let keystore = Keystore::generate();
+	let server = QuicEndpoint::bind(&loopback(), QuicIdentity::from_keystore(&keystore).unwrap()).unwrap();
+	let client = QuicEndpoint::bind(&loopback(), QuicIdentity::generate().unwrap()).unwrap();
+	// The dialer only knows the server's NodeID.
+	let (addr, pin) = (server.local_addr().unwrap(), keystore.node_id());
+
+	let (dialed, accepted) = within(async { tokio::join!(client.connect(addr, pin), server.accept()) }).await;
+	let (dialed, accepted) = (dialed.unwrap(), accepted.unwrap().unwrap());
+	assert_eq!(dialed.peer_node_id(), pin);
+	assert_eq!(accepted.peer_node_id(), client.identity().node_id());
+	assert!(dialed.max_datagram_size().unwrap() >= 1280);
+
+	for i in 0..16u8 {
+		dialed.send(&[i; 1280]).unwrap();
+		assert_eq!(&within(accepted.recv()).await.unwrap()[..], &[i; 1280][..]);
+		accepted.send(&[!i; 1280]).unwrap();
+		assert_eq!(&within(dialed.recv()).await.unwrap()[..], &[!i; 1280][..]);
+	}
+	let too_big = vec![0u8; dialed.max_datagram_size().unwrap() + 1];
+	assert_eq!(dialed.send(&too_big).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn wrong_pin_is_rejected() {
+	let server = QuicEndpoint::bind(&loopback(), QuicIdentity::generate().unwrap()).unwrap();
+	let client = QuicEndpoint::bind(&loopback(), QuicIdentity::generate().unwrap()).unwrap();
+	let addr = server.local_addr().unwrap();
+	within(async {
+		tokio::select! {
+			res = client.connect(addr, [0xee; 32]) => assert!(res.is_err()),
+			_ = server.accept() => panic!("handshake with a wrong pin completed"),
+		}
+	})
+	.await;
+}
+
+#[tokio::test]
+async fn a_stalled_handshake_does_not_hold_up_the_next() {
+	let server = QuicEndpoint::bind(&loopback(), QuicIdentity::generate().unwrap()).unwrap();
+	let (addr, pin) = (server.local_addr().unwrap(), server.identity().node_id());
+
+	// Relays the first dialer's packets to the server and drops every answer,
+	// so its handshake never completes.
+	let relay = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+	let via = relay.local_addr().unwrap();
+	tokio::spawn(async move {
+		let out = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+		let mut buf = [0u8; 2048];
+		loop {
+			let (n, _) = relay.recv_from(&mut buf).await.unwrap();
+			let _ = out.send_to(&buf[..n], addr).await;
+		}
+	});
+	let stalled = QuicEndpoint::bind(&loopback(), QuicIdentity::generate().unwrap()).unwrap();
+	tokio::spawn(async move { stalled.connect(via, pin).await });
+	tokio::time::sleep(Duration::from_millis(200)).await;
+
+	let client = QuicEndpoint::bind(&loopback(), QuicIdentity::generate().unwrap()).unwrap();
+	let (dialed, accepted) = within(async { tokio::join!(client.connect(addr, pin), server.accept()) }).await;
+	dialed.unwrap();
+	assert_eq!(accepted.unwrap().unwrap().peer_node_id(), client.identity().node_id());
+}
@@ -1 +1,35 @@
+//! Binding requests against the in-process STUN server.
 
+use std::time::Duration;
+
+use nyx_transport::stun_server::{Class, StunMessage};
+use nyx_transport::StunServer;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+async fn query(socket: &UdpSocket, server: std::net::SocketAddr) -> StunMessage {
+	let req = StunMessage::binding_request();
+	socket.send_to(&req.encode(None), server).await.unwrap();
+	let mut buf = [0u8; 1500];
+	let (n, src) = timeout(Duration::from_secs(2), socket.recv_from(&mut buf)).await.expect("stun response").unwrap();
+	assert_eq!(src, server);
+	let resp = StunMessage::decode(&buf[..n]).unwrap();
+	assert_eq!(resp.txid, req.txid);
+	resp
+}
+
+#[tokio::test]
+async fn binding_reports_each_clients_source_address() {
+	let server = StunServer::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+	let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+	let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+	// Garbage in front of a real request must not wedge the server.
+	a.send_to(&[0xff; 64], server.local_addr()).await.unwrap();
+
+	for socket in [&a, &b] {
+		let resp = query(socket, server.local_addr()).await;
+		assert_eq!(resp.class, Class::Success);
+		assert_eq!(resp.xor_mapped_address(), Some(socket.local_addr().unwrap()));
+	}
+}